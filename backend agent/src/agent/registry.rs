@@ -291,7 +291,7 @@ impl AgentRegistry {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct AgentStatistics {
     pub total: usize,
     pub online: usize,
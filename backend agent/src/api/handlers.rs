@@ -6,47 +6,50 @@ use crate::command::CommandEngine;
 use crate::data::models::{ActorInfo, AgentStatus, CommandRecord, CommandStatus};
 use crate::telemetry::TelemetryProcessor;
 use actix_web::{web, HttpRequest, HttpResponse, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 // Request/Response DTOs
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct CreateCommandRequest {
     pub verb: String,
     pub args: serde_json::Value,
     pub agent_targets: Vec<String>,
     pub timeout: Option<u64>,
     pub max_retries: Option<u32>,
+    /// Resubmitting with the same key returns the original command
+    pub idempotency_key: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CreateCommandResponse {
     pub correlation_id: String,
     pub status: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct AgentListResponse {
     pub agents: Vec<crate::data::models::AgentInfo>,
     pub total: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CommandListResponse {
     pub commands: Vec<crate::command::queue::QueuedCommand>,
     pub total: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct StatisticsResponse {
     pub agents: crate::agent::registry::AgentStatistics,
     pub commands: crate::command::engine::CommandEngineStats,
     pub telemetry: crate::telemetry::processor::TelemetryStats,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct HealthResponse {
     pub status: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
@@ -142,6 +145,25 @@ pub async fn create_command(
         payload.verb, claims.sub
     );
 
+    if let Some(ref key) = payload.idempotency_key {
+        match command_engine.find_by_idempotency_key(key).await {
+            Ok(Some(correlation_id)) => {
+                debug!("Command with idempotency key {} already exists", key);
+                return Ok(HttpResponse::Ok().json(CreateCommandResponse {
+                    correlation_id,
+                    status: "duplicate".to_string(),
+                }));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to look up idempotency key: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to look up idempotency key"
+                })));
+            }
+        }
+    }
+
     // Create command record
     let correlation_id = Uuid::new_v4().to_string();
     let command = CommandRecord {
@@ -165,6 +187,7 @@ pub async fn create_command(
         scheduled_at: None,
         executed_at: None,
         completed_at: None,
+        idempotency_key: payload.idempotency_key.clone(),
     };
 
     match command_engine.submit_command(command).await {
@@ -464,7 +487,7 @@ pub async fn update_agent_status(
 }
 
 // Query parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct CommandListQuery {
     pub status: Option<String>,
     pub agent_id: Option<String>,
@@ -472,7 +495,7 @@ pub struct CommandListQuery {
     pub limit: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct TelemetryHistoryQuery {
     pub limit: Option<usize>,
     pub hours: Option<u32>,
@@ -1,5 +1,6 @@
 pub mod auth;
 pub mod handlers;
+pub mod openapi;
 pub mod routes;
 
 // pub use auth::*;
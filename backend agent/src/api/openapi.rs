@@ -0,0 +1,367 @@
+//! OpenAPI 3 description of the HTTP API, generated from the handlers'
+//! request/response types. `openapi.json` at the crate root is the committed
+//! copy; the tests fail when it no longer matches what is generated here
+//! (`UPDATE_OPENAPI=1 cargo test openapi` rewrites it).
+
+use crate::api::handlers;
+use crate::command::queue::QueuedCommand;
+use crate::data::models::{AgentInfo, TelemetryRecord};
+use actix_web::HttpResponse;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+
+/// Body of every `{"error": ...}` response.
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct ErrorResponse {
+    error: String,
+}
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+fn reference<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    gen.subschema_for::<T>()
+}
+
+fn inline<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    T::json_schema(gen)
+}
+
+/// One documented route.
+pub struct Operation {
+    pub method: &'static str,
+    pub path: &'static str,
+    /// Reachable without a bearer token
+    pub public: bool,
+    tag: &'static str,
+    summary: &'static str,
+    status: &'static str,
+    query: Option<SchemaFn>,
+    body: Option<SchemaFn>,
+    response: Option<SchemaFn>,
+}
+
+impl Operation {
+    fn new(
+        method: &'static str,
+        path: &'static str,
+        tag: &'static str,
+        summary: &'static str,
+    ) -> Self {
+        Self {
+            method,
+            path,
+            public: false,
+            tag,
+            summary,
+            status: "200",
+            query: None,
+            body: None,
+            response: None,
+        }
+    }
+
+    fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    fn created(mut self) -> Self {
+        self.status = "201";
+        self
+    }
+
+    fn query<T: JsonSchema>(mut self) -> Self {
+        self.query = Some(inline::<T>);
+        self
+    }
+
+    fn body<T: JsonSchema>(mut self) -> Self {
+        self.body = Some(reference::<T>);
+        self
+    }
+
+    fn returns<T: JsonSchema>(mut self) -> Self {
+        self.response = Some(reference::<T>);
+        self
+    }
+
+    fn path_params(&self) -> impl Iterator<Item = &'static str> {
+        self.path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+    }
+
+    fn to_json(&self, gen: &mut SchemaGenerator) -> Value {
+        let mut parameters: Vec<Value> = self
+            .path_params()
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                })
+            })
+            .collect();
+        if let Some(query) = self.query {
+            let object = match query(gen) {
+                Schema::Object(object) => object,
+                Schema::Bool(_) => SchemaObject::default(),
+            };
+            let validation = object.object.unwrap_or_default();
+            for (name, schema) in validation.properties {
+                parameters.push(json!({
+                    "name": name,
+                    "in": "query",
+                    "required": validation.required.contains(&name),
+                    "schema": schema
+                }));
+            }
+        }
+
+        let success = match self.response {
+            Some(schema) => json!(schema(gen)),
+            None => json!({ "type": "object" }),
+        };
+        let error = json!({
+            "application/json": { "schema": { "$ref": "#/components/schemas/ErrorResponse" } }
+        });
+        let mut responses = Map::new();
+        responses.insert(
+            self.status.to_string(),
+            json!({
+                "description": "Success",
+                "content": { "application/json": { "schema": success } }
+            }),
+        );
+        responses.insert(
+            "400".to_string(),
+            json!({ "description": "Invalid request", "content": error }),
+        );
+        if !self.public {
+            responses.insert(
+                "401".to_string(),
+                json!({ "description": "Missing or invalid bearer token" }),
+            );
+            responses.insert(
+                "403".to_string(),
+                json!({ "description": "Role not allowed" }),
+            );
+        }
+
+        let mut operation = Map::new();
+        operation.insert("tags".to_string(), json!([self.tag]));
+        operation.insert("summary".to_string(), json!(self.summary));
+        operation.insert(
+            "operationId".to_string(),
+            json!(format!(
+                "{}{}",
+                self.method,
+                self.path.replace(['/', '{', '}', '.', '-'], "_")
+            )),
+        );
+        if !parameters.is_empty() {
+            operation.insert("parameters".to_string(), Value::Array(parameters));
+        }
+        if let Some(schema) = self.body {
+            operation.insert(
+                "requestBody".to_string(),
+                json!({
+                    "required": true,
+                    "content": { "application/json": { "schema": schema(gen) } }
+                }),
+            );
+        }
+        operation.insert("responses".to_string(), Value::Object(responses));
+        if self.public {
+            operation.insert("security".to_string(), json!([]));
+        }
+        Value::Object(operation)
+    }
+}
+
+fn get(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    Operation::new("get", path, tag, summary)
+}
+
+fn post(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    Operation::new("post", path, tag, summary)
+}
+
+fn put(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    Operation::new("put", path, tag, summary)
+}
+
+/// Every route mounted by `routes::configure_routes`. Keep in step with it;
+/// the tests check each entry is served.
+pub fn operations() -> Vec<Operation> {
+    vec![
+        get("/health", "health", "Liveness")
+            .public()
+            .returns::<handlers::HealthResponse>(),
+        // Agents
+        get("/api/v1/agents", "agents", "List connected agents")
+            .returns::<handlers::AgentListResponse>(),
+        get("/api/v1/agents/{agent_id}", "agents", "Get an agent").returns::<AgentInfo>(),
+        get(
+            "/api/v1/agents/site/{site}",
+            "agents",
+            "List the agents at a site",
+        )
+        .returns::<handlers::AgentListResponse>(),
+        put(
+            "/api/v1/agents/{agent_id}/status/{status}",
+            "agents",
+            "Set an agent's status",
+        ),
+        // Commands
+        post("/api/v1/commands", "commands", "Queue a command for agents")
+            .created()
+            .body::<handlers::CreateCommandRequest>()
+            .returns::<handlers::CreateCommandResponse>(),
+        get("/api/v1/commands", "commands", "List commands")
+            .query::<handlers::CommandListQuery>()
+            .returns::<handlers::CommandListResponse>(),
+        get(
+            "/api/v1/commands/{correlation_id}",
+            "commands",
+            "Get a command",
+        )
+        .returns::<QueuedCommand>(),
+        post(
+            "/api/v1/commands/{correlation_id}/retry",
+            "commands",
+            "Retry a failed command",
+        ),
+        post(
+            "/api/v1/commands/{correlation_id}/cancel",
+            "commands",
+            "Cancel a pending command",
+        ),
+        // Telemetry
+        get(
+            "/api/v1/telemetry/{agent_id}",
+            "telemetry",
+            "Latest telemetry of an agent",
+        )
+        .returns::<TelemetryRecord>(),
+        get(
+            "/api/v1/telemetry/{agent_id}/history",
+            "telemetry",
+            "Telemetry history of an agent",
+        )
+        .query::<handlers::TelemetryHistoryQuery>()
+        .returns::<Vec<TelemetryRecord>>(),
+        get(
+            "/api/v1/statistics",
+            "statistics",
+            "Agent, command and telemetry statistics",
+        )
+        .returns::<handlers::StatisticsResponse>(),
+        get("/ws/agent", "agents", "Agent WebSocket endpoint").public(),
+    ]
+}
+
+/// Builds the document from `operations()`.
+pub fn generate() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    gen.subschema_for::<ErrorResponse>();
+
+    let mut paths = Map::new();
+    for operation in operations() {
+        let item = paths
+            .entry(operation.path.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(item) = item {
+            item.insert(operation.method.to_string(), operation.to_json(&mut gen));
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "ViWorkS Backend Agent",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "security": [{ "bearerAuth": [] }],
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" }
+            },
+            "schemas": gen.take_definitions()
+        }
+    })
+}
+
+static DOCUMENT: OnceLock<Value> = OnceLock::new();
+
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(DOCUMENT.get_or_init(generate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    const COMMITTED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    #[test]
+    fn committed_spec_matches_handlers() {
+        let generated = generate();
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            let text = serde_json::to_string_pretty(&generated).unwrap() + "\n";
+            std::fs::write(COMMITTED, text).unwrap();
+            return;
+        }
+        let committed: Value =
+            serde_json::from_str(&std::fs::read_to_string(COMMITTED).unwrap()).unwrap();
+        assert!(
+            committed == generated,
+            "openapi.json is out of date with the handler types; regenerate it with `UPDATE_OPENAPI=1 cargo test openapi`"
+        );
+    }
+
+    #[actix_web::test]
+    async fn every_operation_is_served_with_the_documented_auth() {
+        let app = init_service(App::new().configure(crate::api::configure_routes)).await;
+        for operation in operations() {
+            let mut path = operation.path.to_string();
+            for name in operation.path_params() {
+                path = path.replace(&format!("{{{}}}", name), "x");
+            }
+            let request = TestRequest::default()
+                .method(operation.method.to_uppercase().parse().unwrap())
+                .uri(&path)
+                .to_request();
+            let status = call_service(&app, request).await.status();
+            let label = format!("{} {}", operation.method, operation.path);
+            if operation.public {
+                assert!(
+                    status != StatusCode::NOT_FOUND && status != StatusCode::METHOD_NOT_ALLOWED,
+                    "{} is not routed ({})",
+                    label,
+                    status
+                );
+            } else {
+                assert_eq!(
+                    status,
+                    StatusCode::UNAUTHORIZED,
+                    "{} is documented as requiring a token",
+                    label
+                );
+            }
+        }
+
+        let request = TestRequest::get().uri("/api/openapi.json").to_request();
+        let served: Value = call_and_read_body_json(&app, request).await;
+        assert_eq!(served, generate());
+    }
+}
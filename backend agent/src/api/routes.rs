@@ -1,5 +1,5 @@
 use crate::api::auth::jwt_validator;
-use crate::api::handlers;
+use crate::api::{handlers, openapi};
 use actix_web::{web, HttpResponse};
 use actix_web_httpauth::middleware::HttpAuthentication;
 
@@ -7,6 +7,9 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     // Health check endpoint (no authentication required)
     cfg.route("/health", web::get().to(handlers::health_check));
 
+    // OpenAPI document for the routes below (no authentication required)
+    cfg.route("/api/openapi.json", web::get().to(openapi::openapi_json));
+
     // API v1 routes with authentication
     cfg.service(
         web::scope("/api/v1")
@@ -236,7 +236,29 @@ impl CommandEngine {
 
     /// Get command by correlation ID
     pub async fn get_command(&self, correlation_id: &str) -> Option<QueuedCommand> {
-        self.queue.get_command(correlation_id).await
+        if let Some(command) = self.queue.get_command(correlation_id).await {
+            return Some(command);
+        }
+
+        // The queue is in memory only; fall back to the stored record
+        match self.data_layer.postgres.get_command(correlation_id).await {
+            Ok(command) => command.map(QueuedCommand::new),
+            Err(e) => {
+                warn!("Failed to load command {}: {}", correlation_id, e);
+                None
+            }
+        }
+    }
+
+    /// Correlation ID of the command submitted with `idempotency_key`, if any
+    pub async fn find_by_idempotency_key(
+        &self,
+        idempotency_key: &str,
+    ) -> BackendAgentResult<Option<String>> {
+        self.data_layer
+            .postgres
+            .find_command_by_idempotency_key(idempotency_key)
+            .await
     }
 
     /// Get all pending commands
@@ -476,7 +498,7 @@ impl CommandEngine {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct CommandEngineStats {
     pub queue: crate::command::queue::QueueStatistics,
     pub execution: crate::command::executor::ExecutionStats,
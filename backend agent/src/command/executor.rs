@@ -71,6 +71,13 @@ impl CommandExecutor {
             "docker_compose_down" => self.validate_docker_compose_down_command(command).await,
             "docker_compose_ps" => self.validate_docker_compose_ps_command(command).await,
             "docker_compose_logs" => self.validate_docker_compose_logs_command(command).await,
+            // Gateway user lifecycle, issued by the admin backend
+            "create_openvpn_user" => {
+                self.validate_required_strings(command, &["username", "password"])
+            }
+            "delete_user" => self.validate_required_strings(command, &["username"]),
+            "terminate_session" => self.validate_required_strings(command, &["username"]),
+            "spawn_container" => self.validate_required_strings(command, &["username"]),
             _ => Err(crate::error::BackendAgentError::Validation(format!(
                 "Unknown command verb: {}",
                 command.verb
@@ -452,6 +459,27 @@ impl CommandExecutor {
         Ok(())
     }
 
+    /// Require each of `fields` to be a non-empty string argument
+    fn validate_required_strings(
+        &self,
+        command: &CommandRecord,
+        fields: &[&str],
+    ) -> BackendAgentResult<()> {
+        for field in fields {
+            match command.args.get(*field).and_then(|value| value.as_str()) {
+                Some(value) if !value.is_empty() => {}
+                _ => {
+                    return Err(crate::error::BackendAgentError::Validation(format!(
+                        "{} command must have a non-empty '{}' string",
+                        command.verb, field
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Execute a command (this is a placeholder - actual execution happens on OS agents)
     pub async fn execute_command(
         &self,
@@ -501,7 +529,7 @@ impl CommandExecutor {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct ExecutionStats {
     pub max_concurrent_commands: usize,
     pub command_timeout: u64,
@@ -7,7 +7,7 @@ use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, schemars::JsonSchema)]
 pub struct QueuedCommand {
     pub command: CommandRecord,
     pub priority: CommandPriority,
@@ -418,7 +418,7 @@ impl CommandQueue {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct QueueStatistics {
     pub pending: usize,
     pub executing: usize,
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::types::Uuid;
@@ -8,7 +9,7 @@ use std::str::FromStr;
 // Agent Management Models
 // ============================================================================
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow, JsonSchema)]
 pub struct AgentInfo {
     pub id: Uuid,
     pub agent_id: String,
@@ -25,7 +26,7 @@ pub struct AgentInfo {
     pub connection_info: Option<ConnectionInfo>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub enum AgentStatus {
     Online,
     Offline,
@@ -67,7 +68,7 @@ impl sqlx::Decode<'_, sqlx::Postgres> for AgentStatus {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub struct ConnectionInfo {
     pub remote_addr: String,
     pub user_agent: Option<String>,
@@ -80,7 +81,7 @@ pub struct ConnectionInfo {
 // Command Models
 // ============================================================================
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow, JsonSchema)]
 pub struct CommandRecord {
     pub id: Uuid,
     pub correlation_id: String,
@@ -98,9 +99,14 @@ pub struct CommandRecord {
     pub retry_count: i32,
     pub max_retries: i32,
     pub error_message: Option<String>,
+    /// Caller-supplied key; resubmitting with the same key returns the
+    /// original command instead of queueing a second one
+    #[serde(default)]
+    #[sqlx(default)]
+    pub idempotency_key: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub enum CommandStatus {
     Pending,
     Queued,
@@ -168,7 +174,7 @@ impl FromStr for CommandStatus {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
 pub enum CommandPriority {
     Low,
     Normal,
@@ -210,14 +216,14 @@ impl sqlx::Decode<'_, sqlx::Postgres> for CommandPriority {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub struct ActorInfo {
     pub id: String,
     pub role: String,
     pub permissions: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub struct CommandResult {
     pub agent_id: String,
     pub status: CommandExecutionStatus,
@@ -229,7 +235,7 @@ pub struct CommandResult {
     pub timestamp: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub enum CommandExecutionStatus {
     Success,
     Error,
@@ -241,7 +247,7 @@ pub enum CommandExecutionStatus {
 // Telemetry Models
 // ============================================================================
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, sqlx::FromRow, JsonSchema)]
 pub struct TelemetryRecord {
     pub id: Uuid,
     pub agent_id: String,
@@ -256,7 +262,7 @@ pub struct TelemetryRecord {
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct MemoryInfo {
     pub total_mb: u64,
     pub used_mb: u64,
@@ -265,7 +271,7 @@ pub struct MemoryInfo {
     pub swap_used_mb: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct DiskInfo {
     pub mount_point: String,
     pub filesystem: String,
@@ -275,7 +281,7 @@ pub struct DiskInfo {
     pub usage_percent: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct NetworkStats {
     pub bytes_sent: u64,
     pub bytes_received: u64,
@@ -141,7 +141,8 @@ impl PostgresClient {
                 result JSONB,
                 retry_count INTEGER NOT NULL DEFAULT 0,
                 max_retries INTEGER NOT NULL DEFAULT 3,
-                error_message TEXT
+                error_message TEXT,
+                idempotency_key VARCHAR(255)
             )
         "#;
 
@@ -150,6 +151,15 @@ impl PostgresClient {
             BackendAgentError::Database(e)
         })?;
 
+        // Tables created before idempotency keys existed
+        sqlx::query("ALTER TABLE commands ADD COLUMN IF NOT EXISTS idempotency_key VARCHAR(255)")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to add commands.idempotency_key: {}", e);
+                BackendAgentError::Database(e)
+            })?;
+
         Ok(())
     }
 
@@ -215,6 +225,7 @@ impl PostgresClient {
             "CREATE INDEX IF NOT EXISTS idx_commands_status ON commands(status)",
             "CREATE INDEX IF NOT EXISTS idx_commands_verb ON commands(verb)",
             "CREATE INDEX IF NOT EXISTS idx_commands_created_at ON commands(created_at)",
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_commands_idempotency_key ON commands(idempotency_key) WHERE idempotency_key IS NOT NULL",
             "CREATE INDEX IF NOT EXISTS idx_telemetry_agent_id ON telemetry(agent_id)",
             "CREATE INDEX IF NOT EXISTS idx_telemetry_timestamp ON telemetry(timestamp)",
             "CREATE INDEX IF NOT EXISTS idx_audit_logs_timestamp ON audit_logs(timestamp)",
@@ -379,8 +390,8 @@ impl PostgresClient {
         let sql = r#"
             INSERT INTO commands (
                 correlation_id, verb, args, agent_targets, actor, status, priority,
-                scheduled_at, max_retries
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                scheduled_at, max_retries, idempotency_key
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
         "#;
 
         let actor = serde_json::to_value(&command.actor)
@@ -396,6 +407,7 @@ impl PostgresClient {
             .bind(&command.priority)
             .bind(&command.scheduled_at)
             .bind(&command.max_retries)
+            .bind(&command.idempotency_key)
             .execute(&self.pool)
             .await
             .map_err(|e| {
@@ -406,6 +418,24 @@ impl PostgresClient {
         Ok(())
     }
 
+    pub async fn find_command_by_idempotency_key(
+        &self,
+        idempotency_key: &str,
+    ) -> Result<Option<String>, BackendAgentError> {
+        let sql = "SELECT correlation_id FROM commands WHERE idempotency_key = $1";
+
+        let row = sqlx::query(sql)
+            .bind(idempotency_key)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to look up command by idempotency key: {}", e);
+                BackendAgentError::Database(e)
+            })?;
+
+        Ok(row.map(|row| row.get("correlation_id")))
+    }
+
     pub async fn get_command(
         &self,
         correlation_id: &str,
@@ -448,6 +478,7 @@ impl PostgresClient {
                     retry_count: row.get("retry_count"),
                     max_retries: row.get("max_retries"),
                     error_message: row.get("error_message"),
+                    idempotency_key: row.get("idempotency_key"),
                 };
                 Ok(Some(command))
             }
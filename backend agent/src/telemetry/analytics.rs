@@ -258,7 +258,7 @@ impl TelemetryAnalytics {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct AnalyticsStats {
     pub alerts_generated: usize,
     pub anomalies_detected: usize,
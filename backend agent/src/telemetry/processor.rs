@@ -249,7 +249,7 @@ impl TelemetryProcessor {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct TelemetryStats {
     pub storage: crate::telemetry::storage::StorageStats,
     pub analytics: crate::telemetry::analytics::AnalyticsStats,
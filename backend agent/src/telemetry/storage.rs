@@ -246,7 +246,7 @@ impl TelemetryStorage {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct StorageStats {
     pub total_records: usize,
     pub records_today: usize,
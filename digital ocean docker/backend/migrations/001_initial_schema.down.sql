@@ -0,0 +1,19 @@
+-- ViWorkS Admin Panel - Initial Database Schema (rollback)
+-- Migration: 001_initial_schema.down.sql
+
+DROP TABLE IF EXISTS verification_requests;
+DROP TABLE IF EXISTS device_binding_requests;
+DROP TABLE IF EXISTS audit_events;
+DROP TABLE IF EXISTS policies;
+DROP TABLE IF EXISTS otp_challenges;
+DROP TABLE IF EXISTS sessions;
+DROP TABLE IF EXISTS mobile_devices;
+DROP TABLE IF EXISTS desktop_devices;
+DROP TABLE IF EXISTS users;
+
+DROP FUNCTION IF EXISTS update_updated_at_column();
+
+DROP TYPE IF EXISTS audit_event_type;
+DROP TYPE IF EXISTS session_status;
+DROP TYPE IF EXISTS device_status;
+DROP TYPE IF EXISTS user_status;
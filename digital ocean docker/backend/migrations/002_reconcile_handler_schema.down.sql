@@ -0,0 +1,27 @@
+-- ViWorkS Admin Panel - Reconcile schema with the api/ handlers (rollback)
+-- Migration: 002_reconcile_handler_schema.down.sql
+
+DROP TABLE IF EXISTS security_alerts;
+DROP TABLE IF EXISTS audit_logs;
+DROP TABLE IF EXISTS connection_logs;
+DROP TABLE IF EXISTS clients;
+DROP TABLE IF EXISTS device_bindings;
+DROP TABLE IF EXISTS user_sessions;
+DROP TABLE IF EXISTS user_devices;
+
+DROP TYPE IF EXISTS alert_severity;
+DROP TYPE IF EXISTS connection_event_type;
+DROP TYPE IF EXISTS client_status;
+
+ALTER TABLE verification_requests ALTER COLUMN location_lng TYPE DECIMAL(11, 8);
+ALTER TABLE verification_requests ALTER COLUMN location_lat TYPE DECIMAL(10, 8);
+ALTER TABLE verification_requests DROP COLUMN IF EXISTS updated_at;
+
+DROP INDEX IF EXISTS idx_users_role;
+ALTER TABLE users DROP COLUMN IF EXISTS is_active;
+ALTER TABLE users DROP COLUMN IF EXISTS role;
+ALTER TABLE users ALTER COLUMN failed_login_attempts DROP NOT NULL;
+ALTER TABLE users ALTER COLUMN updated_at DROP NOT NULL;
+ALTER TABLE users ALTER COLUMN created_at DROP NOT NULL;
+ALTER TABLE users ALTER COLUMN roles DROP NOT NULL;
+ALTER TABLE users ALTER COLUMN status DROP NOT NULL;
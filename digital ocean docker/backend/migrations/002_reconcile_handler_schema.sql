@@ -0,0 +1,155 @@
+-- ViWorkS Admin Panel - Reconcile schema with the api/ handlers
+-- Migration: 002_reconcile_handler_schema.sql
+--
+-- The handlers were written against tables and columns that 001 never created
+-- (users.role/is_active, user_devices, user_sessions, clients, connection_logs,
+-- audit_logs, security_alerts, device_bindings). This migration adds them and
+-- tightens NULL-ability on columns the Rust models already treat as required.
+
+-- Users: single role + active flag used by the auth handlers
+ALTER TABLE users ADD COLUMN role VARCHAR(50);
+UPDATE users SET role = COALESCE(roles->>0, 'user');
+ALTER TABLE users ALTER COLUMN role SET DEFAULT 'user';
+ALTER TABLE users ALTER COLUMN role SET NOT NULL;
+
+UPDATE users SET
+    status = COALESCE(status, 'pending'),
+    roles = COALESCE(roles, '[]'),
+    created_at = COALESCE(created_at, NOW()),
+    updated_at = COALESCE(updated_at, NOW()),
+    failed_login_attempts = COALESCE(failed_login_attempts, 0);
+ALTER TABLE users ALTER COLUMN status SET NOT NULL;
+ALTER TABLE users ALTER COLUMN roles SET NOT NULL;
+ALTER TABLE users ALTER COLUMN created_at SET NOT NULL;
+ALTER TABLE users ALTER COLUMN updated_at SET NOT NULL;
+ALTER TABLE users ALTER COLUMN failed_login_attempts SET NOT NULL;
+
+-- Derived from status so the two can never disagree
+ALTER TABLE users ADD COLUMN is_active BOOLEAN NOT NULL GENERATED ALWAYS AS (status = 'active') STORED;
+
+-- Mobile devices registered through /auth/register-device
+CREATE TABLE user_devices (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id VARCHAR(255) NOT NULL,
+    fcm_token VARCHAR(255),
+    device_model VARCHAR(100) NOT NULL,
+    device_os VARCHAR(50) NOT NULL,
+    app_version VARCHAR(20) NOT NULL,
+    manufacturer VARCHAR(100),
+    device_name VARCHAR(100),
+    is_active BOOLEAN NOT NULL DEFAULT true,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, device_id)
+);
+
+INSERT INTO user_devices (user_id, device_id, fcm_token, device_model, device_os, app_version, is_active, last_used_at, created_at)
+SELECT user_id, device_id, fcm_token,
+       COALESCE(device_model, 'unknown'), COALESCE(device_os, 'unknown'), COALESCE(app_version, 'unknown'),
+       status = 'approved', last_used_at, COALESCE(bound_at, NOW())
+FROM mobile_devices;
+
+-- Desktop client sessions issued after 2FA
+CREATE TABLE user_sessions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    is_revoked BOOLEAN NOT NULL DEFAULT false,
+    ip_address INET,
+    user_agent TEXT
+);
+
+-- Verification requests: updated_at is written on confirmation, coordinates bind as f64
+ALTER TABLE verification_requests ADD COLUMN updated_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE verification_requests ALTER COLUMN location_lat TYPE DOUBLE PRECISION;
+ALTER TABLE verification_requests ALTER COLUMN location_lng TYPE DOUBLE PRECISION;
+
+-- Desktop fingerprints bound through /auth/device-bind
+CREATE TABLE device_bindings (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_fingerprint VARCHAR(255) NOT NULL,
+    is_approved BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, device_fingerprint)
+);
+
+-- Desktop clients and their connection history
+CREATE TYPE client_status AS ENUM ('online', 'offline', 'connecting', 'error');
+CREATE TYPE connection_event_type AS ENUM ('connect', 'disconnect', 'debug', 'info', 'warning', 'error');
+
+CREATE TABLE clients (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(255) UNIQUE NOT NULL,
+    platform VARCHAR(50) NOT NULL,
+    version VARCHAR(50) NOT NULL,
+    status client_status NOT NULL DEFAULT 'offline',
+    ip_address INET,
+    mac_address VARCHAR(17),
+    last_seen TIMESTAMP WITH TIME ZONE,
+    connection_count INTEGER NOT NULL DEFAULT 0,
+    total_connection_time BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE connection_logs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    client_id UUID NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    event_type connection_event_type NOT NULL,
+    ip_address INET,
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Admin/agent actions shown under /monitoring/logs
+CREATE TABLE audit_logs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    action VARCHAR(100) NOT NULL,
+    resource_type VARCHAR(50),
+    resource_id VARCHAR(255),
+    details JSONB,
+    ip_address INET,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Alerts shown under /monitoring/alerts
+CREATE TYPE alert_severity AS ENUM ('low', 'medium', 'high', 'critical');
+
+CREATE TABLE security_alerts (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    severity alert_severity NOT NULL DEFAULT 'low',
+    title VARCHAR(255) NOT NULL,
+    description TEXT,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    details JSONB NOT NULL DEFAULT '{}',
+    is_resolved BOOLEAN NOT NULL DEFAULT false,
+    resolved_at TIMESTAMP WITH TIME ZONE,
+    resolved_by UUID REFERENCES users(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Indexes
+CREATE INDEX idx_users_role ON users(role);
+CREATE INDEX idx_user_devices_user_id ON user_devices(user_id);
+CREATE INDEX idx_user_devices_device_id ON user_devices(device_id);
+CREATE INDEX idx_user_sessions_user_id ON user_sessions(user_id);
+CREATE INDEX idx_user_sessions_token_hash ON user_sessions(token_hash);
+CREATE INDEX idx_user_sessions_expires_at ON user_sessions(expires_at);
+CREATE INDEX idx_device_bindings_user_id ON device_bindings(user_id);
+CREATE INDEX idx_clients_status ON clients(status);
+CREATE INDEX idx_connection_logs_client_id ON connection_logs(client_id);
+CREATE INDEX idx_connection_logs_created_at ON connection_logs(created_at);
+CREATE INDEX idx_audit_logs_created_at ON audit_logs(created_at);
+CREATE INDEX idx_security_alerts_created_at ON security_alerts(created_at);
+CREATE INDEX idx_security_alerts_is_resolved ON security_alerts(is_resolved);
+
+-- updated_at triggers
+CREATE TRIGGER update_device_bindings_updated_at BEFORE UPDATE ON device_bindings FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+CREATE TRIGGER update_clients_updated_at BEFORE UPDATE ON clients FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,13 @@
+-- ViWorkS Admin Panel - OTP challenge store (rollback)
+-- Migration: 003_otp_challenge_store.down.sql
+
+DROP INDEX IF EXISTS idx_otp_challenges_live_key;
+
+ALTER TABLE otp_challenges ALTER COLUMN created_at DROP NOT NULL;
+ALTER TABLE otp_challenges ALTER COLUMN max_attempts DROP NOT NULL;
+ALTER TABLE otp_challenges ALTER COLUMN attempts DROP NOT NULL;
+
+DELETE FROM otp_challenges WHERE user_id IS NULL;
+ALTER TABLE otp_challenges ALTER COLUMN user_id SET NOT NULL;
+
+ALTER TABLE otp_challenges DROP COLUMN IF EXISTS challenge_key;
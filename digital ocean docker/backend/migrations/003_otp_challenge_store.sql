@@ -0,0 +1,23 @@
+-- ViWorkS Admin Panel - OTP challenge store
+-- Migration: 003_otp_challenge_store.sql
+--
+-- otp_challenges becomes the backing table for PgOtpStore. Challenges are looked
+-- up by the handler-visible key (challenge id, desktop session id or username),
+-- and the pre-login demo flow issues challenges before a user is known.
+
+ALTER TABLE otp_challenges ADD COLUMN challenge_key VARCHAR(255);
+UPDATE otp_challenges SET challenge_key = id::text;
+ALTER TABLE otp_challenges ALTER COLUMN challenge_key SET NOT NULL;
+
+ALTER TABLE otp_challenges ALTER COLUMN user_id DROP NOT NULL;
+
+UPDATE otp_challenges SET
+    attempts = COALESCE(attempts, 0),
+    max_attempts = COALESCE(max_attempts, 3),
+    created_at = COALESCE(created_at, NOW());
+ALTER TABLE otp_challenges ALTER COLUMN attempts SET NOT NULL;
+ALTER TABLE otp_challenges ALTER COLUMN max_attempts SET NOT NULL;
+ALTER TABLE otp_challenges ALTER COLUMN created_at SET NOT NULL;
+
+-- At most one live challenge per key; re-issuing replaces it
+CREATE UNIQUE INDEX idx_otp_challenges_live_key ON otp_challenges(challenge_key) WHERE used_at IS NULL;
@@ -0,0 +1,31 @@
+-- ViWorkS Admin Panel - Session-bound access tokens and rotating refresh tokens (rollback)
+-- Migration: 004_session_refresh_tokens.down.sql
+
+CREATE TABLE user_sessions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    is_revoked BOOLEAN NOT NULL DEFAULT false,
+    ip_address INET,
+    user_agent TEXT
+);
+
+CREATE INDEX idx_user_sessions_user_id ON user_sessions(user_id);
+CREATE INDEX idx_user_sessions_token_hash ON user_sessions(token_hash);
+CREATE INDEX idx_user_sessions_expires_at ON user_sessions(expires_at);
+
+DROP TABLE IF EXISTS rotated_refresh_tokens;
+
+ALTER TABLE sessions DROP COLUMN IF EXISTS terminated_reason;
+ALTER TABLE sessions DROP COLUMN IF EXISTS terminated_at;
+
+ALTER TABLE sessions ALTER COLUMN last_activity_at DROP NOT NULL;
+ALTER TABLE sessions ALTER COLUMN started_at DROP NOT NULL;
+ALTER TABLE sessions ALTER COLUMN status DROP NOT NULL;
+
+ALTER TABLE sessions RENAME CONSTRAINT sessions_refresh_token_hash_key TO sessions_refresh_token_key;
+ALTER TABLE sessions RENAME CONSTRAINT sessions_access_token_jti_key TO sessions_access_token_key;
+ALTER TABLE sessions RENAME COLUMN refresh_token_hash TO refresh_token;
+ALTER TABLE sessions RENAME COLUMN access_token_jti TO access_token;
@@ -0,0 +1,34 @@
+-- ViWorkS Admin Panel - Session-bound access tokens and rotating refresh tokens
+-- Migration: 004_session_refresh_tokens.sql
+--
+-- sessions becomes the single session store: one row per login, holding the jti
+-- of the current access token and the SHA-256 of the current opaque refresh
+-- token. Each refresh rotates both; retired refresh token hashes are kept in
+-- rotated_refresh_tokens so a replayed token can be recognised and the session
+-- terminated. user_sessions (raw JWTs) is retired; existing logins must sign in again.
+
+ALTER TABLE sessions RENAME COLUMN access_token TO access_token_jti;
+ALTER TABLE sessions RENAME COLUMN refresh_token TO refresh_token_hash;
+ALTER TABLE sessions RENAME CONSTRAINT sessions_access_token_key TO sessions_access_token_jti_key;
+ALTER TABLE sessions RENAME CONSTRAINT sessions_refresh_token_key TO sessions_refresh_token_hash_key;
+
+UPDATE sessions SET
+    status = COALESCE(status, 'active'),
+    started_at = COALESCE(started_at, NOW()),
+    last_activity_at = COALESCE(last_activity_at, NOW());
+ALTER TABLE sessions ALTER COLUMN status SET NOT NULL;
+ALTER TABLE sessions ALTER COLUMN started_at SET NOT NULL;
+ALTER TABLE sessions ALTER COLUMN last_activity_at SET NOT NULL;
+
+ALTER TABLE sessions ADD COLUMN terminated_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE sessions ADD COLUMN terminated_reason VARCHAR(50);
+
+CREATE TABLE rotated_refresh_tokens (
+    token_hash VARCHAR(64) PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    rotated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_rotated_refresh_tokens_session_id ON rotated_refresh_tokens(session_id);
+
+DROP TABLE user_sessions;
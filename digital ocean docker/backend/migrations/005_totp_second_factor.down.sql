@@ -0,0 +1,6 @@
+-- ViWorkS Admin Panel - TOTP authenticator apps and recovery codes (rollback)
+-- Migration: 005_totp_second_factor.down.sql
+
+DROP TABLE IF EXISTS recovery_codes;
+DROP TABLE IF EXISTS user_totp;
+ALTER TABLE users DROP COLUMN IF EXISTS mfa_method;
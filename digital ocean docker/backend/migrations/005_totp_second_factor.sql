@@ -0,0 +1,32 @@
+-- ViWorkS Admin Panel - TOTP authenticator apps and recovery codes
+-- Migration: 005_totp_second_factor.sql
+
+-- Which second factor the desktop login flow asks for
+ALTER TABLE users ADD COLUMN mfa_method VARCHAR(20) NOT NULL DEFAULT 'mobile_otp'
+    CHECK (mfa_method IN ('mobile_otp', 'totp'));
+
+-- One authenticator per user. The secret is AES-256-GCM encrypted; last_used_step
+-- is the RFC 6238 time step of the last accepted code (replay protection).
+CREATE TABLE user_totp (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    secret_ciphertext BYTEA NOT NULL,
+    secret_nonce BYTEA NOT NULL,
+    confirmed_at TIMESTAMP WITH TIME ZONE,
+    last_used_step BIGINT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Single-use fallback codes, stored as SHA-256
+CREATE TABLE recovery_codes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, code_hash)
+);
+
+CREATE INDEX idx_recovery_codes_user_id ON recovery_codes(user_id);
+
+CREATE TRIGGER update_user_totp_updated_at BEFORE UPDATE ON user_totp FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,6 @@
+-- ViWorkS Admin Panel - WebAuthn (FIDO2) security keys (rollback)
+-- Migration: 006_webauthn_credentials.down.sql
+
+ALTER TABLE sessions DROP COLUMN IF EXISTS webauthn_verified_at;
+DROP TABLE IF EXISTS webauthn_challenges;
+DROP TABLE IF EXISTS webauthn_credentials;
@@ -0,0 +1,37 @@
+-- ViWorkS Admin Panel - WebAuthn (FIDO2) security keys
+-- Migration: 006_webauthn_credentials.sql
+
+-- Registered authenticators. public_key is the SEC1-encoded P-256 key (ES256);
+-- sign_count is the last authenticator signature counter we accepted.
+CREATE TABLE webauthn_credentials (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id BYTEA NOT NULL UNIQUE,
+    public_key BYTEA NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    aaguid UUID NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    -- Set when a counter regression suggests the key was cloned; the credential
+    -- is then refused until an admin deletes it
+    clone_detected_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_webauthn_credentials_user_id ON webauthn_credentials(user_id);
+
+-- Outstanding registration/authentication ceremonies (single use)
+CREATE TABLE webauthn_challenges (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    ceremony VARCHAR(20) NOT NULL CHECK (ceremony IN ('registration', 'authentication')),
+    challenge BYTEA NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_webauthn_challenges_user_id ON webauthn_challenges(user_id);
+
+-- Step-up: set once the session has completed a WebAuthn assertion
+ALTER TABLE sessions ADD COLUMN webauthn_verified_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,6 @@
+-- ViWorkS Admin Panel - Internal CA and per-session client credentials (rollback)
+-- Migration: 007_session_client_credentials.down.sql
+
+DROP TABLE IF EXISTS session_credentials;
+DROP TABLE IF EXISTS issued_certificates;
+DROP TABLE IF EXISTS certificate_authority;
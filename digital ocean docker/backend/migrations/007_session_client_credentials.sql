@@ -0,0 +1,47 @@
+-- ViWorkS Admin Panel - Internal CA and per-session client credentials
+-- Migration: 007_session_client_credentials.sql
+
+-- The internal CA (a single row). The private key is AES-256-GCM encrypted.
+CREATE TABLE certificate_authority (
+    id SMALLINT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    cert_pem TEXT NOT NULL,
+    key_ciphertext BYTEA NOT NULL,
+    key_nonce BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Every certificate the CA has signed; serial is lowercase hex
+CREATE TABLE issued_certificates (
+    serial VARCHAR(64) PRIMARY KEY,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    session_id UUID REFERENCES sessions(id) ON DELETE SET NULL,
+    common_name VARCHAR(255) NOT NULL,
+    not_before TIMESTAMP WITH TIME ZONE NOT NULL,
+    not_after TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    revocation_reason VARCHAR(50),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_issued_certificates_session_id ON issued_certificates(session_id);
+
+-- Gateway material minted by /auth/client-bootstrap, one live set per session.
+-- Secrets are handed to the client once and never stored; these are the handles
+-- needed to revoke them on the gateway. Rows outlive deleted sessions
+-- (session_id SET NULL) until the gateway side has been revoked.
+CREATE TABLE session_credentials (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    session_id UUID REFERENCES sessions(id) ON DELETE SET NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    fwknop_access_id VARCHAR(64) NOT NULL,
+    openvpn_username VARCHAR(64) NOT NULL,
+    certificate_serial VARCHAR(64) NOT NULL REFERENCES issued_certificates(serial),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    -- Last failed attempt to remove the credentials from the gateway (retried)
+    revocation_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_session_credentials_session_id ON session_credentials(session_id);
+CREATE INDEX idx_session_credentials_live ON session_credentials(expires_at) WHERE revoked_at IS NULL;
@@ -0,0 +1,11 @@
+-- ViWorkS Admin Panel - Two-tier internal CA (rollback)
+-- Migration: 008_ca_intermediates.down.sql
+-- The root generated under 008 can still sign leaf certificates directly.
+
+DROP INDEX IF EXISTS idx_issued_certificates_revoked;
+
+ALTER TABLE issued_certificates
+    DROP COLUMN IF EXISTS kind,
+    DROP COLUMN IF EXISTS issuer_id;
+
+DROP TABLE IF EXISTS ca_intermediates;
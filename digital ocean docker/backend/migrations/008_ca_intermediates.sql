@@ -0,0 +1,37 @@
+-- ViWorkS Admin Panel - Two-tier internal CA (root + rotating intermediates)
+-- Migration: 008_ca_intermediates.sql
+
+-- The single-level CA from 007 was created with pathLenConstraint 0 and cannot
+-- sign intermediates. Its certificates are short-lived session certificates:
+-- revoke them and let the next start generate a new root.
+UPDATE issued_certificates
+SET revoked_at = NOW(), revocation_reason = 'ca_replaced'
+WHERE revoked_at IS NULL;
+
+DELETE FROM certificate_authority;
+
+-- Issuing CAs signed by the root in certificate_authority. Exactly one is active.
+-- Retired intermediates stop signing but stay trusted (and keep publishing a CRL)
+-- until they expire, so devices that trust the root need not re-enroll.
+CREATE TABLE ca_intermediates (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    serial VARCHAR(64) NOT NULL UNIQUE,
+    cert_pem TEXT NOT NULL,
+    key_ciphertext BYTEA NOT NULL,
+    key_nonce BYTEA NOT NULL,
+    not_after TIMESTAMP WITH TIME ZONE NOT NULL,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    retired_at TIMESTAMP WITH TIME ZONE,
+    -- Set when retired because of a suspected key compromise; listed in the root CRL
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_ca_intermediates_active ON ca_intermediates(active) WHERE active;
+
+-- issuer_id is NULL for certificates of the replaced single-level CA
+ALTER TABLE issued_certificates
+    ADD COLUMN issuer_id UUID REFERENCES ca_intermediates(id),
+    ADD COLUMN kind VARCHAR(20) NOT NULL DEFAULT 'client' CHECK (kind IN ('client', 'agent'));
+
+CREATE INDEX idx_issued_certificates_revoked ON issued_certificates(issuer_id, not_after) WHERE revoked_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- ViWorkS Admin Panel - Login attempt tracking (rollback)
+-- Migration: 009_login_attempt_tracking.down.sql
+
+DROP INDEX IF EXISTS idx_audit_events_login_failed_ip;
@@ -0,0 +1,9 @@
+-- ViWorkS Admin Panel - Login attempt tracking
+-- Migration: 009_login_attempt_tracking.sql
+
+-- Per-IP throttling counts recent login_failed audit events by source address
+CREATE INDEX idx_audit_events_login_failed_ip ON audit_events(ip_address, created_at)
+    WHERE event_type = 'login_failed';
+
+-- Failed attempts before this point are history, not a reason to keep an account locked
+UPDATE users SET failed_login_attempts = 0 WHERE locked_until IS NULL OR locked_until <= NOW();
@@ -0,0 +1,19 @@
+-- ViWorkS Admin Panel - Policy engine (rollback)
+-- Migration: 010_policy_engine.down.sql
+-- The 'policy_denied' audit event type stays; enum values cannot be dropped.
+
+DROP TRIGGER IF EXISTS record_policy_version ON policies;
+DROP TRIGGER IF EXISTS bump_policy_version ON policies;
+DROP FUNCTION IF EXISTS record_policy_version();
+DROP FUNCTION IF EXISTS bump_policy_version();
+
+-- Restore documents written before the rule language existed
+UPDATE policies p
+SET rbac_roles = v.rbac_roles, abac_rules = v.abac_rules, version = v.version
+FROM policy_versions v
+WHERE v.policy_id = p.id AND v.version = 1 AND v.abac_rules ? 'time_windows';
+
+DROP TABLE IF EXISTS policy_versions;
+
+ALTER TABLE policies ALTER COLUMN version DROP NOT NULL;
+ALTER TABLE policies ALTER COLUMN is_active DROP NOT NULL;
@@ -0,0 +1,97 @@
+-- ViWorkS Admin Panel - Policy engine
+-- Migration: 010_policy_engine.sql
+
+-- Denials are audited alongside logins
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'policy_denied';
+
+UPDATE policies SET version = 1 WHERE version IS NULL;
+UPDATE policies SET is_active = true WHERE is_active IS NULL;
+ALTER TABLE policies ALTER COLUMN version SET NOT NULL;
+ALTER TABLE policies ALTER COLUMN is_active SET NOT NULL;
+
+-- Every revision of every policy; sessions.policy_snapshot names (id, version) pairs
+CREATE TABLE policy_versions (
+    policy_id UUID NOT NULL REFERENCES policies(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    rbac_roles JSONB NOT NULL,
+    abac_rules JSONB NOT NULL,
+    is_active BOOLEAN NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (policy_id, version)
+);
+
+INSERT INTO policy_versions (policy_id, version, name, rbac_roles, abac_rules, is_active, created_by, created_at)
+SELECT id, version, name, rbac_roles, abac_rules, is_active, created_by, COALESCE(updated_at, created_at, NOW())
+FROM policies;
+
+-- A change to what a policy decides is a new version
+CREATE OR REPLACE FUNCTION bump_policy_version()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF (NEW.name, NEW.rbac_roles, NEW.abac_rules, NEW.is_active)
+        IS DISTINCT FROM (OLD.name, OLD.rbac_roles, OLD.abac_rules, OLD.is_active) THEN
+        NEW.version = OLD.version + 1;
+    ELSE
+        NEW.version = OLD.version;
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+-- Updates set viworks.policy_changed_by (transaction-local) to attribute the revision
+CREATE OR REPLACE FUNCTION record_policy_version()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' OR NEW.version <> OLD.version THEN
+        INSERT INTO policy_versions (policy_id, version, name, rbac_roles, abac_rules, is_active, created_by)
+        VALUES (
+            NEW.id, NEW.version, NEW.name, NEW.rbac_roles, NEW.abac_rules, NEW.is_active,
+            CASE
+                WHEN TG_OP = 'INSERT' THEN NEW.created_by
+                ELSE NULLIF(current_setting('viworks.policy_changed_by', true), '')::uuid
+            END
+        );
+    END IF;
+    RETURN NULL;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER bump_policy_version BEFORE UPDATE ON policies FOR EACH ROW EXECUTE FUNCTION bump_policy_version();
+CREATE TRIGGER record_policy_version AFTER INSERT OR UPDATE ON policies FOR EACH ROW EXECUTE FUNCTION record_policy_version();
+
+-- The seeded policy predates the rule language: cover every role and restrict the
+-- admin API to admin roles. Its office hours and countries become disabled rules;
+-- version 1 keeps the original document.
+UPDATE policies
+SET rbac_roles = '["owner", "org_admin", "admin", "security_admin", "security_analyst", "helpdesk", "auditor", "api_service", "operator", "user"]',
+    abac_rules = '{
+        "default_effect": "allow",
+        "rules": [
+            {
+                "id": "admin-roles-only",
+                "description": "Only administrator roles may use the admin API",
+                "effect": "deny",
+                "actions": ["admin"],
+                "when": {"not": {"roles": ["owner", "org_admin", "admin", "security_admin"]}}
+            },
+            {
+                "id": "business-hours",
+                "description": "Gateway access on weekdays 07:00-19:00 Tehran time",
+                "enabled": false,
+                "effect": "deny",
+                "actions": ["client_bootstrap"],
+                "when": {"not": {"time_of_day": {"days": [1, 2, 3, 4, 5], "start": "07:00", "end": "19:00", "utc_offset": "+03:30"}}}
+            },
+            {
+                "id": "allowed-countries",
+                "description": "Sign-in and gateway access only from these countries",
+                "enabled": false,
+                "effect": "deny",
+                "actions": ["login", "client_bootstrap"],
+                "when": {"not": {"geo_country": ["IR", "DE", "FR"]}}
+            }
+        ]
+    }'
+WHERE name = 'default-policy' AND version = 1 AND abac_rules ? 'time_windows';
@@ -0,0 +1,27 @@
+-- ViWorkS Admin Panel - Device-binding approval workflow (rollback)
+-- Migration: 011_device_binding_workflow.down.sql
+-- The 'expired' device status stays; enum values cannot be dropped.
+
+UPDATE policies
+SET abac_rules = jsonb_set(
+        abac_rules, '{rules}',
+        COALESCE(
+            (SELECT jsonb_agg(rule) FROM jsonb_array_elements(abac_rules->'rules') rule
+             WHERE rule->>'id' <> 'bound-devices-only'),
+            '[]'::jsonb
+        ))
+WHERE jsonb_typeof(abac_rules->'rules') = 'array'
+  AND (abac_rules->'rules') @> '[{"id": "bound-devices-only"}]';
+
+DROP INDEX IF EXISTS idx_device_binding_requests_status;
+DROP INDEX IF EXISTS idx_device_binding_requests_pending;
+
+ALTER TABLE device_binding_requests
+    DROP CONSTRAINT IF EXISTS device_binding_requests_device_type_check,
+    DROP COLUMN IF EXISTS note,
+    DROP COLUMN IF EXISTS reviewed_at,
+    DROP COLUMN IF EXISTS reviewed_by,
+    DROP COLUMN IF EXISTS user_agent,
+    DROP COLUMN IF EXISTS ip_address,
+    ALTER COLUMN status DROP NOT NULL,
+    ALTER COLUMN requested_at DROP NOT NULL;
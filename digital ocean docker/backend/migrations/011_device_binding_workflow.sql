@@ -0,0 +1,38 @@
+-- ViWorkS Admin Panel - Device-binding approval workflow
+-- Migration: 011_device_binding_workflow.sql
+
+-- Requests nobody reviewed before expires_at
+ALTER TYPE device_status ADD VALUE IF NOT EXISTS 'expired';
+
+ALTER TABLE device_binding_requests
+    ADD COLUMN ip_address INET,
+    ADD COLUMN user_agent TEXT,
+    ADD COLUMN reviewed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN reviewed_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN note TEXT,
+    ADD CONSTRAINT device_binding_requests_device_type_check CHECK (device_type IN ('desktop', 'mobile'));
+
+UPDATE device_binding_requests SET status = 'pending' WHERE status IS NULL;
+UPDATE device_binding_requests SET requested_at = NOW() WHERE requested_at IS NULL;
+ALTER TABLE device_binding_requests ALTER COLUMN status SET NOT NULL;
+ALTER TABLE device_binding_requests ALTER COLUMN requested_at SET NOT NULL;
+
+-- One open request per device
+CREATE UNIQUE INDEX idx_device_binding_requests_pending
+    ON device_binding_requests(user_id, device_type, fingerprint)
+    WHERE status = 'pending';
+CREATE INDEX idx_device_binding_requests_status ON device_binding_requests(status, requested_at);
+
+-- Login can be limited to approved desktop devices (X-Device-Id); off until an admin enables it
+UPDATE policies
+SET abac_rules = jsonb_set(abac_rules, '{rules}', (abac_rules->'rules') || '[{
+        "id": "bound-devices-only",
+        "description": "Sign-in only from desktop devices an admin has approved",
+        "enabled": false,
+        "effect": "deny",
+        "actions": ["login", "client_bootstrap"],
+        "when": {"not": {"device_status": ["approved"]}}
+    }]'::jsonb)
+WHERE name = 'default-policy'
+  AND jsonb_typeof(abac_rules->'rules') = 'array'
+  AND NOT (abac_rules->'rules') @> '[{"id": "bound-devices-only"}]';
@@ -0,0 +1,11 @@
+-- ViWorkS Admin Panel - Device public-key attestation (rollback)
+-- Migration: 012_device_attestation.down.sql
+-- The device_key_* audit event values stay; enum values cannot be dropped.
+
+DROP INDEX IF EXISTS idx_sessions_device_id;
+DROP TABLE IF EXISTS device_nonces;
+
+ALTER TABLE desktop_devices
+    DROP COLUMN IF EXISTS key_revoked_at,
+    DROP COLUMN IF EXISTS key_rotated_at,
+    DROP COLUMN IF EXISTS key_algorithm;
@@ -0,0 +1,23 @@
+-- ViWorkS Admin Panel - Device public-key attestation
+-- Migration: 012_device_attestation.sql
+
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'device_key_rotated';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'device_key_revoked';
+
+-- device_pubkey holds the current key (base64); rows enrolled before
+-- attestation have no algorithm and cannot sign in while it is required
+ALTER TABLE desktop_devices
+    ADD COLUMN key_algorithm VARCHAR(16) CHECK (key_algorithm IN ('ed25519', 'p256')),
+    ADD COLUMN key_rotated_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN key_revoked_at TIMESTAMP WITH TIME ZONE;
+
+-- Single-use server nonces a device signs to prove it holds its key
+CREATE TABLE device_nonces (
+    nonce VARCHAR(64) PRIMARY KEY,
+    purpose VARCHAR(32) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX idx_device_nonces_expires_at ON device_nonces(expires_at);
+CREATE INDEX IF NOT EXISTS idx_sessions_device_id ON sessions(device_id) WHERE device_id IS NOT NULL;
@@ -0,0 +1,31 @@
+-- ViWorkS Admin Panel - Tamper-evident audit log (rollback)
+-- Migration: 013_audit_hash_chain.down.sql
+-- The new audit event values stay; enum values cannot be dropped.
+
+DROP INDEX IF EXISTS idx_audit_events_target_user_id;
+DROP TABLE IF EXISTS audit_checkpoints;
+
+DROP TRIGGER IF EXISTS reject_audit_truncate ON audit_events;
+DROP TRIGGER IF EXISTS reject_audit_change ON audit_events;
+DROP TRIGGER IF EXISTS chain_audit_event ON audit_events;
+DROP FUNCTION IF EXISTS reject_audit_change();
+DROP FUNCTION IF EXISTS chain_audit_event();
+
+ALTER TABLE audit_events
+    DROP CONSTRAINT IF EXISTS audit_events_seq_key,
+    DROP COLUMN IF EXISTS hash,
+    DROP COLUMN IF EXISTS prev_hash,
+    DROP COLUMN IF EXISTS seq,
+    ALTER COLUMN created_at DROP NOT NULL;
+
+DROP FUNCTION IF EXISTS audit_event_hash(BIGINT, TEXT, UUID, TEXT, UUID, UUID, UUID, JSONB, INET, TEXT, TIMESTAMP WITH TIME ZONE);
+
+-- References to rows deleted while the chain was in place cannot be restored
+UPDATE audit_events SET user_id = NULL WHERE user_id IS NOT NULL AND user_id NOT IN (SELECT id FROM users);
+UPDATE audit_events SET target_user_id = NULL WHERE target_user_id IS NOT NULL AND target_user_id NOT IN (SELECT id FROM users);
+UPDATE audit_events SET session_id = NULL WHERE session_id IS NOT NULL AND session_id NOT IN (SELECT id FROM sessions);
+
+ALTER TABLE audit_events
+    ADD CONSTRAINT audit_events_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL,
+    ADD CONSTRAINT audit_events_session_id_fkey FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE SET NULL,
+    ADD CONSTRAINT audit_events_target_user_id_fkey FOREIGN KEY (target_user_id) REFERENCES users(id) ON DELETE SET NULL;
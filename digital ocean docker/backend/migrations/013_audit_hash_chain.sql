@@ -0,0 +1,117 @@
+-- ViWorkS Admin Panel - Tamper-evident audit log
+-- Migration: 013_audit_hash_chain.sql
+
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'user_unlocked';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'user_password_reset';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'device_rejected';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'client_created';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'client_updated';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'client_deleted';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'client_connected';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'client_disconnected';
+
+-- Audit rows outlive the users and sessions they mention; ON DELETE SET NULL
+-- would rewrite history, so the references become plain ids
+ALTER TABLE audit_events
+    DROP CONSTRAINT IF EXISTS audit_events_user_id_fkey,
+    DROP CONSTRAINT IF EXISTS audit_events_session_id_fkey,
+    DROP CONSTRAINT IF EXISTS audit_events_target_user_id_fkey;
+
+ALTER TABLE audit_events
+    ADD COLUMN seq BIGINT,
+    ADD COLUMN prev_hash CHAR(64),
+    ADD COLUMN hash CHAR(64);
+
+-- SHA-256 over a canonical rendering of the row and its predecessor's hash.
+-- The verifier recomputes hashes with this same function.
+CREATE OR REPLACE FUNCTION audit_event_hash(
+    p_seq BIGINT,
+    p_prev_hash TEXT,
+    p_id UUID,
+    p_event_type TEXT,
+    p_user_id UUID,
+    p_session_id UUID,
+    p_target_user_id UUID,
+    p_details JSONB,
+    p_ip_address INET,
+    p_user_agent TEXT,
+    p_created_at TIMESTAMP WITH TIME ZONE
+) RETURNS TEXT AS $$
+    SELECT encode(sha256(convert_to(jsonb_build_array(
+        p_seq, p_prev_hash, p_id, p_event_type, p_user_id, p_session_id, p_target_user_id,
+        p_details, p_ip_address::text, p_user_agent,
+        to_char(p_created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS.US"Z"')
+    )::text, 'UTF8')), 'hex')
+$$ LANGUAGE sql STABLE;
+
+-- Chain existing rows in the order they were written
+DO $$
+DECLARE
+    r RECORD;
+    next_seq BIGINT := 0;
+    previous TEXT := repeat('0', 64);
+BEGIN
+    FOR r IN SELECT * FROM audit_events ORDER BY created_at, id LOOP
+        next_seq := next_seq + 1;
+        UPDATE audit_events SET
+            seq = next_seq,
+            prev_hash = previous,
+            hash = audit_event_hash(next_seq, previous, r.id, r.event_type::text, r.user_id, r.session_id,
+                r.target_user_id, r.details, r.ip_address, r.user_agent, r.created_at)
+        WHERE id = r.id
+        RETURNING hash INTO previous;
+    END LOOP;
+END $$;
+
+UPDATE audit_events SET created_at = NOW() WHERE created_at IS NULL;
+
+ALTER TABLE audit_events
+    ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN seq SET NOT NULL,
+    ALTER COLUMN prev_hash SET NOT NULL,
+    ALTER COLUMN hash SET NOT NULL,
+    ADD CONSTRAINT audit_events_seq_key UNIQUE (seq);
+
+-- Every insert, whichever code path it comes from, is appended to the chain.
+-- The advisory lock serializes writers so sequence numbers have no holes.
+CREATE OR REPLACE FUNCTION chain_audit_event()
+RETURNS TRIGGER AS $$
+DECLARE
+    last_seq BIGINT;
+    last_hash TEXT;
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext('audit_events'));
+    SELECT seq, hash INTO last_seq, last_hash FROM audit_events ORDER BY seq DESC LIMIT 1;
+
+    NEW.created_at = COALESCE(NEW.created_at, NOW());
+    NEW.seq = COALESCE(last_seq, 0) + 1;
+    NEW.prev_hash = COALESCE(last_hash, repeat('0', 64));
+    NEW.hash = audit_event_hash(NEW.seq, NEW.prev_hash, NEW.id, NEW.event_type::text, NEW.user_id,
+        NEW.session_id, NEW.target_user_id, NEW.details, NEW.ip_address, NEW.user_agent, NEW.created_at);
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE OR REPLACE FUNCTION reject_audit_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only (% rejected)', TG_OP;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER chain_audit_event BEFORE INSERT ON audit_events FOR EACH ROW EXECUTE FUNCTION chain_audit_event();
+CREATE TRIGGER reject_audit_change BEFORE UPDATE OR DELETE ON audit_events FOR EACH ROW EXECUTE FUNCTION reject_audit_change();
+CREATE TRIGGER reject_audit_truncate BEFORE TRUNCATE ON audit_events FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_change();
+
+-- Signed snapshots of the chain head; a verifier holding the public key can
+-- detect a rewritten chain even if every row's hash was recomputed
+CREATE TABLE audit_checkpoints (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    seq BIGINT UNIQUE NOT NULL,
+    hash CHAR(64) NOT NULL,
+    signature TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_audit_events_target_user_id ON audit_events(target_user_id);
@@ -0,0 +1,4 @@
+-- ViWorkS Admin Panel - SIEM export cursor (rollback)
+-- Migration: 014_siem_export.down.sql
+
+DROP TABLE IF EXISTS siem_export_cursors;
@@ -0,0 +1,13 @@
+-- ViWorkS Admin Panel - SIEM export cursor
+-- Migration: 014_siem_export.sql
+
+-- Highest audit_events.seq each export target has accepted; the exporter
+-- resumes after it, so events are delivered at least once across restarts
+CREATE TABLE siem_export_cursors (
+    target TEXT PRIMARY KEY,
+    last_seq BIGINT NOT NULL DEFAULT 0,
+    exported_count BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    last_error_at TIMESTAMP WITH TIME ZONE,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,19 @@
+-- ViWorkS Admin Panel - Security alert rules engine (rollback)
+-- Migration: 015_security_alert_rules.down.sql
+
+DROP TABLE IF EXISTS alert_engine_state;
+
+DROP INDEX IF EXISTS idx_verification_requests_user_created_at;
+DROP INDEX IF EXISTS idx_audit_events_type_created_at;
+DROP INDEX IF EXISTS idx_security_alerts_severity;
+DROP INDEX IF EXISTS idx_security_alerts_open_dedup;
+
+ALTER TABLE security_alerts
+    DROP COLUMN IF EXISTS resolution_note,
+    DROP COLUMN IF EXISTS acknowledged_by,
+    DROP COLUMN IF EXISTS acknowledged_at,
+    DROP COLUMN IF EXISTS last_seen_at,
+    DROP COLUMN IF EXISTS occurrences,
+    DROP COLUMN IF EXISTS source_ip,
+    DROP COLUMN IF EXISTS dedup_key,
+    DROP COLUMN IF EXISTS rule_id;
@@ -0,0 +1,36 @@
+-- ViWorkS Admin Panel - Security alert rules engine
+-- Migration: 015_security_alert_rules.sql
+
+-- Alerts raised by a rule carry its id and a dedup key (what the rule grouped
+-- on, e.g. a user or an IP); further matches update the open alert instead of
+-- raising another one
+ALTER TABLE security_alerts
+    ADD COLUMN rule_id TEXT,
+    ADD COLUMN dedup_key TEXT,
+    ADD COLUMN source_ip INET,
+    ADD COLUMN occurrences INTEGER NOT NULL DEFAULT 1,
+    ADD COLUMN last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    ADD COLUMN acknowledged_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN acknowledged_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN resolution_note TEXT;
+
+CREATE UNIQUE INDEX idx_security_alerts_open_dedup
+    ON security_alerts(rule_id, dedup_key) WHERE NOT is_resolved;
+CREATE INDEX idx_security_alerts_severity ON security_alerts(severity);
+
+-- Lookups the rules run for every event they evaluate
+CREATE INDEX idx_audit_events_type_created_at ON audit_events(event_type, created_at);
+CREATE INDEX idx_verification_requests_user_created_at ON verification_requests(user_id, created_at);
+
+-- How far the engine has read; a single row, locked while a replica evaluates
+-- so events are processed once
+CREATE TABLE alert_engine_state (
+    id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+    last_seq BIGINT NOT NULL,
+    last_verification_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Start from the current head rather than raising alerts for old history
+INSERT INTO alert_engine_state (last_seq, last_verification_at)
+SELECT COALESCE((SELECT MAX(seq) FROM audit_events), 0), NOW();
@@ -0,0 +1,7 @@
+-- ViWorkS Admin Panel - SCIM 2.0 provisioning (rollback)
+-- Migration: 016_scim_provisioning.down.sql
+
+DROP INDEX IF EXISTS idx_users_roles;
+DROP INDEX IF EXISTS idx_users_external_id;
+
+ALTER TABLE users DROP COLUMN IF EXISTS external_id;
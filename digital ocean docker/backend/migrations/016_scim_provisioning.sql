@@ -0,0 +1,10 @@
+-- ViWorkS Admin Panel - SCIM 2.0 provisioning
+-- Migration: 016_scim_provisioning.sql
+
+-- The identity provider's own id for a user (SCIM `externalId`); providers
+-- look users up by it before creating them
+ALTER TABLE users ADD COLUMN external_id VARCHAR(255);
+CREATE UNIQUE INDEX idx_users_external_id ON users(external_id) WHERE external_id IS NOT NULL;
+
+-- SCIM groups are role names; membership is `roles ? 'role'`
+CREATE INDEX idx_users_roles ON users USING GIN (roles);
@@ -0,0 +1,4 @@
+-- ViWorkS Admin Panel - Gateway commands submitted through the backend agent (rollback)
+-- Migration: 017_agent_commands.down.sql
+
+DROP TABLE IF EXISTS agent_commands;
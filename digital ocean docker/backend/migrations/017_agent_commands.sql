@@ -0,0 +1,36 @@
+-- ViWorkS Admin Panel - Gateway commands submitted through the backend agent
+-- Migration: 017_agent_commands.sql
+
+-- One row per gateway command (user created, disabled, deleted, session
+-- terminated). The idempotency key is sent with every submission so a retry
+-- after a lost reply does not queue the command twice on the backend agent;
+-- correlation_id is the backend agent's id once it has accepted the command.
+CREATE TABLE agent_commands (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    idempotency_key VARCHAR(255) NOT NULL UNIQUE,
+    event VARCHAR(64) NOT NULL,
+    verb VARCHAR(64) NOT NULL,
+    args JSONB NOT NULL DEFAULT '{}',
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    session_id UUID,
+    correlation_id VARCHAR(255),
+    status VARCHAR(16) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'submitted', 'completed', 'failed', 'cancelled')),
+    remote_status VARCHAR(32),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- Admin retries of a failed command; each is submitted under `key#n`
+    resubmissions INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    result JSONB,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- The dispatcher's work queue: pending rows that are due, and submitted rows
+-- still waiting for the backend agent to finish them
+CREATE INDEX idx_agent_commands_due ON agent_commands(next_attempt_at)
+    WHERE status IN ('pending', 'submitted');
+CREATE INDEX idx_agent_commands_user_id ON agent_commands(user_id);
+CREATE INDEX idx_agent_commands_created_at ON agent_commands(created_at);
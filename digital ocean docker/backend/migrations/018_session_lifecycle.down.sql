@@ -0,0 +1,9 @@
+-- ViWorkS Admin Panel - Session idle/absolute lifetimes and gateway teardown (rollback)
+-- Migration: 018_session_lifecycle.down.sql
+
+DROP INDEX IF EXISTS idx_sessions_pending_teardown;
+DROP INDEX IF EXISTS idx_sessions_active_user;
+
+ALTER TABLE sessions DROP COLUMN IF EXISTS gateway_teardown_at;
+ALTER TABLE sessions DROP COLUMN IF EXISTS absolute_expires_at;
+ALTER TABLE sessions DROP COLUMN IF EXISTS idle_timeout_seconds;
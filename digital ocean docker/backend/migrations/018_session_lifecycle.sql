@@ -0,0 +1,22 @@
+-- ViWorkS Admin Panel - Session idle/absolute lifetimes and gateway teardown
+-- Migration: 018_session_lifecycle.sql
+
+-- The limits in force when the session started (policy `session` block or the
+-- SESSION_* defaults). expires_at never moves past absolute_expires_at; a NULL
+-- idle_timeout_seconds means the session has no idle timeout.
+ALTER TABLE sessions ADD COLUMN idle_timeout_seconds INTEGER;
+ALTER TABLE sessions ADD COLUMN absolute_expires_at TIMESTAMP WITH TIME ZONE;
+-- When the gateway was asked to tear the ended session down
+ALTER TABLE sessions ADD COLUMN gateway_teardown_at TIMESTAMP WITH TIME ZONE;
+
+UPDATE sessions SET absolute_expires_at = expires_at;
+ALTER TABLE sessions ALTER COLUMN absolute_expires_at SET NOT NULL;
+
+-- Sessions that lapsed before the reaper existed end without a gateway call
+UPDATE sessions
+SET status = 'expired', terminated_at = expires_at, terminated_reason = 'expired'
+WHERE status = 'active' AND expires_at <= NOW();
+UPDATE sessions SET gateway_teardown_at = COALESCE(terminated_at, NOW()) WHERE status <> 'active';
+
+CREATE INDEX idx_sessions_active_user ON sessions(user_id, started_at) WHERE status = 'active';
+CREATE INDEX idx_sessions_pending_teardown ON sessions(terminated_at) WHERE status <> 'active' AND gateway_teardown_at IS NULL;
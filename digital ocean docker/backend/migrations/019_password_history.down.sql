@@ -0,0 +1,4 @@
+-- ViWorkS Admin Panel - Password history for reuse checks (rollback)
+-- Migration: 019_password_history.down.sql
+
+DROP TABLE IF EXISTS password_history;
@@ -0,0 +1,17 @@
+-- ViWorkS Admin Panel - Password history for reuse checks
+-- Migration: 019_password_history.sql
+
+-- The last PASSWORD_HISTORY_DEPTH password hashes of each user, newest last
+-- (by id). Seeded with the current hashes so the reuse check covers the
+-- password every user has now.
+CREATE TABLE password_history (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    password_hash VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_password_history_user_id ON password_history(user_id, id);
+
+INSERT INTO password_history (user_id, password_hash, created_at)
+SELECT id, password_hash, COALESCE(updated_at, created_at, NOW()) FROM users;
@@ -0,0 +1,10 @@
+-- ViWorkS Admin Panel - Self-service password reset and email verification (rollback)
+-- Migration: 020_account_recovery.down.sql
+-- The password_reset_* and email_* audit event values stay; enum values cannot be dropped.
+
+DROP TABLE IF EXISTS account_tokens;
+
+DROP TRIGGER IF EXISTS reset_users_email_verification ON users;
+DROP FUNCTION IF EXISTS reset_email_verification();
+
+ALTER TABLE users DROP COLUMN IF EXISTS email_verified_at;
@@ -0,0 +1,42 @@
+-- ViWorkS Admin Panel - Self-service password reset and email verification
+-- Migration: 020_account_recovery.sql
+
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'password_reset_requested';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'password_reset_completed';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'email_verification_sent';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'email_verified';
+
+-- When the user last proved they receive mail at `email`; cleared whenever
+-- the address changes, whoever changes it
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMP WITH TIME ZONE;
+
+CREATE OR REPLACE FUNCTION reset_email_verification()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.email IS DISTINCT FROM OLD.email THEN
+        NEW.email_verified_at = NULL;
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER reset_users_email_verification BEFORE UPDATE OF email ON users
+    FOR EACH ROW EXECUTE FUNCTION reset_email_verification();
+
+-- Single-use tokens mailed to users. Only the SHA-256 of a token is kept;
+-- `email` is the address it was sent to, so a token for an address the user
+-- no longer has cannot verify the new one.
+CREATE TABLE account_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose VARCHAR(32) NOT NULL CHECK (purpose IN ('password_reset', 'email_verification')),
+    token_hash CHAR(64) NOT NULL UNIQUE,
+    email VARCHAR(255) NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    requested_ip INET,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_account_tokens_user_purpose ON account_tokens(user_id, purpose, created_at);
+CREATE INDEX idx_account_tokens_expires_at ON account_tokens(expires_at);
@@ -0,0 +1,14 @@
+-- ViWorkS Admin Panel - Push approval with number matching (rollback)
+-- Migration: 021_push_approval.down.sql
+-- The push_approval_* audit event values stay; enum values cannot be dropped.
+
+DROP INDEX IF EXISTS idx_verification_requests_pending_push;
+DROP INDEX IF EXISTS idx_verification_requests_challenge;
+
+ALTER TABLE verification_requests
+    DROP COLUMN IF EXISTS responded_at,
+    DROP COLUMN IF EXISTS push_message_id,
+    DROP COLUMN IF EXISTS response_token_hash,
+    DROP COLUMN IF EXISTS number_choices,
+    DROP COLUMN IF EXISTS challenge_id,
+    DROP COLUMN IF EXISTS push_status;
@@ -0,0 +1,25 @@
+-- ViWorkS Admin Panel - Push approval with number matching for the mobile second factor
+-- Migration: 021_push_approval.sql
+
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'push_approval_sent';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'push_approval_approved';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'push_approval_denied';
+ALTER TYPE audit_event_type ADD VALUE IF NOT EXISTS 'push_approval_timed_out';
+
+-- A push prompt is a verification request whose `code` is the two-digit number
+-- shown on the desktop. Rows of the older code flow keep push_status NULL.
+ALTER TABLE verification_requests
+    ADD COLUMN push_status VARCHAR(16) CHECK (push_status IN ('pending', 'approved', 'denied', 'expired')),
+    -- Login challenge (`challenge:<id>` in the OTP store) the prompt answers
+    ADD COLUMN challenge_id VARCHAR(64),
+    -- Numbers offered on the phone, the right one among them
+    ADD COLUMN number_choices SMALLINT[],
+    -- SHA-256 (hex) of the single-use token the push carries
+    ADD COLUMN response_token_hash CHAR(64) UNIQUE,
+    ADD COLUMN push_message_id TEXT,
+    ADD COLUMN responded_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX idx_verification_requests_challenge ON verification_requests(challenge_id)
+    WHERE challenge_id IS NOT NULL;
+CREATE INDEX idx_verification_requests_pending_push ON verification_requests(expires_at)
+    WHERE push_status = 'pending';
@@ -0,0 +1,4 @@
+-- ViWorkS Admin Panel - Record the second factor a session was started with (rollback)
+-- Migration: 022_session_second_factor.down.sql
+
+ALTER TABLE sessions DROP COLUMN IF EXISTS second_factor;
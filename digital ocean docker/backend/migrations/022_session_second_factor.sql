@@ -0,0 +1,6 @@
+-- ViWorkS Admin Panel - Record the second factor a session was started with
+-- Migration: 022_session_second_factor.sql
+
+-- 'totp', 'mobile_otp' or 'push_approval'. Gateway credentials are only minted
+-- for sessions that have one; sessions from before this migration sign in again.
+ALTER TABLE sessions ADD COLUMN second_factor VARCHAR(32);
@@ -0,0 +1,5 @@
+-- ViWorkS Admin Panel - Keep device verification codes out of verification_requests (rollback)
+-- Migration: 023_verification_code_nullable.down.sql
+
+UPDATE verification_requests SET code = '' WHERE code IS NULL;
+ALTER TABLE verification_requests ALTER COLUMN code SET NOT NULL;
@@ -0,0 +1,7 @@
+-- ViWorkS Admin Panel - Keep device verification codes out of verification_requests
+-- Migration: 023_verification_code_nullable.sql
+
+-- Device verification codes live hashed in the OTP store (`verification:<id>`);
+-- their rows leave `code` NULL. Push prompts still store their number in it.
+ALTER TABLE verification_requests ALTER COLUMN code DROP NOT NULL;
+UPDATE verification_requests SET code = NULL WHERE push_status IS NULL;
@@ -53,28 +53,24 @@ if ! PGPASSWORD="${POSTGRES_PASSWORD:-viworks_password_2024}" psql -h postgres -
 fi
 log "✅ Database connectivity test passed"
 
-    # Run database migrations if needed with better error handling
+    # Run database migrations (versioned, checksummed, serialized by an advisory lock)
     log "🔧 Checking database migrations..."
     if [ -d "/app/migrations" ]; then
+        # Databases created by the old psql loop have the schema but no schema_migrations
+        # table: record 001 as already applied instead of re-running it.
+        legacy_schema=$(PGPASSWORD="${POSTGRES_PASSWORD:-viworks_password_2024}" psql -h postgres -p 5432 -U admin -d viworks -tAc \
+            "SELECT to_regclass('public.users') IS NOT NULL AND to_regclass('public.schema_migrations') IS NULL;" 2>/dev/null || echo "f")
+        if [ "$legacy_schema" = "t" ]; then
+            log "📋 Existing schema without migration history, baselining version 001..."
+            MIGRATIONS_DIR=/app/migrations /app/app migrate baseline 1
+        fi
+
         log "📋 Running database migrations..."
-        migration_errors=0
-        
-        for migration_file in /app/migrations/*.sql; do
-            if [ -f "$migration_file" ]; then
-                log "📄 Running migration: $(basename "$migration_file")"
-                if PGPASSWORD="${POSTGRES_PASSWORD:-viworks_password_2024}" psql -h postgres -p 5432 -U admin -d viworks -f "$migration_file" 2>&1; then
-                    log "✅ Migration $(basename "$migration_file") completed successfully"
-                else
-                    log "⚠️  Migration $(basename "$migration_file") failed, but continuing..."
-                    ((migration_errors++))
-                fi
-            fi
-        done
-        
-        if [ $migration_errors -gt 0 ]; then
-            log "⚠️  $migration_errors migration(s) had errors, but continuing..."
-        else
+        if MIGRATIONS_DIR=/app/migrations /app/app migrate up; then
             log "✅ All database migrations completed successfully"
+        else
+            log "❌ Critical: Database migrations failed. Exiting."
+            exit 1
         fi
     else
         log "ℹ️  No migrations directory found, skipping migrations"
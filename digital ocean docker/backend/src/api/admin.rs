@@ -0,0 +1,106 @@
+use actix_web::{web, HttpResponse};
+use uuid::Uuid;
+use crate::api::{agent, alerts, audit, devices, pki, policies, sessions, users};
+use crate::auth::{
+    policy::PolicyAction, webauthn::WebAuthnService, AdminWebAuthnMiddleware, AuthMiddleware, PolicyMiddleware,
+};
+
+/// Admin console routes. Every request must be allowed by the `admin` policy
+/// action, and admin roles must have verified the session with a WebAuthn
+/// security key (see `AdminWebAuthnMiddleware`).
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .wrap(AdminWebAuthnMiddleware::new())
+            .wrap(PolicyMiddleware::new(PolicyAction::Admin))
+            .wrap(AuthMiddleware::new())
+            .route("/users", web::get().to(users::get_users))
+            .route("/sessions", web::get().to(sessions::list_sessions))
+            .route("/users/{id}/webauthn-credentials", web::get().to(list_user_credentials))
+            .route(
+                "/users/{id}/webauthn-credentials/{credential_id}",
+                web::delete().to(remove_user_credential),
+            )
+            // Internal CA
+            .route("/pki/intermediates", web::get().to(pki::list_intermediates))
+            .route("/pki/intermediates/rotate", web::post().to(pki::rotate_intermediate))
+            .route("/pki/agents/certificates", web::post().to(pki::sign_agent_csr))
+            .route("/pki/certificates/{serial}/revoke", web::post().to(pki::revoke_certificate))
+            // Access policies
+            .route("/policies", web::get().to(policies::list_policies))
+            .route("/policies", web::post().to(policies::create_policy))
+            .route("/policies/evaluate", web::post().to(policies::evaluate_policies))
+            .route("/policies/{id}", web::put().to(policies::update_policy))
+            .route("/policies/{id}/versions", web::get().to(policies::list_policy_versions))
+            // Device-binding requests
+            .route("/device/requests", web::get().to(devices::list_device_requests))
+            .route("/device/requests/{id}/approve", web::post().to(devices::approve_device_request))
+            .route("/device/requests/{id}/reject", web::post().to(devices::reject_device_request))
+            .route("/device/requests/{id}/revoke", web::post().to(devices::revoke_device_request))
+            // Enrolled desktop device keys
+            .route("/devices", web::get().to(devices::list_devices))
+            .route("/devices/{id}/revoke-key", web::post().to(devices::revoke_device_key))
+            // Hash-chained audit log
+            .route("/audit/events", web::get().to(audit::list_audit_events))
+            .route("/audit/export", web::get().to(audit::export_audit_events))
+            .route("/audit/verify", web::get().to(audit::verify_audit_log))
+            .route("/audit/checkpoints", web::get().to(audit::list_audit_checkpoints))
+            .route("/audit/checkpoints", web::post().to(audit::create_audit_checkpoint))
+            .route("/audit/siem", web::get().to(audit::siem_export_status))
+            // Security alerts raised by the rule engine
+            .route("/alerts", web::get().to(alerts::list_alerts))
+            .route("/alerts/rules", web::get().to(alerts::list_alert_rules))
+            .route("/alerts/rules/reload", web::post().to(alerts::reload_alert_rules))
+            .route("/alerts/{id}/acknowledge", web::post().to(alerts::acknowledge_alert))
+            .route("/alerts/{id}/resolve", web::post().to(alerts::resolve_alert))
+            // Gateway commands sent through the backend agent
+            .route("/agent-commands", web::get().to(agent::list_agent_commands))
+            .route("/agent-commands/{id}", web::get().to(agent::get_agent_command))
+            .route("/agent-commands/{id}/retry", web::post().to(agent::retry_agent_command))
+    );
+}
+
+pub async fn list_user_credentials(
+    webauthn_service: web::Data<WebAuthnService>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let credentials = webauthn_service
+        .list_credentials(path.into_inner())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "credentials": credentials
+    })))
+}
+
+/// Removes a lost or cloned key so the user can enroll a new one.
+pub async fn remove_user_credential(
+    webauthn_service: web::Data<WebAuthnService>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (user_id, credential_id) = path.into_inner();
+
+    let removed = webauthn_service
+        .remove_credential(user_id, credential_id)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    if removed {
+        Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "Security key removed"
+        })))
+    } else {
+        Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Security key not found"
+        })))
+    }
+}
@@ -1,276 +1,239 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use reqwest::Client;
-use tracing::{info, error};
+use uuid::Uuid;
+use crate::auth::{policy::PolicyAction, AdminWebAuthnMiddleware, AuthMiddleware, ClientContext, Claims, PolicyMiddleware};
+use crate::database::listing::ListQuery;
+use crate::services::agent_bridge::{AgentBridge, AgentCommand, GatewayCommand, AGENT_COMMAND_LIST};
+use crate::services::audit::{AuditEvent, AuditEventType, AuditLog};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(rename = "AgentCreateUserRequest")]
 pub struct CreateUserRequest {
     pub username: String,
     pub password: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct SpawnContainerRequest {
     pub username: String,
-    pub session_id: String,
+    pub session_id: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct TerminateSessionRequest {
-    pub session_id: String,
+    pub session_id: Uuid,
 }
 
-#[derive(Debug, Serialize)]
-pub struct CreateUserResponse {
-    pub ok: bool,
-    pub message: String,
+/// A gateway command as recorded in `agent_commands`; `status` moves from
+/// pending to submitted to completed or failed as the backend agent runs it.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AgentCommandResponse {
+    pub success: bool,
+    pub command: AgentCommand,
 }
 
-#[derive(Debug, Serialize)]
-pub struct SpawnContainerResponse {
-    pub url: String,
-    pub port: u16,
-    pub container_id: String,
-}
-
-#[derive(Debug, Serialize)]
-pub struct TerminateSessionResponse {
-    pub terminated: bool,
-    pub message: String,
-}
-
-// Gateway Agent integration endpoints
+// Gateway commands, queued through the backend agent
 pub async fn create_user(
     pool: web::Data<PgPool>,
+    bridge: web::Data<AgentBridge>,
+    audit: web::Data<AuditLog>,
     req: web::Json<CreateUserRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let username = req.username.clone();
-    let password = req.password.clone();
-    
-    info!("Creating VPN user: {}", username);
-    
-    // Call the Gateway Agent to create the VPN user
-    let client = Client::new();
-    let gateway_agent_url = std::env::var("GATEWAY_AGENT_URL")
-        .unwrap_or_else(|_| "http://localhost:8443".to_string());
-    
-    let command_request = serde_json::json!({
-        "command": "openvpn_create",
-        "params": {
-            "username": username,
-            "password": password
-        }
-    });
-    
-    match client
-        .post(&format!("{}/api/v1/command", gateway_agent_url))
-        .json(&command_request)
-        .send()
+    let req = req.into_inner();
+    let user = sqlx::query!("SELECT id, email FROM users WHERE username = $1", req.username)
+        .fetch_optional(pool.get_ref())
         .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                info!("VPN user created successfully via Gateway Agent: {}", username);
-                
-                // Log the action
-                let _ = sqlx::query!(
-                    r#"
-                    INSERT INTO audit_logs (action, resource_type, resource_id, details, created_at)
-                    VALUES ($1, $2, $3, $4, NOW())
-                    "#,
-                    "VPN_USER_CREATED",
-                    "user",
-                    username,
-                    serde_json::json!({
-                        "username": username,
-                        "action": "vpn_user_created",
-                        "gateway_agent": true,
-                        "timestamp": chrono::Utc::now().to_rfc3339()
-                    })
-                )
-                .execute(pool.get_ref())
-                .await;
-                
-                Ok(HttpResponse::Ok().json(CreateUserResponse {
-                    ok: true,
-                    message: "VPN user created successfully via Gateway Agent".to_string(),
-                }))
-            } else {
-                error!("Gateway Agent returned error status: {}", response.status());
-                Ok(HttpResponse::InternalServerError().json(CreateUserResponse {
-                    ok: false,
-                    message: "Failed to create VPN user via Gateway Agent".to_string(),
-                }))
-            }
-        }
-        Err(e) => {
-            error!("Failed to call Gateway Agent: {}", e);
-            Ok(HttpResponse::InternalServerError().json(CreateUserResponse {
-                ok: false,
-                message: format!("Failed to connect to Gateway Agent: {}", e),
-            }))
-        }
-    }
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    let command = GatewayCommand::CreateUser {
+        user_id: user.as_ref().map(|user| user.id),
+        email: user.map(|user| user.email),
+        username: req.username,
+        password: req.password,
+    };
+    queue(&bridge, &audit, command, &claims, &http_req).await
 }
 
 pub async fn spawn_container(
     pool: web::Data<PgPool>,
+    bridge: web::Data<AgentBridge>,
+    audit: web::Data<AuditLog>,
     req: web::Json<SpawnContainerRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let username = req.username.clone();
-    let session_id = req.session_id.clone();
-    
-    info!("Spawning container for user: {}, session: {}", username, session_id);
-    
-    // Call the Gateway Agent to spawn a container
-    let client = Client::new();
-    let gateway_agent_url = std::env::var("GATEWAY_AGENT_URL")
-        .unwrap_or_else(|_| "http://localhost:8443".to_string());
-    
-    let command_request = serde_json::json!({
-        "command": "docker_spawn",
-        "params": {
-            "username": username,
-            "session_id": session_id,
-            "image": "viworks/chrome:latest"
-        }
-    });
-    
-    match client
-        .post(&format!("{}/api/v1/command", gateway_agent_url))
-        .json(&command_request)
-        .send()
+    let req = req.into_inner();
+    let user_id = sqlx::query_scalar!("SELECT id FROM users WHERE username = $1", req.username)
+        .fetch_optional(pool.get_ref())
         .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                let response_data: serde_json::Value = response.json().await
-                    .unwrap_or_else(|_| serde_json::json!({"error": "Failed to parse response"}));
-                
-                info!("Container spawned successfully via Gateway Agent: {}", session_id);
-                
-                // Log the action
-                let _ = sqlx::query!(
-                    r#"
-                    INSERT INTO audit_logs (action, resource_type, resource_id, details, created_at)
-                    VALUES ($1, $2, $3, $4, NOW())
-                    "#,
-                    "CONTAINER_SPAWNED",
-                    "session",
-                    session_id,
-                    serde_json::json!({
-                        "username": username,
-                        "session_id": session_id,
-                        "gateway_agent": true,
-                        "response": response_data,
-                        "timestamp": chrono::Utc::now().to_rfc3339()
-                    })
-                )
-                .execute(pool.get_ref())
-                .await;
-                
-                // Extract container info from response
-                let container_id = response_data["container_id"].as_str().unwrap_or("unknown");
-                let port = response_data["port"].as_u64().unwrap_or(5801) as u16;
-                let url = response_data["url"].as_str().unwrap_or("https://gw.example.com/");
-                
-                Ok(HttpResponse::Ok().json(SpawnContainerResponse {
-                    url: url.to_string(),
-                    port,
-                    container_id: container_id.to_string(),
-                }))
-            } else {
-                error!("Gateway Agent returned error status: {}", response.status());
-                Ok(HttpResponse::InternalServerError().json(SpawnContainerResponse {
-                    url: "".to_string(),
-                    port: 0,
-                    container_id: "".to_string(),
-                }))
-            }
-        }
-        Err(e) => {
-            error!("Failed to call Gateway Agent: {}", e);
-            Ok(HttpResponse::InternalServerError().json(SpawnContainerResponse {
-                url: "".to_string(),
-                port: 0,
-                container_id: "".to_string(),
-            }))
-        }
-    }
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    let command = GatewayCommand::SpawnContainer {
+        user_id,
+        username: req.username,
+        session_id: req.session_id,
+    };
+    queue(&bridge, &audit, command, &claims, &http_req).await
 }
 
 pub async fn terminate_session(
     pool: web::Data<PgPool>,
+    bridge: web::Data<AgentBridge>,
+    audit: web::Data<AuditLog>,
     req: web::Json<TerminateSessionRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let session_id = req.session_id.clone();
-    
-    info!("Terminating session: {}", session_id);
-    
-    // Call the Gateway Agent to terminate the session
-    let client = Client::new();
-    let gateway_agent_url = std::env::var("GATEWAY_AGENT_URL")
-        .unwrap_or_else(|_| "http://localhost:8443".to_string());
-    
-    let command_request = serde_json::json!({
-        "command": "session_terminate",
-        "params": {
-            "session_id": session_id
-        }
-    });
-    
-    match client
-        .post(&format!("{}/api/v1/command", gateway_agent_url))
-        .json(&command_request)
-        .send()
-        .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                info!("Session terminated successfully via Gateway Agent: {}", session_id);
-                
-                // Log the action
-                let _ = sqlx::query!(
-                    r#"
-                    INSERT INTO audit_logs (action, resource_type, resource_id, details, created_at)
-                    VALUES ($1, $2, $3, $4, NOW())
-                    "#,
-                    "SESSION_TERMINATED_BY_ADMIN",
-                    "session",
-                    session_id,
-                    serde_json::json!({
-                        "session_id": session_id,
-                        "action": "session_terminated",
-                        "terminated_by": "admin",
-                        "gateway_agent": true,
-                        "timestamp": chrono::Utc::now().to_rfc3339()
-                    })
-                )
-                .execute(pool.get_ref())
-                .await;
-    
-    Ok(HttpResponse::Ok().json(TerminateSessionResponse {
-        terminated: true,
-        message: "Session terminated successfully".to_string(),
-    }))
+    let session = sqlx::query!(
+        "SELECT s.user_id, u.username FROM sessions s JOIN users u ON u.id = s.user_id WHERE s.id = $1",
+        req.session_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    let Some(session) = session else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Session not found"
+        })));
+    };
+
+    let command = GatewayCommand::TerminateSession {
+        user_id: Some(session.user_id),
+        username: session.username,
+        session_id: req.session_id,
+    };
+    queue(&bridge, &audit, command, &claims, &http_req).await
+}
+
+async fn queue(
+    bridge: &AgentBridge,
+    audit: &AuditLog,
+    command: GatewayCommand,
+    claims: &Claims,
+    http_req: &HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let command = bridge.enqueue(command, claims.user_id()).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::AdminAction)
+                .actor(claims)
+                .details(serde_json::json!({
+                    "action": "agent_command_queued",
+                    "command_id": command.id,
+                    "verb": command.verb,
+                    "args": command.args
+                }))
+                .context(&ClientContext::from_request(http_req)),
+        )
+        .await;
+
+    Ok(HttpResponse::Accepted().json(AgentCommandResponse { success: true, command }))
+}
+
+/// Gateway commands, newest first.
+pub async fn list_agent_commands(
+    bridge: web::Data<AgentBridge>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let list = match query.resolve(&AGENT_COMMAND_LIST) {
+        Ok(list) => list,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))),
+    };
+
+    let page = bridge.list(&list).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "commands": page.items,
+        "total": page.total,
+        "limit": page.limit,
+        "next_cursor": page.next_cursor
+    })))
+}
+
+pub async fn get_agent_command(
+    bridge: web::Data<AgentBridge>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let command = bridge.get(path.into_inner()).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    match command {
+        Some(command) => Ok(HttpResponse::Ok().json(AgentCommandResponse { success: true, command })),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Command not found"
+        }))),
+    }
 }
 
-fn generate_random_path() -> String {
-    use rand::Rng;
-    let mut rng = rand::thread_rng();
-    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
-    (0..32)
-        .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
-        })
-        .collect()
+/// Sends a failed or cancelled command to the backend agent again.
+pub async fn retry_agent_command(
+    bridge: web::Data<AgentBridge>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<Uuid>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let command_id = path.into_inner();
+    let command = bridge.retry(command_id).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    let Some(command) = command else {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Command not found or not failed"
+        })));
+    };
+
+    println!("🔁 Gateway command {} retried by {}", command_id, claims.username);
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::AdminAction)
+                .actor(&claims)
+                .details(serde_json::json!({
+                    "action": "agent_command_retried",
+                    "command_id": command_id,
+                    "verb": command.verb
+                }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
+    Ok(HttpResponse::Accepted().json(AgentCommandResponse { success: true, command }))
 }
 
+/// Gateway commands issued by hand; every request must be allowed by the `admin` policy action,
+/// after WebAuthn verification for admin roles.
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/agent")
+            .wrap(AdminWebAuthnMiddleware::new())
+            .wrap(PolicyMiddleware::new(PolicyAction::Admin))
+            .wrap(AuthMiddleware::new())
             .route("/user/create", web::post().to(create_user))
             .route("/container/spawn", web::post().to(spawn_container))
             .route("/session/terminate", web::post().to(terminate_session))
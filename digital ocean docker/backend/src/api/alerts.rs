@@ -0,0 +1,162 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::auth::{ClientContext, Claims};
+use crate::database::listing::ListQuery;
+use crate::services::alerts::{AlertEngine, Severity, ALERT_LIST};
+use crate::services::audit::{AuditEvent, AuditEventType, AuditLog};
+
+/// Filters on top of the shared list parameters.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AlertQuery {
+    pub severity: Option<String>,
+    pub rule_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct ResolveRequest {
+    pub note: Option<String>,
+}
+
+/// Security alerts, most recently seen first.
+pub async fn list_alerts(
+    alerts: web::Data<AlertEngine>,
+    query: web::Query<ListQuery>,
+    filters: web::Query<AlertQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut list = match query.resolve(&ALERT_LIST) {
+        Ok(list) => list,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))),
+    };
+    let filters = filters.into_inner();
+    if let Some(severity) = filters.severity {
+        if Severity::parse(&severity).is_none() {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "severity must be one of: low, medium, high, critical"
+            })));
+        }
+        list = list.matching("severity", severity);
+    }
+    if let Some(rule_id) = filters.rule_id {
+        list = list.matching("rule_id", rule_id);
+    }
+
+    let page = alerts.list(&list).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "alerts": page.items,
+        "total": page.total,
+        "limit": page.limit,
+        "next_cursor": page.next_cursor
+    })))
+}
+
+pub async fn acknowledge_alert(
+    alerts: web::Data<AlertEngine>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<Uuid>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let alert_id = path.into_inner();
+    let acknowledged = alerts.acknowledge(alert_id, claims.user_id()).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+    if !acknowledged {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Alert not found or not open"
+        })));
+    }
+
+    println!("👀 Security alert {} acknowledged by {}", alert_id, claims.username);
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::AdminAction)
+                .actor(&claims)
+                .details(serde_json::json!({
+                    "action": "alert_acknowledged",
+                    "alert_id": alert_id
+                }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Alert acknowledged"
+    })))
+}
+
+pub async fn resolve_alert(
+    alerts: web::Data<AlertEngine>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<Uuid>,
+    request: Option<web::Json<ResolveRequest>>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let alert_id = path.into_inner();
+    let note = request.map(|request| request.into_inner()).unwrap_or_default().note;
+    let resolved = alerts
+        .resolve(alert_id, claims.user_id(), note.as_deref())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+    if !resolved {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Alert not found or already resolved"
+        })));
+    }
+
+    println!("✅ Security alert {} resolved by {}", alert_id, claims.username);
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::AdminAction)
+                .actor(&claims)
+                .details(serde_json::json!({
+                    "action": "alert_resolved",
+                    "alert_id": alert_id,
+                    "note": note
+                }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Alert resolved"
+    })))
+}
+
+/// The rules in force and the outcome of the last reload.
+pub async fn list_alert_rules(alerts: web::Data<AlertEngine>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "rules": alerts.rules()
+    }))
+}
+
+/// Re-reads the rule file now instead of waiting for the engine to notice.
+pub async fn reload_alert_rules(alerts: web::Data<AlertEngine>) -> HttpResponse {
+    match alerts.reload() {
+        Ok(count) => {
+            println!("🔁 Reloaded {} alert rule(s)", count);
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "message": format!("Loaded {} alert rule(s)", count),
+                "rules": alerts.rules()
+            }))
+        }
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("{:#}", e)
+        })),
+    }
+}
@@ -0,0 +1,184 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::config::AppConfig;
+use crate::services::audit::{AuditEventType, AuditFilter, AuditLog, ExportFormat};
+use crate::services::siem::SiemExporter;
+
+const MAX_PER_PAGE: i64 = 200;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AuditQuery {
+    pub event_type: Option<String>,
+    pub user_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    /// Export only: `ndjson` (default) or `csv`
+    pub format: Option<String>,
+}
+
+impl AuditQuery {
+    fn filter(&self) -> Result<AuditFilter, HttpResponse> {
+        let event_type = match self.event_type.as_deref() {
+            Some(value) => Some(AuditEventType::parse(value).ok_or_else(|| {
+                HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Unknown audit event type: {}", value)
+                }))
+            })?),
+            None => None,
+        };
+
+        Ok(AuditFilter {
+            event_type,
+            user_id: self.user_id,
+            from: self.from,
+            to: self.to,
+        })
+    }
+}
+
+/// Filtered, paginated audit events, newest first.
+pub async fn list_audit_events(
+    audit: web::Data<AuditLog>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(response) => return Ok(response),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(50).clamp(1, MAX_PER_PAGE);
+
+    let (events, total) = audit.query(&filter, page, per_page).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "events": events,
+        "total": total,
+        "page": page,
+        "per_page": per_page
+    })))
+}
+
+/// Streams every matching event, oldest first, as NDJSON or CSV.
+pub async fn export_audit_events(
+    audit: web::Data<AuditLog>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(response) => return Ok(response),
+    };
+    let Some(format) = ExportFormat::parse(query.format.as_deref().unwrap_or("ndjson")) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "format must be one of: ndjson, csv"
+        })));
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"audit-{}.{}\"",
+                Utc::now().format("%Y%m%dT%H%M%SZ"),
+                format.extension()
+            ),
+        ))
+        .streaming(audit.export(filter, format)))
+}
+
+/// Recomputes the hash chain and checks it against the signed checkpoints.
+pub async fn verify_audit_log(audit: web::Data<AuditLog>) -> Result<HttpResponse, actix_web::Error> {
+    let report = audit.verify().await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    if !report.valid {
+        println!("🚨 Audit log verification found {} issue(s)", report.issue_count);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "report": report
+    })))
+}
+
+pub async fn list_audit_checkpoints(audit: web::Data<AuditLog>) -> Result<HttpResponse, actix_web::Error> {
+    let checkpoints = audit.list_checkpoints().await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "public_key": audit.public_key(),
+        "checkpoints": checkpoints
+    })))
+}
+
+/// Signs the current chain head now instead of waiting for the periodic checkpoint.
+pub async fn create_audit_checkpoint(audit: web::Data<AuditLog>) -> Result<HttpResponse, actix_web::Error> {
+    let checkpoint = audit.checkpoint().await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    match checkpoint {
+        Some(checkpoint) => Ok(HttpResponse::Created().json(serde_json::json!({
+            "success": true,
+            "message": "Audit checkpoint created",
+            "checkpoint": checkpoint
+        }))),
+        None => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "Audit log head is already checkpointed"
+        }))),
+    }
+}
+
+/// How far each SIEM export target is behind the head of the audit log.
+pub async fn siem_export_status(
+    pool: web::Data<PgPool>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let head_seq = sqlx::query_scalar!("SELECT MAX(seq) FROM audit_events")
+        .fetch_one(pool.get_ref())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?
+        .unwrap_or(0);
+
+    let cursors = SiemExporter::cursors(pool.get_ref()).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    let targets: Vec<serde_json::Value> = cursors
+        .into_iter()
+        .map(|cursor| serde_json::json!({
+            "active": cursor.target == config.siem_export_target,
+            "lag": head_seq - cursor.last_seq,
+            "cursor": cursor
+        }))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "enabled": !config.siem_export_target.is_empty(),
+        "format": config.siem_export_format,
+        "head_seq": head_seq,
+        "targets": targets
+    })))
+}
@@ -1,15 +1,30 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use sqlx::{PgPool, Row};
+use sqlx::PgPool;
 use anyhow::Result;
 use sqlx::types::ipnetwork::IpNetwork;
-use chrono::Utc;
-use crate::models::Client;
-
+use crate::database::listing::{ListQuery, ListSpec, MatchFilter, SortField};
+use crate::auth::{policy::PolicyAction, AdminWebAuthnMiddleware, AuthMiddleware, AuthService, ClientContext, Claims, PolicyMiddleware};
+use crate::services::audit::{AuditEvent, AuditEventType, AuditLog};
+use crate::websocket::{channels, WebSocketEvent, WebSocketSessionManager};
+
+/// Client administration; every request must be allowed by the `admin` policy
+/// action, after WebAuthn verification for admin roles. Only log submission is
+/// left to the desktop client, which proves its own session in the body.
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    // Registered ahead of the scope, which would otherwise claim the path
+    cfg.service(
+        web::resource("/clients/{id}/logs")
+            .wrap(AuthMiddleware::new())
+            .route(web::post().to(submit_client_log))
+    );
     cfg.service(
         web::scope("/clients")
+            .wrap(AdminWebAuthnMiddleware::new())
+            .wrap(PolicyMiddleware::new(PolicyAction::Admin))
+            .wrap(AuthMiddleware::new())
             .route("", web::get().to(list_clients))
             .route("", web::post().to(create_client))
             .route("/{id}", web::get().to(get_client))
@@ -19,11 +34,10 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig) {
             .route("/{id}/connect", web::post().to(connect_client))
             .route("/{id}/disconnect", web::post().to(disconnect_client))
             .route("/{id}/disconnect-session", web::post().to(disconnect_client_session))
-            .route("/{id}/logs", web::post().to(submit_client_log))
     );
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct CreateClientRequest {
     pub name: String,
     pub platform: String,
@@ -32,7 +46,7 @@ pub struct CreateClientRequest {
     pub mac_address: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct UpdateClientRequest {
     pub name: Option<String>,
     pub platform: Option<String>,
@@ -42,7 +56,7 @@ pub struct UpdateClientRequest {
     pub status: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ClientResponse {
     pub id: String,
     pub name: String,
@@ -57,15 +71,15 @@ pub struct ClientResponse {
     pub updated_at: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ClientListResponse {
     pub clients: Vec<ClientResponse>,
     pub total: i64,
-    pub page: i64,
-    pub per_page: i64,
+    pub limit: i64,
+    pub next_cursor: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ClientStatusResponse {
     pub id: String,
     pub name: String,
@@ -74,7 +88,7 @@ pub struct ClientStatusResponse {
     pub ip_address: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct ClientConnectRequest {
     pub username: String,
     pub session_token: String,
@@ -85,14 +99,14 @@ pub struct ClientConnectRequest {
     pub mac_address: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct ClientDisconnectRequest {
     pub username: String,
     pub session_token: String,
     pub client_id: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct ClientLogRequest {
     pub username: String,
     pub session_token: String,
@@ -102,80 +116,101 @@ pub struct ClientLogRequest {
     pub details: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ClientConnectResponse {
     pub success: bool,
     pub message: String,
     pub client_id: Option<Uuid>,
 }
 
+/// Tells the admin dashboard (`client_updates` channel) that an agent went online or offline.
+pub fn notify_admins(session_manager: &WebSocketSessionManager, client_id: Uuid, status: &str) {
+    session_manager.broadcast_to_channel(
+        channels::CLIENT_UPDATES,
+        WebSocketEvent::client_update(client_id.to_string(), status.to_string()),
+    );
+}
+
+/// Agents matching `status`, `q` (name or platform) and `from`/`to`
+/// (registration date), a page at a time.
+static CLIENT_LIST: ListSpec = ListSpec {
+    select: "id, name, platform, version, status::text AS status, ip_address, mac_address, \
+             last_seen, connection_count, created_at, updated_at",
+    from: "clients",
+    id: "id",
+    sorts: &[
+        SortField { name: "created_at", expr: "created_at", sql_type: "timestamptz" },
+        SortField { name: "name", expr: "name", sql_type: "text" },
+        SortField { name: "last_seen", expr: "COALESCE(last_seen, '-infinity')", sql_type: "timestamptz" },
+        SortField { name: "connection_count", expr: "connection_count", sql_type: "integer" },
+    ],
+    default_sort: "-created_at",
+    date: Some("created_at"),
+    search: &["name", "platform"],
+    status: Some(MatchFilter { expr: "status", values: &["online", "offline", "connecting", "error"] }),
+    role: None,
+};
+
+#[derive(sqlx::FromRow)]
+struct ClientRow {
+    id: Uuid,
+    name: String,
+    platform: String,
+    version: String,
+    status: String,
+    ip_address: Option<IpNetwork>,
+    mac_address: Option<String>,
+    last_seen: Option<chrono::DateTime<chrono::Utc>>,
+    connection_count: i32,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
 pub async fn list_clients(
     pool: web::Data<PgPool>,
-    query: web::Query<std::collections::HashMap<String, String>>,
+    query: web::Query<ListQuery>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let page = query.get("page").and_then(|s| s.parse::<i64>().ok()).unwrap_or(1);
-    let per_page = query.get("per_page").and_then(|s| s.parse::<i64>().ok()).unwrap_or(10);
-    let offset = (page - 1) * per_page;
-
-    // Get total count
-    let total = sqlx::query(
-        "SELECT COUNT(*) FROM clients"
-    )
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|e| {
-        eprintln!("Database error: {}", e);
-        actix_web::error::ErrorInternalServerError("Database error")
-    })?
-    .get::<i64, _>(0);
-
-    // Get clients with pagination
-    let clients = sqlx::query(
-        r#"
-        SELECT id, name, platform, version, status::text, ip_address, mac_address::text as mac_address, 
-               last_seen, connection_count, created_at, updated_at
-        FROM clients 
-        ORDER BY created_at DESC 
-        LIMIT $1 OFFSET $2
-        "#
-    )
-    .bind(per_page)
-    .bind(offset)
-    .fetch_all(pool.get_ref())
-    .await
-    .map_err(|e| {
+    let list = match query.resolve(&CLIENT_LIST) {
+        Ok(list) => list,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))),
+    };
+    let page = list.fetch::<ClientRow>(pool.get_ref()).await.map_err(|e| {
         eprintln!("Database error: {}", e);
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
 
-    let client_responses: Vec<ClientResponse> = clients
+    let client_responses: Vec<ClientResponse> = page
+        .items
         .into_iter()
         .map(|row| ClientResponse {
-            id: row.get::<Uuid, _>("id").to_string(),
-            name: row.get::<String, _>("name"),
-            platform: row.get::<String, _>("platform"),
-            version: row.get::<String, _>("version"),
-            ip_address: row.get::<Option<IpNetwork>, _>("ip_address").map(|ip| ip.to_string()),
-            mac_address: row.get::<Option<String>, _>("mac_address"),
-            status: row.get::<String, _>("status"),
-            last_seen: row.get::<Option<chrono::DateTime<Utc>>, _>("last_seen").map(|dt| dt.to_rfc3339()),
-            connection_count: row.get::<i32, _>("connection_count"),
-            created_at: row.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
-            updated_at: row.get::<chrono::DateTime<Utc>, _>("updated_at").to_rfc3339(),
+            id: row.id.to_string(),
+            name: row.name,
+            platform: row.platform,
+            version: row.version,
+            ip_address: row.ip_address.map(|ip| ip.to_string()),
+            mac_address: row.mac_address,
+            status: row.status,
+            last_seen: row.last_seen.map(|dt| dt.to_rfc3339()),
+            connection_count: row.connection_count,
+            created_at: row.created_at.to_rfc3339(),
+            updated_at: row.updated_at.to_rfc3339(),
         })
         .collect();
 
     Ok(HttpResponse::Ok().json(ClientListResponse {
         clients: client_responses,
-        total,
-        page,
-        per_page,
+        total: page.total,
+        limit: page.limit,
+        next_cursor: page.next_cursor,
     }))
 }
 
 pub async fn create_client(
     pool: web::Data<PgPool>,
+    audit: web::Data<AuditLog>,
     req: web::Json<CreateClientRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let client_id = Uuid::new_v4();
     
@@ -186,18 +221,18 @@ pub async fn create_client(
         None
     };
 
-    sqlx::query(
+    sqlx::query!(
         r#"
         INSERT INTO clients (id, name, platform, version, status, ip_address, mac_address, last_seen)
         VALUES ($1, $2, $3, $4, 'offline'::client_status, $5, $6, NOW())
-        "#
+        "#,
+        client_id,
+        req.name,
+        req.platform,
+        req.version,
+        ip_address,
+        req.mac_address,
     )
-    .bind(client_id)
-    .bind(&req.name)
-    .bind(&req.platform)
-    .bind(&req.version)
-    .bind(ip_address)
-    .bind(&req.mac_address)
     .execute(pool.get_ref())
     .await
     .map_err(|e| {
@@ -205,6 +240,20 @@ pub async fn create_client(
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
 
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::ClientCreated)
+                .actor(&claims)
+                .details(serde_json::json!({
+                    "client_id": client_id,
+                    "name": req.name,
+                    "platform": req.platform,
+                    "version": req.version
+                }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
     Ok(HttpResponse::Created().json(serde_json::json!({
         "success": true,
         "message": "Client created successfully",
@@ -218,15 +267,15 @@ pub async fn get_client(
 ) -> Result<HttpResponse, actix_web::Error> {
     let client_id = path.into_inner();
 
-    let client = sqlx::query(
+    let client = sqlx::query!(
         r#"
-        SELECT id, name, platform, version, status::text, ip_address, mac_address::text as mac_address, 
+        SELECT id, name, platform, version, status::text AS "status!", ip_address, mac_address, 
                last_seen, connection_count, created_at, updated_at
         FROM clients 
         WHERE id = $1
-        "#
+        "#,
+        client_id
     )
-    .bind(client_id)
     .fetch_optional(pool.get_ref())
     .await
     .map_err(|e| {
@@ -237,17 +286,17 @@ pub async fn get_client(
     match client {
         Some(row) => {
             let response = ClientResponse {
-                id: row.get::<Uuid, _>("id").to_string(),
-                name: row.get::<String, _>("name"),
-                platform: row.get::<String, _>("platform"),
-                version: row.get::<String, _>("version"),
-                ip_address: row.get::<Option<IpNetwork>, _>("ip_address").map(|ip| ip.to_string()),
-                mac_address: row.get::<Option<String>, _>("mac_address"),
-                status: row.get::<String, _>("status"),
-                last_seen: row.get::<Option<chrono::DateTime<Utc>>, _>("last_seen").map(|dt| dt.to_rfc3339()),
-                connection_count: row.get::<i32, _>("connection_count"),
-                created_at: row.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
-                updated_at: row.get::<chrono::DateTime<Utc>, _>("updated_at").to_rfc3339(),
+                id: row.id.to_string(),
+                name: row.name,
+                platform: row.platform,
+                version: row.version,
+                ip_address: row.ip_address.map(|ip| ip.to_string()),
+                mac_address: row.mac_address,
+                status: row.status,
+                last_seen: row.last_seen.map(|dt| dt.to_rfc3339()),
+                connection_count: row.connection_count,
+                created_at: row.created_at.to_rfc3339(),
+                updated_at: row.updated_at.to_rfc3339(),
             };
             Ok(HttpResponse::Ok().json(response))
         }
@@ -262,79 +311,61 @@ pub async fn get_client(
 
 pub async fn update_client(
     pool: web::Data<PgPool>,
+    audit: web::Data<AuditLog>,
     path: web::Path<Uuid>,
     req: web::Json<UpdateClientRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let client_id = path.into_inner();
 
-    // Build dynamic update query
-    let mut query_parts = Vec::new();
-    let mut bind_values: Vec<Box<dyn sqlx::Encode<'_, sqlx::Postgres> + Send + Sync>> = Vec::new();
-    let mut param_count = 1;
-
-    if let Some(name) = &req.name {
-        query_parts.push(format!("name = ${}", param_count));
-        bind_values.push(Box::new(name.clone()));
-        param_count += 1;
-    }
-
-    if let Some(platform) = &req.platform {
-        query_parts.push(format!("platform = ${}", param_count));
-        bind_values.push(Box::new(platform.clone()));
-        param_count += 1;
-    }
-
-    if let Some(version) = &req.version {
-        query_parts.push(format!("version = ${}", param_count));
-        bind_values.push(Box::new(version.clone()));
-        param_count += 1;
-    }
-
-    if let Some(ip_address) = &req.ip_address {
-        if let Ok(ip) = ip_address.parse::<IpNetwork>() {
-            query_parts.push(format!("ip_address = ${}", param_count));
-            bind_values.push(Box::new(ip));
-            param_count += 1;
-        }
-    }
-
-    if let Some(mac_address) = &req.mac_address {
-        query_parts.push(format!("mac_address = ${}", param_count));
-        bind_values.push(Box::new(mac_address.clone()));
-        param_count += 1;
-    }
-
-    if let Some(status) = &req.status {
-        query_parts.push(format!("status = ${}::client_status", param_count));
-        bind_values.push(Box::new(status.clone()));
-        param_count += 1;
-    }
-
-    if query_parts.is_empty() {
+    if req.name.is_none()
+        && req.platform.is_none()
+        && req.version.is_none()
+        && req.ip_address.is_none()
+        && req.mac_address.is_none()
+        && req.status.is_none()
+    {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
             "success": false,
             "message": "No fields to update"
         })));
     }
 
-    query_parts.push("updated_at = NOW()".to_string());
-    query_parts.push(format!("id = ${}", param_count));
-    bind_values.push(Box::new(client_id));
-
-    let query = format!(
-        "UPDATE clients SET {} WHERE id = ${}",
-        query_parts.join(", "),
-        param_count
-    );
+    let ip_address = match &req.ip_address {
+        Some(ip) => Some(ip.parse::<IpNetwork>().map_err(|_| {
+            actix_web::error::ErrorBadRequest("Invalid IP address")
+        })?),
+        None => None,
+    };
 
-    // Execute the update
-    let result = sqlx::query(&query)
-        .execute(pool.get_ref())
-        .await
-        .map_err(|e| {
-            eprintln!("Database error: {}", e);
-            actix_web::error::ErrorInternalServerError("Database error")
-        })?;
+    // Fields left as NULL keep their current value
+    let result = sqlx::query!(
+        r#"
+        UPDATE clients
+        SET name = COALESCE($1, name),
+            platform = COALESCE($2, platform),
+            version = COALESCE($3, version),
+            ip_address = COALESCE($4, ip_address),
+            mac_address = COALESCE($5, mac_address),
+            status = COALESCE($6::text::client_status, status),
+            updated_at = NOW()
+        WHERE id = $7
+        "#,
+        req.name,
+        req.platform,
+        req.version,
+        ip_address,
+        req.mac_address,
+        req.status,
+        client_id,
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
 
     if result.rows_affected() == 0 {
         Ok(HttpResponse::NotFound().json(serde_json::json!({
@@ -342,6 +373,14 @@ pub async fn update_client(
             "message": "Client not found"
         })))
     } else {
+        audit
+            .record(
+                AuditEvent::new(AuditEventType::ClientUpdated)
+                    .actor(&claims)
+                    .details(serde_json::json!({ "client_id": client_id, "changes": &*req }))
+                    .context(&ClientContext::from_request(&http_req)),
+            )
+            .await;
         Ok(HttpResponse::Ok().json(serde_json::json!({
             "success": true,
             "message": "Client updated successfully"
@@ -351,31 +390,42 @@ pub async fn update_client(
 
 pub async fn delete_client(
     pool: web::Data<PgPool>,
+    audit: web::Data<AuditLog>,
     path: web::Path<Uuid>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let client_id = path.into_inner();
 
-    let result = sqlx::query(
-        "DELETE FROM clients WHERE id = $1"
+    let name = sqlx::query_scalar!(
+        "DELETE FROM clients WHERE id = $1 RETURNING name",
+        client_id
     )
-    .bind(client_id)
-    .execute(pool.get_ref())
+    .fetch_optional(pool.get_ref())
     .await
     .map_err(|e| {
         eprintln!("Database error: {}", e);
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
 
-    if result.rows_affected() == 0 {
-        Ok(HttpResponse::NotFound().json(serde_json::json!({
-            "success": false,
-            "message": "Client not found"
-        })))
-    } else {
+    if let Some(name) = name {
+        audit
+            .record(
+                AuditEvent::new(AuditEventType::ClientDeleted)
+                    .actor(&claims)
+                    .details(serde_json::json!({ "client_id": client_id, "name": name }))
+                    .context(&ClientContext::from_request(&http_req)),
+            )
+            .await;
         Ok(HttpResponse::Ok().json(serde_json::json!({
             "success": true,
             "message": "Client deleted successfully"
         })))
+    } else {
+        Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "success": false,
+            "message": "Client not found"
+        })))
     }
 }
 
@@ -385,14 +435,14 @@ pub async fn get_client_status(
 ) -> Result<HttpResponse, actix_web::Error> {
     let client_id = path.into_inner();
 
-    let client = sqlx::query(
+    let client = sqlx::query!(
         r#"
-        SELECT id, name, status::text, last_seen, ip_address
+        SELECT id, name, status::text AS "status!", last_seen, ip_address
         FROM clients 
         WHERE id = $1
-        "#
+        "#,
+        client_id
     )
-    .bind(client_id)
     .fetch_optional(pool.get_ref())
     .await
     .map_err(|e| {
@@ -403,11 +453,11 @@ pub async fn get_client_status(
     match client {
         Some(row) => {
             let response = ClientStatusResponse {
-                id: row.get::<Uuid, _>("id").to_string(),
-                name: row.get::<String, _>("name"),
-                status: row.get::<String, _>("status"),
-                last_seen: row.get::<Option<chrono::DateTime<Utc>>, _>("last_seen").map(|dt| dt.to_rfc3339()),
-                ip_address: row.get::<Option<IpNetwork>, _>("ip_address").map(|ip| ip.to_string()),
+                id: row.id.to_string(),
+                name: row.name,
+                status: row.status,
+                last_seen: row.last_seen.map(|dt| dt.to_rfc3339()),
+                ip_address: row.ip_address.map(|ip| ip.to_string()),
             };
             Ok(HttpResponse::Ok().json(response))
         }
@@ -422,49 +472,37 @@ pub async fn get_client_status(
 
 pub async fn connect_client(
     pool: web::Data<PgPool>,
+    auth_service: web::Data<AuthService>,
+    audit: web::Data<AuditLog>,
+    session_manager: web::Data<WebSocketSessionManager>,
     req: web::Json<ClientConnectRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = req.username.clone();
     let session_token = req.session_token.clone();
 
     // Validate session
-    let session = sqlx::query(
-        r#"
-        SELECT s.id, s.user_id, s.token_hash, s.expires_at, s.created_at
-        FROM user_sessions s
-        JOIN users u ON s.user_id = u.id
-        WHERE u.username = $1 AND s.token_hash = $2 AND s.expires_at > NOW() AND s.is_revoked = false
-        "#
-    )
-    .bind(&username)
-    .bind(&session_token)
-    .fetch_optional(pool.get_ref())
-    .await
-    .map_err(|e| {
-        eprintln!("Database error: {}", e);
-        actix_web::error::ErrorInternalServerError("Database error")
-    })?;
+    let user_id = auth_service
+        .validate_session_token(&username, &session_token)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
 
-    if session.is_none() {
+    let Some(user_id) = user_id else {
         return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
             "success": false,
             "message": "Invalid session"
         })));
-    }
-
-    let session = session.unwrap();
-    let user_id: Uuid = session.get("user_id");
+    };
 
     // Check if client already exists
-    let existing_client = sqlx::query(
-        r#"
-        SELECT id, name, platform, version, status::text, ip_address, mac_address, 
-               last_seen, connection_count, total_connection_time, created_at, updated_at
-        FROM clients 
-        WHERE name = $1
-        "#
+    let existing_client = sqlx::query_scalar!(
+        "SELECT id FROM clients WHERE name = $1",
+        req.client_name
     )
-    .bind(&req.client_name)
     .fetch_optional(pool.get_ref())
     .await
     .map_err(|e| {
@@ -472,25 +510,24 @@ pub async fn connect_client(
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
 
-    let client_id = if let Some(client) = existing_client {
-        let client_id: Uuid = client.get("id");
+    let client_id = if let Some(client_id) = existing_client {
         // Update existing client
         let ip_address = req.ip_address.parse::<IpNetwork>().ok();
 
-        sqlx::query(
+        sqlx::query!(
             r#"
             UPDATE clients 
             SET status = 'online'::client_status, ip_address = $1, mac_address = $2, 
                 platform = $3, version = $4, last_seen = NOW(), 
                 connection_count = connection_count + 1, updated_at = NOW()
             WHERE id = $5
-            "#
+            "#,
+            ip_address,
+            req.mac_address,
+            req.platform,
+            req.version,
+            client_id,
         )
-        .bind(ip_address)
-        .bind(&req.mac_address)
-        .bind(&req.platform)
-        .bind(&req.version)
-        .bind(client_id)
         .execute(pool.get_ref())
         .await
         .map_err(|e| {
@@ -504,18 +541,18 @@ pub async fn connect_client(
         let client_id = Uuid::new_v4();
         let ip_address = req.ip_address.parse::<IpNetwork>().ok();
 
-        sqlx::query(
+        sqlx::query!(
             r#"
             INSERT INTO clients (id, name, platform, version, status, ip_address, mac_address, last_seen, connection_count)
             VALUES ($1, $2, $3, $4, 'online'::client_status, $5, $6, NOW(), 1)
-            "#
+            "#,
+            client_id,
+            req.client_name,
+            req.platform,
+            req.version,
+            ip_address,
+            req.mac_address,
         )
-        .bind(client_id)
-        .bind(&req.client_name)
-        .bind(&req.platform)
-        .bind(&req.version)
-        .bind(ip_address)
-        .bind(&req.mac_address)
         .execute(pool.get_ref())
         .await
         .map_err(|e| {
@@ -527,20 +564,20 @@ pub async fn connect_client(
     };
 
     // Log connection event
-    sqlx::query(
+    sqlx::query!(
         r#"
         INSERT INTO connection_logs (client_id, user_id, event_type, ip_address, details)
         VALUES ($1, $2, 'connect'::connection_event_type, $3, $4)
-        "#
+        "#,
+        client_id,
+        user_id,
+        req.ip_address.parse::<IpNetwork>().ok(),
+        serde_json::json!({
+            "platform": req.platform,
+            "version": req.version,
+            "mac_address": req.mac_address
+        }),
     )
-    .bind(client_id)
-    .bind(user_id)
-    .bind(req.ip_address.parse::<IpNetwork>().ok())
-    .bind(serde_json::json!({
-        "platform": req.platform,
-        "version": req.version,
-        "mac_address": req.mac_address
-    }))
     .execute(pool.get_ref())
     .await
     .map_err(|e| {
@@ -548,6 +585,21 @@ pub async fn connect_client(
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
 
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::ClientConnected)
+                .actor(&claims)
+                .target(user_id)
+                .details(serde_json::json!({
+                    "client_id": client_id,
+                    "name": req.client_name,
+                    "client_ip": req.ip_address
+                }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+    notify_admins(&session_manager, client_id, "online");
+
     Ok(HttpResponse::Ok().json(ClientConnectResponse {
         success: true,
         message: "Client connected successfully".to_string(),
@@ -557,48 +609,41 @@ pub async fn connect_client(
 
 pub async fn disconnect_client_session(
     pool: web::Data<PgPool>,
+    auth_service: web::Data<AuthService>,
+    audit: web::Data<AuditLog>,
+    session_manager: web::Data<WebSocketSessionManager>,
     req: web::Json<ClientDisconnectRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = req.username.clone();
     let session_token = req.session_token.clone();
 
     // Validate session
-    let session = sqlx::query(
-        r#"
-        SELECT s.id, s.user_id, s.token_hash, s.expires_at, s.created_at
-        FROM user_sessions s
-        JOIN users u ON s.user_id = u.id
-        WHERE u.username = $1 AND s.token_hash = $2 AND s.expires_at > NOW() AND s.is_revoked = false
-        "#
-    )
-    .bind(&username)
-    .bind(&session_token)
-    .fetch_optional(pool.get_ref())
-    .await
-    .map_err(|e| {
-        eprintln!("Database error: {}", e);
-        actix_web::error::ErrorInternalServerError("Database error")
-    })?;
+    let user_id = auth_service
+        .validate_session_token(&username, &session_token)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
 
-    if session.is_none() {
+    let Some(user_id) = user_id else {
         return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
             "success": false,
             "message": "Invalid session"
         })));
-    }
-
-    let session = session.unwrap();
-    let user_id: Uuid = session.get("user_id");
+    };
 
     // Update client status to offline
-    let result = sqlx::query(
+    let result = sqlx::query!(
         r#"
         UPDATE clients 
         SET status = 'offline'::client_status, updated_at = NOW()
         WHERE id = $1
-        "#
+        "#,
+        req.client_id
     )
-    .bind(req.client_id)
     .execute(pool.get_ref())
     .await
     .map_err(|e| {
@@ -614,18 +659,18 @@ pub async fn disconnect_client_session(
     }
 
     // Log disconnection event
-    sqlx::query(
+    sqlx::query!(
         r#"
         INSERT INTO connection_logs (client_id, user_id, event_type, details)
         VALUES ($1, $2, 'disconnect'::connection_event_type, $3)
-        "#
+        "#,
+        req.client_id,
+        user_id,
+        serde_json::json!({
+            "disconnected_by": "admin_panel",
+            "timestamp": chrono::Utc::now()
+        }),
     )
-    .bind(req.client_id)
-    .bind(user_id)
-    .bind(serde_json::json!({
-        "disconnected_by": "admin_panel",
-        "timestamp": chrono::Utc::now()
-    }))
     .execute(pool.get_ref())
     .await
     .map_err(|e| {
@@ -633,6 +678,17 @@ pub async fn disconnect_client_session(
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
 
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::ClientDisconnected)
+                .actor(&claims)
+                .target(user_id)
+                .details(serde_json::json!({ "client_id": req.client_id }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+    notify_admins(&session_manager, req.client_id, "offline");
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
         "message": "Client disconnected successfully"
@@ -641,18 +697,22 @@ pub async fn disconnect_client_session(
 
 pub async fn disconnect_client(
     pool: web::Data<PgPool>,
+    audit: web::Data<AuditLog>,
+    session_manager: web::Data<WebSocketSessionManager>,
     path: web::Path<Uuid>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let client_id = path.into_inner();
 
-    let result = sqlx::query(
+    let result = sqlx::query!(
         r#"
         UPDATE clients 
         SET status = 'offline'::client_status, updated_at = NOW()
         WHERE id = $1
-        "#
+        "#,
+        client_id
     )
-    .bind(client_id)
     .execute(pool.get_ref())
     .await
     .map_err(|e| {
@@ -666,6 +726,15 @@ pub async fn disconnect_client(
             "message": "Client not found"
         })))
     } else {
+        audit
+            .record(
+                AuditEvent::new(AuditEventType::ClientDisconnected)
+                    .actor(&claims)
+                    .details(serde_json::json!({ "client_id": client_id }))
+                    .context(&ClientContext::from_request(&http_req)),
+            )
+            .await;
+        notify_admins(&session_manager, client_id, "offline");
         Ok(HttpResponse::Ok().json(serde_json::json!({
             "success": true,
             "message": "Client disconnected successfully"
@@ -675,54 +744,43 @@ pub async fn disconnect_client(
 
 pub async fn submit_client_log(
     pool: web::Data<PgPool>,
+    auth_service: web::Data<AuthService>,
     req: web::Json<ClientLogRequest>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = req.username.clone();
     let session_token = req.session_token.clone();
 
     // Validate session
-    let session = sqlx::query(
-        r#"
-        SELECT s.id, s.user_id, s.token_hash, s.expires_at, s.created_at
-        FROM user_sessions s
-        JOIN users u ON s.user_id = u.id
-        WHERE u.username = $1 AND s.token_hash = $2 AND s.expires_at > NOW() AND s.is_revoked = false
-        "#
-    )
-    .bind(&username)
-    .bind(&session_token)
-    .fetch_optional(pool.get_ref())
-    .await
-    .map_err(|e| {
-        eprintln!("Database error: {}", e);
-        actix_web::error::ErrorInternalServerError("Database error")
-    })?;
+    let user_id = auth_service
+        .validate_session_token(&username, &session_token)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
 
-    if session.is_none() {
+    let Some(user_id) = user_id else {
         return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
             "success": false,
             "message": "Invalid session"
         })));
-    }
-
-    let session = session.unwrap();
-    let user_id: Uuid = session.get("user_id");
+    };
 
     // Log the client event
-    sqlx::query(
+    sqlx::query!(
         r#"
         INSERT INTO connection_logs (client_id, user_id, event_type, details)
-        VALUES ($1, $2, $3::connection_event_type, $4)
-        "#
+        VALUES ($1, $2, $3::text::connection_event_type, $4)
+        "#,
+        req.client_id,
+        user_id,
+        req.log_level,
+        serde_json::json!({
+            "message": req.message,
+            "details": req.details,
+            "timestamp": chrono::Utc::now()
+        }),
     )
-    .bind(req.client_id)
-    .bind(user_id)
-    .bind(&req.log_level)
-    .bind(serde_json::json!({
-        "message": req.message,
-        "details": req.details,
-        "timestamp": chrono::Utc::now()
-    }))
     .execute(pool.get_ref())
     .await
     .map_err(|e| {
@@ -0,0 +1,219 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::auth::attestation::AttestationService;
+use crate::auth::devices::{DeviceBinding, DeviceBindingService, ReviewOutcome};
+use crate::auth::{ClientContext, Claims};
+use crate::services::audit::{AuditEvent, AuditEventType, AuditLog};
+use crate::websocket::{channels, WebSocketEvent, WebSocketSessionManager};
+
+const REQUEST_STATUSES: &[&str] = &["pending", "approved", "rejected", "revoked", "expired"];
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DeviceRequestQuery {
+    pub status: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DeviceQuery {
+    pub user_id: Option<Uuid>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct ReviewRequest {
+    pub note: Option<String>,
+}
+
+/// Pushes a request's new state to admins watching the `device_requests` channel.
+pub fn notify_admins(session_manager: &WebSocketSessionManager, binding: &DeviceBinding) {
+    session_manager.broadcast_to_channel(
+        channels::DEVICE_REQUESTS,
+        WebSocketEvent::device_binding(
+            binding.id.to_string(),
+            binding.username.clone(),
+            binding.device_type.clone(),
+            binding.status.clone(),
+        ),
+    );
+}
+
+pub async fn list_device_requests(
+    device_bindings: web::Data<DeviceBindingService>,
+    query: web::Query<DeviceRequestQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let status = query.status.as_deref();
+    if let Some(status) = status {
+        if !REQUEST_STATUSES.contains(&status) {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("status must be one of: {}", REQUEST_STATUSES.join(", "))
+            })));
+        }
+    }
+
+    let requests = device_bindings.list(status).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "requests": requests
+    })))
+}
+
+pub async fn approve_device_request(
+    device_bindings: web::Data<DeviceBindingService>,
+    session_manager: web::Data<WebSocketSessionManager>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<Uuid>,
+    request: Option<web::Json<ReviewRequest>>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let note = request.map(|request| request.into_inner()).unwrap_or_default().note;
+    let outcome = device_bindings
+        .approve(path.into_inner(), claims.user_id(), note.as_deref())
+        .await;
+    let review = Review { event_type: AuditEventType::DeviceBound, action: "approved", note };
+    review_response(outcome, &session_manager, &audit, &claims, &http_req, review).await
+}
+
+pub async fn reject_device_request(
+    device_bindings: web::Data<DeviceBindingService>,
+    session_manager: web::Data<WebSocketSessionManager>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<Uuid>,
+    request: Option<web::Json<ReviewRequest>>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let note = request.map(|request| request.into_inner()).unwrap_or_default().note;
+    let outcome = device_bindings
+        .reject(path.into_inner(), claims.user_id(), note.as_deref())
+        .await;
+    let review = Review { event_type: AuditEventType::DeviceRejected, action: "rejected", note };
+    review_response(outcome, &session_manager, &audit, &claims, &http_req, review).await
+}
+
+/// Unbinds an approved device; logins that require a bound device stop working for it.
+pub async fn revoke_device_request(
+    device_bindings: web::Data<DeviceBindingService>,
+    session_manager: web::Data<WebSocketSessionManager>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<Uuid>,
+    request: Option<web::Json<ReviewRequest>>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let note = request.map(|request| request.into_inner()).unwrap_or_default().note;
+    let outcome = device_bindings
+        .revoke(path.into_inner(), claims.user_id(), note.as_deref())
+        .await;
+    let review = Review { event_type: AuditEventType::DeviceUnbound, action: "revoked", note };
+    review_response(outcome, &session_manager, &audit, &claims, &http_req, review).await
+}
+
+/// What an admin did to a device-binding request, for the response and audit log.
+struct Review {
+    event_type: AuditEventType,
+    action: &'static str,
+    note: Option<String>,
+}
+
+async fn review_response(
+    outcome: anyhow::Result<ReviewOutcome>,
+    session_manager: &WebSocketSessionManager,
+    audit: &AuditLog,
+    claims: &Claims,
+    http_req: &HttpRequest,
+    review: Review,
+) -> Result<HttpResponse, actix_web::Error> {
+    let action = review.action;
+    let outcome = outcome.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    match outcome {
+        ReviewOutcome::Done(binding) => {
+            println!(
+                "📱 Device {} ({}) of {} {} by {}",
+                binding.fingerprint, binding.device_type, binding.username, action, claims.username
+            );
+            audit
+                .record(
+                    AuditEvent::new(review.event_type)
+                        .actor(claims)
+                        .target(binding.user_id)
+                        .details(serde_json::json!({
+                            "request_id": binding.id,
+                            "device_type": binding.device_type,
+                            "fingerprint": binding.fingerprint,
+                            "note": review.note
+                        }))
+                        .context(&ClientContext::from_request(http_req)),
+                )
+                .await;
+            notify_admins(session_manager, &binding);
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "message": format!("Device binding request {}", action),
+                "request": binding
+            })))
+        }
+        ReviewOutcome::NotFound => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Device binding request not found"
+        }))),
+        ReviewOutcome::Conflict(reason) => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("Cannot mark request {}: {}", action, reason)
+        }))),
+    }
+}
+
+/// Enrolled desktop devices and the state of their keys.
+pub async fn list_devices(
+    attestation: web::Data<AttestationService>,
+    query: web::Query<DeviceQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let devices = attestation.list_devices(query.user_id).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "devices": devices
+    })))
+}
+
+/// Revokes a desktop device's key and terminates every session bound to it.
+pub async fn revoke_device_key(
+    attestation: web::Data<AttestationService>,
+    path: web::Path<Uuid>,
+    request: Option<web::Json<ReviewRequest>>,
+    claims: web::ReqData<Claims>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let device_id = path.into_inner();
+    let note = request.map(|request| request.into_inner()).unwrap_or_default().note;
+
+    let revoked = attestation
+        .revoke_key(device_id, claims.user_id(), note.as_deref())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    if revoked {
+        println!("🚫 Revoked key of device {} by {}", device_id, claims.username);
+        Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "Device key revoked"
+        })))
+    } else {
+        Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Device not found or key already revoked"
+        })))
+    }
+}
@@ -1,10 +1,11 @@
 use actix_web::{web, HttpResponse, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::PgPool;
 use chrono::Utc;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct HealthResponse {
     pub status: String,
     pub timestamp: chrono::DateTime<Utc>,
@@ -13,14 +14,14 @@ pub struct HealthResponse {
     pub services: ServiceHealth,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ServiceHealth {
     pub database: ServiceStatus,
     pub redis: ServiceStatus,
     pub overall: ServiceStatus,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ServiceStatus {
     pub status: String,
     pub response_time: Option<u64>,
@@ -42,7 +43,7 @@ pub async fn health_check(pool: web::Data<PgPool>) -> Result<HttpResponse> {
 
     // Check database health
     let db_start = std::time::Instant::now();
-    let db_result = sqlx::query!("SELECT 1 as test")
+    let db_result = sqlx::query("SELECT 1 as test")
         .fetch_one(pool.get_ref())
         .await;
     let db_response_time = db_start.elapsed().as_millis() as u64;
@@ -115,7 +116,7 @@ fn get_uptime() -> String {
     "0h 0m 0s".to_string()
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SystemInfo {
     pub version: String,
     pub environment: String,
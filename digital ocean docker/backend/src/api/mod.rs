@@ -5,10 +5,19 @@ pub mod users;
 pub mod sessions;
 pub mod health;
 pub mod agent;
+pub mod admin;
+pub mod pki;
+pub mod policies;
+pub mod devices;
+pub mod audit;
+pub mod alerts;
+pub mod openapi;
+pub mod scim;
 
 use actix_web::web;
 
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/openapi.json", web::get().to(openapi::openapi_json));
     cfg.service(
         web::scope("/v1")
             .configure(auth::configure_routes)
@@ -18,5 +27,7 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig) {
             .configure(sessions::configure_routes)
             .configure(health::configure_routes)
             .configure(agent::configure_routes)
+            .configure(admin::configure_routes)
+            .configure(pki::configure_routes)
     );
 }
@@ -1,9 +1,12 @@
 use actix_web::{web, HttpResponse, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use chrono::Utc;
+use crate::auth::{policy::PolicyAction, AdminWebAuthnMiddleware, AuthMiddleware, PolicyMiddleware};
+use crate::database::listing::{ListQuery, ListSpec, MatchFilter, SortField};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, JsonSchema)]
 pub struct SecurityAlert {
     pub id: uuid::Uuid,
     pub severity: String,
@@ -13,7 +16,7 @@ pub struct SecurityAlert {
     pub is_resolved: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, JsonSchema)]
 pub struct SystemLog {
     pub id: uuid::Uuid,
     pub level: String,
@@ -22,7 +25,7 @@ pub struct SystemLog {
     pub created_at: chrono::DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct HealthCheckResponse {
     pub status: String,
     pub timestamp: chrono::DateTime<Utc>,
@@ -30,9 +33,14 @@ pub struct HealthCheckResponse {
     pub version: String,
 }
 
+/// Alerts and system logs for the admin console; every request must be allowed
+/// by the `admin` policy action, after WebAuthn verification for admin roles.
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/monitoring")
+            .wrap(AdminWebAuthnMiddleware::new())
+            .wrap(PolicyMiddleware::new(PolicyAction::Admin))
+            .wrap(AuthMiddleware::new())
             .route("/health", web::get().to(health_check))
             .route("/alerts", web::get().to(get_security_alerts))
             .route("/logs", web::get().to(get_system_logs))
@@ -50,106 +58,88 @@ pub async fn health_check() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// Security alerts matching `status` (`open` or `resolved`), `q` (title) and
+/// `from`/`to`, a page at a time.
+static ALERT_LIST: ListSpec = ListSpec {
+    select: "id, severity::text AS severity, title, description, created_at, is_resolved",
+    from: "security_alerts",
+    id: "id",
+    sorts: &[
+        SortField { name: "created_at", expr: "created_at", sql_type: "timestamptz" },
+        SortField { name: "severity", expr: "security_alerts.severity", sql_type: "alert_severity" },
+    ],
+    default_sort: "-created_at",
+    date: Some("created_at"),
+    search: &["title"],
+    status: Some(MatchFilter {
+        expr: "CASE WHEN is_resolved THEN 'resolved' ELSE 'open' END",
+        values: &["open", "resolved"],
+    }),
+    role: None,
+};
+
+/// Admin actions from `audit_logs` matching `q` (action or resource type) and
+/// `from`/`to`, a page at a time.
+static LOG_LIST: ListSpec = ListSpec {
+    select: "id, action AS level, COALESCE(details::text, 'No details') AS message, \
+             COALESCE(resource_type, 'system') AS source, created_at",
+    from: "audit_logs",
+    id: "id",
+    sorts: &[SortField { name: "created_at", expr: "created_at", sql_type: "timestamptz" }],
+    default_sort: "-created_at",
+    date: Some("created_at"),
+    search: &["action", "resource_type"],
+    status: None,
+    role: None,
+};
+
 pub async fn get_security_alerts(
     pool: web::Data<PgPool>,
-    query: web::Query<std::collections::HashMap<String, String>>,
+    query: web::Query<ListQuery>,
 ) -> Result<HttpResponse> {
-    let page = query.get("page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(1);
-    let per_page = query.get("per_page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(10);
-    let offset = (page - 1) * per_page;
-
-    let alerts_result = sqlx::query!(
-        r#"
-        SELECT id, severity::text as severity, title, description, created_at, is_resolved 
-        FROM security_alerts 
-        ORDER BY created_at DESC 
-        LIMIT $1 OFFSET $2
-        "#,
-        per_page,
-        offset
-    )
-    .fetch_all(pool.get_ref())
-    .await;
-
-    let total_result = sqlx::query!("SELECT COUNT(*) as count FROM security_alerts")
-        .fetch_one(pool.get_ref())
-        .await;
-
-    match (alerts_result, total_result) {
-        (Ok(alerts), Ok(total)) => {
-            let alert_responses: Vec<SecurityAlert> = alerts
-                .into_iter()
-                .map(|row| SecurityAlert {
-                    id: row.id,
-                    severity: row.severity.unwrap_or_else(|| "low".to_string()),
-                    title: row.title,
-                    description: row.description,
-                    created_at: row.created_at,
-                    is_resolved: row.is_resolved,
-                })
-                .collect();
+    let list = match query.resolve(&ALERT_LIST) {
+        Ok(list) => list,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))),
+    };
 
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "alerts": alert_responses,
-                "total": total.count.unwrap_or(0),
-                "page": page,
-                "per_page": per_page
+    match list.fetch::<SecurityAlert>(pool.get_ref()).await {
+        Ok(page) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "alerts": page.items,
+            "total": page.total,
+            "limit": page.limit,
+            "next_cursor": page.next_cursor
+        }))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch security alerts"
             })))
         }
-        _ => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to fetch security alerts"
-        }))),
     }
 }
 
 pub async fn get_system_logs(
     pool: web::Data<PgPool>,
-    query: web::Query<std::collections::HashMap<String, String>>,
+    query: web::Query<ListQuery>,
 ) -> Result<HttpResponse> {
-    let page = query.get("page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(1);
-    let per_page = query.get("per_page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(10);
-    let offset = (page - 1) * per_page;
+    let list = match query.resolve(&LOG_LIST) {
+        Ok(list) => list,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))),
+    };
 
     // Use audit_logs table instead of system_logs since that's what exists in the schema
-    let logs_result = sqlx::query!(
-        r#"
-        SELECT id, action as level, details::text as message, resource_type as source, created_at 
-        FROM audit_logs 
-        ORDER BY created_at DESC 
-        LIMIT $1 OFFSET $2
-        "#,
-        per_page,
-        offset
-    )
-    .fetch_all(pool.get_ref())
-    .await;
-
-    let total_result = sqlx::query!("SELECT COUNT(*) as count FROM audit_logs")
-        .fetch_one(pool.get_ref())
-        .await;
-
-    match (logs_result, total_result) {
-        (Ok(logs), Ok(total)) => {
-            let log_responses: Vec<SystemLog> = logs
-                .into_iter()
-                .map(|row| SystemLog {
-                    id: row.id,
-                    level: row.level,
-                    message: row.message.unwrap_or_else(|| "No details".to_string()),
-                    source: row.source.unwrap_or_else(|| "system".to_string()),
-                    created_at: row.created_at,
-                })
-                .collect();
-
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "logs": log_responses,
-                "total": total.count.unwrap_or(0),
-                "page": page,
-                "per_page": per_page
+    match list.fetch::<SystemLog>(pool.get_ref()).await {
+        Ok(page) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "logs": page.items,
+            "total": page.total,
+            "limit": page.limit,
+            "next_cursor": page.next_cursor
+        }))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch system logs"
             })))
         }
-        _ => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to fetch system logs"
-        }))),
     }
 }
@@ -0,0 +1,468 @@
+//! OpenAPI 3 description of the `/api/v1` routes, generated from the
+//! handlers' request/response types. `openapi.json` at the crate root is the
+//! committed copy; the tests fail when it no longer matches what is generated
+//! here (`UPDATE_OPENAPI=1 cargo test openapi` rewrites it).
+
+use actix_web::HttpResponse;
+use once_cell::sync::Lazy;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use crate::api::{agent, alerts, audit, auth, clients, devices, health, monitoring, pki, policies, sessions, users};
+use crate::auth::attestation::KeyProof;
+use crate::auth::jwt::TokenResponse;
+use crate::auth::policy::PolicyChange;
+use crate::database::listing::ListQuery;
+
+/// Body of every `{"error": ...}` response.
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct ErrorResponse {
+    error: String,
+}
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+fn reference<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    gen.subschema_for::<T>()
+}
+
+fn inline<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    T::json_schema(gen)
+}
+
+/// One documented route, relative to `/api/v1`.
+pub struct Operation {
+    pub method: &'static str,
+    pub path: &'static str,
+    /// Reachable without a bearer token
+    pub public: bool,
+    tag: &'static str,
+    summary: &'static str,
+    uuid_path: bool,
+    query: Vec<SchemaFn>,
+    body: Option<(SchemaFn, bool)>,
+    response: Option<SchemaFn>,
+    content_type: &'static str,
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &'static str, tag: &'static str, summary: &'static str) -> Self {
+        Self {
+            method,
+            path,
+            public: false,
+            tag,
+            summary,
+            uuid_path: false,
+            query: Vec::new(),
+            body: None,
+            response: None,
+            content_type: "application/json",
+        }
+    }
+
+    fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    /// Path parameters are UUIDs rather than free-form strings.
+    fn uuid_path(mut self) -> Self {
+        self.uuid_path = true;
+        self
+    }
+
+    fn query<T: JsonSchema>(mut self) -> Self {
+        self.query.push(inline::<T>);
+        self
+    }
+
+    fn body<T: JsonSchema>(mut self) -> Self {
+        self.body = Some((reference::<T>, true));
+        self
+    }
+
+    fn optional_body<T: JsonSchema>(mut self) -> Self {
+        self.body = Some((reference::<T>, false));
+        self
+    }
+
+    fn returns<T: JsonSchema>(mut self) -> Self {
+        self.response = Some(reference::<T>);
+        self
+    }
+
+    /// The success response is a plain-text document rather than JSON.
+    fn text(mut self, content_type: &'static str) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    fn path_params(&self) -> impl Iterator<Item = &'static str> {
+        self.path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+    }
+
+    fn to_json(&self, gen: &mut SchemaGenerator) -> Value {
+        let mut parameters: Vec<Value> = self
+            .path_params()
+            .map(|name| {
+                let schema = if self.uuid_path {
+                    json!({ "type": "string", "format": "uuid" })
+                } else {
+                    json!({ "type": "string" })
+                };
+                json!({ "name": name, "in": "path", "required": true, "schema": schema })
+            })
+            .collect();
+        for query in &self.query {
+            let object = match query(gen) {
+                Schema::Object(object) => object,
+                Schema::Bool(_) => SchemaObject::default(),
+            };
+            let validation = object.object.unwrap_or_default();
+            for (name, schema) in validation.properties {
+                parameters.push(json!({
+                    "name": name,
+                    "in": "query",
+                    "required": validation.required.contains(&name),
+                    "schema": schema
+                }));
+            }
+        }
+
+        let success = match self.response {
+            Some(schema) => json!({ "schema": schema(gen) }),
+            None if self.content_type == "application/json" => json!({ "schema": { "type": "object" } }),
+            None => json!({ "schema": { "type": "string" } }),
+        };
+        let error = json!({ "application/json": { "schema": { "$ref": "#/components/schemas/ErrorResponse" } } });
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_string(),
+            json!({ "description": "Success", "content": { self.content_type: success } }),
+        );
+        responses.insert("400".to_string(), json!({ "description": "Invalid request", "content": error }));
+        if !self.public {
+            responses.insert("401".to_string(), json!({ "description": "Missing or invalid bearer token", "content": error }));
+        }
+
+        let mut operation = Map::new();
+        operation.insert("tags".to_string(), json!([self.tag]));
+        operation.insert("summary".to_string(), json!(self.summary));
+        operation.insert(
+            "operationId".to_string(),
+            json!(format!("{}{}", self.method, self.path.replace(['/', '{', '}', '.', '-'], "_"))),
+        );
+        if !parameters.is_empty() {
+            operation.insert("parameters".to_string(), Value::Array(parameters));
+        }
+        if let Some((schema, required)) = self.body {
+            operation.insert(
+                "requestBody".to_string(),
+                json!({
+                    "required": required,
+                    "content": { "application/json": { "schema": schema(gen) } }
+                }),
+            );
+        }
+        operation.insert("responses".to_string(), Value::Object(responses));
+        if self.public {
+            operation.insert("security".to_string(), json!([]));
+        }
+        Value::Object(operation)
+    }
+}
+
+fn get(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    Operation::new("get", path, tag, summary)
+}
+
+fn post(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    Operation::new("post", path, tag, summary)
+}
+
+fn put(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    Operation::new("put", path, tag, summary)
+}
+
+fn delete(path: &'static str, tag: &'static str, summary: &'static str) -> Operation {
+    Operation::new("delete", path, tag, summary)
+}
+
+/// Every route mounted by `api::configure_routes`. Keep in step with the
+/// modules' `configure_routes`; the tests check each entry is served.
+pub fn operations() -> Vec<Operation> {
+    vec![
+        // Authentication
+        post("/auth/login", "auth", "Check the password and open a login challenge for /auth/validate-2fa").public()
+            .body::<auth::LoginRequest>().returns::<auth::MfaChallengeResponse>(),
+        post("/auth/refresh", "auth", "Rotate a refresh token").public()
+            .body::<auth::RefreshTokenRequest>().returns::<TokenResponse>(),
+        post("/auth/password-reset/request", "auth", "Mail a password reset link").public()
+            .body::<auth::PasswordResetRequest>(),
+        post("/auth/password-reset/confirm", "auth", "Set a new password with a reset link").public()
+            .body::<auth::PasswordResetConfirmRequest>(),
+        post("/auth/email/verification", "auth", "Mail the caller an email verification link"),
+        post("/auth/email/verify", "auth", "Confirm an email address").public()
+            .body::<auth::EmailVerifyRequest>(),
+        post("/auth/logout", "auth", "Revoke the current session"),
+        post("/auth/validate-2fa", "auth", "Complete a login challenge with the second factor").public()
+            .body::<auth::ValidateCodeRequest>().returns::<auth::TwoFactorResponse>(),
+        get("/auth/connection-configs", "auth", "Mint tunnel credentials for the desktop client's session")
+            .returns::<auth::ClientBootstrapResponse>(),
+        post("/auth/register-device", "auth", "Register one of the caller's mobile devices")
+            .body::<auth::DeviceRegistrationRequest>().returns::<auth::DeviceRegistrationResponse>(),
+        post("/auth/verification-code", "auth", "Push a verification code to one of the caller's mobile devices")
+            .body::<auth::DeviceVerificationRequest>().returns::<auth::DeviceVerificationResponse>(),
+        post("/auth/confirm-verification", "auth", "Approve (with the pushed code) or deny a verification request")
+            .body::<auth::ConfirmVerificationRequest>().returns::<auth::ConfirmVerificationResponse>(),
+        get("/auth/devices", "auth", "Devices registered to the caller")
+            .returns::<auth::DeviceListResponse>(),
+        post("/auth/challenge-initiate", "auth", "Check the password and start a login challenge").public()
+            .body::<auth::ChallengeInitiateRequest>().returns::<auth::ChallengeInitiateResponse>(),
+        get("/auth/challenge-code/{session_id}", "auth", "Code for a pending login challenge").public(),
+        post("/auth/challenge-verify", "auth", "Complete a login challenge").public()
+            .body::<auth::ChallengeVerifyRequest>().returns::<auth::ChallengeVerifyResponse>(),
+        post("/auth/device-bind", "auth", "Ask for a device to be bound to an account").public()
+            .body::<auth::DeviceBindRequest>(),
+        post("/auth/device/nonce", "auth", "Issue a nonce for a device to sign").public()
+            .body::<auth::DeviceNonceRequest>(),
+        post("/auth/device/rotate-key", "auth", "Replace the caller's device key")
+            .body::<KeyProof>(),
+        post("/auth/client-bootstrap", "auth", "Mint tunnel credentials for the desktop client")
+            .body::<auth::ClientBootstrapRequest>().returns::<auth::ClientBootstrapResponse>(),
+        post("/auth/login-with-checks", "auth", "Log in after client-side system checks").public()
+            .body::<auth::SystemCheckRequest>().returns::<auth::LoginWithChecksResponse>(),
+        post("/auth/request-mobile-otp", "auth", "Start a mobile second-factor challenge").public()
+            .body::<auth::MobileOtpRequest>().returns::<auth::OtpChallengeResponse>(),
+        post("/auth/validate-mobile-otp", "auth", "Complete a mobile second-factor challenge").public()
+            .body::<auth::ValidateOtpRequest>(),
+        post("/auth/push-approval/answer", "auth", "Answer a push approval prompt from the mobile device").public()
+            .body::<auth::PushApprovalAnswerRequest>(),
+        post("/auth/push-approval/status", "auth", "Poll a push approval and complete the login once approved").public()
+            .body::<auth::PushApprovalStatusRequest>(),
+        post("/auth/totp/enroll", "totp", "Start authenticator app enrolment"),
+        post("/auth/totp/confirm", "totp", "Confirm authenticator app enrolment")
+            .body::<auth::TotpCodeRequest>(),
+        post("/auth/totp/recovery-codes", "totp", "Replace the recovery codes")
+            .body::<auth::TotpCodeRequest>(),
+        post("/auth/totp/disable", "totp", "Turn the authenticator app off")
+            .body::<auth::TotpCodeRequest>(),
+        post("/auth/webauthn/register/begin", "webauthn", "Start registering a security key"),
+        post("/auth/webauthn/register/finish", "webauthn", "Finish registering a security key")
+            .body::<auth::WebAuthnRegisterFinishRequest>(),
+        post("/auth/webauthn/authenticate/begin", "webauthn", "Start a security key assertion"),
+        post("/auth/webauthn/authenticate/finish", "webauthn", "Finish a security key assertion")
+            .body::<auth::WebAuthnAuthenticateFinishRequest>(),
+        get("/auth/webauthn/credentials", "webauthn", "The caller's security keys"),
+        delete("/auth/webauthn/credentials/{id}", "webauthn", "Remove one of the caller's security keys").uuid_path(),
+        // Clients
+        get("/clients", "clients", "List clients")
+            .query::<ListQuery>().returns::<clients::ClientListResponse>(),
+        post("/clients", "clients", "Create a client").body::<clients::CreateClientRequest>(),
+        get("/clients/{id}", "clients", "Get a client").uuid_path().returns::<clients::ClientResponse>(),
+        put("/clients/{id}", "clients", "Update a client").uuid_path().body::<clients::UpdateClientRequest>(),
+        delete("/clients/{id}", "clients", "Delete a client").uuid_path(),
+        get("/clients/{id}/status", "clients", "Connection status of a client").uuid_path()
+            .returns::<clients::ClientStatusResponse>(),
+        post("/clients/{id}/connect", "clients", "Record a client connecting").uuid_path()
+            .body::<clients::ClientConnectRequest>().returns::<clients::ClientConnectResponse>(),
+        post("/clients/{id}/disconnect", "clients", "Mark a client disconnected").uuid_path(),
+        post("/clients/{id}/disconnect-session", "clients", "Record a client session ending").uuid_path()
+            .body::<clients::ClientDisconnectRequest>(),
+        post("/clients/{id}/logs", "clients", "Submit a client log line").uuid_path()
+            .body::<clients::ClientLogRequest>(),
+        // Monitoring
+        get("/monitoring/health", "monitoring", "Service health").returns::<monitoring::HealthCheckResponse>(),
+        get("/monitoring/alerts", "monitoring", "List security alerts").query::<ListQuery>(),
+        get("/monitoring/logs", "monitoring", "List system logs").query::<ListQuery>(),
+        // Users
+        get("/users", "users", "List users").query::<ListQuery>(),
+        post("/users", "users", "Create a user").body::<users::CreateUserRequest>(),
+        get("/users/{id}", "users", "Get a user"),
+        put("/users/{id}", "users", "Update a user").body::<users::UpdateUserRequest>(),
+        delete("/users/{id}", "users", "Delete a user"),
+        post("/users/{id}/unlock", "users", "Clear a lockout"),
+        post("/users/{id}/reset-password", "users", "Reset a user's password"),
+        // Sessions
+        get("/sessions", "sessions", "List sessions")
+            .query::<ListQuery>().returns::<sessions::SessionListResponse>(),
+        get("/sessions/{id}", "sessions", "Get a session").uuid_path().returns::<sessions::SessionResponse>(),
+        post("/sessions/{id}/revoke", "sessions", "Revoke a session").uuid_path(),
+        get("/sessions/user/{user_id}", "sessions", "List a user's sessions").uuid_path()
+            .query::<ListQuery>().returns::<sessions::SessionListResponse>(),
+        post("/sessions/user/{user_id}/revoke-all", "sessions", "Revoke all of a user's sessions").uuid_path(),
+        post("/sessions/cleanup", "sessions", "Expire lapsed and idle sessions now"),
+        // Health
+        get("/health", "health", "Liveness").public(),
+        get("/health/full", "health", "Health of each dependency").public().returns::<health::HealthResponse>(),
+        get("/health/system-info", "health", "Version and environment").public().returns::<health::SystemInfo>(),
+        // Backend agent
+        post("/agent/user/create", "agent", "Queue creating a user on the gateway")
+            .body::<agent::CreateUserRequest>().returns::<agent::AgentCommandResponse>(),
+        post("/agent/container/spawn", "agent", "Queue starting a browser container")
+            .body::<agent::SpawnContainerRequest>().returns::<agent::AgentCommandResponse>(),
+        post("/agent/session/terminate", "agent", "Queue ending a session on the gateway")
+            .body::<agent::TerminateSessionRequest>().returns::<agent::AgentCommandResponse>(),
+        // Internal CA
+        get("/pki/ca.pem", "pki", "Root CA certificate").public().text("application/x-pem-file"),
+        get("/pki/chain.pem", "pki", "Issuing chain").public().text("application/x-pem-file"),
+        get("/pki/crl.pem", "pki", "Certificate revocation list").public().text("application/x-pem-file"),
+        get("/pki/certificates/{serial}/status", "pki", "Revocation status of a certificate").public(),
+        post("/pki/client/csr", "pki", "Sign a client CSR").body::<pki::CsrRequest>(),
+        // Administration
+        get("/admin/users", "admin", "List users").query::<ListQuery>(),
+        get("/admin/sessions", "admin", "List sessions")
+            .query::<ListQuery>().returns::<sessions::SessionListResponse>(),
+        get("/admin/users/{id}/webauthn-credentials", "admin", "A user's security keys").uuid_path(),
+        delete("/admin/users/{id}/webauthn-credentials/{credential_id}", "admin", "Remove a user's security key")
+            .uuid_path(),
+        get("/admin/pki/intermediates", "admin", "Intermediate CAs"),
+        post("/admin/pki/intermediates/rotate", "admin", "Rotate the issuing intermediate")
+            .body::<pki::RotateIntermediateRequest>(),
+        post("/admin/pki/agents/certificates", "admin", "Sign an agent CSR").body::<pki::AgentCsrRequest>(),
+        post("/admin/pki/certificates/{serial}/revoke", "admin", "Revoke a certificate")
+            .body::<pki::RevokeCertificateRequest>(),
+        get("/admin/policies", "admin", "List access policies"),
+        post("/admin/policies", "admin", "Create an access policy").body::<PolicyChange>(),
+        post("/admin/policies/evaluate", "admin", "Dry-run a policy decision").body::<policies::EvaluateRequest>(),
+        put("/admin/policies/{id}", "admin", "Update an access policy").uuid_path().body::<PolicyChange>(),
+        get("/admin/policies/{id}/versions", "admin", "Version history of a policy").uuid_path(),
+        get("/admin/device/requests", "admin", "Device-binding requests").query::<devices::DeviceRequestQuery>(),
+        post("/admin/device/requests/{id}/approve", "admin", "Approve a device-binding request").uuid_path()
+            .optional_body::<devices::ReviewRequest>(),
+        post("/admin/device/requests/{id}/reject", "admin", "Reject a device-binding request").uuid_path()
+            .optional_body::<devices::ReviewRequest>(),
+        post("/admin/device/requests/{id}/revoke", "admin", "Revoke an approved device").uuid_path()
+            .optional_body::<devices::ReviewRequest>(),
+        get("/admin/devices", "admin", "Enrolled device keys").query::<devices::DeviceQuery>(),
+        post("/admin/devices/{id}/revoke-key", "admin", "Revoke a device key").uuid_path()
+            .optional_body::<devices::ReviewRequest>(),
+        get("/admin/audit/events", "admin", "Audit events").query::<audit::AuditQuery>(),
+        get("/admin/audit/export", "admin", "Export audit events").query::<audit::AuditQuery>(),
+        get("/admin/audit/verify", "admin", "Verify the audit hash chain"),
+        get("/admin/audit/checkpoints", "admin", "Signed audit checkpoints"),
+        post("/admin/audit/checkpoints", "admin", "Sign a checkpoint now"),
+        get("/admin/audit/siem", "admin", "SIEM export status"),
+        get("/admin/alerts", "admin", "Security alerts")
+            .query::<ListQuery>().query::<alerts::AlertQuery>(),
+        get("/admin/alerts/rules", "admin", "Alert rules in force"),
+        post("/admin/alerts/rules/reload", "admin", "Reload the alert rule file"),
+        post("/admin/alerts/{id}/acknowledge", "admin", "Acknowledge an alert").uuid_path(),
+        post("/admin/alerts/{id}/resolve", "admin", "Resolve an alert").uuid_path()
+            .optional_body::<alerts::ResolveRequest>(),
+        get("/admin/agent-commands", "admin", "Gateway commands sent through the backend agent")
+            .query::<ListQuery>(),
+        get("/admin/agent-commands/{id}", "admin", "Get a gateway command").uuid_path()
+            .returns::<agent::AgentCommandResponse>(),
+        post("/admin/agent-commands/{id}/retry", "admin", "Retry a failed gateway command").uuid_path()
+            .returns::<agent::AgentCommandResponse>(),
+    ]
+}
+
+/// Builds the document from `operations()`.
+pub fn generate() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    gen.subschema_for::<ErrorResponse>();
+
+    let mut paths = Map::new();
+    for operation in operations() {
+        let item = paths
+            .entry(operation.path.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(item) = item {
+            item.insert(operation.method.to_string(), operation.to_json(&mut gen));
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "ViWorkS Admin Backend",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "servers": [{ "url": "/api/v1" }],
+        "security": [{ "bearerAuth": [] }],
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" }
+            },
+            "schemas": gen.take_definitions()
+        }
+    })
+}
+
+static DOCUMENT: Lazy<Value> = Lazy::new(generate);
+
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(&*DOCUMENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, TestRequest};
+    use actix_web::{http::StatusCode, web, App};
+
+    const COMMITTED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    #[test]
+    fn committed_spec_matches_handlers() {
+        let generated = generate();
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            let text = serde_json::to_string_pretty(&generated).unwrap() + "\n";
+            std::fs::write(COMMITTED, text).unwrap();
+            return;
+        }
+        let committed: Value = serde_json::from_str(&std::fs::read_to_string(COMMITTED).unwrap()).unwrap();
+        assert!(
+            committed == generated,
+            "openapi.json is out of date with the handler types; regenerate it with `UPDATE_OPENAPI=1 cargo test openapi`"
+        );
+    }
+
+    #[test]
+    fn operations_are_unique() {
+        let mut seen = std::collections::HashSet::new();
+        for operation in operations() {
+            assert!(seen.insert((operation.method, operation.path)), "{} {} listed twice", operation.method, operation.path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn every_operation_is_served_with_the_documented_auth() {
+        let app = init_service(App::new().service(web::scope("/api").configure(crate::api::configure_routes))).await;
+        for operation in operations() {
+            let mut path = operation.path.to_string();
+            for name in operation.path_params() {
+                path = path.replace(&format!("{{{}}}", name), &uuid::Uuid::nil().to_string());
+            }
+            let request = TestRequest::default()
+                .method(operation.method.to_uppercase().parse().unwrap())
+                .uri(&format!("/api/v1{}", path))
+                .to_request();
+            let status = call_service(&app, request).await.status();
+            let label = format!("{} {}", operation.method, operation.path);
+            if operation.public {
+                assert!(
+                    status != StatusCode::NOT_FOUND && status != StatusCode::METHOD_NOT_ALLOWED,
+                    "{} is not routed ({})",
+                    label,
+                    status
+                );
+                assert_ne!(status, StatusCode::UNAUTHORIZED, "{} is documented as public", label);
+            } else {
+                assert_eq!(status, StatusCode::UNAUTHORIZED, "{} is documented as requiring a token", label);
+            }
+        }
+
+        let request = TestRequest::get().uri("/api/openapi.json").to_request();
+        let served: Value = call_and_read_body_json(&app, request).await;
+        assert_eq!(served, generate());
+    }
+}
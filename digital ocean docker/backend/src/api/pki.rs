@@ -0,0 +1,290 @@
+use actix_web::{web, HttpResponse};
+use chrono::{Duration, Utc};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::auth::ca::{CertificateAuthority, CertificateKind, CsrOutcome, ADMIN_REVOCATION_REASONS};
+use crate::auth::{AuthMiddleware, Claims};
+use crate::config::AppConfig;
+
+const PEM_CONTENT_TYPE: &str = "application/x-pem-file";
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct CsrRequest {
+    pub csr_pem: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AgentCsrRequest {
+    pub agent_id: String,
+    pub csr_pem: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct RotateIntermediateRequest {
+    /// Revoke the previous intermediate (suspected key compromise) instead of retiring it
+    #[serde(default)]
+    pub revoke_previous: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct RevokeCertificateRequest {
+    pub reason: String,
+}
+
+/// Root certificate: the trust anchor for clients, agents and the gateway.
+pub async fn get_ca_certificate(ca: web::Data<CertificateAuthority>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(PEM_CONTENT_TYPE)
+        .body(ca.ca_pem().to_string())
+}
+
+/// Active intermediate followed by the root.
+pub async fn get_ca_chain(ca: web::Data<CertificateAuthority>) -> Result<HttpResponse, actix_web::Error> {
+    let chain = ca.chain_pem().await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type(PEM_CONTENT_TYPE).body(chain))
+}
+
+/// CRLs of the root and of every unexpired intermediate, concatenated.
+pub async fn get_crl(ca: web::Data<CertificateAuthority>) -> Result<HttpResponse, actix_web::Error> {
+    let crl = ca.crl_pem().await.map_err(|e| {
+        eprintln!("Failed to build CRL: {}", e);
+        actix_web::error::ErrorInternalServerError("Failed to build CRL")
+    })?;
+
+    Ok(HttpResponse::Ok().content_type(PEM_CONTENT_TYPE).body(crl))
+}
+
+/// OCSP-style status lookup by hex serial.
+pub async fn get_certificate_status(
+    ca: web::Data<CertificateAuthority>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let serial = path.into_inner();
+
+    let status = ca.status(&serial).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    match status {
+        Some(status) => Ok(HttpResponse::Ok().json(status)),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "serial": serial,
+            "status": "unknown"
+        }))),
+    }
+}
+
+/// Signs a client CSR for the caller's session. The certificate is bound to the
+/// session and lives no longer than it (or CLIENT_CREDENTIAL_TTL).
+pub async fn sign_client_csr(
+    pool: web::Data<PgPool>,
+    config: web::Data<AppConfig>,
+    ca: web::Data<CertificateAuthority>,
+    request: web::Json<CsrRequest>,
+    claims: web::ReqData<Claims>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (Some(user_id), Some(session_id)) = (claims.user_id(), claims.session_id()) else {
+        return Err(actix_web::error::ErrorUnauthorized("Invalid token subject"));
+    };
+
+    let session_expires_at = sqlx::query_scalar!(
+        "SELECT expires_at FROM sessions WHERE id = $1 AND user_id = $2 AND status = 'active' AND expires_at > NOW()",
+        session_id,
+        user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    let Some(session_expires_at) = session_expires_at else {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Session is no longer active"
+        })));
+    };
+
+    let not_after = (Utc::now() + Duration::seconds(config.client_credential_ttl as i64)).min(session_expires_at);
+    let outcome = ca
+        .sign_csr(
+            &request.csr_pem,
+            CertificateKind::Client,
+            Some(user_id),
+            Some(session_id),
+            &claims.username,
+            not_after,
+        )
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to sign client CSR: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to sign certificate")
+        })?;
+
+    match outcome {
+        CsrOutcome::Signed(certificate) => {
+            println!("📜 Signed client certificate {} for {}", certificate.serial, claims.username);
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "certificate": certificate,
+                "ca_pem": ca.ca_pem()
+            })))
+        }
+        CsrOutcome::Rejected(reason) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": reason
+        }))),
+    }
+}
+
+/// Signs an agent CSR (admin only). The agent id becomes the CN and DNS SAN.
+pub async fn sign_agent_csr(
+    config: web::Data<AppConfig>,
+    ca: web::Data<CertificateAuthority>,
+    request: web::Json<AgentCsrRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !is_valid_agent_id(&request.agent_id) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "agent_id must be a hostname-style identifier (letters, digits, '-' and '.')"
+        })));
+    }
+
+    let not_after = Utc::now() + Duration::days(config.agent_certificate_ttl_days as i64);
+    let outcome = ca
+        .sign_csr(&request.csr_pem, CertificateKind::Agent, None, None, &request.agent_id, not_after)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to sign agent CSR: {}", e);
+            actix_web::error::ErrorInternalServerError("Failed to sign certificate")
+        })?;
+
+    match outcome {
+        CsrOutcome::Signed(certificate) => {
+            println!("📜 Signed agent certificate {} for {}", certificate.serial, request.agent_id);
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "certificate": certificate,
+                "ca_pem": ca.ca_pem()
+            })))
+        }
+        CsrOutcome::Rejected(reason) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": reason
+        }))),
+    }
+}
+
+pub async fn revoke_certificate(
+    ca: web::Data<CertificateAuthority>,
+    path: web::Path<String>,
+    request: web::Json<RevokeCertificateRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !ADMIN_REVOCATION_REASONS.contains(&request.reason.as_str()) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("reason must be one of: {}", ADMIN_REVOCATION_REASONS.join(", "))
+        })));
+    }
+
+    let serial = path.into_inner().to_ascii_lowercase();
+    let revoked = ca.revoke(&serial, &request.reason).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    if revoked {
+        println!("🚫 Revoked certificate {} ({})", serial, request.reason);
+        Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "Certificate revoked"
+        })))
+    } else {
+        Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Certificate not found or already revoked"
+        })))
+    }
+}
+
+pub async fn list_intermediates(ca: web::Data<CertificateAuthority>) -> Result<HttpResponse, actix_web::Error> {
+    let intermediates = ca.list_intermediates().await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "intermediates": intermediates
+    })))
+}
+
+/// Switches signing to a new intermediate. Devices trust the root, so nothing
+/// needs re-enrolling unless the previous intermediate is revoked.
+pub async fn rotate_intermediate(
+    ca: web::Data<CertificateAuthority>,
+    request: web::Json<RotateIntermediateRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rotated = ca.rotate_intermediate(request.revoke_previous).await.map_err(|e| {
+        eprintln!("Failed to rotate CA intermediate: {}", e);
+        actix_web::error::ErrorInternalServerError("Failed to rotate intermediate")
+    })?;
+
+    println!(
+        "🔄 Rotated CA intermediate to {} (previous {:?}, revoked: {})",
+        rotated.id, rotated.previous_id, request.revoke_previous
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "intermediate": rotated
+    })))
+}
+
+fn is_valid_agent_id(agent_id: &str) -> bool {
+    !agent_id.is_empty()
+        && agent_id.len() <= 253
+        && agent_id
+            .split('.')
+            .all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+}
+
+/// Public CA material (root, chain, CRL, status) plus CSR signing for signed-in clients.
+/// Agent enrollment and intermediate rotation live under /admin/pki.
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/pki")
+            .route("/ca.pem", web::get().to(get_ca_certificate))
+            .route("/chain.pem", web::get().to(get_ca_chain))
+            .route("/crl.pem", web::get().to(get_crl))
+            .route("/certificates/{serial}/status", web::get().to(get_certificate_status))
+            .service(
+                web::resource("/client/csr")
+                    .wrap(AuthMiddleware::new())
+                    .route(web::post().to(sign_client_csr))
+            )
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_id_validation() {
+        assert!(is_valid_agent_id("gw-agent-01"));
+        assert!(is_valid_agent_id("os-agent.branch1.viworks.local"));
+        assert!(!is_valid_agent_id(""));
+        assert!(!is_valid_agent_id("-agent"));
+        assert!(!is_valid_agent_id("agent..local"));
+        assert!(!is_valid_agent_id("agent_01"));
+        assert!(!is_valid_agent_id("agent 01"));
+    }
+}
@@ -0,0 +1,174 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::auth::policy::{PolicyChange, PolicyDocument, PolicyInput, PolicyService, StoredPolicy};
+use crate::auth::{ClientContext, Claims};
+use crate::services::audit::{AuditEvent, AuditEventType, AuditLog};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct EvaluateRequest {
+    #[serde(flatten)]
+    pub input: PolicyInput,
+    /// Evaluate as if this policy were saved
+    #[serde(default)]
+    pub draft: Option<DraftPolicy>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DraftPolicy {
+    /// Replaces the active policy with this id; omitted for a new policy
+    pub id: Option<Uuid>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub rbac_roles: Vec<String>,
+    pub abac_rules: serde_json::Value,
+}
+
+pub async fn list_policies(policy_service: web::Data<PolicyService>) -> Result<HttpResponse, actix_web::Error> {
+    let policies = policy_service.list().await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "policies": policies
+    })))
+}
+
+pub async fn create_policy(
+    policy_service: web::Data<PolicyService>,
+    audit: web::Data<AuditLog>,
+    request: web::Json<PolicyChange>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    if request.name.is_none() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "name is required"
+        })));
+    }
+    if let Err(error) = request.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": error })));
+    }
+
+    let policy = policy_service.create(&request, claims.user_id()).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    println!("🛡️ Policy {} ({}) created by {}", policy.name, policy.id, claims.username);
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::PolicyUpdated)
+                .actor(&claims)
+                .details(serde_json::json!({ "policy_id": policy.id, "name": policy.name, "version": policy.version }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "success": true,
+        "policy": policy
+    })))
+}
+
+/// Partial update; any change to the rules, roles, name or active flag bumps the version.
+pub async fn update_policy(
+    policy_service: web::Data<PolicyService>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<Uuid>,
+    request: web::Json<PolicyChange>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(error) = request.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": error })));
+    }
+
+    let policy = policy_service
+        .update(path.into_inner(), &request, claims.user_id())
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    match policy {
+        Some(policy) => {
+            println!("🛡️ Policy {} updated to version {} by {}", policy.id, policy.version, claims.username);
+            audit
+                .record(
+                    AuditEvent::new(AuditEventType::PolicyUpdated)
+                        .actor(&claims)
+                        .details(serde_json::json!({ "policy_id": policy.id, "name": policy.name, "version": policy.version }))
+                        .context(&ClientContext::from_request(&http_req)),
+                )
+                .await;
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "policy": policy
+            })))
+        }
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Policy not found"
+        }))),
+    }
+}
+
+pub async fn list_policy_versions(
+    policy_service: web::Data<PolicyService>,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let versions = policy_service.versions(path.into_inner()).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    if versions.is_empty() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Policy not found"
+        })));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "versions": versions
+    })))
+}
+
+/// Dry run: decides the given attributes against the active policies (optionally
+/// with a draft in place) and explains every rule, without recording anything.
+pub async fn evaluate_policies(
+    policy_service: web::Data<PolicyService>,
+    request: web::Json<EvaluateRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let request = request.into_inner();
+
+    let draft = match request.draft {
+        Some(draft) => {
+            if let Err(error) = PolicyDocument::parse(&draft.abac_rules) {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": error })));
+            }
+            Some(StoredPolicy {
+                id: draft.id.unwrap_or_else(Uuid::nil),
+                name: draft.name.unwrap_or_else(|| "draft".to_string()),
+                version: 0,
+                rbac_roles: serde_json::json!(draft.rbac_roles),
+                abac_rules: draft.abac_rules,
+            })
+        }
+        None => None,
+    };
+
+    let decision = policy_service.dry_run(&request.input, draft).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "decision": decision
+    })))
+}
@@ -0,0 +1,572 @@
+use actix_web::http::StatusCode;
+use actix_web::{error::InternalError, mime, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::api::sessions::notify_admins;
+use crate::auth::credentials::CredentialService;
+use crate::auth::middleware::ScimAuthMiddleware;
+use crate::auth::ClientContext;
+use crate::services::audit::{AuditEvent, AuditEventType, AuditLog};
+use crate::services::agent_bridge::{random_password, AgentBridge, GatewayCommand};
+use crate::services::scim::{
+    error_body, group_patch, parse_filter, user_patch, MemberChange, PatchRequest, ScimError,
+    ScimService, ScimUserInput, ScimUserRow, UserChanges, DEFAULT_COUNT, GROUP_SCHEMA,
+    LIST_RESPONSE_SCHEMA, MAX_COUNT, RESOURCE_TYPE_SCHEMA, SERVICE_PROVIDER_CONFIG_SCHEMA,
+    USER_SCHEMA,
+};
+use crate::websocket::WebSocketSessionManager;
+
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+    pub excluded_attributes: Option<String>,
+}
+
+impl ScimListQuery {
+    /// 1-based start index and page size, clamped as RFC 7644 §3.4.2.4 asks.
+    fn page(&self) -> (i64, i64) {
+        let start_index = self.start_index.unwrap_or(1).max(1);
+        let count = self.count.unwrap_or(DEFAULT_COUNT).clamp(0, MAX_COUNT);
+        (start_index, count)
+    }
+
+    fn excludes(&self, attribute: &str) -> bool {
+        self.excluded_attributes
+            .as_deref()
+            .is_some_and(|excluded| excluded.split(',').any(|name| name.trim().eq_ignore_ascii_case(attribute)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroupInput {
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<Value>,
+}
+
+fn scim_response(status: StatusCode, body: Value) -> HttpResponse {
+    HttpResponse::build(status).content_type(SCIM_CONTENT_TYPE).json(body)
+}
+
+fn scim_error(e: ScimError) -> HttpResponse {
+    match &e {
+        ScimError::Database(e) => eprintln!("Database error: {}", e),
+        ScimError::Internal(e) => eprintln!("SCIM request failed: {:#}", e),
+        _ => {}
+    }
+    let status = StatusCode::from_u16(e.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    scim_response(status, e.body())
+}
+
+/// The `/scim/v2` URL the request came in on, for `meta.location` and `$ref`s.
+fn base_url(req: &HttpRequest) -> String {
+    let connection = req.connection_info();
+    format!("{}://{}/scim/v2", connection.scheme(), connection.host())
+}
+
+fn list_response(total: i64, start_index: i64, resources: Vec<Value>) -> HttpResponse {
+    scim_response(
+        StatusCode::OK,
+        json!({
+            "schemas": [LIST_RESPONSE_SCHEMA],
+            "totalResults": total,
+            "startIndex": start_index,
+            "itemsPerPage": resources.len(),
+            "Resources": resources
+        }),
+    )
+}
+
+fn parse_user_id(id: &str) -> Result<Uuid, ScimError> {
+    Uuid::parse_str(id).map_err(|_| ScimError::NotFound(format!("User {} not found", id)))
+}
+
+/// Audit event attributed to the identity provider rather than an admin.
+fn scim_event(event_type: AuditEventType, user_id: Uuid, details: Value, req: &HttpRequest) -> AuditEvent {
+    AuditEvent::new(event_type)
+        .target(user_id)
+        .details(json!({ "actor": "scim" }))
+        .details(details)
+        .context(&ClientContext::from_request(req))
+}
+
+/// Ends a deprovisioned user's sessions and revokes their gateway credentials.
+async fn end_sessions(
+    scim: &ScimService,
+    credential_service: &CredentialService,
+    session_manager: &WebSocketSessionManager,
+    user_id: Uuid,
+) -> Result<usize, ScimError> {
+    let session_ids = scim.terminate_sessions(user_id).await?;
+    for session_id in &session_ids {
+        notify_admins(session_manager, *session_id, Some(user_id), None, "ended", Some("deprovisioned"));
+    }
+    if !session_ids.is_empty() {
+        if let Err(e) = credential_service.revoke_ended_sessions().await {
+            eprintln!("Failed to revoke gateway credentials for user {}: {}", user_id, e);
+        }
+    }
+    Ok(session_ids.len())
+}
+
+pub async fn service_provider_config() -> HttpResponse {
+    scim_response(
+        StatusCode::OK,
+        json!({
+            "schemas": [SERVICE_PROVIDER_CONFIG_SCHEMA],
+            "patch": { "supported": true },
+            "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+            "filter": { "supported": true, "maxResults": MAX_COUNT },
+            "changePassword": { "supported": true },
+            "sort": { "supported": false },
+            "etag": { "supported": false },
+            "authenticationSchemes": [{
+                "type": "oauthbearertoken",
+                "name": "OAuth Bearer Token",
+                "description": "The static token configured as SCIM_BEARER_TOKEN",
+                "primary": true
+            }]
+        }),
+    )
+}
+
+pub async fn resource_types(req: HttpRequest) -> HttpResponse {
+    let base_url = base_url(&req);
+    let resource_type = |name: &str, endpoint: &str, schema: &str| {
+        json!({
+            "schemas": [RESOURCE_TYPE_SCHEMA],
+            "id": name,
+            "name": name,
+            "endpoint": endpoint,
+            "schema": schema,
+            "meta": {
+                "resourceType": "ResourceType",
+                "location": format!("{}/ResourceTypes/{}", base_url, name)
+            }
+        })
+    };
+    list_response(
+        2,
+        1,
+        vec![
+            resource_type("User", "/Users", USER_SCHEMA),
+            resource_type("Group", "/Groups", GROUP_SCHEMA),
+        ],
+    )
+}
+
+pub async fn list_users(
+    scim: web::Data<ScimService>,
+    query: web::Query<ScimListQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let filter = match query.filter.as_deref().map(parse_filter).transpose() {
+        Ok(filter) => filter,
+        Err(e) => return scim_error(e),
+    };
+    let (start_index, count) = query.page();
+    match scim.list_users(filter.as_ref(), start_index, count).await {
+        Ok((total, users)) => {
+            let base_url = base_url(&req);
+            list_response(total, start_index, users.iter().map(|user| user.to_resource(&base_url)).collect())
+        }
+        Err(e) => scim_error(e),
+    }
+}
+
+pub async fn get_user(scim: web::Data<ScimService>, path: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let user = match parse_user_id(&path) {
+        Ok(user_id) => scim.get_user(user_id).await,
+        Err(e) => Err(e),
+    };
+    match user {
+        Ok(user) => scim_response(StatusCode::OK, user.to_resource(&base_url(&req))),
+        Err(e) => scim_error(e),
+    }
+}
+
+pub async fn create_user(
+    scim: web::Data<ScimService>,
+    audit: web::Data<AuditLog>,
+    bridge: web::Data<AgentBridge>,
+    input: web::Json<ScimUserInput>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let input = input.into_inner();
+    let password = input.password.clone().unwrap_or_else(random_password);
+    let user = match scim.create_user(input).await {
+        Ok(user) => user,
+        Err(e) => return scim_error(e),
+    };
+    let command = GatewayCommand::CreateUser {
+        user_id: Some(user.id),
+        username: user.username.clone(),
+        email: Some(user.email.clone()),
+        password,
+    };
+    if let Err(e) = bridge.enqueue(command, None).await {
+        eprintln!("Failed to queue gateway account for user {}: {}", user.id, e);
+    }
+    audit
+        .record(scim_event(
+            AuditEventType::UserCreated,
+            user.id,
+            json!({ "username": user.username, "external_id": user.external_id, "active": user.is_active() }),
+            &req,
+        ))
+        .await;
+    let resource = user.to_resource(&base_url(&req));
+    let location = resource["meta"]["location"].as_str().unwrap_or_default().to_string();
+    HttpResponse::Created()
+        .content_type(SCIM_CONTENT_TYPE)
+        .insert_header(("Location", location))
+        .json(resource)
+}
+
+/// Applies a PUT or PATCH, ending the user's sessions (here and on the
+/// gateway) when it deactivates them.
+#[allow(clippy::too_many_arguments)]
+async fn apply_user_changes(
+    scim: &ScimService,
+    audit: &AuditLog,
+    bridge: &AgentBridge,
+    credential_service: &CredentialService,
+    session_manager: &WebSocketSessionManager,
+    user_id: Uuid,
+    changes: UserChanges,
+    req: &HttpRequest,
+) -> Result<ScimUserRow, ScimError> {
+    let (user, was_active) = scim.update_user(user_id, &changes).await?;
+    let suspended = was_active && !user.is_active();
+    let sessions_terminated = if suspended {
+        let command = GatewayCommand::DisableUser { user_id, username: user.username.clone() };
+        if let Err(e) = bridge.enqueue(command, None).await {
+            eprintln!("Failed to queue gateway session termination for user {}: {}", user_id, e);
+        }
+        end_sessions(scim, credential_service, session_manager, user_id).await?
+    } else {
+        0
+    };
+    audit
+        .record(scim_event(
+            AuditEventType::UserUpdated,
+            user_id,
+            json!({
+                "username": user.username,
+                "changed": changes.changed(),
+                "suspended": suspended,
+                "sessions_terminated": sessions_terminated
+            }),
+            req,
+        ))
+        .await;
+    Ok(user)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn replace_user(
+    scim: web::Data<ScimService>,
+    audit: web::Data<AuditLog>,
+    bridge: web::Data<AgentBridge>,
+    credential_service: web::Data<CredentialService>,
+    session_manager: web::Data<WebSocketSessionManager>,
+    path: web::Path<String>,
+    input: web::Json<ScimUserInput>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let result = async {
+        let user_id = parse_user_id(&path)?;
+        let changes = UserChanges::replace(input.into_inner())?;
+        apply_user_changes(&scim, &audit, &bridge, &credential_service, &session_manager, user_id, changes, &req).await
+    }
+    .await;
+    match result {
+        Ok(user) => scim_response(StatusCode::OK, user.to_resource(&base_url(&req))),
+        Err(e) => scim_error(e),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn patch_user(
+    scim: web::Data<ScimService>,
+    audit: web::Data<AuditLog>,
+    bridge: web::Data<AgentBridge>,
+    credential_service: web::Data<CredentialService>,
+    session_manager: web::Data<WebSocketSessionManager>,
+    path: web::Path<String>,
+    patch: web::Json<PatchRequest>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let result = async {
+        let user_id = parse_user_id(&path)?;
+        let changes = user_patch(&patch.operations)?;
+        apply_user_changes(&scim, &audit, &bridge, &credential_service, &session_manager, user_id, changes, &req).await
+    }
+    .await;
+    match result {
+        Ok(user) => scim_response(StatusCode::OK, user.to_resource(&base_url(&req))),
+        Err(e) => scim_error(e),
+    }
+}
+
+/// Deprovisions a user: suspends them, ends their sessions and gateway
+/// credentials, queues the removal of their gateway account, then deletes the
+/// row. If the removal cannot be queued the user stays suspended and the IdP's
+/// retry of the DELETE completes it.
+pub async fn delete_user(
+    scim: web::Data<ScimService>,
+    audit: web::Data<AuditLog>,
+    bridge: web::Data<AgentBridge>,
+    credential_service: web::Data<CredentialService>,
+    session_manager: web::Data<WebSocketSessionManager>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let suspended = async {
+        let user_id = parse_user_id(&path)?;
+        let changes = UserChanges { active: Some(false), ..Default::default() };
+        let (user, _) = scim.update_user(user_id, &changes).await?;
+        let sessions_terminated = end_sessions(&scim, &credential_service, &session_manager, user_id).await?;
+        Ok::<_, ScimError>((user, sessions_terminated))
+    }
+    .await;
+    let (user, sessions_terminated) = match suspended {
+        Ok(suspended) => suspended,
+        Err(e) => return scim_error(e),
+    };
+
+    let command = GatewayCommand::DeleteUser { user_id: user.id, username: user.username.clone() };
+    let gateway_command = match bridge.enqueue(command, None).await {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("Failed to queue gateway removal of {}: {}", user.username, e);
+            return scim_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                error_body(500, None, "The gateway removal could not be queued; the user is suspended and the deletion can be retried"),
+            );
+        }
+    };
+
+    if let Err(e) = scim.delete_user(user.id).await {
+        return scim_error(e);
+    }
+    audit
+        .record(scim_event(
+            AuditEventType::UserDeleted,
+            user.id,
+            json!({
+                "username": user.username,
+                "sessions_terminated": sessions_terminated,
+                "gateway_command_id": gateway_command.id
+            }),
+            &req,
+        ))
+        .await;
+    HttpResponse::NoContent().finish()
+}
+
+pub async fn list_groups(
+    scim: web::Data<ScimService>,
+    query: web::Query<ScimListQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let filter = match query.filter.as_deref().map(parse_filter).transpose() {
+        Ok(filter) => filter,
+        Err(e) => return scim_error(e),
+    };
+    let (start_index, count) = query.page();
+    match scim.list_groups(filter.as_ref(), start_index, count).await {
+        Ok((total, groups)) => {
+            let base_url = base_url(&req);
+            let include_members = !query.excludes("members");
+            list_response(
+                total,
+                start_index,
+                groups.iter().map(|group| group.to_resource(&base_url, include_members)).collect(),
+            )
+        }
+        Err(e) => scim_error(e),
+    }
+}
+
+pub async fn get_group(
+    scim: web::Data<ScimService>,
+    path: web::Path<String>,
+    query: web::Query<ScimListQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    match scim.get_group(&path).await {
+        Ok(group) => scim_response(StatusCode::OK, group.to_resource(&base_url(&req), !query.excludes("members"))),
+        Err(e) => scim_error(e),
+    }
+}
+
+/// Sets `role` on the given users and answers with the resulting group.
+async fn update_group(
+    scim: &ScimService,
+    audit: &AuditLog,
+    role: &str,
+    changes: Vec<MemberChange>,
+    status: StatusCode,
+    req: &HttpRequest,
+) -> HttpResponse {
+    if let Err(e) = scim.update_members(role, &changes).await {
+        return scim_error(e);
+    }
+    for change in &changes {
+        let (action, user_ids) = match change {
+            MemberChange::Add(ids) => ("role_granted", ids.as_slice()),
+            MemberChange::Remove(ids) => ("role_revoked", ids.as_slice()),
+            MemberChange::Replace(ids) => ("role_members_replaced", ids.as_slice()),
+            MemberChange::RemoveAll => ("role_members_cleared", &[][..]),
+        };
+        audit
+            .record(
+                AuditEvent::new(AuditEventType::AdminAction)
+                    .details(json!({ "actor": "scim", "action": action, "role": role, "users": user_ids }))
+                    .context(&ClientContext::from_request(req)),
+            )
+            .await;
+    }
+    match scim.get_group(role).await {
+        Ok(group) => scim_response(status, group.to_resource(&base_url(req), true)),
+        Err(e) => scim_error(e),
+    }
+}
+
+/// Creating a group grants its role to the listed members; a role nobody
+/// holds and no policy names cannot exist, so `members` must not be empty.
+pub async fn create_group(
+    scim: web::Data<ScimService>,
+    audit: web::Data<AuditLog>,
+    input: web::Json<ScimGroupInput>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let input = input.into_inner();
+    let role = input.display_name.trim().to_string();
+    if role.is_empty() {
+        return scim_error(ScimError::BadRequest("invalidValue", "displayName is required".to_string()));
+    }
+    let changes = match group_patch_members(input.members) {
+        Ok(ids) if !ids.is_empty() => vec![MemberChange::Add(ids)],
+        Ok(_) => {
+            return scim_error(ScimError::BadRequest(
+                "invalidValue",
+                "A group is a role and needs at least one member".to_string(),
+            ))
+        }
+        Err(e) => return scim_error(e),
+    };
+    match scim.get_group(&role).await {
+        Ok(_) => scim_error(ScimError::Conflict(format!("Group {} already exists", role))),
+        Err(ScimError::NotFound(_)) => update_group(&scim, &audit, &role, changes, StatusCode::CREATED, &req).await,
+        Err(e) => scim_error(e),
+    }
+}
+
+/// Member ids of a POSTed or PUT group, read as a `replace` of `members`.
+fn group_patch_members(members: Vec<Value>) -> Result<Vec<Uuid>, ScimError> {
+    let patch: PatchRequest = serde_json::from_value(json!({
+        "Operations": [{ "op": "replace", "path": "members", "value": members }]
+    }))
+    .map_err(|_| ScimError::BadRequest("invalidValue", "Invalid members".to_string()))?;
+    match group_patch(&patch.operations)?.pop() {
+        Some(MemberChange::Replace(ids)) => Ok(ids),
+        _ => Ok(Vec::new()),
+    }
+}
+
+pub async fn replace_group(
+    scim: web::Data<ScimService>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<String>,
+    input: web::Json<ScimGroupInput>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let input = input.into_inner();
+    if input.display_name != *path {
+        return scim_error(ScimError::BadRequest("mutability", "displayName cannot be modified".to_string()));
+    }
+    if let Err(e) = scim.get_group(&path).await {
+        return scim_error(e);
+    }
+    match group_patch_members(input.members) {
+        Ok(ids) => update_group(&scim, &audit, &path, vec![MemberChange::Replace(ids)], StatusCode::OK, &req).await,
+        Err(e) => scim_error(e),
+    }
+}
+
+pub async fn patch_group(
+    scim: web::Data<ScimService>,
+    audit: web::Data<AuditLog>,
+    path: web::Path<String>,
+    patch: web::Json<PatchRequest>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(e) = scim.get_group(&path).await {
+        return scim_error(e);
+    }
+    match group_patch(&patch.operations) {
+        Ok(changes) => update_group(&scim, &audit, &path, changes, StatusCode::OK, &req).await,
+        Err(e) => scim_error(e),
+    }
+}
+
+pub async fn delete_group() -> HttpResponse {
+    scim_error(ScimError::BadRequest(
+        "mutability",
+        "Groups are roles and cannot be deleted; remove their members instead".to_string(),
+    ))
+}
+
+/// Accepts `application/scim+json` as well as `application/json` bodies and
+/// answers malformed ones with a SCIM error.
+fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .content_type(|mime| mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON))
+        .error_handler(|err, _req| {
+            let response = scim_response(
+                StatusCode::BAD_REQUEST,
+                error_body(400, Some("invalidSyntax"), &err.to_string()),
+            );
+            InternalError::from_response(err, response).into()
+        })
+}
+
+fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| {
+        let response = scim_response(StatusCode::BAD_REQUEST, error_body(400, Some("invalidValue"), &err.to_string()));
+        InternalError::from_response(err, response).into()
+    })
+}
+
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/scim/v2")
+            .app_data(json_config())
+            .app_data(query_config())
+            .wrap(ScimAuthMiddleware::new())
+            .route("/ServiceProviderConfig", web::get().to(service_provider_config))
+            .route("/ResourceTypes", web::get().to(resource_types))
+            .route("/Users", web::get().to(list_users))
+            .route("/Users", web::post().to(create_user))
+            .route("/Users/{id}", web::get().to(get_user))
+            .route("/Users/{id}", web::put().to(replace_user))
+            .route("/Users/{id}", web::patch().to(patch_user))
+            .route("/Users/{id}", web::delete().to(delete_user))
+            .route("/Groups", web::get().to(list_groups))
+            .route("/Groups", web::post().to(create_group))
+            .route("/Groups/{id}", web::get().to(get_group))
+            .route("/Groups/{id}", web::put().to(replace_group))
+            .route("/Groups/{id}", web::patch().to(patch_group))
+            .route("/Groups/{id}", web::delete().to(delete_group)),
+    );
+}
@@ -1,10 +1,17 @@
-use actix_web::{web, HttpResponse, Result};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 use chrono::Utc;
+use crate::database::listing::{ListQuery, ListSpec, MatchFilter, SortField};
+use crate::auth::{credentials::CredentialService, policy::PolicyAction, AdminWebAuthnMiddleware, AuthMiddleware, ClientContext, Claims, PolicyMiddleware};
+use crate::services::agent_bridge::{AgentBridge, GatewayCommand};
+use crate::services::audit::{AuditEvent, AuditEventType, AuditLog};
+use crate::services::session_reaper::SessionReaper;
+use crate::websocket::{channels, WebSocketEvent, WebSocketSessionManager};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, JsonSchema)]
 pub struct SessionResponse {
     pub id: Uuid,
     pub user_id: Uuid,
@@ -16,17 +23,22 @@ pub struct SessionResponse {
     pub user_agent: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SessionListResponse {
     pub sessions: Vec<SessionResponse>,
     pub total: i64,
-    pub page: i64,
-    pub per_page: i64,
+    pub limit: i64,
+    pub next_cursor: Option<String>,
 }
 
+/// Session administration; every request must be allowed by the `admin` policy action,
+/// after WebAuthn verification for admin roles.
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/sessions")
+            .wrap(AdminWebAuthnMiddleware::new())
+            .wrap(PolicyMiddleware::new(PolicyAction::Admin))
+            .wrap(AuthMiddleware::new())
             .route("", web::get().to(list_sessions))
             .route("/{id}", web::get().to(get_session))
             .route("/{id}/revoke", web::post().to(revoke_session))
@@ -36,60 +48,48 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     );
 }
 
+/// Sessions matching `status`, `role` and `q` (username or email of the user)
+/// and `from`/`to` (start time), a page at a time.
+static SESSION_LIST: ListSpec = ListSpec {
+    select: "s.id, s.user_id, u.username, s.expires_at, s.started_at AS created_at, \
+             s.status = 'terminated' AS is_revoked, s.ip_address::text AS ip_address, s.user_agent",
+    from: "sessions s JOIN users u ON s.user_id = u.id",
+    id: "s.id",
+    sorts: &[
+        SortField { name: "created_at", expr: "s.started_at", sql_type: "timestamptz" },
+        SortField { name: "expires_at", expr: "s.expires_at", sql_type: "timestamptz" },
+        SortField { name: "last_activity_at", expr: "s.last_activity_at", sql_type: "timestamptz" },
+        SortField { name: "username", expr: "u.username", sql_type: "text" },
+    ],
+    default_sort: "-created_at",
+    date: Some("s.started_at"),
+    search: &["u.username", "u.email"],
+    status: Some(MatchFilter { expr: "s.status", values: &["active", "expired", "terminated"] }),
+    role: Some(MatchFilter { expr: "u.role", values: &[] }),
+};
+
 pub async fn list_sessions(
     pool: web::Data<PgPool>,
-    query: web::Query<std::collections::HashMap<String, String>>,
+    query: web::Query<ListQuery>,
 ) -> Result<HttpResponse> {
-    let page = query.get("page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(1);
-    let per_page = query.get("per_page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(10);
-    let offset = (page - 1) * per_page;
-
-    let sessions_result = sqlx::query!(
-        r#"
-        SELECT 
-            s.id, s.user_id, u.username, s.expires_at, s.created_at, 
-            s.is_revoked, s.ip_address::text as ip_address, s.user_agent
-        FROM user_sessions s
-        JOIN users u ON s.user_id = u.id
-        ORDER BY s.created_at DESC 
-        LIMIT $1 OFFSET $2
-        "#,
-        per_page,
-        offset
-    )
-    .fetch_all(pool.get_ref())
-    .await;
-
-    let total_result = sqlx::query!("SELECT COUNT(*) as count FROM user_sessions")
-        .fetch_one(pool.get_ref())
-        .await;
-
-    match (sessions_result, total_result) {
-        (Ok(sessions), Ok(total)) => {
-            let session_responses: Vec<SessionResponse> = sessions
-                .into_iter()
-                .map(|row| SessionResponse {
-                    id: row.id,
-                    user_id: row.user_id,
-                    username: row.username,
-                    expires_at: row.expires_at,
-                    created_at: row.created_at,
-                    is_revoked: row.is_revoked,
-                    ip_address: row.ip_address,
-                    user_agent: row.user_agent,
-                })
-                .collect();
+    let list = match query.resolve(&SESSION_LIST) {
+        Ok(list) => list,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))),
+    };
 
-            Ok(HttpResponse::Ok().json(SessionListResponse {
-                sessions: session_responses,
-                total: total.count.unwrap_or(0),
-                page,
-                per_page,
-            }))
+    match list.fetch::<SessionResponse>(pool.get_ref()).await {
+        Ok(page) => Ok(HttpResponse::Ok().json(SessionListResponse {
+            sessions: page.items,
+            total: page.total,
+            limit: page.limit,
+            next_cursor: page.next_cursor,
+        })),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch sessions"
+            })))
         }
-        _ => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to fetch sessions"
-        }))),
     }
 }
 
@@ -99,12 +99,13 @@ pub async fn get_session(
 ) -> Result<HttpResponse> {
     let session_id = path.into_inner();
 
-    let session_result = sqlx::query!(
+    let session_result = sqlx::query_as!(
+        SessionResponse,
         r#"
         SELECT 
-            s.id, s.user_id, u.username, s.expires_at, s.created_at, 
-            s.is_revoked, s.ip_address::text as ip_address, s.user_agent
-        FROM user_sessions s
+            s.id, s.user_id, u.username, s.expires_at, s.started_at AS created_at, 
+            s.status = 'terminated' AS "is_revoked!", s.ip_address::text as ip_address, s.user_agent
+        FROM sessions s
         JOIN users u ON s.user_id = u.id
         WHERE s.id = $1
         "#,
@@ -114,19 +115,7 @@ pub async fn get_session(
     .await;
 
     match session_result {
-        Ok(Some(session)) => {
-            let session_response = SessionResponse {
-                id: session.id,
-                user_id: session.user_id,
-                username: session.username,
-                expires_at: session.expires_at,
-                created_at: session.created_at,
-                is_revoked: session.is_revoked,
-                ip_address: session.ip_address,
-                user_agent: session.user_agent,
-            };
-            Ok(HttpResponse::Ok().json(session_response))
-        }
+        Ok(Some(session)) => Ok(HttpResponse::Ok().json(session)),
         Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
             "error": "Session not found"
         }))),
@@ -136,22 +125,103 @@ pub async fn get_session(
     }
 }
 
+/// Tells the admin dashboard (`sessions` channel) that a session started or ended.
+pub fn notify_admins(
+    session_manager: &WebSocketSessionManager,
+    session_id: Uuid,
+    user_id: Option<Uuid>,
+    username: Option<&str>,
+    status: &str,
+    reason: Option<&str>,
+) {
+    session_manager.broadcast_to_channel(
+        channels::SESSIONS,
+        WebSocketEvent::session_update(
+            session_id.to_string(),
+            user_id.map(|id| id.to_string()),
+            username.map(str::to_string),
+            status.to_string(),
+            reason.map(str::to_string),
+        ),
+    );
+}
+
+/// Queues ending revoked sessions on the gateway. Failures are logged: the
+/// sessions are already over here and their credentials revoked.
+async fn terminate_on_gateway(
+    pool: &PgPool,
+    bridge: &AgentBridge,
+    claims: &Claims,
+    user_id: Uuid,
+    session_ids: &[Uuid],
+) {
+    if session_ids.is_empty() {
+        return;
+    }
+    let username = match sqlx::query_scalar!("SELECT username FROM users WHERE id = $1", user_id)
+        .fetch_one(pool)
+        .await
+    {
+        Ok(username) => username,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return;
+        }
+    };
+    for session_id in session_ids {
+        let command = GatewayCommand::TerminateSession {
+            user_id: Some(user_id),
+            username: username.clone(),
+            session_id: *session_id,
+        };
+        if let Err(e) = bridge.enqueue(command, claims.user_id()).await {
+            eprintln!("Failed to queue gateway termination of session {}: {}", session_id, e);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn revoke_session(
     pool: web::Data<PgPool>,
+    bridge: web::Data<AgentBridge>,
+    credential_service: web::Data<CredentialService>,
+    audit: web::Data<AuditLog>,
+    session_manager: web::Data<WebSocketSessionManager>,
     path: web::Path<Uuid>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse> {
     let session_id = path.into_inner();
 
-    let update_result = sqlx::query!(
-        "UPDATE user_sessions SET is_revoked = true WHERE id = $1",
+    let update_result = sqlx::query_scalar!(
+        r#"
+        UPDATE sessions
+        SET status = 'terminated', terminated_at = NOW(), terminated_reason = 'admin_revoked'
+        WHERE id = $1 AND status = 'active'
+        RETURNING user_id
+        "#,
         session_id
     )
-    .execute(pool.get_ref())
+    .fetch_optional(pool.get_ref())
     .await;
 
     match update_result {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
+        Ok(user_id) => {
+            if let Some(user_id) = user_id {
+                audit
+                    .record(
+                        AuditEvent::new(AuditEventType::SessionTerminated)
+                            .actor(&claims)
+                            .target(user_id)
+                            .details(serde_json::json!({ "session_id": session_id, "reason": "admin_revoked" }))
+                            .context(&ClientContext::from_request(&http_req)),
+                    )
+                    .await;
+                notify_admins(&session_manager, session_id, Some(user_id), None, "ended", Some("admin_revoked"));
+                if let Err(e) = credential_service.revoke_for_session(session_id, "session_ended").await {
+                    eprintln!("Failed to revoke gateway credentials for session {}: {}", session_id, e);
+                }
+                terminate_on_gateway(&pool, &bridge, &claims, user_id, &[session_id]).await;
                 Ok(HttpResponse::Ok().json(serde_json::json!({
                     "message": "Session revoked successfully"
                 })))
@@ -167,23 +237,54 @@ pub async fn revoke_session(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn revoke_user_sessions(
     pool: web::Data<PgPool>,
+    bridge: web::Data<AgentBridge>,
+    credential_service: web::Data<CredentialService>,
+    audit: web::Data<AuditLog>,
+    session_manager: web::Data<WebSocketSessionManager>,
     path: web::Path<Uuid>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse> {
     let user_id = path.into_inner();
 
-    let update_result = sqlx::query!(
-        "UPDATE user_sessions SET is_revoked = true WHERE user_id = $1",
+    let update_result = sqlx::query_scalar!(
+        r#"
+        UPDATE sessions
+        SET status = 'terminated', terminated_at = NOW(), terminated_reason = 'admin_revoked'
+        WHERE user_id = $1 AND status = 'active'
+        RETURNING id
+        "#,
         user_id
     )
-    .execute(pool.get_ref())
+    .fetch_all(pool.get_ref())
     .await;
 
     match update_result {
-        Ok(result) => {
+        Ok(session_ids) => {
+            audit
+                .record(
+                    AuditEvent::new(AuditEventType::SessionTerminated)
+                        .actor(&claims)
+                        .target(user_id)
+                        .details(serde_json::json!({
+                            "reason": "admin_revoked",
+                            "sessions_terminated": session_ids.len()
+                        }))
+                        .context(&ClientContext::from_request(&http_req)),
+                )
+                .await;
+            for session_id in &session_ids {
+                notify_admins(&session_manager, *session_id, Some(user_id), None, "ended", Some("admin_revoked"));
+            }
+            if let Err(e) = credential_service.revoke_ended_sessions().await {
+                eprintln!("Failed to revoke gateway credentials for user {}: {}", user_id, e);
+            }
+            terminate_on_gateway(&pool, &bridge, &claims, user_id, &session_ids).await;
             Ok(HttpResponse::Ok().json(serde_json::json!({
-                "message": format!("Revoked {} sessions for user", result.rows_affected())
+                "message": format!("Revoked {} sessions for user", session_ids.len())
             })))
         }
         _ => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
@@ -192,88 +293,78 @@ pub async fn revoke_user_sessions(
     }
 }
 
+/// Runs the session reaper now: expires lapsed and idle sessions (the rows are
+/// kept) and queues gateway teardown of ended ones.
 pub async fn cleanup_expired_sessions(
-    pool: web::Data<PgPool>,
+    reaper: web::Data<SessionReaper>,
+    bridge: web::Data<AgentBridge>,
+    audit: web::Data<AuditLog>,
+    session_manager: web::Data<WebSocketSessionManager>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse> {
-    let delete_result = sqlx::query!(
-        "DELETE FROM user_sessions WHERE expires_at < $1",
-        Utc::now()
-    )
-    .execute(pool.get_ref())
-    .await;
-
-    match delete_result {
-        Ok(result) => {
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "message": format!("Cleaned up {} expired sessions", result.rows_affected())
-            })))
+    let sweep = match reaper.sweep(&bridge).await {
+        Ok(sweep) => sweep,
+        Err(e) => {
+            eprintln!("Session reaper sweep failed: {:#}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to cleanup expired sessions"
+            })));
         }
-        _ => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to cleanup expired sessions"
-        }))),
+    };
+
+    for session in sweep.torn_down.iter().filter(|session| session.unannounced()) {
+        notify_admins(
+            &session_manager,
+            session.id,
+            Some(session.user_id),
+            Some(&session.username),
+            "ended",
+            session.reason.as_deref(),
+        );
     }
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::AdminAction)
+                .actor(&claims)
+                .details(serde_json::json!({
+                    "action": "cleanup_expired_sessions",
+                    "sessions_expired": sweep.expired,
+                    "sessions_torn_down": sweep.torn_down.len()
+                }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": format!("Expired {} sessions", sweep.expired),
+        "sessions_expired": sweep.expired,
+        "sessions_torn_down": sweep.torn_down.len()
+    })))
 }
 
 pub async fn get_user_sessions(
     pool: web::Data<PgPool>,
     path: web::Path<Uuid>,
-    query: web::Query<std::collections::HashMap<String, String>>,
+    query: web::Query<ListQuery>,
 ) -> Result<HttpResponse> {
-    let user_id = path.into_inner();
-    let page = query.get("page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(1);
-    let per_page = query.get("per_page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(10);
-    let offset = (page - 1) * per_page;
-
-    let sessions_result = sqlx::query!(
-        r#"
-        SELECT 
-            s.id, s.user_id, u.username, s.expires_at, s.created_at, 
-            s.is_revoked, s.ip_address::text as ip_address, s.user_agent
-        FROM user_sessions s
-        JOIN users u ON s.user_id = u.id
-        WHERE s.user_id = $1
-        ORDER BY s.created_at DESC 
-        LIMIT $2 OFFSET $3
-        "#,
-        user_id,
-        per_page,
-        offset
-    )
-    .fetch_all(pool.get_ref())
-    .await;
+    let list = match query.resolve(&SESSION_LIST) {
+        Ok(list) => list.within("s.user_id", path.into_inner()),
+        Err(message) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))),
+    };
 
-    let total_result = sqlx::query!(
-        "SELECT COUNT(*) as count FROM user_sessions WHERE user_id = $1",
-        user_id
-    )
-    .fetch_one(pool.get_ref())
-    .await;
-
-    match (sessions_result, total_result) {
-        (Ok(sessions), Ok(total)) => {
-            let session_responses: Vec<SessionResponse> = sessions
-                .into_iter()
-                .map(|row| SessionResponse {
-                    id: row.id,
-                    user_id: row.user_id,
-                    username: row.username,
-                    expires_at: row.expires_at,
-                    created_at: row.created_at,
-                    is_revoked: row.is_revoked,
-                    ip_address: row.ip_address,
-                    user_agent: row.user_agent,
-                })
-                .collect();
-
-            Ok(HttpResponse::Ok().json(SessionListResponse {
-                sessions: session_responses,
-                total: total.count.unwrap_or(0),
-                page,
-                per_page,
-            }))
+    match list.fetch::<SessionResponse>(pool.get_ref()).await {
+        Ok(page) => Ok(HttpResponse::Ok().json(SessionListResponse {
+            sessions: page.items,
+            total: page.total,
+            limit: page.limit,
+            next_cursor: page.next_cursor,
+        })),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to fetch user sessions"
+            })))
         }
-        _ => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Failed to fetch user sessions"
-        }))),
     }
 }
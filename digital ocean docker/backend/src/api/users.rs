@@ -1,12 +1,17 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, Row};
+use sqlx::PgPool;
 use uuid::Uuid;
-use chrono::Utc;
-use bcrypt::{hash, DEFAULT_COST};
+use crate::database::listing::{ListQuery, ListSpec, MatchFilter, SortField};
 use crate::models::{User, UserStatus};
+use crate::auth::{policy::PolicyAction, AdminWebAuthnMiddleware, AuthMiddleware, ClientContext, Claims, PolicyMiddleware};
+use crate::auth::login_attempts::LoginAttemptService;
+use crate::auth::password::{PasswordCheck, PasswordOwner, PasswordService};
+use crate::services::agent_bridge::{AgentBridge, GatewayCommand};
+use crate::services::audit::{AuditEvent, AuditEventType, AuditLog};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct CreateUserRequest {
     pub username: String,
     pub email: String,
@@ -15,7 +20,7 @@ pub struct CreateUserRequest {
     pub is_active: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct UpdateUserRequest {
     pub username: Option<String>,
     pub email: Option<String>,
@@ -25,26 +30,46 @@ pub struct UpdateUserRequest {
 
 // User struct is imported from crate::models
 
+/// Users matching `status`, `role`, `q` (username or email) and `from`/`to`
+/// (creation date), a page at a time.
+static USER_LIST: ListSpec = ListSpec {
+    select: "id, username, email, password_hash, mobile, status, roles, created_at, updated_at, \
+             last_login_at, failed_login_attempts, locked_until",
+    from: "users",
+    id: "id",
+    sorts: &[
+        SortField { name: "created_at", expr: "created_at", sql_type: "timestamptz" },
+        SortField { name: "username", expr: "username", sql_type: "text" },
+        SortField { name: "email", expr: "email", sql_type: "text" },
+        SortField {
+            name: "last_login_at",
+            expr: "COALESCE(last_login_at, '-infinity')",
+            sql_type: "timestamptz",
+        },
+    ],
+    default_sort: "-created_at",
+    date: Some("created_at"),
+    search: &["username", "email"],
+    status: Some(MatchFilter { expr: "status", values: &["active", "inactive", "pending", "suspended"] }),
+    role: Some(MatchFilter { expr: "role", values: &[] }),
+};
+
 pub async fn get_users(
     pool: web::Data<PgPool>,
+    query: web::Query<ListQuery>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let users = sqlx::query_as::<_, User>(
-        r#"
-        SELECT id, username, email, password_hash, mobile, status, roles, 
-               created_at, updated_at, last_login_at, failed_login_attempts, locked_until
-        FROM users 
-        ORDER BY created_at DESC
-        "#
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    .map_err(|e| {
+    let list = match query.resolve(&USER_LIST) {
+        Ok(list) => list,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))),
+    };
+    let page = list.fetch::<User>(pool.get_ref()).await.map_err(|e| {
         eprintln!("Database error: {}", e);
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
 
     // Transform to frontend-compatible format
-    let response_users: Vec<serde_json::Value> = users
+    let response_users: Vec<serde_json::Value> = page
+        .items
         .into_iter()
         .map(|user| serde_json::json!({
             "id": user.id.to_string(),
@@ -61,7 +86,10 @@ pub async fn get_users(
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
-        "users": response_users
+        "users": response_users,
+        "total": page.total,
+        "limit": page.limit,
+        "next_cursor": page.next_cursor
     })))
 }
 
@@ -73,15 +101,16 @@ pub async fn get_user(
     let user_id_uuid = Uuid::parse_str(&user_id)
         .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID"))?;
 
-    let user = sqlx::query_as::<_, User>(
+    let user = sqlx::query_as!(
+        User,
         r#"
-        SELECT id, username, email, password_hash, mobile, status, roles, 
+        SELECT id, username, email, password_hash, mobile, status as "status: UserStatus", roles, 
                created_at, updated_at, last_login_at, failed_login_attempts, locked_until
         FROM users 
         WHERE id = $1
-        "#
+        "#,
+        user_id_uuid
     )
-    .bind(user_id_uuid)
     .fetch_optional(pool.get_ref())
     .await
     .map_err(|e| {
@@ -110,20 +139,28 @@ pub async fn get_user(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_user(
     pool: web::Data<PgPool>,
+    passwords: web::Data<PasswordService>,
+    audit: web::Data<AuditLog>,
+    bridge: web::Data<AgentBridge>,
     user_data: web::Json<CreateUserRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = Uuid::new_v4();
-    let password_hash = hash(&user_data.password, DEFAULT_COST)
-        .map_err(|e| {
-            eprintln!("Password hashing error: {}", e);
-            actix_web::error::ErrorInternalServerError("Password hashing failed")
-        })?;
+    let check = passwords
+        .check_new_password(&user_data.password, PasswordOwner::New { role: Some(&user_data.role) })
+        .await
+        .map_err(password_error)?;
+    if let PasswordCheck::Rejected(reason) = check {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": reason })));
+    }
+    let password_hash = passwords.hash_password(&user_data.password).map_err(password_error)?;
 
     // Check if username already exists
-    let existing_user = sqlx::query("SELECT id FROM users WHERE username = $1")
-        .bind(&user_data.username)
+    let existing_user = sqlx::query_scalar!("SELECT id FROM users WHERE username = $1", user_data.username)
         .fetch_optional(pool.get_ref())
         .await
         .map_err(|e| {
@@ -138,8 +175,7 @@ pub async fn create_user(
     }
 
     // Check if email already exists
-    let existing_email = sqlx::query("SELECT id FROM users WHERE email = $1")
-        .bind(&user_data.email)
+    let existing_email = sqlx::query_scalar!("SELECT id FROM users WHERE email = $1", user_data.email)
         .fetch_optional(pool.get_ref())
         .await
         .map_err(|e| {
@@ -153,26 +189,54 @@ pub async fn create_user(
         })));
     }
 
-    let user = sqlx::query_as::<_, User>(
+    let status = if user_data.is_active { UserStatus::Active } else { UserStatus::Pending };
+    let user = sqlx::query_as!(
+        User,
         r#"
-        INSERT INTO users (id, username, email, password_hash, status, roles, created_at, updated_at)
-        VALUES ($1, $2, $3, $4, $5::user_status, $6, NOW(), NOW())
-        RETURNING id, username, email, password_hash, mobile, status, roles, 
+        INSERT INTO users (id, username, email, password_hash, status, role, roles, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, NOW(), NOW())
+        RETURNING id, username, email, password_hash, mobile, status as "status: UserStatus", roles, 
                   created_at, updated_at, last_login_at, failed_login_attempts, locked_until
-        "#
+        "#,
+        user_id,
+        user_data.username,
+        user_data.email,
+        password_hash,
+        status as UserStatus,
+        user_data.role,
+        serde_json::json!([user_data.role]),
     )
-    .bind(user_id)
-    .bind(&user_data.username)
-    .bind(&user_data.email)
-    .bind(&password_hash)
-    .bind(if user_data.is_active { UserStatus::Active } else { UserStatus::Pending })
-    .bind(serde_json::json!([user_data.role]))
     .fetch_one(pool.get_ref())
     .await
     .map_err(|e| {
         eprintln!("Database error: {}", e);
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
+    passwords.remember(user.id, &password_hash).await.map_err(password_error)?;
+
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::UserCreated)
+                .actor(&claims)
+                .target(user.id)
+                .details(serde_json::json!({
+                    "username": user.username,
+                    "email": user.email,
+                    "role": user_data.role
+                }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
+    let command = GatewayCommand::CreateUser {
+        user_id: Some(user.id),
+        username: user.username.clone(),
+        email: Some(user.email.clone()),
+        password: user_data.password.clone(),
+    };
+    if let Err(e) = bridge.enqueue(command, claims.user_id()).await {
+        eprintln!("Failed to queue gateway account for user {}: {}", user.id, e);
+    }
 
     let response_user = serde_json::json!({
         "id": user.id.to_string(),
@@ -191,16 +255,19 @@ pub async fn create_user(
 
 pub async fn update_user(
     pool: web::Data<PgPool>,
+    audit: web::Data<AuditLog>,
+    bridge: web::Data<AgentBridge>,
     path: web::Path<String>,
     user_data: web::Json<UpdateUserRequest>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = path.into_inner();
     let user_id_uuid = Uuid::parse_str(&user_id)
         .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID"))?;
 
     // Check if user exists
-    let existing_user = sqlx::query("SELECT id FROM users WHERE id = $1")
-        .bind(user_id_uuid)
+    let existing_user = sqlx::query_scalar!("SELECT id FROM users WHERE id = $1", user_id_uuid)
         .fetch_optional(pool.get_ref())
         .await
         .map_err(|e| {
@@ -214,88 +281,66 @@ pub async fn update_user(
         })));
     }
 
-    // Build dynamic update query
-    let mut query_parts = Vec::new();
-    let mut param_count = 1;
-
-    if let Some(username) = &user_data.username {
-        query_parts.push(format!("username = ${}", param_count));
-        param_count += 1;
-    }
-
-    if let Some(email) = &user_data.email {
-        query_parts.push(format!("email = ${}", param_count));
-        param_count += 1;
-    }
-
-    if let Some(role) = &user_data.role {
-        query_parts.push(format!("role = ${}::user_role", param_count));
-        param_count += 1;
-    }
-
-    if let Some(is_active) = user_data.is_active {
-        query_parts.push(format!("is_active = ${}", param_count));
-        param_count += 1;
-    }
-
-    if query_parts.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "No fields to update"
-        })));
-    }
-
-    query_parts.push("updated_at = NOW()".to_string());
-
-    let query = format!(
+    // Fields left as NULL keep their current value; is_active maps onto status
+    let user = sqlx::query!(
         r#"
         UPDATE users 
-        SET {}
-        WHERE id = ${}
-        RETURNING id, username, email, role::text, is_active, last_login_at, 
+        SET username = COALESCE($1, username),
+            email = COALESCE($2, email),
+            role = COALESCE($3, role),
+            roles = CASE WHEN $3::text IS NULL THEN roles ELSE jsonb_build_array($3::text) END,
+            status = CASE
+                WHEN $4::bool IS NULL THEN status
+                WHEN $4 THEN 'active'::user_status
+                ELSE 'inactive'::user_status
+            END,
+            updated_at = NOW()
+        WHERE id = $5
+        RETURNING id, username, email, role, is_active, last_login_at, 
                   failed_login_attempts, locked_until, created_at, updated_at
         "#,
-        query_parts.join(", "),
-        param_count
-    );
+        user_data.username,
+        user_data.email,
+        user_data.role,
+        user_data.is_active,
+        user_id_uuid,
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
 
-    let mut query_builder = sqlx::query(&query);
-    
-    if let Some(username) = &user_data.username {
-        query_builder = query_builder.bind(username);
-    }
-    if let Some(email) = &user_data.email {
-        query_builder = query_builder.bind(email);
-    }
-    if let Some(role) = &user_data.role {
-        query_builder = query_builder.bind(role);
-    }
-    if let Some(is_active) = user_data.is_active {
-        query_builder = query_builder.bind(is_active);
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::UserUpdated)
+                .actor(&claims)
+                .target(user.id)
+                .details(serde_json::json!({ "changes": &*user_data }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
+    // A deactivated user's gateway sessions end with it
+    if user_data.is_active == Some(false) {
+        let command = GatewayCommand::DisableUser { user_id: user.id, username: user.username.clone() };
+        if let Err(e) = bridge.enqueue(command, claims.user_id()).await {
+            eprintln!("Failed to queue gateway session termination for user {}: {}", user.id, e);
+        }
     }
-    
-    query_builder = query_builder.bind(user_id_uuid);
 
-    let user = query_builder
-        .fetch_one(pool.get_ref())
-        .await
-        .map_err(|e| {
-            eprintln!("Database error: {}", e);
-            actix_web::error::ErrorInternalServerError("Database error")
-        })?;
-
-    let updated_user = User {
-        id: user.get::<Uuid, _>("id").to_string(),
-        username: user.get("username"),
-        email: user.get("email"),
-        role: user.get("role"),
-        is_active: user.get("is_active"),
-        last_login_at: user.get::<Option<chrono::DateTime<Utc>>, _>("last_login_at")
-            .map(|dt| dt.to_rfc3339()),
-        failed_login_attempts: user.get("failed_login_attempts"),
-        locked_until: user.get::<Option<chrono::DateTime<Utc>>, _>("locked_until")
-            .map(|dt| dt.to_rfc3339()),
-        created_at: user.get::<chrono::DateTime<Utc>, _>("created_at").to_rfc3339(),
-        updated_at: user.get::<chrono::DateTime<Utc>, _>("updated_at").to_rfc3339(),
+    let updated_user = super::auth::User {
+        id: user.id.to_string(),
+        username: user.username,
+        email: user.email,
+        role: user.role,
+        is_active: user.is_active,
+        last_login_at: user.last_login_at.map(|dt| dt.to_rfc3339()),
+        failed_login_attempts: user.failed_login_attempts,
+        locked_until: user.locked_until.map(|dt| dt.to_rfc3339()),
+        created_at: user.created_at.to_rfc3339(),
+        updated_at: user.updated_at.to_rfc3339(),
     };
 
     Ok(HttpResponse::Ok().json(updated_user))
@@ -303,15 +348,18 @@ pub async fn update_user(
 
 pub async fn delete_user(
     pool: web::Data<PgPool>,
+    audit: web::Data<AuditLog>,
+    bridge: web::Data<AgentBridge>,
     path: web::Path<String>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = path.into_inner();
     let user_id_uuid = Uuid::parse_str(&user_id)
         .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID"))?;
 
     // Check if user exists
-    let existing_user = sqlx::query("SELECT id FROM users WHERE id = $1")
-        .bind(user_id_uuid)
+    let existing_user = sqlx::query_scalar!("SELECT username FROM users WHERE id = $1", user_id_uuid)
         .fetch_optional(pool.get_ref())
         .await
         .map_err(|e| {
@@ -319,15 +367,22 @@ pub async fn delete_user(
             actix_web::error::ErrorInternalServerError("Database error")
         })?;
 
-    if existing_user.is_none() {
+    let Some(username) = existing_user else {
         return Ok(HttpResponse::NotFound().json(serde_json::json!({
             "error": "User not found"
         })));
-    }
+    };
+
+    // Queued before the row goes: if this fails the user stays rather than
+    // leaving their gateway account behind
+    let command = GatewayCommand::DeleteUser { user_id: user_id_uuid, username: username.clone() };
+    bridge.enqueue(command, claims.user_id()).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
 
     // Delete user
-    sqlx::query("DELETE FROM users WHERE id = $1")
-        .bind(user_id_uuid)
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id_uuid)
         .execute(pool.get_ref())
         .await
         .map_err(|e| {
@@ -335,31 +390,53 @@ pub async fn delete_user(
             actix_web::error::ErrorInternalServerError("Database error")
         })?;
 
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::UserDeleted)
+                .actor(&claims)
+                .target(user_id_uuid)
+                .details(serde_json::json!({ "username": username }))
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
+
     Ok(HttpResponse::NoContent().finish())
 }
 
 pub async fn unlock_user(
-    pool: web::Data<PgPool>,
+    login_attempts: web::Data<LoginAttemptService>,
+    audit: web::Data<AuditLog>,
     path: web::Path<String>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = path.into_inner();
     let user_id_uuid = Uuid::parse_str(&user_id)
         .map_err(|_| actix_web::error::ErrorBadRequest("Invalid user ID"))?;
 
-    sqlx::query(
-        r#"
-        UPDATE users 
-        SET failed_login_attempts = 0, locked_until = NULL, updated_at = NOW()
-        WHERE id = $1
-        "#
-    )
-    .bind(user_id_uuid)
-    .execute(pool.get_ref())
-    .await
-    .map_err(|e| {
-        eprintln!("Database error: {}", e);
-        actix_web::error::ErrorInternalServerError("Database error")
-    })?;
+    // Clears the failure streak and lock; per-IP throttling drains on its own
+    let unlocked = login_attempts
+        .unlock(user_id_uuid)
+        .await
+        .map_err(|e| {
+            eprintln!("Database error: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    if !unlocked {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "User not found"
+        })));
+    }
+
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::UserUnlocked)
+                .actor(&claims)
+                .target(user_id_uuid)
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "User unlocked successfully"
@@ -368,7 +445,11 @@ pub async fn unlock_user(
 
 pub async fn reset_user_password(
     pool: web::Data<PgPool>,
+    passwords: web::Data<PasswordService>,
+    audit: web::Data<AuditLog>,
     path: web::Path<String>,
+    claims: web::ReqData<Claims>,
+    http_req: HttpRequest,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = path.into_inner();
     let user_id_uuid = Uuid::parse_str(&user_id)
@@ -376,27 +457,35 @@ pub async fn reset_user_password(
 
     // Generate a random password
     let new_password = generate_random_password();
-    let password_hash = hash(&new_password, DEFAULT_COST)
-        .map_err(|e| {
-            eprintln!("Password hashing error: {}", e);
-            actix_web::error::ErrorInternalServerError("Password hashing failed")
-        })?;
+    let password_hash = passwords.hash_password(&new_password).map_err(password_error)?;
 
-    sqlx::query(
+    let updated = sqlx::query!(
         r#"
         UPDATE users 
         SET password_hash = $1, failed_login_attempts = 0, locked_until = NULL, updated_at = NOW()
         WHERE id = $2
-        "#
+        "#,
+        password_hash,
+        user_id_uuid
     )
-    .bind(&password_hash)
-    .bind(user_id_uuid)
     .execute(pool.get_ref())
     .await
     .map_err(|e| {
         eprintln!("Database error: {}", e);
         actix_web::error::ErrorInternalServerError("Database error")
     })?;
+    if updated.rows_affected() > 0 {
+        passwords.remember(user_id_uuid, &password_hash).await.map_err(password_error)?;
+    }
+
+    audit
+        .record(
+            AuditEvent::new(AuditEventType::UserPasswordReset)
+                .actor(&claims)
+                .target(user_id_uuid)
+                .context(&ClientContext::from_request(&http_req)),
+        )
+        .await;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "Password reset successfully",
@@ -404,6 +493,11 @@ pub async fn reset_user_password(
     })))
 }
 
+fn password_error(e: anyhow::Error) -> actix_web::Error {
+    eprintln!("Password check failed: {}", e);
+    actix_web::error::ErrorInternalServerError("Database error")
+}
+
 fn generate_random_password() -> String {
     use rand::Rng;
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
@@ -418,9 +512,14 @@ fn generate_random_password() -> String {
         .collect()
 }
 
+/// User administration; every request must be allowed by the `admin` policy action,
+/// after WebAuthn verification for admin roles.
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/users")
+            .wrap(AdminWebAuthnMiddleware::new())
+            .wrap(PolicyMiddleware::new(PolicyAction::Admin))
+            .wrap(AuthMiddleware::new())
             .route("", web::get().to(get_users))
             .route("", web::post().to(create_user))
             .route("/{id}", web::get().to(get_user))
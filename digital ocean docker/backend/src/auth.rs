@@ -1,18 +1,35 @@
 // Authentication and authorization module
 // This will be implemented in Phase 3
 
+pub mod attestation;
+pub mod ca;
+pub mod credentials;
+pub mod devices;
 pub mod jwt;
+pub mod login_attempts;
 pub mod password;
+pub mod policy;
+pub mod push_approval;
 pub mod middleware;
+pub mod otp;
+pub mod totp;
+pub mod webauthn;
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
-use sqlx::{PgPool, Row};
+use sqlx::PgPool;
+use sqlx::types::ipnetwork::IpNetwork;
 use anyhow::Result;
-use crate::auth::{jwt::{JwtService, TokenResponse}, password::PasswordService};
+use crate::auth::{
+    attestation::{Attestation, AttestationPurpose, AttestationService},
+    jwt::{JwtService, TokenResponse},
+    login_attempts::{LoginAttemptService, LoginGate},
+    password::{PasswordCheck, PasswordOwner, PasswordService},
+    policy::{PolicyAction, PolicyDecision, PolicyService, SessionLimitMode, SessionLimits},
+};
 use crate::config::AppConfig;
-use crate::models::UserStatus;
+use crate::services::audit::{AuditEvent, AuditEventType};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -20,6 +37,8 @@ pub struct User {
     pub username: String,
     pub email: String,
     pub role: UserRole,
+    /// `status = 'active'`; users are filtered on `status`, of which the
+    /// `is_active` column is only a generated copy
     pub is_active: bool,
     pub last_login_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
@@ -41,7 +60,8 @@ impl From<&str> for UserRole {
     fn from(s: &str) -> Self {
         match s {
             "owner" => UserRole::Owner,
-            "org_admin" => UserRole::OrgAdmin,
+            // "admin" is the role of the account seeded by 001_initial_schema
+            "org_admin" | "admin" => UserRole::OrgAdmin,
             "security_admin" => UserRole::SecurityAdmin,
             "security_analyst" => UserRole::SecurityAnalyst,
             "helpdesk" => UserRole::Helpdesk,
@@ -52,35 +72,118 @@ impl From<&str> for UserRole {
     }
 }
 
-impl ToString for UserRole {
-    fn to_string(&self) -> String {
-        match self {
-            UserRole::Owner => "owner".to_string(),
-            UserRole::OrgAdmin => "org_admin".to_string(),
-            UserRole::SecurityAdmin => "security_admin".to_string(),
-            UserRole::SecurityAnalyst => "security_analyst".to_string(),
-            UserRole::Helpdesk => "helpdesk".to_string(),
-            UserRole::Auditor => "auditor".to_string(),
-            UserRole::ApiService => "api_service".to_string(),
+impl UserRole {
+    /// Roles that can change users, policies or security settings
+    pub fn is_admin(&self) -> bool {
+        matches!(self, UserRole::Owner | UserRole::OrgAdmin | UserRole::SecurityAdmin)
+    }
+}
+
+impl std::fmt::Display for UserRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let role = match self {
+            UserRole::Owner => "owner",
+            UserRole::OrgAdmin => "org_admin",
+            UserRole::SecurityAdmin => "security_admin",
+            UserRole::SecurityAnalyst => "security_analyst",
+            UserRole::Helpdesk => "helpdesk",
+            UserRole::Auditor => "auditor",
+            UserRole::ApiService => "api_service",
+        };
+        f.write_str(role)
+    }
+}
+
+/// Where a session was started or refreshed from, recorded on the session row.
+/// The client's device, version and location feed policy decisions.
+#[derive(Debug, Clone, Default)]
+pub struct ClientContext {
+    pub ip_address: Option<IpNetwork>,
+    pub user_agent: Option<String>,
+    /// Desktop device fingerprint sent by the client (`X-Device-Id`); only
+    /// trustworthy once `attestation` has verified the signature below
+    pub device_id: Option<String>,
+    /// `X-Device-Nonce` and `X-Device-Signature`
+    pub device_nonce: Option<String>,
+    pub device_signature: Option<String>,
+    /// `X-Client-Version`
+    pub client_version: Option<String>,
+    /// ISO country code set by the edge proxy's GeoIP lookup (`X-Geo-Country`);
+    /// the proxy must overwrite any value the client sent
+    pub geo_country: Option<String>,
+    /// Autonomous system number of the client's network from the same lookup
+    /// (`X-Geo-Asn`); feeds the new-network alert rule
+    pub geo_asn: Option<String>,
+}
+
+impl ClientContext {
+    pub fn from_request(req: &actix_web::HttpRequest) -> Self {
+        let ip_address = req
+            .connection_info()
+            .realip_remote_addr()
+            .and_then(|addr| {
+                addr.parse::<std::net::SocketAddr>()
+                    .map(|socket| socket.ip())
+                    .or_else(|_| addr.parse::<std::net::IpAddr>())
+                    .ok()
+            })
+            .map(IpNetwork::from);
+        let user_agent = req
+            .headers()
+            .get(actix_web::http::header::USER_AGENT)
+            .and_then(|ua| ua.to_str().ok())
+            .map(str::to_string);
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+
+        Self {
+            ip_address,
+            user_agent,
+            device_id: header("X-Device-Id"),
+            device_nonce: header("X-Device-Nonce"),
+            device_signature: header("X-Device-Signature"),
+            client_version: header("X-Client-Version"),
+            geo_country: header("X-Geo-Country"),
+            geo_asn: header("X-Geo-Asn"),
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct LoginRequest {
-    pub username: String,
-    pub password: String,
+#[derive(Debug)]
+pub enum RefreshOutcome {
+    Rotated(TokenResponse),
+    /// Unknown token, or its session is expired, terminated or the user is inactive
+    Invalid,
+    /// An already-rotated token was presented; the whole session has been terminated
+    Reused { session_id: Uuid },
+    /// The session is bound to a device and the request was not signed by it
+    DeviceRejected(&'static str),
 }
 
-#[derive(Debug, Serialize)]
-pub struct LoginResponse {
-    pub user: User,
-    pub token: TokenResponse,
+#[derive(Debug)]
+pub enum SessionStart {
+    Started(TokenResponse),
+    /// The login policy refused the session; nothing was created
+    Denied(PolicyDecision),
+    /// The device signature was missing or invalid; nothing was created
+    DeviceRejected(&'static str),
+    /// The user already has `max` sessions and the limit denies new ones
+    LimitReached { max: u32 },
 }
 
 pub struct AuthService {
     pub jwt_service: JwtService,
     password_service: PasswordService,
+    login_attempts: LoginAttemptService,
+    policy: PolicyService,
+    attestation: AttestationService,
+    session_defaults: SessionLimits,
     db_pool: PgPool,
 }
 
@@ -88,116 +191,107 @@ impl AuthService {
     pub fn new(config: &AppConfig, db_pool: PgPool) -> Self {
         Self {
             jwt_service: JwtService::new(config),
-            password_service: PasswordService::new(config.bcrypt_cost),
+            password_service: PasswordService::new(config, db_pool.clone()),
+            login_attempts: LoginAttemptService::new(config, db_pool.clone()),
+            policy: PolicyService::new(db_pool.clone()),
+            attestation: AttestationService::new(config, db_pool.clone()),
+            session_defaults: SessionLimits {
+                idle_timeout_minutes: Some(config.session_idle_timeout_minutes as u32).filter(|minutes| *minutes > 0),
+                absolute_timeout_hours: Some(config.session_absolute_timeout_hours as u32),
+                max_concurrent: Some(config.session_max_concurrent).filter(|max| *max > 0),
+                on_limit: Some(match config.session_limit_mode.as_str() {
+                    "deny" => SessionLimitMode::Deny,
+                    _ => SessionLimitMode::EvictOldest,
+                }),
+            },
             db_pool,
         }
     }
     
-    pub async fn authenticate_user(&self, username: &str, password: &str) -> Result<Option<User>> {
-        // Get user from database using runtime query
-        let row = sqlx::query(
+    /// `None` for wrong credentials, a locked account or a throttled address.
+    pub async fn authenticate_user(&self, username: &str, password: &str, context: &ClientContext) -> Result<Option<User>> {
+        let row = sqlx::query!(
             r#"
-            SELECT 
-                id, username, email, role::text, 
-                status::text, last_login::timestamptz, created_at::timestamptz, updated_at::timestamptz
+            SELECT id, username, email, role, is_active, last_login_at, locked_until, created_at, updated_at
             FROM users 
             WHERE username = $1 AND status = 'active'
-            "#
+            "#,
+            username
         )
-        .bind(username)
         .fetch_optional(&self.db_pool)
         .await?;
         
-        let user = if let Some(row) = row {
-            let status: String = row.get("status");
-            let user_status = UserStatus::from(status.clone());
-            Some(User {
-                id: row.get("id"),
-                username: row.get("username"),
-                email: row.get("email"),
-                role: UserRole::from(row.get::<String, _>("role").as_str()),
-                is_active: status == "active",
-                last_login_at: row.get("last_login"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            })
-        } else {
-            None
-        };
+        let gate = self
+            .login_attempts
+            .gate(username, row.as_ref().map(|row| (row.id, row.locked_until)), context)
+            .await?;
+        if gate != LoginGate::Open {
+            return Ok(None);
+        }
+        
+        let user = row.map(|row| User {
+            id: row.id,
+            username: row.username,
+            email: row.email,
+            role: UserRole::from(row.role.as_str()),
+            is_active: row.is_active,
+            last_login_at: row.last_login_at,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        });
         
         if let Some(user) = user {
-            // Get password hash using runtime query
-            let row = sqlx::query("SELECT password_hash FROM users WHERE id = $1")
-                .bind(user.id)
+            let password_hash = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = $1", user.id)
                 .fetch_one(&self.db_pool)
                 .await?;
-            let password_hash: String = row.get("password_hash");
             
-            // Verify password
-            if self.password_service.verify_password(password, &password_hash)? {
-                // Update last login using runtime query
-                sqlx::query("UPDATE users SET last_login = NOW() WHERE id = $1")
-                    .bind(user.id)
+            // Verify password (upgrading an outdated hash)
+            if self.password_service.verify_and_upgrade(user.id, password, &password_hash).await? {
+                self.login_attempts.record_success(user.id).await?;
+                sqlx::query!("UPDATE users SET last_login_at = NOW() WHERE id = $1", user.id)
                     .execute(&self.db_pool)
                     .await?;
                 
                 Ok(Some(user))
             } else {
+                self.login_attempts
+                    .record_failure(Some(user.id), username, context, "invalid_password")
+                    .await?;
                 Ok(None)
             }
         } else {
+            self.login_attempts
+                .record_failure(None, username, context, "unknown_user")
+                .await?;
             Ok(None)
         }
     }
     
-    pub fn hash_password(&self, password: &str) -> Result<String, bcrypt::BcryptError> {
+    pub fn hash_password(&self, password: &str) -> Result<String> {
         self.password_service.hash_password(password)
     }
     
-    pub async fn login(&self, login_req: &LoginRequest) -> Result<Option<LoginResponse>> {
-        if let Some(user) = self.authenticate_user(&login_req.username, &login_req.password).await? {
-            let token = self.jwt_service.create_token_response(
-                user.id,
-                &user.username,
-                &user.role.to_string()
-            )?;
-            
-            Ok(Some(LoginResponse { user, token }))
-        } else {
-            Ok(None)
-        }
-    }
-    
     pub async fn get_user_by_id(&self, user_id: Uuid) -> Result<Option<User>> {
-        let row = sqlx::query(
+        let user = sqlx::query!(
             r#"
-            SELECT 
-                id, username, email, role::text, 
-                status::text, last_login::timestamptz, created_at::timestamptz, updated_at::timestamptz
+            SELECT id, username, email, role, is_active, last_login_at, created_at, updated_at
             FROM users 
             WHERE id = $1 AND status = 'active'
-            "#
+            "#,
+            user_id
         )
-        .bind(user_id)
         .fetch_optional(&self.db_pool)
-        .await?;
-        
-        let user = if let Some(row) = row {
-            let status: String = row.get("status");
-            let user_status = UserStatus::from(status.clone());
-            Some(User {
-                id: row.get("id"),
-                username: row.get("username"),
-                email: row.get("email"),
-                role: UserRole::from(row.get::<String, _>("role").as_str()),
-                is_active: status == "active",
-                last_login_at: row.get("last_login"),
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            })
-        } else {
-            None
-        };
+        .await?
+        .map(|row| User {
+            id: row.id,
+            username: row.username,
+            email: row.email,
+            role: UserRole::from(row.role.as_str()),
+            is_active: row.is_active,
+            last_login_at: row.last_login_at,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        });
         
         Ok(user)
     }
@@ -209,38 +303,46 @@ impl AuthService {
         password: &str,
         role: UserRole,
     ) -> Result<User> {
-        // Validate password strength
-        self.password_service.validate_password_strength(password)?;
+        let role = role.to_string();
+        
+        // Validate password strength and that it is not breached
+        if let PasswordCheck::Rejected(reason) = self
+            .password_service
+            .check_new_password(password, PasswordOwner::New { role: Some(&role) })
+            .await?
+        {
+            anyhow::bail!(reason);
+        }
         
         // Hash password
         let password_hash = self.password_service.hash_password(password)?;
         
-        // Insert user using runtime query
-        let row = sqlx::query(
+        let row = sqlx::query!(
             r#"
-            INSERT INTO users (username, email, password_hash, role)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO users (username, email, password_hash, role, roles)
+            VALUES ($1, $2, $3, $4::text, jsonb_build_array($4::text))
             RETURNING 
                 id, username, email, role, 
                 is_active, last_login_at, created_at, updated_at
-            "#
+            "#,
+            username,
+            email,
+            password_hash,
+            role
         )
-        .bind(username)
-        .bind(email)
-        .bind(password_hash)
-        .bind(role.to_string())
         .fetch_one(&self.db_pool)
         .await?;
+        self.password_service.remember(row.id, &password_hash).await?;
         
         let user = User {
-            id: row.get("id"),
-            username: row.get("username"),
-            email: row.get("email"),
-            role: UserRole::from(row.get::<String, _>("role").as_str()),
-            is_active: row.get("is_active"),
-            last_login_at: row.get("last_login_at"),
-            created_at: row.get("created_at"),
-            updated_at: row.get("updated_at"),
+            id: row.id,
+            username: row.username,
+            email: row.email,
+            role: UserRole::from(row.role.as_str()),
+            is_active: row.is_active,
+            last_login_at: row.last_login_at,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
         };
         
         Ok(user)
@@ -249,8 +351,301 @@ impl AuthService {
     pub fn validate_token(&self, token: &str) -> Result<jwt::Claims> {
         Ok(self.jwt_service.validate_token(token)?)
     }
+    
+    /// Only for callers that have checked the password and `second_factor`, which
+    /// is recorded on the session. Checks the login policy, then creates a session row (with the decision as its
+    /// policy snapshot) and issues its first access/refresh token pair. The policy's
+    /// session limits (or the configured defaults) are fixed on the row; a user at
+    /// their concurrent-session limit loses their oldest sessions or is refused.
+    pub async fn start_session(
+        &self,
+        user_id: Uuid,
+        username: &str,
+        role: &str,
+        second_factor: &str,
+        context: &ClientContext,
+    ) -> Result<SessionStart> {
+        // Only an attested device counts as the caller's device, for the policy and the session binding
+        let device = match self.attestation.verify(user_id, context, AttestationPurpose::Login).await? {
+            Attestation::Verified(device) => Some(device),
+            Attestation::Absent if !self.attestation.required() => None,
+            Attestation::Absent => return Ok(SessionStart::DeviceRejected("device signature required")),
+            Attestation::Rejected(reason) => return Ok(SessionStart::DeviceRejected(reason)),
+        };
+        let context = &ClientContext {
+            device_id: device.as_ref().map(|device| device.fingerprint.clone()),
+            ..context.clone()
+        };
+        
+        let decision = self.policy.enforce(PolicyAction::Login, user_id, role, context).await?;
+        if !decision.allowed {
+            return Ok(SessionStart::Denied(decision));
+        }
+        
+        let limits = decision.session.or(&self.session_defaults);
+        let mut tx = self.db_pool.begin().await?;
+        
+        // Serialises logins of one user so concurrent ones cannot both pass the limit
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        
+        let mut evicted = Vec::new();
+        if let Some(max) = limits.max_concurrent {
+            let live = sqlx::query_scalar!(
+                r#"
+                SELECT id FROM sessions
+                WHERE user_id = $1 AND status = 'active' AND expires_at > NOW()
+                  AND (idle_timeout_seconds IS NULL OR last_activity_at > NOW() - make_interval(secs => idle_timeout_seconds))
+                ORDER BY started_at, id
+                "#,
+                user_id
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            
+            if live.len() >= max as usize {
+                if limits.on_limit == Some(SessionLimitMode::Deny) {
+                    return Ok(SessionStart::LimitReached { max });
+                }
+                evicted = live[..=live.len() - max as usize].to_vec();
+                sqlx::query!(
+                    r#"
+                    UPDATE sessions
+                    SET status = 'terminated', terminated_at = NOW(), terminated_reason = 'concurrent_limit'
+                    WHERE id = ANY($1)
+                    "#,
+                    &evicted
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        
+        let session_id = Uuid::new_v4();
+        let issued = self.jwt_service.issue_tokens(user_id, username, role, session_id)?;
+        let now = Utc::now();
+        let absolute_expires_at = now + chrono::Duration::hours(limits.absolute_timeout_hours.unwrap_or(24) as i64);
+        let expires_at = (now + self.jwt_service.refresh_expiration()).min(absolute_expires_at);
+        
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (id, user_id, access_token_jti, refresh_token_hash, expires_at, ip_address, user_agent,
+                                  policy_snapshot, device_id, idle_timeout_seconds, absolute_expires_at, second_factor)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            "#,
+            session_id,
+            user_id,
+            issued.access_token_jti,
+            issued.refresh_token_hash,
+            expires_at,
+            context.ip_address,
+            context.user_agent,
+            decision.snapshot(),
+            device.map(|device| device.id),
+            limits.idle_timeout_minutes.map(|minutes| minutes as i32 * 60),
+            absolute_expires_at,
+            second_factor
+        )
+        .execute(&mut *tx)
+        .await?;
+        
+        for evicted_id in &evicted {
+            AuditEvent::new(AuditEventType::SessionTerminated)
+                .target(user_id)
+                .session(*evicted_id)
+                .details(serde_json::json!({
+                    "reason": "concurrent_limit",
+                    "max_concurrent": limits.max_concurrent,
+                    "replaced_by": session_id
+                }))
+                .context(context)
+                .write(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        
+        AuditEvent::new(AuditEventType::SessionStarted)
+            .user(Some(user_id))
+            .session(session_id)
+            .details(serde_json::json!({
+                "username": username,
+                "device_id": context.device_id,
+                "second_factor": second_factor,
+                "geo_country": context.geo_country,
+                "asn": context.geo_asn
+            }))
+            .context(context)
+            .write(&self.db_pool)
+            .await?;
+        
+        Ok(SessionStart::Started(issued.response))
+    }
+    
+    /// Exchanges a refresh token for a new pair. The presented token is retired;
+    /// presenting it again terminates the session it belonged to.
+    pub async fn refresh_session(&self, refresh_token: &str, context: &ClientContext) -> Result<RefreshOutcome> {
+        let token_hash = JwtService::hash_refresh_token(refresh_token);
+        let mut tx = self.db_pool.begin().await?;
+        
+        let session = sqlx::query!(
+            r#"
+            SELECT s.id, s.user_id, s.device_id, s.status::text AS "status!", s.expires_at, u.username, u.role,
+                   u.status = 'active' AS "user_active!",
+                   (s.idle_timeout_seconds IS NOT NULL
+                    AND s.last_activity_at <= NOW() - make_interval(secs => s.idle_timeout_seconds)) AS "idle!"
+            FROM sessions s
+            JOIN users u ON s.user_id = u.id
+            WHERE s.refresh_token_hash = $1
+            FOR UPDATE OF s
+            "#,
+            token_hash
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        
+        let Some(session) = session else {
+            let reused_session = sqlx::query_scalar!(
+                "SELECT session_id FROM rotated_refresh_tokens WHERE token_hash = $1",
+                token_hash
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+            
+            let Some(session_id) = reused_session else {
+                return Ok(RefreshOutcome::Invalid);
+            };
+            
+            Self::terminate_session(&mut tx, session_id, "refresh_token_reuse").await?;
+            tx.commit().await?;
+            return Ok(RefreshOutcome::Reused { session_id });
+        };
+        
+        if session.status != "active" || session.expires_at <= Utc::now() || session.idle || !session.user_active {
+            return Ok(RefreshOutcome::Invalid);
+        }
+        
+        if let Some(reason) = self
+            .attestation
+            .verify_bound(session.device_id, session.user_id, context, AttestationPurpose::Refresh)
+            .await?
+        {
+            return Ok(RefreshOutcome::DeviceRejected(reason));
+        }
+        
+        let issued = self.jwt_service.issue_tokens(session.user_id, &session.username, &session.role, session.id)?;
+        let expires_at = Utc::now() + self.jwt_service.refresh_expiration();
+        
+        sqlx::query!(
+            "INSERT INTO rotated_refresh_tokens (token_hash, session_id) VALUES ($1, $2)",
+            token_hash,
+            session.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET access_token_jti = $2,
+                refresh_token_hash = $3,
+                expires_at = LEAST($4, absolute_expires_at),
+                last_activity_at = NOW(),
+                ip_address = COALESCE($5, ip_address),
+                user_agent = COALESCE($6, user_agent)
+            WHERE id = $1
+            "#,
+            session.id,
+            issued.access_token_jti,
+            issued.refresh_token_hash,
+            expires_at,
+            context.ip_address,
+            context.user_agent
+        )
+        .execute(&mut *tx)
+        .await?;
+        
+        tx.commit().await?;
+        Ok(RefreshOutcome::Rotated(issued.response))
+    }
+    
+    /// Terminates a session; its access and refresh tokens stop working immediately.
+    pub async fn end_session(&self, session_id: Uuid, reason: &str) -> Result<bool> {
+        let mut conn = self.db_pool.acquire().await?;
+        Self::terminate_session(&mut conn, session_id, reason).await
+    }
+    
+    async fn terminate_session(conn: &mut sqlx::PgConnection, session_id: Uuid, reason: &str) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET status = 'terminated', terminated_at = NOW(), terminated_reason = $2
+            WHERE id = $1 AND status = 'active'
+            "#,
+            session_id,
+            reason
+        )
+        .execute(conn)
+        .await?;
+        
+        Ok(result.rows_affected() > 0)
+    }
+    
+    /// Validates the JWT and that it is still the current access token of an active,
+    /// unexpired and not idle session, and records the activity on the session
+    /// (at most every 30 seconds, so busy clients do not write on every request).
+    pub async fn validate_access_token(&self, token: &str) -> Result<Option<jwt::Claims>> {
+        let Ok(claims) = self.jwt_service.validate_token(token) else {
+            return Ok(None);
+        };
+        let Some(session_id) = claims.session_id() else {
+            return Ok(None);
+        };
+        
+        let current = sqlx::query_scalar!(
+            r#"
+            WITH live AS (
+                SELECT id, last_activity_at FROM sessions
+                WHERE id = $1 AND access_token_jti = $2 AND status = 'active' AND expires_at > NOW()
+                  AND (idle_timeout_seconds IS NULL OR last_activity_at > NOW() - make_interval(secs => idle_timeout_seconds))
+            ), touched AS (
+                UPDATE sessions SET last_activity_at = NOW()
+                WHERE id IN (SELECT id FROM live WHERE last_activity_at < NOW() - INTERVAL '30 seconds')
+            )
+            SELECT EXISTS(SELECT 1 FROM live) AS "exists!"
+            "#,
+            session_id,
+            claims.jti
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+        
+        Ok(current.then_some(claims))
+    }
+    
+    /// Whether the session was started with a second factor (sessions from before
+    /// it was recorded were not).
+    pub async fn completed_second_factor(&self, session_id: Uuid) -> Result<bool> {
+        let completed = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM sessions WHERE id = $1 AND second_factor IS NOT NULL) AS "exists!""#,
+            session_id
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+        
+        Ok(completed)
+    }
+    
+    /// Resolves a desktop client's `username` + `session_token` pair to the user id.
+    pub async fn validate_session_token(&self, username: &str, token: &str) -> Result<Option<Uuid>> {
+        let claims = self.validate_access_token(token).await?;
+        
+        Ok(claims
+            .filter(|claims| claims.username == username)
+            .and_then(|claims| claims.user_id()))
+    }
 }
 
 // Re-export for convenience
 pub use jwt::Claims;
-pub use middleware::{AuthMiddleware, RoleMiddleware, get_claims_from_request};
+pub use middleware::{AdminWebAuthnMiddleware, AuthMiddleware, PolicyMiddleware, bearer_token, get_claims_from_request};
@@ -0,0 +1,488 @@
+//! Desktop device attestation.
+//!
+//! A desktop client generates an Ed25519 or P-256 key pair and registers the
+//! public key when it asks to be bound (see `devices`). The device's
+//! fingerprint is the hex SHA-256 of that first public key and stays the
+//! device's identity across key rotations.
+//!
+//! Requests that must come from the device carry a fresh server nonce
+//! (`POST /auth/device/nonce`) and a signature over
+//! `viworks-device-attestation:v1:<purpose>:<nonce>` in the
+//! `X-Device-Id`, `X-Device-Nonce` and `X-Device-Signature` headers.
+//! Keys, nonces and signatures are base64url without padding. P-256 keys are
+//! SEC1 points and their signatures may be DER or fixed-size `r || s`.
+//!
+//! Sessions started from an attested device are bound to it: refreshing the
+//! token or bootstrapping gateway credentials needs a signature from the same
+//! device, so a stolen refresh token is useless anywhere else.
+
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::auth::ClientContext;
+use crate::services::audit::{AuditEvent, AuditEventType};
+use crate::config::AppConfig;
+
+const NONCE_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlgorithm {
+    Ed25519,
+    P256,
+}
+
+impl KeyAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyAlgorithm::Ed25519 => "ed25519",
+            KeyAlgorithm::P256 => "p256",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ed25519" => Some(KeyAlgorithm::Ed25519),
+            "p256" => Some(KeyAlgorithm::P256),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AttestationPurpose {
+    Enroll,
+    Login,
+    Refresh,
+    Bootstrap,
+    Rotate,
+}
+
+impl AttestationPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttestationPurpose::Enroll => "enroll",
+            AttestationPurpose::Login => "login",
+            AttestationPurpose::Refresh => "refresh",
+            AttestationPurpose::Bootstrap => "bootstrap",
+            AttestationPurpose::Rotate => "rotate",
+        }
+    }
+}
+
+/// The exact bytes a device signs for `purpose`.
+pub fn signed_message(purpose: AttestationPurpose, nonce: &str) -> Vec<u8> {
+    format!("viworks-device-attestation:v1:{}:{}", purpose.as_str(), nonce).into_bytes()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevicePublicKey {
+    pub algorithm: KeyAlgorithm,
+    bytes: Vec<u8>,
+}
+
+impl DevicePublicKey {
+    pub fn parse(algorithm: KeyAlgorithm, encoded: &str) -> Result<Self, &'static str> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(encoded.trim())
+            .map_err(|_| "public key is not valid base64url")?;
+
+        let valid = match algorithm {
+            KeyAlgorithm::Ed25519 => <[u8; 32]>::try_from(bytes.as_slice())
+                .ok()
+                .and_then(|key| ed25519_dalek::VerifyingKey::from_bytes(&key).ok())
+                .is_some(),
+            KeyAlgorithm::P256 => p256::ecdsa::VerifyingKey::from_sec1_bytes(&bytes).is_ok(),
+        };
+        if !valid {
+            return Err("public key is not a valid key for its algorithm");
+        }
+
+        Ok(Self { algorithm, bytes })
+    }
+
+    pub fn encoded(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.bytes)
+    }
+
+    /// Hex SHA-256 of the key bytes.
+    pub fn fingerprint(&self) -> String {
+        hex::encode(Sha256::digest(&self.bytes))
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self.algorithm {
+            KeyAlgorithm::Ed25519 => {
+                let Ok(key) = <[u8; 32]>::try_from(self.bytes.as_slice()) else {
+                    return false;
+                };
+                let (Ok(key), Ok(signature)) = (
+                    ed25519_dalek::VerifyingKey::from_bytes(&key),
+                    ed25519_dalek::Signature::from_slice(signature),
+                ) else {
+                    return false;
+                };
+                key.verify_strict(message, &signature).is_ok()
+            }
+            KeyAlgorithm::P256 => {
+                use p256::ecdsa::signature::Verifier;
+                let Ok(key) = p256::ecdsa::VerifyingKey::from_sec1_bytes(&self.bytes) else {
+                    return false;
+                };
+                let signature = p256::ecdsa::Signature::from_der(signature)
+                    .or_else(|_| p256::ecdsa::Signature::from_slice(signature));
+                signature.is_ok_and(|signature| key.verify(message, &signature).is_ok())
+            }
+        }
+    }
+}
+
+/// A new key together with its signature over a server nonce, proving the
+/// client holds the private half. Sent when enrolling or rotating.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct KeyProof {
+    pub algorithm: KeyAlgorithm,
+    pub public_key: String,
+    pub nonce: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedNonce {
+    pub nonce: String,
+    pub purpose: AttestationPurpose,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AttestedDevice {
+    pub id: Uuid,
+    pub fingerprint: String,
+}
+
+#[derive(Debug)]
+pub enum Attestation {
+    /// The request carried no device headers
+    Absent,
+    Verified(AttestedDevice),
+    Rejected(&'static str),
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceKeyRecord {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub fingerprint: String,
+    pub platform: String,
+    pub status: String,
+    pub key_algorithm: Option<String>,
+    pub enrolled_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub key_rotated_at: Option<DateTime<Utc>>,
+    pub key_revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Issues nonces and checks device signatures against `desktop_devices`.
+pub struct AttestationService {
+    pool: PgPool,
+    nonce_ttl: Duration,
+    required: bool,
+}
+
+impl AttestationService {
+    pub fn new(config: &AppConfig, pool: PgPool) -> Self {
+        Self {
+            pool,
+            nonce_ttl: Duration::seconds(config.device_nonce_ttl_seconds as i64),
+            required: config.device_attestation_required,
+        }
+    }
+
+    /// Whether requests without a device signature are refused.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    pub async fn issue_nonce(&self, purpose: AttestationPurpose) -> Result<IssuedNonce> {
+        let mut bytes = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let nonce = URL_SAFE_NO_PAD.encode(bytes);
+        let expires_at = Utc::now() + self.nonce_ttl;
+
+        sqlx::query!("DELETE FROM device_nonces WHERE expires_at <= NOW()")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query!(
+            "INSERT INTO device_nonces (nonce, purpose, expires_at) VALUES ($1, $2, $3)",
+            nonce,
+            purpose.as_str(),
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(IssuedNonce { nonce, purpose, expires_at })
+    }
+
+    /// Burns a nonce; true only if it was issued for `purpose` and has not expired.
+    async fn consume_nonce(&self, nonce: &str, purpose: AttestationPurpose) -> Result<bool> {
+        let expires_at = sqlx::query_scalar!(
+            "DELETE FROM device_nonces WHERE nonce = $1 AND purpose = $2 RETURNING expires_at",
+            nonce,
+            purpose.as_str()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(expires_at.is_some_and(|expires_at| expires_at > Utc::now()))
+    }
+
+    /// Checks a new key's proof of possession and returns the key.
+    pub async fn prove_possession(
+        &self,
+        proof: &KeyProof,
+        purpose: AttestationPurpose,
+    ) -> Result<Result<DevicePublicKey, &'static str>> {
+        let key = match DevicePublicKey::parse(proof.algorithm, &proof.public_key) {
+            Ok(key) => key,
+            Err(reason) => return Ok(Err(reason)),
+        };
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(proof.signature.trim()) else {
+            return Ok(Err("signature is not valid base64url"));
+        };
+        if !self.consume_nonce(&proof.nonce, purpose).await? {
+            return Ok(Err("nonce is unknown, used or expired"));
+        }
+        if !key.verify(&signed_message(purpose, &proof.nonce), &signature) {
+            return Ok(Err("signature does not match the public key"));
+        }
+
+        Ok(Ok(key))
+    }
+
+    /// Verifies the request's device headers against the user's approved devices.
+    pub async fn verify(
+        &self,
+        user_id: Uuid,
+        context: &ClientContext,
+        purpose: AttestationPurpose,
+    ) -> Result<Attestation> {
+        let Some(fingerprint) = context.device_id.as_deref() else {
+            return Ok(Attestation::Absent);
+        };
+        let (Some(nonce), Some(signature)) = (context.device_nonce.as_deref(), context.device_signature.as_deref())
+        else {
+            return Ok(Attestation::Rejected("device signature missing"));
+        };
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+            return Ok(Attestation::Rejected("device signature is not valid base64url"));
+        };
+        if !self.consume_nonce(nonce, purpose).await? {
+            return Ok(Attestation::Rejected("device nonce is unknown, used or expired"));
+        }
+
+        let device = sqlx::query!(
+            r#"
+            SELECT id, device_pubkey, key_algorithm, status::text AS status, key_revoked_at
+            FROM desktop_devices
+            WHERE user_id = $1 AND fingerprint = $2
+            "#,
+            user_id,
+            fingerprint
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(device) = device else {
+            return Ok(Attestation::Rejected("device is not enrolled"));
+        };
+        if device.status.as_deref() != Some("approved") {
+            return Ok(Attestation::Rejected("device is not approved"));
+        }
+        if device.key_revoked_at.is_some() {
+            return Ok(Attestation::Rejected("device key has been revoked"));
+        }
+        let Some(key) = device
+            .key_algorithm
+            .as_deref()
+            .and_then(KeyAlgorithm::parse)
+            .and_then(|algorithm| DevicePublicKey::parse(algorithm, &device.device_pubkey).ok())
+        else {
+            return Ok(Attestation::Rejected("device has no registered key"));
+        };
+        if !key.verify(&signed_message(purpose, nonce), &signature) {
+            return Ok(Attestation::Rejected("device signature is invalid"));
+        }
+
+        sqlx::query!("UPDATE desktop_devices SET last_used_at = NOW() WHERE id = $1", device.id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(Attestation::Verified(AttestedDevice {
+            id: device.id,
+            fingerprint: fingerprint.to_string(),
+        }))
+    }
+
+    /// The device a session was started from, if it was attested.
+    pub async fn session_device(&self, session_id: Uuid) -> Result<Option<Uuid>> {
+        let device_id = sqlx::query_scalar!("SELECT device_id FROM sessions WHERE id = $1", session_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(device_id.flatten())
+    }
+
+    /// Checks a request made on a session against the device the session is
+    /// bound to. Returns the reason when it must be refused.
+    pub async fn verify_bound(
+        &self,
+        bound_device: Option<Uuid>,
+        user_id: Uuid,
+        context: &ClientContext,
+        purpose: AttestationPurpose,
+    ) -> Result<Option<&'static str>> {
+        let Some(bound_device) = bound_device else {
+            return Ok(self.required.then_some("session is not bound to a device"));
+        };
+
+        Ok(match self.verify(user_id, context, purpose).await? {
+            Attestation::Verified(device) if device.id == bound_device => None,
+            Attestation::Verified(_) => Some("request was signed by a different device"),
+            Attestation::Absent => Some("device signature required"),
+            Attestation::Rejected(reason) => Some(reason),
+        })
+    }
+
+    /// Replaces the key of an attested device after the new key proved possession.
+    pub async fn rotate_key(&self, device: &AttestedDevice, user_id: Uuid, key: &DevicePublicKey) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE desktop_devices
+            SET device_pubkey = $2, key_algorithm = $3, key_rotated_at = NOW()
+            WHERE id = $1
+            "#,
+            device.id,
+            key.encoded(),
+            key.algorithm.as_str()
+        )
+        .execute(&self.pool)
+        .await?;
+
+        AuditEvent::new(AuditEventType::DeviceKeyRotated)
+            .user(Some(user_id))
+            .details(serde_json::json!({ "device_id": device.id, "fingerprint": device.fingerprint, "algorithm": key.algorithm }))
+            .write(&self.pool)
+            .await
+    }
+
+    /// Revokes a device's key and terminates the sessions bound to it. The
+    /// device must enroll a new key before it can attest again. Returns false
+    /// if the device does not exist or its key is already revoked.
+    pub async fn revoke_key(&self, device_id: Uuid, admin_id: Option<Uuid>, reason: Option<&str>) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let device = sqlx::query!(
+            r#"
+            UPDATE desktop_devices SET key_revoked_at = NOW()
+            WHERE id = $1 AND key_revoked_at IS NULL
+            RETURNING user_id, fingerprint
+            "#,
+            device_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(device) = device else {
+            return Ok(false);
+        };
+
+        let terminated = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET status = 'terminated', terminated_at = NOW(), terminated_reason = 'device_key_revoked'
+            WHERE device_id = $1 AND status = 'active'
+            "#,
+            device_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        AuditEvent::new(AuditEventType::DeviceKeyRevoked)
+            .user(admin_id)
+            .target(device.user_id)
+            .details(serde_json::json!({
+                "device_id": device_id,
+                "fingerprint": device.fingerprint,
+                "reason": reason,
+                "sessions_terminated": terminated
+            }))
+            .write(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Desktop devices with their key state, newest first.
+    pub async fn list_devices(&self, user_id: Option<Uuid>) -> Result<Vec<DeviceKeyRecord>> {
+        let devices = sqlx::query_as!(
+            DeviceKeyRecord,
+            r#"
+            SELECT d.id, d.user_id, u.username, d.fingerprint, d.platform, d.status::text AS "status!",
+                   d.key_algorithm, d.enrolled_at, d.last_used_at, d.key_rotated_at, d.key_revoked_at
+            FROM desktop_devices d
+            JOIN users u ON u.id = d.user_id
+            WHERE $1::uuid IS NULL OR d.user_id = $1
+            ORDER BY d.enrolled_at DESC NULLS LAST
+            LIMIT 500
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(devices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ed25519_signature_over_purpose_and_nonce() {
+        use ed25519_dalek::Signer;
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let encoded = URL_SAFE_NO_PAD.encode(signing_key.verifying_key().as_bytes());
+        let key = DevicePublicKey::parse(KeyAlgorithm::Ed25519, &encoded).unwrap();
+
+        let signature = signing_key.sign(&signed_message(AttestationPurpose::Login, "abc"));
+        assert!(key.verify(&signed_message(AttestationPurpose::Login, "abc"), &signature.to_bytes()));
+        assert!(!key.verify(&signed_message(AttestationPurpose::Refresh, "abc"), &signature.to_bytes()));
+        assert_eq!(key.fingerprint().len(), 64);
+    }
+
+    #[test]
+    fn test_p256_accepts_der_and_fixed_signatures() {
+        use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+        let signing_key = SigningKey::from_slice(&[9u8; 32]).unwrap();
+        let point = signing_key.verifying_key().to_encoded_point(true);
+        let key = DevicePublicKey::parse(KeyAlgorithm::P256, &URL_SAFE_NO_PAD.encode(point.as_bytes())).unwrap();
+
+        let message = signed_message(AttestationPurpose::Bootstrap, "n");
+        let signature: Signature = signing_key.sign(&message);
+        assert!(key.verify(&message, signature.to_der().as_bytes()));
+        assert!(key.verify(&message, &signature.to_bytes()));
+        assert!(DevicePublicKey::parse(KeyAlgorithm::P256, &URL_SAFE_NO_PAD.encode([1u8; 33])).is_err());
+    }
+}
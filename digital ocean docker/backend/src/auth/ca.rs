@@ -0,0 +1,790 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertificateRevocationListParams,
+    CertificateSigningRequestParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod,
+    KeyPair, KeyUsagePurpose, PublicKeyData, RevocationReason, RevokedCertParams, SanType, SerialNumber,
+};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+use uuid::Uuid;
+use crate::config::AppConfig;
+
+const ROOT_COMMON_NAME: &str = "ViWorkS Root CA";
+const INTERMEDIATE_COMMON_NAME: &str = "ViWorkS Issuing CA";
+const CA_ORGANIZATION: &str = "ViWorkS";
+const ROOT_VALIDITY_DAYS: i64 = 3650;
+const INTERMEDIATE_VALIDITY_DAYS: i64 = 1095;
+/// CRLs are generated on request; relying parties should refetch daily
+const CRL_VALIDITY_HOURS: i64 = 24;
+/// Tolerate client clocks that run slightly behind
+const NOT_BEFORE_SKEW_MINUTES: i64 = 5;
+
+/// Reasons an administrator may give when revoking a certificate by hand
+pub const ADMIN_REVOCATION_REASONS: &[&str] = &[
+    "unspecified",
+    "key_compromise",
+    "affiliation_changed",
+    "superseded",
+    "cessation_of_operation",
+    "privilege_withdrawn",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertificateKind {
+    /// Desktop clients connecting to the gateway's stunnel
+    Client,
+    /// OS/gateway agents (mTLS in both directions)
+    Agent,
+}
+
+impl CertificateKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CertificateKind::Client => "client",
+            CertificateKind::Agent => "agent",
+        }
+    }
+}
+
+/// A certificate together with the private key the CA generated for it.
+#[derive(Debug, Clone)]
+pub struct IssuedCertificate {
+    pub serial: String,
+    pub cert_pem: String,
+    /// Issuing intermediate, to be presented along with `cert_pem`
+    pub chain_pem: String,
+    pub key_pem: String,
+    pub not_after: DateTime<Utc>,
+}
+
+/// A certificate signed from a CSR; the requester keeps the private key.
+#[derive(Debug, Clone, Serialize)]
+pub struct SignedCertificate {
+    pub serial: String,
+    pub cert_pem: String,
+    pub chain_pem: String,
+    pub not_after: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum CsrOutcome {
+    Signed(SignedCertificate),
+    Rejected(&'static str),
+}
+
+/// OCSP-style answer for one serial.
+#[derive(Debug, Serialize)]
+pub struct CertificateStatus {
+    pub serial: String,
+    /// "good", "revoked" or "expired"
+    pub status: &'static str,
+    pub kind: String,
+    pub issuer_id: Option<Uuid>,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub revocation_reason: Option<String>,
+    pub produced_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntermediateInfo {
+    pub id: Uuid,
+    pub serial: String,
+    pub not_after: DateTime<Utc>,
+    pub active: bool,
+    pub retired_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotatedIntermediate {
+    pub id: Uuid,
+    pub serial: String,
+    pub not_after: DateTime<Utc>,
+    pub previous_id: Option<Uuid>,
+    /// Unexpired certificates revoked along with a compromised intermediate
+    pub revoked_certificates: u64,
+}
+
+struct Signer {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+struct Intermediate {
+    id: Uuid,
+    cert_pem: String,
+    not_after: DateTime<Utc>,
+    signer: Signer,
+}
+
+struct GeneratedCa {
+    serial: String,
+    cert_pem: String,
+    key_pem: String,
+    not_after: DateTime<Utc>,
+}
+
+/// Two-tier internal certificate authority for stunnel client and agent mTLS
+/// certificates.
+///
+/// The root only signs intermediates and the root CRL; leaf certificates come
+/// from the active intermediate. Devices trust the root, so rotating the
+/// intermediate needs no re-enrollment: retired intermediates keep verifying
+/// (and publishing a CRL) until they expire. Keys live in `certificate_authority`
+/// and `ca_intermediates`, encrypted with CA_ENCRYPTION_KEY, so every replica
+/// signs with the same CA. Each signed certificate is recorded in
+/// `issued_certificates`.
+pub struct CertificateAuthority {
+    pool: PgPool,
+    cipher: Aes256Gcm,
+    root_pem: String,
+    root: Signer,
+    issuing: Mutex<Arc<Intermediate>>,
+}
+
+impl CertificateAuthority {
+    pub async fn load_or_create(config: &AppConfig, pool: PgPool) -> Result<Self> {
+        let cipher = Aes256Gcm::new(&config.encryption_key(&config.ca_encryption_key, "ca")?.into());
+
+        let existing = sqlx::query!("SELECT cert_pem, key_ciphertext, key_nonce FROM certificate_authority WHERE id = 1")
+            .fetch_optional(&pool)
+            .await?;
+
+        if existing.is_none() {
+            let root = generate_root()?;
+            let (ciphertext, nonce) = encrypt(&cipher, root.key_pem.as_bytes())?;
+            // Another replica may have raced us; whichever row landed first wins
+            sqlx::query!(
+                r#"
+                INSERT INTO certificate_authority (id, cert_pem, key_ciphertext, key_nonce)
+                VALUES (1, $1, $2, $3)
+                ON CONFLICT (id) DO NOTHING
+                "#,
+                root.cert_pem,
+                ciphertext,
+                nonce
+            )
+            .execute(&pool)
+            .await?;
+        }
+
+        let row = sqlx::query!("SELECT cert_pem, key_ciphertext, key_nonce FROM certificate_authority WHERE id = 1")
+            .fetch_one(&pool)
+            .await?;
+        let root = load_signer(&cipher, &row.cert_pem, &row.key_ciphertext, &row.key_nonce)?;
+
+        let active = match load_active_intermediate(&pool, &cipher).await? {
+            Some(active) => active,
+            None => {
+                let generated = generate_intermediate(&root)?;
+                let (ciphertext, nonce) = encrypt(&cipher, generated.key_pem.as_bytes())?;
+                // The unique index on the active flag settles races between replicas
+                sqlx::query!(
+                    r#"
+                    INSERT INTO ca_intermediates (serial, cert_pem, key_ciphertext, key_nonce, not_after)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT DO NOTHING
+                    "#,
+                    generated.serial,
+                    generated.cert_pem,
+                    ciphertext,
+                    nonce,
+                    generated.not_after
+                )
+                .execute(&pool)
+                .await?;
+
+                load_active_intermediate(&pool, &cipher)
+                    .await?
+                    .ok_or_else(|| anyhow!("No active CA intermediate"))?
+            }
+        };
+
+        Ok(Self {
+            pool,
+            cipher,
+            root_pem: row.cert_pem,
+            root,
+            issuing: Mutex::new(Arc::new(active)),
+        })
+    }
+
+    /// PEM of the root certificate, the trust anchor for clients, agents and the gateway.
+    pub fn ca_pem(&self) -> &str {
+        &self.root_pem
+    }
+
+    /// Active intermediate followed by the root.
+    pub async fn chain_pem(&self) -> Result<String> {
+        let intermediate = self.issuing_intermediate().await?;
+        Ok(format!("{}{}", intermediate.cert_pem, self.root_pem))
+    }
+
+    /// Signs a fresh client-auth key pair for `common_name`, valid until `not_after`
+    /// (or the intermediate's expiry, whichever comes first).
+    pub async fn issue_client_certificate(
+        &self,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        common_name: &str,
+        not_after: DateTime<Utc>,
+    ) -> Result<IssuedCertificate> {
+        let key_pair = KeyPair::generate()?;
+        let signed = self
+            .sign(CertificateKind::Client, &key_pair, Some(user_id), session_id, common_name, not_after)
+            .await?;
+
+        Ok(IssuedCertificate {
+            serial: signed.serial,
+            cert_pem: signed.cert_pem,
+            chain_pem: signed.chain_pem,
+            key_pem: key_pair.serialize_pem(),
+            not_after: signed.not_after,
+        })
+    }
+
+    /// Signs the public key of a PEM CSR. The subject and extensions requested in
+    /// the CSR are ignored; the CA decides them from `kind` and `common_name`.
+    pub async fn sign_csr(
+        &self,
+        csr_pem: &str,
+        kind: CertificateKind,
+        user_id: Option<Uuid>,
+        session_id: Option<Uuid>,
+        common_name: &str,
+        not_after: DateTime<Utc>,
+    ) -> Result<CsrOutcome> {
+        // Parsing also checks the CSR's self-signature (proof of key possession)
+        let Ok(csr) = CertificateSigningRequestParams::from_pem(csr_pem) else {
+            return Ok(CsrOutcome::Rejected("Invalid certificate signing request"));
+        };
+
+        let signed = self
+            .sign(kind, &csr.public_key, user_id, session_id, common_name, not_after)
+            .await?;
+        Ok(CsrOutcome::Signed(signed))
+    }
+
+    /// Marks a certificate revoked. Returns false if it was unknown or already revoked.
+    pub async fn revoke(&self, serial: &str, reason: &str) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE issued_certificates SET revoked_at = NOW(), revocation_reason = $2
+            WHERE serial = $1 AND revoked_at IS NULL
+            "#,
+            serial,
+            reason
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes unexpired client certificates whose session is no longer active.
+    /// Client certificates are always session-bound.
+    pub async fn revoke_ended_session_certificates(&self) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE issued_certificates c SET revoked_at = NOW(), revocation_reason = 'session_ended'
+            WHERE c.kind = 'client' AND c.revoked_at IS NULL AND c.not_after > NOW()
+              AND NOT EXISTS (
+                  SELECT 1 FROM sessions s
+                  WHERE s.id = c.session_id AND s.status = 'active' AND s.expires_at > NOW()
+              )
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn status(&self, serial: &str) -> Result<Option<CertificateStatus>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT serial, kind, issuer_id, not_before, not_after, revoked_at, revocation_reason
+            FROM issued_certificates
+            WHERE serial = $1
+            "#,
+            serial.to_ascii_lowercase()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let now = Utc::now();
+        Ok(row.map(|row| CertificateStatus {
+            status: if row.revoked_at.is_some() {
+                "revoked"
+            } else if row.not_after <= now {
+                "expired"
+            } else {
+                "good"
+            },
+            serial: row.serial,
+            kind: row.kind,
+            issuer_id: row.issuer_id,
+            not_before: row.not_before,
+            not_after: row.not_after,
+            revoked_at: row.revoked_at,
+            revocation_reason: row.revocation_reason,
+            produced_at: now,
+        }))
+    }
+
+    /// PEM bundle of the root CRL (revoked intermediates) followed by one CRL per
+    /// unexpired intermediate, suitable for stunnel's CRLfile.
+    pub async fn crl_pem(&self) -> Result<String> {
+        let now = Utc::now();
+
+        let revoked_intermediates = sqlx::query!(
+            r#"
+            SELECT serial, revoked_at AS "revoked_at!"
+            FROM ca_intermediates
+            WHERE revoked_at IS NOT NULL AND not_after > NOW()
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut bundle = build_crl(
+            &self.root,
+            revoked_intermediates
+                .into_iter()
+                .map(|row| (row.serial, row.revoked_at, "ca_compromise".to_string()))
+                .collect(),
+            now,
+        )?;
+
+        let intermediates = sqlx::query!(
+            r#"
+            SELECT id, cert_pem, key_ciphertext, key_nonce
+            FROM ca_intermediates
+            WHERE not_after > NOW()
+            ORDER BY created_at
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for intermediate in intermediates {
+            let signer = load_signer(
+                &self.cipher,
+                &intermediate.cert_pem,
+                &intermediate.key_ciphertext,
+                &intermediate.key_nonce,
+            )?;
+            let revoked = sqlx::query!(
+                r#"
+                SELECT serial, revoked_at AS "revoked_at!", revocation_reason
+                FROM issued_certificates
+                WHERE issuer_id = $1 AND revoked_at IS NOT NULL AND not_after > NOW()
+                "#,
+                intermediate.id
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            bundle.push_str(&build_crl(
+                &signer,
+                revoked
+                    .into_iter()
+                    .map(|row| (row.serial, row.revoked_at, row.revocation_reason.unwrap_or_default()))
+                    .collect(),
+                now,
+            )?);
+        }
+
+        Ok(bundle)
+    }
+
+    pub async fn list_intermediates(&self) -> Result<Vec<IntermediateInfo>> {
+        let intermediates = sqlx::query_as!(
+            IntermediateInfo,
+            r#"
+            SELECT id, serial, not_after, active, retired_at, revoked_at, created_at
+            FROM ca_intermediates
+            ORDER BY created_at DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(intermediates)
+    }
+
+    /// Replaces the active intermediate. The previous one is retired (still trusted
+    /// until it expires) or, with `revoke_previous`, revoked in the root CRL along
+    /// with every unexpired certificate it signed.
+    pub async fn rotate_intermediate(&self, revoke_previous: bool) -> Result<RotatedIntermediate> {
+        let generated = generate_intermediate(&self.root)?;
+        let (ciphertext, nonce) = encrypt(&self.cipher, generated.key_pem.as_bytes())?;
+
+        let mut tx = self.pool.begin().await?;
+
+        let previous_id = sqlx::query_scalar!(
+            r#"
+            UPDATE ca_intermediates
+            SET active = FALSE, retired_at = NOW(), revoked_at = CASE WHEN $1::BOOLEAN THEN NOW() END
+            WHERE active
+            RETURNING id
+            "#,
+            revoke_previous
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO ca_intermediates (serial, cert_pem, key_ciphertext, key_nonce, not_after)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id
+            "#,
+            generated.serial,
+            generated.cert_pem,
+            ciphertext,
+            nonce,
+            generated.not_after
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let mut revoked_certificates = 0;
+        if let (true, Some(previous_id)) = (revoke_previous, previous_id) {
+            revoked_certificates = sqlx::query!(
+                r#"
+                UPDATE issued_certificates SET revoked_at = NOW(), revocation_reason = 'ca_compromise'
+                WHERE issuer_id = $1 AND revoked_at IS NULL AND not_after > NOW()
+                "#,
+                previous_id
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+
+        tx.commit().await?;
+
+        let key = KeyPair::from_pem(&generated.key_pem)?;
+        let cert = CertificateParams::from_ca_cert_pem(&generated.cert_pem)?.self_signed(&key)?;
+        *self.issuing.lock().unwrap_or_else(|e| e.into_inner()) = Arc::new(Intermediate {
+            id,
+            cert_pem: generated.cert_pem,
+            not_after: generated.not_after,
+            signer: Signer { cert, key },
+        });
+
+        Ok(RotatedIntermediate {
+            id,
+            serial: generated.serial,
+            not_after: generated.not_after,
+            previous_id,
+            revoked_certificates,
+        })
+    }
+
+    /// The active intermediate, reloaded if another replica rotated it.
+    async fn issuing_intermediate(&self) -> Result<Arc<Intermediate>> {
+        let active_id = sqlx::query_scalar!("SELECT id FROM ca_intermediates WHERE active")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let cached = self.issuing.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if Some(cached.id) == active_id {
+            return Ok(cached);
+        }
+
+        let active = Arc::new(
+            load_active_intermediate(&self.pool, &self.cipher)
+                .await?
+                .ok_or_else(|| anyhow!("No active CA intermediate"))?,
+        );
+        *self.issuing.lock().unwrap_or_else(|e| e.into_inner()) = active.clone();
+        Ok(active)
+    }
+
+    async fn sign(
+        &self,
+        kind: CertificateKind,
+        public_key: &impl PublicKeyData,
+        user_id: Option<Uuid>,
+        session_id: Option<Uuid>,
+        common_name: &str,
+        not_after: DateTime<Utc>,
+    ) -> Result<SignedCertificate> {
+        let intermediate = self.issuing_intermediate().await?;
+        let serial = generate_serial();
+        let not_before = Utc::now() - Duration::minutes(NOT_BEFORE_SKEW_MINUTES);
+        let not_after = not_after.min(intermediate.not_after);
+
+        let params = leaf_params(kind, common_name, &serial, not_before, not_after)?;
+        let cert = params.signed_by(public_key, &intermediate.signer.cert, &intermediate.signer.key)?;
+        let serial = hex::encode(serial);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO issued_certificates
+                (serial, user_id, session_id, common_name, not_before, not_after, issuer_id, kind)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            serial,
+            user_id,
+            session_id,
+            common_name,
+            not_before,
+            not_after,
+            intermediate.id,
+            kind.as_str()
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(SignedCertificate {
+            serial,
+            cert_pem: cert.pem(),
+            chain_pem: intermediate.cert_pem.clone(),
+            not_after,
+        })
+    }
+}
+
+async fn load_active_intermediate(pool: &PgPool, cipher: &Aes256Gcm) -> Result<Option<Intermediate>> {
+    let row = sqlx::query!(
+        "SELECT id, cert_pem, key_ciphertext, key_nonce, not_after FROM ca_intermediates WHERE active"
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let signer = load_signer(cipher, &row.cert_pem, &row.key_ciphertext, &row.key_nonce)?;
+    Ok(Some(Intermediate {
+        id: row.id,
+        cert_pem: row.cert_pem,
+        not_after: row.not_after,
+        signer,
+    }))
+}
+
+fn load_signer(cipher: &Aes256Gcm, cert_pem: &str, key_ciphertext: &[u8], key_nonce: &[u8]) -> Result<Signer> {
+    let key_pem = String::from_utf8(decrypt(cipher, key_ciphertext, key_nonce)?)?;
+    let key = KeyPair::from_pem(&key_pem)?;
+    // rcgen signs with an issuer `Certificate`; rebuilding one from the stored
+    // certificate keeps the subject and key identifier of the distributed CA cert
+    let cert = CertificateParams::from_ca_cert_pem(cert_pem)?.self_signed(&key)?;
+    Ok(Signer { cert, key })
+}
+
+fn ca_params(common_name: &str, path_len: u8, serial: &[u8], not_after: DateTime<Utc>) -> Result<CertificateParams> {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, common_name);
+    params.distinguished_name.push(DnType::OrganizationName, CA_ORGANIZATION);
+    params.serial_number = Some(SerialNumber::from_slice(serial));
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(path_len));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign, KeyUsagePurpose::DigitalSignature];
+    params.not_before = to_offset_date_time(Utc::now() - Duration::minutes(NOT_BEFORE_SKEW_MINUTES))?;
+    params.not_after = to_offset_date_time(not_after)?;
+    Ok(params)
+}
+
+fn generate_root() -> Result<GeneratedCa> {
+    let key_pair = KeyPair::generate()?;
+    let not_after = Utc::now() + Duration::days(ROOT_VALIDITY_DAYS);
+    let serial = generate_serial();
+    let params = ca_params(ROOT_COMMON_NAME, 1, &serial, not_after)?;
+
+    let cert = params.self_signed(&key_pair)?;
+    Ok(GeneratedCa {
+        serial: hex::encode(serial),
+        cert_pem: cert.pem(),
+        key_pem: key_pair.serialize_pem(),
+        not_after,
+    })
+}
+
+fn generate_intermediate(root: &Signer) -> Result<GeneratedCa> {
+    let key_pair = KeyPair::generate()?;
+    // An intermediate cannot outlive the root that vouches for it
+    let root_not_after = DateTime::from_timestamp(root.cert.params().not_after.unix_timestamp(), 0)
+        .ok_or_else(|| anyhow!("Invalid root CA expiry"))?;
+    let not_after = (Utc::now() + Duration::days(INTERMEDIATE_VALIDITY_DAYS)).min(root_not_after);
+
+    let serial = generate_serial();
+    let common_name = format!("{} {}", INTERMEDIATE_COMMON_NAME, &hex::encode(serial)[..8]);
+    let mut params = ca_params(&common_name, 0, &serial, not_after)?;
+    params.use_authority_key_identifier_extension = true;
+
+    let cert = params.signed_by(&key_pair, &root.cert, &root.key)?;
+    Ok(GeneratedCa {
+        serial: hex::encode(serial),
+        cert_pem: cert.pem(),
+        key_pem: key_pair.serialize_pem(),
+        not_after,
+    })
+}
+
+fn leaf_params(
+    kind: CertificateKind,
+    common_name: &str,
+    serial: &[u8],
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+) -> Result<CertificateParams> {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, common_name);
+    params.distinguished_name.push(DnType::OrganizationName, CA_ORGANIZATION);
+    params.serial_number = Some(SerialNumber::from_slice(serial));
+    params.not_before = to_offset_date_time(not_before)?;
+    params.not_after = to_offset_date_time(not_after)?;
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.use_authority_key_identifier_extension = true;
+
+    match kind {
+        CertificateKind::Client => {
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        }
+        CertificateKind::Agent => {
+            // Agents dial out to the backend and accept connections on their own API
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth, ExtendedKeyUsagePurpose::ServerAuth];
+            params.subject_alt_names = vec![SanType::DnsName(common_name.try_into()?)];
+        }
+    }
+
+    Ok(params)
+}
+
+/// Signs a CRL over `(serial hex, revoked at, reason)` entries. The CRL number is
+/// the issue time, which keeps it increasing across replicas.
+fn build_crl(issuer: &Signer, revoked: Vec<(String, DateTime<Utc>, String)>, now: DateTime<Utc>) -> Result<String> {
+    let revoked_certs = revoked
+        .into_iter()
+        .map(|(serial, revoked_at, reason)| {
+            Ok(RevokedCertParams {
+                serial_number: SerialNumber::from_slice(&hex::decode(serial)?),
+                revocation_time: to_offset_date_time(revoked_at)?,
+                reason_code: Some(revocation_reason_code(&reason)),
+                invalidity_date: None,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let params = CertificateRevocationListParams {
+        this_update: to_offset_date_time(now)?,
+        next_update: to_offset_date_time(now + Duration::hours(CRL_VALIDITY_HOURS))?,
+        crl_number: SerialNumber::from(now.timestamp() as u64),
+        issuing_distribution_point: None,
+        revoked_certs,
+        // The authority key identifier must match the issuer's subject key identifier
+        key_identifier_method: match &issuer.cert.params().key_identifier_method {
+            KeyIdMethod::PreSpecified(id) => KeyIdMethod::PreSpecified(id.clone()),
+            _ => KeyIdMethod::Sha256,
+        },
+    };
+
+    Ok(params.signed_by(&issuer.cert, &issuer.key)?.pem()?)
+}
+
+/// Maps our revocation reasons onto RFC 5280 reason codes
+fn revocation_reason_code(reason: &str) -> RevocationReason {
+    match reason {
+        "key_compromise" => RevocationReason::KeyCompromise,
+        "ca_compromise" => RevocationReason::CaCompromise,
+        "affiliation_changed" => RevocationReason::AffiliationChanged,
+        "superseded" => RevocationReason::Superseded,
+        "privilege_withdrawn" => RevocationReason::PrivilegeWithdrawn,
+        "cessation_of_operation" | "session_ended" | "expired" | "provisioning_failed" | "ca_replaced" => {
+            RevocationReason::CessationOfOperation
+        }
+        _ => RevocationReason::Unspecified,
+    }
+}
+
+/// 128-bit positive serial (RFC 5280 allows up to 20 octets)
+fn generate_serial() -> [u8; 16] {
+    let mut serial = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut serial);
+    // Keep the DER INTEGER positive and without a leading zero octet
+    serial[0] = (serial[0] & 0x3f) | 0x40;
+    serial
+}
+
+fn to_offset_date_time(at: DateTime<Utc>) -> Result<OffsetDateTime> {
+    Ok(OffsetDateTime::from_unix_timestamp(at.timestamp())?)
+}
+
+fn encrypt(cipher: &Aes256Gcm, plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow!("Failed to encrypt CA key"))?;
+    Ok((ciphertext, nonce.to_vec()))
+}
+
+fn decrypt(cipher: &Aes256Gcm, ciphertext: &[u8], nonce: &[u8]) -> Result<Vec<u8>> {
+    if nonce.len() != 12 {
+        return Err(anyhow!("Invalid CA key nonce"));
+    }
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt CA key (wrong CA_ENCRYPTION_KEY?)"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(generated: &GeneratedCa) -> Signer {
+        let key = KeyPair::from_pem(&generated.key_pem).unwrap();
+        let cert = CertificateParams::from_ca_cert_pem(&generated.cert_pem).unwrap().self_signed(&key).unwrap();
+        Signer { cert, key }
+    }
+
+    #[test]
+    fn test_intermediate_chains_to_root_through_pem() {
+        let root = signer(&generate_root().unwrap());
+        assert!(matches!(root.cert.params().is_ca, IsCa::Ca(BasicConstraints::Constrained(1))));
+
+        let generated = generate_intermediate(&root).unwrap();
+        let intermediate = signer(&generated);
+        assert!(matches!(intermediate.cert.params().is_ca, IsCa::Ca(BasicConstraints::Constrained(0))));
+        assert!(generated.not_after <= Utc::now() + Duration::days(INTERMEDIATE_VALIDITY_DAYS));
+
+        let now = Utc::now();
+        let leaf_key = KeyPair::generate().unwrap();
+        let params = leaf_params(CertificateKind::Agent, "gw-agent-01", &generate_serial(), now, now + Duration::days(1)).unwrap();
+        assert!(params.signed_by(&leaf_key, &intermediate.cert, &intermediate.key).is_ok());
+    }
+
+    #[test]
+    fn test_crl_lists_revoked_serials() {
+        let root = signer(&generate_root().unwrap());
+        let serial = hex::encode(generate_serial());
+        let crl = build_crl(&root, vec![(serial, Utc::now(), "key_compromise".to_string())], Utc::now()).unwrap();
+        assert!(crl.starts_with("-----BEGIN X509 CRL-----"));
+        assert!(build_crl(&root, vec![("not-hex".to_string(), Utc::now(), String::new())], Utc::now()).is_err());
+    }
+
+    #[test]
+    fn test_serial_is_positive_and_full_length() {
+        for _ in 0..32 {
+            let serial = generate_serial();
+            assert!(serial[0] & 0x80 == 0 && serial[0] != 0);
+        }
+    }
+}
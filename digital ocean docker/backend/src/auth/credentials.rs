@@ -0,0 +1,320 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::auth::ca::{CertificateAuthority, IssuedCertificate};
+use crate::config::AppConfig;
+use crate::services::gateway::GatewayClient;
+
+/// fwknop expects a 256-bit Rijndael key and a 512-bit HMAC-SHA256 key (base64)
+const FWKNOP_KEY_LEN: usize = 32;
+const FWKNOP_HMAC_KEY_LEN: usize = 64;
+const OPENVPN_PASSWORD_LEN: usize = 24;
+
+/// Everything a desktop client needs to reach the gateway for one session.
+/// Secrets exist only in this value; the database keeps revocation handles.
+#[derive(Debug, Clone)]
+pub struct SessionCredentials {
+    pub fwknop_key_rijndael: String,
+    pub fwknop_key_hmac: String,
+    pub certificate: IssuedCertificate,
+    pub openvpn_username: String,
+    pub openvpn_password: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Why outstanding credentials should be revoked, or `None` while they are
+/// still usable. `session` is the owning session's status and expiry, absent
+/// when the session row is gone.
+fn revocation_reason(
+    session: Option<(&str, DateTime<Utc>)>,
+    credential_expires_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Option<&'static str> {
+    if credential_expires_at <= now {
+        return Some("expired");
+    }
+    match session {
+        Some(("active", session_expires_at)) if session_expires_at > now => None,
+        _ => Some("session_ended"),
+    }
+}
+
+/// Mints per-session gateway credentials (fwknop keys, a stunnel client
+/// certificate and single-use OpenVPN credentials) and revokes them when the
+/// session ends.
+pub struct CredentialService {
+    pool: PgPool,
+    ca: Arc<CertificateAuthority>,
+    gateway: Arc<GatewayClient>,
+    ttl: Duration,
+}
+
+impl CredentialService {
+    pub fn new(config: &AppConfig, pool: PgPool, ca: Arc<CertificateAuthority>, gateway: Arc<GatewayClient>) -> Self {
+        Self {
+            pool,
+            ca,
+            gateway,
+            ttl: Duration::seconds(config.client_credential_ttl as i64),
+        }
+    }
+
+    pub fn ca(&self) -> &CertificateAuthority {
+        &self.ca
+    }
+
+    /// Mints a fresh set for `session_id`, revoking any set minted earlier for it.
+    /// `None` if the session is no longer active. The session row stays locked
+    /// until the new set is recorded, so concurrent mints for one session run
+    /// one after the other and leave a single live set.
+    pub async fn mint_for_session(&self, user_id: Uuid, username: &str, session_id: Uuid) -> Result<Option<SessionCredentials>> {
+        let mut tx = self.pool.begin().await?;
+        let session_expires_at = sqlx::query_scalar!(
+            r#"
+            SELECT expires_at FROM sessions
+            WHERE id = $1 AND user_id = $2 AND status = 'active' AND expires_at > NOW()
+            FOR UPDATE
+            "#,
+            session_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(session_expires_at) = session_expires_at else {
+            return Ok(None);
+        };
+
+        self.revoke_for_session(session_id, "superseded").await?;
+
+        let expires_at = (Utc::now() + self.ttl).min(session_expires_at);
+        let handle = credential_handle(session_id);
+        let credentials = SessionCredentials {
+            fwknop_key_rijndael: STANDARD.encode(random_bytes(FWKNOP_KEY_LEN)),
+            fwknop_key_hmac: STANDARD.encode(random_bytes(FWKNOP_HMAC_KEY_LEN)),
+            certificate: self
+                .ca
+                .issue_client_certificate(user_id, Some(session_id), username, expires_at)
+                .await?,
+            openvpn_username: handle.clone(),
+            openvpn_password: hex::encode(random_bytes(OPENVPN_PASSWORD_LEN)),
+            expires_at,
+        };
+
+        if let Err(e) = self.push_to_gateway(&handle, username, &credentials).await {
+            self.discard(&handle, &credentials).await?;
+            return Err(e);
+        }
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO session_credentials
+                (session_id, user_id, fwknop_access_id, openvpn_username, certificate_serial, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            session_id,
+            user_id,
+            handle,
+            credentials.openvpn_username,
+            credentials.certificate.serial,
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await;
+        let recorded = match inserted {
+            Ok(_) => tx.commit().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = recorded {
+            // Without its row the set would never be revoked
+            self.discard(&handle, &credentials).await?;
+            return Err(e.into());
+        }
+
+        Ok(Some(credentials))
+    }
+
+    /// Undoes a set that was never handed out: removes whatever made it to the
+    /// gateway and revokes its certificate.
+    async fn discard(&self, handle: &str, credentials: &SessionCredentials) -> Result<()> {
+        if let Err(e) = self.remove_from_gateway(handle, &credentials.openvpn_username).await {
+            eprintln!("Failed to remove unrecorded gateway credentials {}: {}", handle, e);
+        }
+        self.ca.revoke(&credentials.certificate.serial, "provisioning_failed").await?;
+        Ok(())
+    }
+
+    /// Revokes the live credentials of one session. Returns how many sets were revoked.
+    pub async fn revoke_for_session(&self, session_id: Uuid, reason: &str) -> Result<usize> {
+        let live = sqlx::query!(
+            r#"
+            SELECT id, fwknop_access_id, openvpn_username, certificate_serial
+            FROM session_credentials
+            WHERE session_id = $1 AND revoked_at IS NULL
+            "#,
+            session_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut revoked = 0;
+        for row in live {
+            self.revoke(row.id, &row.fwknop_access_id, &row.openvpn_username, &row.certificate_serial, reason)
+                .await?;
+            revoked += 1;
+        }
+        Ok(revoked)
+    }
+
+    /// Revokes credentials whose session has ended (terminated, expired or deleted)
+    /// or which outlived their own expiry. Gateway failures are recorded on the
+    /// row and retried on the next sweep.
+    pub async fn revoke_ended_sessions(&self) -> Result<usize> {
+        let outstanding = sqlx::query!(
+            r#"
+            SELECT c.id, c.fwknop_access_id, c.openvpn_username, c.certificate_serial, c.expires_at,
+                   s.status::text AS "session_status?", s.expires_at AS "session_expires_at?"
+            FROM session_credentials c
+            LEFT JOIN sessions s ON c.session_id = s.id
+            WHERE c.revoked_at IS NULL
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = Utc::now();
+        let mut revoked = 0;
+        for row in outstanding {
+            let session = row.session_status.as_deref().zip(row.session_expires_at);
+            let Some(reason) = revocation_reason(session, row.expires_at, now) else {
+                continue;
+            };
+            match self
+                .revoke(row.id, &row.fwknop_access_id, &row.openvpn_username, &row.certificate_serial, reason)
+                .await
+            {
+                Ok(()) => revoked += 1,
+                Err(e) => eprintln!("Failed to revoke session credentials {}: {}", row.id, e),
+            }
+        }
+        Ok(revoked)
+    }
+
+    async fn revoke(&self, id: Uuid, access_id: &str, openvpn_username: &str, serial: &str, reason: &str) -> Result<()> {
+        // The certificate is revoked first: it only depends on our own database
+        self.ca.revoke(serial, reason).await?;
+
+        if let Err(e) = self.remove_from_gateway(access_id, openvpn_username).await {
+            sqlx::query!(
+                "UPDATE session_credentials SET revocation_error = $2 WHERE id = $1",
+                id,
+                e.to_string()
+            )
+            .execute(&self.pool)
+            .await?;
+            return Err(e);
+        }
+
+        sqlx::query!(
+            "UPDATE session_credentials SET revoked_at = NOW(), revocation_error = NULL WHERE id = $1",
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn push_to_gateway(&self, handle: &str, username: &str, credentials: &SessionCredentials) -> Result<()> {
+        self.gateway
+            .command(
+                "fwknop_access_add",
+                serde_json::json!({
+                    "access_id": handle,
+                    "username": username,
+                    "key_base64": credentials.fwknop_key_rijndael,
+                    "hmac_key_base64": credentials.fwknop_key_hmac,
+                    "expires_at": credentials.expires_at.to_rfc3339()
+                }),
+            )
+            .await?;
+        self.gateway
+            .command(
+                "openvpn_create",
+                serde_json::json!({
+                    "username": credentials.openvpn_username,
+                    "password": credentials.openvpn_password,
+                    "single_use": true,
+                    "expires_at": credentials.expires_at.to_rfc3339()
+                }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_from_gateway(&self, access_id: &str, openvpn_username: &str) -> Result<()> {
+        self.gateway
+            .command("fwknop_access_remove", serde_json::json!({ "access_id": access_id }))
+            .await?;
+        self.gateway
+            .command("openvpn_delete", serde_json::json!({ "username": openvpn_username }))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Gateway-side name shared by the fwknop stanza and the OpenVPN user: the
+/// session prefix for operators, a random suffix so every mint is distinct.
+fn credential_handle(session_id: Uuid) -> String {
+    let session = session_id.simple().to_string();
+    format!("vw-{}-{}", &session[..8], hex::encode(random_bytes(4)))
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_of_a_live_session_are_kept() {
+        let now = Utc::now();
+        let session = Some(("active", now + Duration::hours(1)));
+        assert_eq!(revocation_reason(session, now + Duration::minutes(30), now), None);
+    }
+
+    #[test]
+    fn test_expired_credentials_are_revoked_first() {
+        let now = Utc::now();
+        let live = Some(("active", now + Duration::hours(1)));
+        assert_eq!(revocation_reason(live, now, now), Some("expired"));
+        assert_eq!(revocation_reason(Some(("terminated", now)), now - Duration::seconds(1), now), Some("expired"));
+    }
+
+    #[test]
+    fn test_credentials_of_ended_sessions_are_revoked() {
+        let now = Utc::now();
+        let credential_expires_at = now + Duration::minutes(30);
+        for session in [
+            None,
+            Some(("terminated", now + Duration::hours(1))),
+            Some(("expired", now + Duration::hours(1))),
+            Some(("active", now)),
+            Some(("active", now - Duration::minutes(1))),
+        ] {
+            assert_eq!(
+                revocation_reason(session, credential_expires_at, now),
+                Some("session_ended"),
+                "{:?}",
+                session
+            );
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::auth::ClientContext;
+use crate::config::AppConfig;
+
+/// Open requests a single user may have at once
+const MAX_PENDING_PER_USER: i64 = 5;
+/// Upper bound on the serialized `device_info` a client may attach
+const MAX_DEVICE_INFO_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceType {
+    Desktop,
+    Mobile,
+}
+
+impl DeviceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceType::Desktop => "desktop",
+            DeviceType::Mobile => "mobile",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceBinding {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub device_type: String,
+    pub fingerprint: String,
+    pub device_info: serde_json::Value,
+    pub status: String,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub approved_at: Option<DateTime<Utc>>,
+    pub approved_by: Option<Uuid>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub reviewed_by: Option<Uuid>,
+    pub note: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum BindOutcome {
+    Requested(DeviceBinding),
+    /// The device already has an open request; it is returned unchanged
+    AlreadyPending(DeviceBinding),
+    /// The device is already approved for this user
+    AlreadyBound,
+    TooManyPending,
+    Invalid(&'static str),
+}
+
+#[derive(Debug)]
+pub enum ReviewOutcome {
+    Done(Box<DeviceBinding>),
+    NotFound,
+    /// The request is not in a state the action applies to (e.g. approving an expired one)
+    Conflict(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Review {
+    Approve,
+    Reject,
+    Revoke,
+}
+
+impl Review {
+    fn required_status(self) -> &'static str {
+        match self {
+            Review::Approve | Review::Reject => "pending",
+            Review::Revoke => "approved",
+        }
+    }
+
+    fn resulting_status(self) -> &'static str {
+        match self {
+            Review::Approve => "approved",
+            Review::Reject => "rejected",
+            Review::Revoke => "revoked",
+        }
+    }
+
+    /// Why the action does not apply to a request in `status` expiring at
+    /// `expires_at`, if it does not.
+    fn conflict(self, status: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Option<String> {
+        if status != self.required_status() {
+            return Some(format!("request is {}", status));
+        }
+        if matches!(self, Review::Approve) && expires_at <= now {
+            return Some("request has expired".to_string());
+        }
+        None
+    }
+
+    /// Status written to the bound device table; rejecting never touches it.
+    fn device_status(self) -> Option<&'static str> {
+        match self {
+            Review::Approve => Some("approved"),
+            Review::Revoke => Some("revoked"),
+            Review::Reject => None,
+        }
+    }
+}
+
+/// Device-binding requests (`device_binding_requests`): clients ask to bind a
+/// desktop or mobile device, an admin approves, rejects or later revokes it.
+/// Approval promotes the device into `desktop_devices` / `mobile_devices` with
+/// status `approved`, which is what the `device_status` policy condition reads.
+pub struct DeviceBindingService {
+    pool: PgPool,
+    request_ttl: Duration,
+}
+
+impl DeviceBindingService {
+    pub fn new(config: &AppConfig, pool: PgPool) -> Self {
+        Self {
+            pool,
+            request_ttl: Duration::hours(config.device_request_ttl_hours as i64),
+        }
+    }
+
+    /// Opens a pending request for a user whose credentials were already checked.
+    pub async fn request(
+        &self,
+        user_id: Uuid,
+        device_type: DeviceType,
+        fingerprint: &str,
+        device_info: serde_json::Value,
+        context: &ClientContext,
+    ) -> Result<BindOutcome> {
+        let fingerprint = fingerprint.trim();
+        if fingerprint.is_empty() || fingerprint.len() > 255 {
+            return Ok(BindOutcome::Invalid("fingerprint must be 1-255 characters"));
+        }
+        if !device_info.is_object() || device_info.to_string().len() > MAX_DEVICE_INFO_BYTES {
+            return Ok(BindOutcome::Invalid("device_info must be a JSON object of at most 4 KiB"));
+        }
+
+        if self.bound_status(user_id, device_type, fingerprint).await?.as_deref() == Some("approved") {
+            return Ok(BindOutcome::AlreadyBound);
+        }
+
+        let open = sqlx::query!(
+            r#"
+            SELECT id, fingerprint = $2 AND device_type = $3 AS "same_device!"
+            FROM device_binding_requests
+            WHERE user_id = $1 AND status = 'pending' AND expires_at > NOW()
+            "#,
+            user_id,
+            fingerprint,
+            device_type.as_str()
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        if let Some(existing) = open.iter().find(|request| request.same_device) {
+            return Ok(match self.get(existing.id).await? {
+                Some(binding) => BindOutcome::AlreadyPending(binding),
+                None => BindOutcome::TooManyPending,
+            });
+        }
+        if open.len() as i64 >= MAX_PENDING_PER_USER {
+            return Ok(BindOutcome::TooManyPending);
+        }
+
+        // A pending request past its expiry that the sweeper has not reached yet
+        // would still hold the unique index
+        sqlx::query!(
+            r#"
+            UPDATE device_binding_requests SET status = 'expired'
+            WHERE user_id = $1 AND device_type = $2 AND fingerprint = $3
+              AND status = 'pending' AND expires_at <= NOW()
+            "#,
+            user_id,
+            device_type.as_str(),
+            fingerprint
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO device_binding_requests
+                (user_id, device_type, fingerprint, device_info, status, expires_at, ip_address, user_agent)
+            VALUES ($1, $2, $3, $4, 'pending', $5, $6, $7)
+            ON CONFLICT (user_id, device_type, fingerprint) WHERE status = 'pending' DO NOTHING
+            RETURNING id
+            "#,
+            user_id,
+            device_type.as_str(),
+            fingerprint,
+            device_info,
+            Utc::now() + self.request_ttl,
+            context.ip_address,
+            context.user_agent
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        // Lost a race with an identical request: report that one
+        let (id, created) = match id {
+            Some(id) => (id, true),
+            None => {
+                let existing = sqlx::query_scalar!(
+                    r#"
+                    SELECT id FROM device_binding_requests
+                    WHERE user_id = $1 AND device_type = $2 AND fingerprint = $3 AND status = 'pending'
+                    "#,
+                    user_id,
+                    device_type.as_str(),
+                    fingerprint
+                )
+                .fetch_one(&self.pool)
+                .await?;
+                (existing, false)
+            }
+        };
+
+        let binding = self
+            .get(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("device binding request {} disappeared", id))?;
+        Ok(if created {
+            BindOutcome::Requested(binding)
+        } else {
+            BindOutcome::AlreadyPending(binding)
+        })
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<Option<DeviceBinding>> {
+        let binding = sqlx::query_as!(
+            DeviceBinding,
+            r#"
+            SELECT r.id, r.user_id, u.username, r.device_type, r.fingerprint, r.device_info,
+                   r.status::text AS "status!", r.requested_at, r.expires_at, r.approved_at, r.approved_by,
+                   r.reviewed_at, r.reviewed_by, r.note, host(r.ip_address) AS ip_address, r.user_agent
+            FROM device_binding_requests r
+            JOIN users u ON u.id = r.user_id
+            WHERE r.id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(binding)
+    }
+
+    /// Newest first; `status` filters by request status.
+    pub async fn list(&self, status: Option<&str>) -> Result<Vec<DeviceBinding>> {
+        let bindings = sqlx::query_as!(
+            DeviceBinding,
+            r#"
+            SELECT r.id, r.user_id, u.username, r.device_type, r.fingerprint, r.device_info,
+                   r.status::text AS "status!", r.requested_at, r.expires_at, r.approved_at, r.approved_by,
+                   r.reviewed_at, r.reviewed_by, r.note, host(r.ip_address) AS ip_address, r.user_agent
+            FROM device_binding_requests r
+            JOIN users u ON u.id = r.user_id
+            WHERE $1::text IS NULL OR r.status::text = $1
+            ORDER BY r.requested_at DESC
+            LIMIT 500
+            "#,
+            status
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(bindings)
+    }
+
+    /// Approves a pending, unexpired request and binds the device.
+    pub async fn approve(&self, id: Uuid, admin_id: Option<Uuid>, note: Option<&str>) -> Result<ReviewOutcome> {
+        self.review(id, Review::Approve, admin_id, note).await
+    }
+
+    pub async fn reject(&self, id: Uuid, admin_id: Option<Uuid>, note: Option<&str>) -> Result<ReviewOutcome> {
+        self.review(id, Review::Reject, admin_id, note).await
+    }
+
+    /// Unbinds a previously approved device.
+    pub async fn revoke(&self, id: Uuid, admin_id: Option<Uuid>, note: Option<&str>) -> Result<ReviewOutcome> {
+        self.review(id, Review::Revoke, admin_id, note).await
+    }
+
+    async fn review(&self, id: Uuid, review: Review, admin_id: Option<Uuid>, note: Option<&str>) -> Result<ReviewOutcome> {
+        let mut tx = self.pool.begin().await?;
+
+        let request = sqlx::query!(
+            r#"
+            SELECT user_id, device_type, fingerprint, device_info, status::text AS "status!", expires_at
+            FROM device_binding_requests
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(request) = request else {
+            return Ok(ReviewOutcome::NotFound);
+        };
+        if let Some(conflict) = review.conflict(&request.status, request.expires_at, Utc::now()) {
+            return Ok(ReviewOutcome::Conflict(conflict));
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE device_binding_requests
+            SET status = $2::text::device_status,
+                reviewed_by = $3,
+                reviewed_at = NOW(),
+                note = COALESCE($4, note),
+                approved_by = CASE WHEN $2 = 'approved' THEN $3 ELSE approved_by END,
+                approved_at = CASE WHEN $2 = 'approved' THEN NOW() ELSE approved_at END
+            WHERE id = $1
+            "#,
+            id,
+            review.resulting_status(),
+            admin_id,
+            note
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(device_status) = review.device_status() {
+            let info = &request.device_info;
+            let text = |key: &str| info.get(key).and_then(|value| value.as_str()).map(str::to_string);
+
+            if request.device_type == DeviceType::Desktop.as_str() {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO desktop_devices (user_id, fingerprint, device_pubkey, platform, status, key_algorithm)
+                    VALUES ($1, $2, COALESCE($3, ''), COALESCE($4, 'unknown'), $5::text::device_status, $6)
+                    ON CONFLICT (user_id, fingerprint) DO UPDATE SET
+                        status = EXCLUDED.status,
+                        device_pubkey = CASE WHEN $3 IS NULL THEN desktop_devices.device_pubkey ELSE EXCLUDED.device_pubkey END,
+                        key_algorithm = CASE WHEN $3 IS NULL THEN desktop_devices.key_algorithm ELSE EXCLUDED.key_algorithm END,
+                        key_revoked_at = CASE WHEN $3 IS NOT NULL AND EXCLUDED.status = 'approved' THEN NULL ELSE desktop_devices.key_revoked_at END,
+                        platform = CASE WHEN $4 IS NULL THEN desktop_devices.platform ELSE EXCLUDED.platform END,
+                        enrolled_at = CASE WHEN EXCLUDED.status = 'approved' THEN NOW() ELSE desktop_devices.enrolled_at END
+                    "#,
+                    request.user_id,
+                    request.fingerprint,
+                    text("pubkey"),
+                    text("platform"),
+                    device_status,
+                    text("key_algorithm")
+                )
+                .execute(&mut *tx)
+                .await?;
+            } else {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO mobile_devices (user_id, device_id, fcm_token, device_model, device_os, app_version, status)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7::text::device_status)
+                    ON CONFLICT (user_id, device_id) DO UPDATE SET
+                        status = EXCLUDED.status,
+                        fcm_token = COALESCE(EXCLUDED.fcm_token, mobile_devices.fcm_token),
+                        device_model = COALESCE(EXCLUDED.device_model, mobile_devices.device_model),
+                        device_os = COALESCE(EXCLUDED.device_os, mobile_devices.device_os),
+                        app_version = COALESCE(EXCLUDED.app_version, mobile_devices.app_version),
+                        bound_at = CASE WHEN EXCLUDED.status = 'approved' THEN NOW() ELSE mobile_devices.bound_at END
+                    "#,
+                    request.user_id,
+                    request.fingerprint,
+                    text("fcm_token"),
+                    text("model"),
+                    text("os"),
+                    text("app_version"),
+                    device_status
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        match self.get(id).await? {
+            Some(binding) => Ok(ReviewOutcome::Done(Box::new(binding))),
+            None => Ok(ReviewOutcome::NotFound),
+        }
+    }
+
+    /// Marks pending requests past `expires_at` as expired and returns them.
+    pub async fn expire_stale(&self) -> Result<Vec<DeviceBinding>> {
+        let expired = sqlx::query_scalar!(
+            r#"
+            UPDATE device_binding_requests SET status = 'expired'
+            WHERE status = 'pending' AND expires_at <= NOW()
+            RETURNING id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut bindings = Vec::with_capacity(expired.len());
+        for id in expired {
+            if let Some(binding) = self.get(id).await? {
+                bindings.push(binding);
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    async fn bound_status(&self, user_id: Uuid, device_type: DeviceType, fingerprint: &str) -> Result<Option<String>> {
+        let status = match device_type {
+            DeviceType::Desktop => sqlx::query_scalar!(
+                "SELECT status::text FROM desktop_devices WHERE user_id = $1 AND fingerprint = $2",
+                user_id,
+                fingerprint
+            )
+            .fetch_optional(&self.pool)
+            .await?,
+            DeviceType::Mobile => sqlx::query_scalar!(
+                "SELECT status::text FROM mobile_devices WHERE user_id = $1 AND device_id = $2",
+                user_id,
+                fingerprint
+            )
+            .fetch_optional(&self.pool)
+            .await?,
+        };
+
+        Ok(status.flatten())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_unexpired_pending_requests_are_approved() {
+        let now = Utc::now();
+        assert_eq!(Review::Approve.conflict("pending", now + Duration::minutes(5), now), None);
+        assert_eq!(
+            Review::Approve.conflict("pending", now, now).as_deref(),
+            Some("request has expired")
+        );
+        for status in ["approved", "rejected", "revoked", "expired"] {
+            assert_eq!(
+                Review::Approve.conflict(status, now + Duration::minutes(5), now),
+                Some(format!("request is {}", status))
+            );
+        }
+    }
+
+    #[test]
+    fn test_expired_requests_can_still_be_rejected() {
+        let now = Utc::now();
+        assert_eq!(Review::Reject.conflict("pending", now - Duration::hours(1), now), None);
+        assert!(Review::Reject.conflict("approved", now + Duration::hours(1), now).is_some());
+    }
+
+    #[test]
+    fn test_only_approved_bindings_are_revoked() {
+        let now = Utc::now();
+        // The request's own expiry only limits approval
+        assert_eq!(Review::Revoke.conflict("approved", now - Duration::days(30), now), None);
+        assert!(Review::Revoke.conflict("pending", now + Duration::hours(1), now).is_some());
+    }
+
+    #[test]
+    fn test_review_promotes_into_device_tables() {
+        assert_eq!(Review::Approve.resulting_status(), "approved");
+        assert_eq!(Review::Approve.device_status(), Some("approved"));
+        assert_eq!(Review::Revoke.resulting_status(), "revoked");
+        assert_eq!(Review::Revoke.device_status(), Some("revoked"));
+        assert_eq!(Review::Reject.resulting_status(), "rejected");
+        assert_eq!(Review::Reject.device_status(), None);
+    }
+}
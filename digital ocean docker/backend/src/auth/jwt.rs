@@ -1,6 +1,9 @@
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation, errors::Error as JwtError};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use chrono::{Duration, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 use crate::config::AppConfig;
 
@@ -9,12 +12,23 @@ pub struct Claims {
     pub sub: String, // User ID
     pub username: String,
     pub role: String,
+    pub sid: String, // Session the token belongs to
     pub exp: i64, // Expiration time
     pub iat: i64, // Issued at
     pub jti: String, // JWT ID for token tracking
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Claims {
+    pub fn user_id(&self) -> Option<Uuid> {
+        Uuid::parse_str(&self.sub).ok()
+    }
+
+    pub fn session_id(&self) -> Option<Uuid> {
+        Uuid::parse_str(&self.sid).ok()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct TokenResponse {
     pub access_token: String,
     pub token_type: String,
@@ -22,75 +36,111 @@ pub struct TokenResponse {
     pub refresh_token: Option<String>,
 }
 
+/// A freshly issued access/refresh pair plus what the session row needs to track it.
+pub struct IssuedTokens {
+    pub response: TokenResponse,
+    pub access_token_jti: String,
+    pub refresh_token_hash: String,
+}
+
 pub struct JwtService {
     encoding_key: EncodingKey,
     decoding_key: DecodingKey,
     expiration: i64,
+    refresh_expiration: i64,
 }
 
 impl JwtService {
     pub fn new(config: &AppConfig) -> Self {
         let encoding_key = EncodingKey::from_secret(config.jwt_secret.as_ref());
         let decoding_key = DecodingKey::from_secret(config.jwt_secret.as_ref());
-        
+
         Self {
             encoding_key,
             decoding_key,
             expiration: config.jwt_expiration as i64,
+            refresh_expiration: config.jwt_refresh_expiration as i64,
         }
     }
-    
-    pub fn generate_token(&self, user_id: Uuid, username: &str, role: &str) -> Result<String, JwtError> {
+
+    pub fn refresh_expiration(&self) -> Duration {
+        Duration::seconds(self.refresh_expiration)
+    }
+
+    pub fn generate_token(&self, user_id: Uuid, username: &str, role: &str, session_id: Uuid) -> Result<(String, Claims), JwtError> {
         let now = Utc::now();
         let expires_at = now + Duration::seconds(self.expiration);
-        
+
         let claims = Claims {
             sub: user_id.to_string(),
             username: username.to_string(),
             role: role.to_string(),
+            sid: session_id.to_string(),
             exp: expires_at.timestamp(),
             iat: now.timestamp(),
             jti: Uuid::new_v4().to_string(),
         };
-        
-        encode(&Header::default(), &claims, &self.encoding_key)
+
+        let token = encode(&Header::default(), &claims, &self.encoding_key)?;
+        Ok((token, claims))
     }
-    
+
     pub fn validate_token(&self, token: &str) -> Result<Claims, JwtError> {
         let token_data = decode::<Claims>(
             token,
             &self.decoding_key,
             &Validation::default()
         )?;
-        
+
         Ok(token_data.claims)
     }
-    
-    pub fn generate_refresh_token(&self, user_id: Uuid) -> Result<String, JwtError> {
-        let now = Utc::now();
-        let expires_at = now + Duration::days(30); // 30 days for refresh token
-        
-        let claims = Claims {
-            sub: user_id.to_string(),
-            username: "".to_string(), // Not needed for refresh token
-            role: "".to_string(), // Not needed for refresh token
-            exp: expires_at.timestamp(),
-            iat: now.timestamp(),
-            jti: Uuid::new_v4().to_string(),
-        };
-        
-        encode(&Header::default(), &claims, &self.encoding_key)
+
+    /// Refresh tokens are opaque random strings; only their hash is persisted.
+    pub fn generate_refresh_token() -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        hex::encode(bytes)
+    }
+
+    pub fn hash_refresh_token(refresh_token: &str) -> String {
+        hex::encode(Sha256::digest(refresh_token.as_bytes()))
     }
-    
-    pub fn create_token_response(&self, user_id: Uuid, username: &str, role: &str) -> Result<TokenResponse, JwtError> {
-        let access_token = self.generate_token(user_id, username, role)?;
-        let refresh_token = self.generate_refresh_token(user_id)?;
-        
-        Ok(TokenResponse {
-            access_token,
-            token_type: "Bearer".to_string(),
-            expires_in: self.expiration,
-            refresh_token: Some(refresh_token),
+
+    pub fn issue_tokens(&self, user_id: Uuid, username: &str, role: &str, session_id: Uuid) -> Result<IssuedTokens, JwtError> {
+        let (access_token, claims) = self.generate_token(user_id, username, role, session_id)?;
+        let refresh_token = Self::generate_refresh_token();
+
+        Ok(IssuedTokens {
+            access_token_jti: claims.jti,
+            refresh_token_hash: Self::hash_refresh_token(&refresh_token),
+            response: TokenResponse {
+                access_token,
+                token_type: "Bearer".to_string(),
+                expires_in: self.expiration,
+                refresh_token: Some(refresh_token),
+            },
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issued_tokens_round_trip() {
+        let service = JwtService::new(&AppConfig::default());
+        let user_id = Uuid::new_v4();
+        let session_id = Uuid::new_v4();
+
+        let issued = service.issue_tokens(user_id, "alice", "admin", session_id).unwrap();
+        let claims = service.validate_token(&issued.response.access_token).unwrap();
+        assert_eq!(claims.user_id(), Some(user_id));
+        assert_eq!(claims.session_id(), Some(session_id));
+        assert_eq!(claims.jti, issued.access_token_jti);
+
+        let refresh_token = issued.response.refresh_token.unwrap();
+        assert_eq!(JwtService::hash_refresh_token(&refresh_token), issued.refresh_token_hash);
+        assert!(service.validate_token(&refresh_token).is_err());
+    }
+}
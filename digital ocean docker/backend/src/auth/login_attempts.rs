@@ -0,0 +1,228 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::auth::ClientContext;
+use crate::services::audit::{AuditEvent, AuditEventType};
+use crate::config::AppConfig;
+
+/// Doublings beyond this would overflow long before they reach any sane cap
+const MAX_DOUBLINGS: i32 = 30;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LockoutPolicy {
+    pub threshold: u32,
+    pub base: Duration,
+    pub max: Duration,
+    pub ip_max_failures: u32,
+    pub ip_window: Duration,
+}
+
+impl LockoutPolicy {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            threshold: config.login_lockout_threshold,
+            base: Duration::seconds(config.login_lockout_base_seconds as i64),
+            max: Duration::seconds(config.login_lockout_max_seconds as i64),
+            ip_max_failures: config.login_ip_max_failures,
+            ip_window: Duration::seconds(config.login_ip_window_seconds as i64),
+        }
+    }
+
+    /// Lock duration after the `failures`-th consecutive failure, doubling with
+    /// every failure past the threshold.
+    pub fn lockout_for(&self, failures: i32) -> Option<Duration> {
+        let past_threshold = failures - self.threshold as i32;
+        if past_threshold < 0 {
+            return None;
+        }
+
+        let seconds = self
+            .base
+            .num_seconds()
+            .saturating_mul(1i64 << past_threshold.min(MAX_DOUBLINGS));
+        Some(Duration::seconds(seconds).min(self.max))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginGate {
+    Open,
+    /// The account is locked; the attempt was recorded without checking the password
+    Locked { until: DateTime<Utc> },
+    /// Too many recent failures from this address
+    Throttled { retry_after: Duration },
+}
+
+/// Shared bookkeeping for every password login endpoint: per-account
+/// exponential lockout (`users.failed_login_attempts` / `locked_until`),
+/// per-IP throttling and `login_failed` audit events.
+///
+/// Handlers call `gate` before verifying a password or one-time code, then
+/// exactly one of `record_failure` or `record_success`.
+pub struct LoginAttemptService {
+    pool: PgPool,
+    policy: LockoutPolicy,
+}
+
+impl LoginAttemptService {
+    pub fn new(config: &AppConfig, pool: PgPool) -> Self {
+        Self {
+            pool,
+            policy: LockoutPolicy::from_config(config),
+        }
+    }
+
+    /// `account` is the user's id and `locked_until` when the username exists.
+    /// Throttled attempts are not recorded, so a blocked address drains on its own.
+    pub async fn gate(
+        &self,
+        username: &str,
+        account: Option<(Uuid, Option<DateTime<Utc>>)>,
+        context: &ClientContext,
+    ) -> Result<LoginGate> {
+        let now = Utc::now();
+
+        if let Some(ip_address) = context.ip_address {
+            let recent = sqlx::query!(
+                r#"
+                SELECT COUNT(*) AS "failures!", MIN(created_at) AS oldest
+                FROM audit_events
+                WHERE event_type = 'login_failed' AND ip_address = $1 AND created_at > $2
+                "#,
+                ip_address,
+                now - self.policy.ip_window
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            if recent.failures >= self.policy.ip_max_failures as i64 {
+                let retry_after = recent
+                    .oldest
+                    .map(|oldest| oldest + self.policy.ip_window - now)
+                    .unwrap_or(self.policy.ip_window)
+                    .max(Duration::seconds(1));
+                return Ok(LoginGate::Throttled { retry_after });
+            }
+        }
+
+        if let Some((user_id, Some(until))) = account {
+            if until > now {
+                self.audit_failure(
+                    Some(user_id),
+                    username,
+                    context,
+                    serde_json::json!({ "reason": "account_locked", "locked_until": until }),
+                )
+                .await?;
+                return Ok(LoginGate::Locked { until });
+            }
+        }
+
+        Ok(LoginGate::Open)
+    }
+
+    /// Records a failed attempt (`user_id` is None for unknown usernames) and
+    /// locks the account once it crosses the threshold. Returns the new lock expiry.
+    pub async fn record_failure(
+        &self,
+        user_id: Option<Uuid>,
+        username: &str,
+        context: &ClientContext,
+        reason: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let mut failures = None;
+        let mut locked_until = None;
+
+        if let Some(user_id) = user_id {
+            failures = sqlx::query_scalar!(
+                r#"
+                UPDATE users
+                SET failed_login_attempts = failed_login_attempts + 1, updated_at = NOW()
+                WHERE id = $1
+                RETURNING failed_login_attempts
+                "#,
+                user_id
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            if let Some(lockout) = failures.and_then(|failures| self.policy.lockout_for(failures)) {
+                let until = Utc::now() + lockout;
+                sqlx::query!("UPDATE users SET locked_until = $2 WHERE id = $1", user_id, until)
+                    .execute(&self.pool)
+                    .await?;
+                locked_until = Some(until);
+            }
+        }
+
+        self.audit_failure(
+            user_id,
+            username,
+            context,
+            serde_json::json!({
+                "reason": reason,
+                "failed_attempts": failures,
+                "locked_until": locked_until
+            }),
+        )
+        .await?;
+
+        Ok(locked_until)
+    }
+
+    /// Clears the failure streak after a correct password.
+    pub async fn record_success(&self, user_id: Uuid) -> Result<()> {
+        self.unlock(user_id).await?;
+        Ok(())
+    }
+
+    /// Clears failures and any lock. Returns false if the user does not exist.
+    pub async fn unlock(&self, user_id: Uuid) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users
+            SET failed_login_attempts = 0, locked_until = NULL, updated_at = NOW()
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn audit_failure(
+        &self,
+        user_id: Option<Uuid>,
+        username: &str,
+        context: &ClientContext,
+        mut details: serde_json::Value,
+    ) -> Result<()> {
+        details["username"] = serde_json::Value::from(username);
+
+        AuditEvent::new(AuditEventType::LoginFailed)
+            .user(user_id)
+            .details(details)
+            .context(context)
+            .write(&self.pool)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_doubles_past_threshold_and_caps() {
+        let policy = LockoutPolicy::from_config(&AppConfig::default());
+        assert_eq!(policy.lockout_for(4), None);
+        assert_eq!(policy.lockout_for(5), Some(Duration::seconds(60)));
+        assert_eq!(policy.lockout_for(6), Some(Duration::seconds(120)));
+        assert_eq!(policy.lockout_for(9), Some(Duration::seconds(960)));
+        assert_eq!(policy.lockout_for(11), Some(Duration::seconds(3600)));
+        assert_eq!(policy.lockout_for(i32::MAX), Some(Duration::seconds(3600)));
+    }
+}
@@ -1,15 +1,22 @@
 use actix_web::{
-    dev::{ServiceRequest, ServiceResponse},
-    http::header::{self, HeaderValue},
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
     web::Data,
-    Error,
+    Error, HttpMessage, HttpResponse,
 };
 use futures_util::future::{ready, LocalBoxFuture, Ready};
-use std::future::Future;
-use std::pin::Pin;
-use crate::auth::jwt::JwtService;
-use crate::config::AppConfig;
+use std::rc::Rc;
+use crate::auth::{
+    policy::{PolicyAction, PolicyService},
+    webauthn::WebAuthnService,
+    AuthService, Claims, ClientContext,
+};
+use crate::services::scim::{error_body, ScimService};
 
+/// Validates the `Authorization: Bearer <jwt>` header against the `AuthService`
+/// registered as app data (signature, expiry, and that the token is still current
+/// for an active session) and stores the decoded `Claims` in request extensions.
 pub struct AuthMiddleware;
 
 impl AuthMiddleware {
@@ -18,29 +25,373 @@ impl AuthMiddleware {
     }
 }
 
-pub struct RoleMiddleware {
-    required_role: String,
+impl Default for AuthMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = AuthMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AuthMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let token = get_claims_from_request(&req);
+        let auth_service = req.app_data::<Data<AuthService>>().cloned();
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let claims = match (token, auth_service) {
+                (Some(token), Some(auth_service)) => {
+                    auth_service.validate_access_token(&token).await.map_err(|e| {
+                        eprintln!("Session lookup failed: {}", e);
+                        actix_web::error::ErrorInternalServerError("Database error")
+                    })?
+                }
+                _ => None,
+            };
+
+            match claims {
+                Some(claims) => {
+                    req.extensions_mut().insert(claims);
+                    service.call(req).await.map(ServiceResponse::map_into_left_body)
+                }
+                None => {
+                    let response = HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "Missing or invalid authorization token"
+                    }));
+                    Ok(req.into_response(response).map_into_right_body())
+                }
+            }
+        })
+    }
+}
+
+/// Decides every request against the active policies for `action` (see
+/// `auth::policy`) and rejects denied ones with 403 and the reason.
+/// Must be registered inside (i.e. `.wrap`ped before) `AuthMiddleware`.
+pub struct PolicyMiddleware {
+    action: PolicyAction,
+}
+
+impl PolicyMiddleware {
+    pub fn new(action: PolicyAction) -> Self {
+        Self { action }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for PolicyMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = PolicyMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PolicyMiddlewareService {
+            service: Rc::new(service),
+            action: self.action,
+        }))
+    }
+}
+
+pub struct PolicyMiddlewareService<S> {
+    service: Rc<S>,
+    action: PolicyAction,
+}
+
+impl<S, B> Service<ServiceRequest> for PolicyMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let claims = req.extensions().get::<Claims>().cloned();
+        let policy_service = req.app_data::<Data<PolicyService>>().cloned();
+        let context = ClientContext::from_request(req.request());
+        let action = self.action;
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let (Some(claims), Some(user_id), Some(policy_service)) =
+                (claims.as_ref(), claims.as_ref().and_then(Claims::user_id), policy_service)
+            else {
+                let response = HttpResponse::Unauthorized().json(serde_json::json!({
+                    "error": "Missing or invalid authorization token"
+                }));
+                return Ok(req.into_response(response).map_into_right_body());
+            };
+
+            let decision = policy_service
+                .enforce(action, user_id, &claims.role, &context)
+                .await
+                .map_err(|e| {
+                    eprintln!("Policy evaluation failed: {}", e);
+                    actix_web::error::ErrorInternalServerError("Database error")
+                })?;
+
+            if decision.allowed {
+                service.call(req).await.map(ServiceResponse::map_into_left_body)
+            } else {
+                let response = HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "Access denied by policy",
+                    "reason": decision.reason
+                }));
+                Ok(req.into_response(response).map_into_right_body())
+            }
+        })
+    }
+}
+
+/// Requires admin roles to have completed a WebAuthn assertion in the current
+/// session (when `WEBAUTHN_REQUIRE_FOR_ADMINS` is set). Other roles pass through.
+/// Must be registered inside (i.e. `.wrap`ped before) `AuthMiddleware`.
+pub struct AdminWebAuthnMiddleware;
+
+impl AdminWebAuthnMiddleware {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for AdminWebAuthnMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AdminWebAuthnMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = AdminWebAuthnMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AdminWebAuthnMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AdminWebAuthnMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AdminWebAuthnMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let claims = req.extensions().get::<Claims>().cloned();
+        let webauthn_service = req.app_data::<Data<WebAuthnService>>().cloned();
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let (Some(claims), Some(webauthn_service)) = (claims, webauthn_service) else {
+                let response = HttpResponse::Unauthorized().json(serde_json::json!({
+                    "error": "Missing or invalid authorization token"
+                }));
+                return Ok(req.into_response(response).map_into_right_body());
+            };
+
+            let verified = match claims.session_id() {
+                _ if !webauthn_service.required_for(&claims.role) => true,
+                Some(session_id) => webauthn_service.session_verified(session_id).await.map_err(|e| {
+                    eprintln!("Session lookup failed: {}", e);
+                    actix_web::error::ErrorInternalServerError("Database error")
+                })?,
+                None => false,
+            };
+
+            if verified {
+                service.call(req).await.map(ServiceResponse::map_into_left_body)
+            } else {
+                let response = HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "WebAuthn verification required",
+                    "webauthn_required": true
+                }));
+                Ok(req.into_response(response).map_into_right_body())
+            }
+        })
+    }
+}
+
+/// Admits SCIM requests carrying `Authorization: Bearer <SCIM_BEARER_TOKEN>`,
+/// checked against the `ScimService` registered as app data. Everything else
+/// (including every request while SCIM is disabled) gets a SCIM 401.
+pub struct ScimAuthMiddleware;
+
+impl ScimAuthMiddleware {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for ScimAuthMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ScimAuthMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ScimAuthMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ScimAuthMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
 }
 
-impl RoleMiddleware {
-    pub fn new(required_role: String) -> Self {
-        Self { required_role }
+pub struct ScimAuthMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ScimAuthMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let authorized = match (
+            req.app_data::<Data<ScimService>>(),
+            req.headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer ")),
+        ) {
+            (Some(scim), Some(token)) => scim.authorize(token),
+            _ => false,
+        };
+
+        if !authorized {
+            let response = HttpResponse::Unauthorized()
+                .content_type("application/scim+json")
+                .json(error_body(401, None, "Missing or invalid SCIM bearer token"));
+            return Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) });
+        }
+
+        let service = Rc::clone(&self.service);
+        Box::pin(async move { service.call(req).await.map(ServiceResponse::map_into_left_body) })
     }
 }
 
 pub fn get_claims_from_request(req: &ServiceRequest) -> Option<String> {
-    req.headers()
+    bearer_token(req.headers())
+}
+
+/// Subprotocol prefix browsers use to pass the access token when opening a
+/// WebSocket, since they cannot set `Authorization` on the upgrade request:
+/// `new WebSocket(url, ["viworks.events", "bearer." + token])`.
+pub const WEBSOCKET_BEARER_PROTOCOL_PREFIX: &str = "bearer.";
+
+/// The access token from `Authorization: Bearer <jwt>`, falling back to a
+/// `bearer.<jwt>` entry in `Sec-WebSocket-Protocol`.
+pub fn bearer_token(headers: &header::HeaderMap) -> Option<String> {
+    let authorization = headers
         .get(header::AUTHORIZATION)
         .and_then(|auth_header| {
             auth_header
                 .to_str()
                 .ok()
-                .and_then(|auth_str| {
-                    if auth_str.starts_with("Bearer ") {
-                        Some(auth_str[7..].to_string())
-                    } else {
-                        None
-                    }
-                })
-        })
+                .and_then(|auth_str| auth_str.strip_prefix("Bearer ").map(str::to_string))
+        });
+
+    authorization.or_else(|| {
+        headers
+            .get(header::SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|protocols| protocols.to_str().ok())
+            .and_then(|protocols| {
+                protocols
+                    .split(',')
+                    .find_map(|protocol| protocol.trim().strip_prefix(WEBSOCKET_BEARER_PROTOCOL_PREFIX))
+                    .map(str::to_string)
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderMap, HeaderValue};
+
+    #[test]
+    fn bearer_token_prefers_authorization_and_falls_back_to_websocket_protocol() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("viworks.events, bearer.aaa.bbb.ccc"),
+        );
+        assert_eq!(bearer_token(&headers).as_deref(), Some("aaa.bbb.ccc"));
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer xxx.yyy.zzz"));
+        assert_eq!(bearer_token(&headers).as_deref(), Some("xxx.yyy.zzz"));
+
+        assert_eq!(bearer_token(&HeaderMap::new()), None);
+    }
 }
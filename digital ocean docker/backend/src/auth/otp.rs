@@ -0,0 +1,343 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use redis::aio::ConnectionManager;
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Wrong guesses allowed before a challenge is burned
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct IssuedOtp {
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OtpVerification {
+    Valid { user_id: Option<Uuid> },
+    Invalid { attempts_remaining: i32 },
+    TooManyAttempts,
+    Expired,
+    NotFound,
+}
+
+#[derive(Debug, Clone)]
+pub struct OtpChallengeStatus {
+    pub user_id: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+/// Storage for one-time codes. Codes are only ever returned from `issue`;
+/// stores keep a hash, count wrong guesses and consume a code on first success.
+#[async_trait]
+pub trait OtpStore: Send + Sync {
+    /// Generates a fresh 6-digit code for `key`, replacing any live challenge for it.
+    async fn issue(&self, key: &str, user_id: Option<Uuid>, ttl: Duration) -> Result<IssuedOtp>;
+
+    async fn verify(&self, key: &str, code: &str) -> Result<OtpVerification>;
+
+    /// Settles a challenge whose second factor was checked elsewhere (e.g. TOTP):
+    /// consumes it if `accepted`, otherwise counts a failed attempt.
+    async fn settle(&self, key: &str, accepted: bool) -> Result<OtpVerification>;
+
+    /// The live (unused, unexpired) challenge for `key`, if any.
+    async fn status(&self, key: &str) -> Result<Option<OtpChallengeStatus>>;
+}
+
+pub fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    format!("{:06}", rng.gen_range(0..1_000_000))
+}
+
+/// HMAC-SHA256 of the code under OTP_HMAC_KEY, which lives in the server's
+/// configuration rather than next to the hashes: a leaked table or Redis dump
+/// can't be brute-forced over the million possible codes. The key is mixed in so
+/// a hash can't be replayed against another challenge.
+pub fn hash_code(secret: &[u8; 32], key: &str, code: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(key.as_bytes());
+    mac.update(b":");
+    mac.update(code.trim().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn hashes_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Postgres-backed store over `otp_challenges`; survives restarts and is shared by all replicas.
+pub struct PgOtpStore {
+    pool: PgPool,
+    hash_key: [u8; 32],
+    max_attempts: i32,
+}
+
+impl PgOtpStore {
+    pub fn new(pool: PgPool, hash_key: [u8; 32]) -> Self {
+        Self { pool, hash_key, max_attempts: DEFAULT_MAX_ATTEMPTS }
+    }
+}
+
+#[async_trait]
+impl OtpStore for PgOtpStore {
+    async fn issue(&self, key: &str, user_id: Option<Uuid>, ttl: Duration) -> Result<IssuedOtp> {
+        let code = generate_code();
+        let expires_at = Utc::now() + ttl;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "UPDATE otp_challenges SET used_at = NOW() WHERE challenge_key = $1 AND used_at IS NULL",
+            key
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO otp_challenges (challenge_key, user_id, code_hash, expires_at, max_attempts)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            key,
+            user_id,
+            hash_code(&self.hash_key, key, &code),
+            expires_at,
+            self.max_attempts
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(IssuedOtp { code, expires_at })
+    }
+
+    async fn verify(&self, key: &str, code: &str) -> Result<OtpVerification> {
+        let code_hash = hash_code(&self.hash_key, key, code);
+        self.check(key, |stored_hash| hashes_match(stored_hash, &code_hash)).await
+    }
+
+    async fn settle(&self, key: &str, accepted: bool) -> Result<OtpVerification> {
+        self.check(key, |_| accepted).await
+    }
+
+    async fn status(&self, key: &str) -> Result<Option<OtpChallengeStatus>> {
+        let status = sqlx::query_as!(
+            OtpChallengeStatus,
+            r#"
+            SELECT user_id, expires_at, attempts, max_attempts
+            FROM otp_challenges
+            WHERE challenge_key = $1 AND used_at IS NULL AND expires_at > NOW()
+            "#,
+            key
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(status)
+    }
+}
+
+impl PgOtpStore {
+    /// Locks the live challenge for `key` and consumes it or counts a failed attempt
+    /// depending on `accept(code_hash)`.
+    async fn check(&self, key: &str, accept: impl FnOnce(&str) -> bool + Send) -> Result<OtpVerification> {
+        let mut tx = self.pool.begin().await?;
+
+        let challenge = sqlx::query!(
+            r#"
+            SELECT id, user_id, code_hash, expires_at, attempts, max_attempts
+            FROM otp_challenges
+            WHERE challenge_key = $1 AND used_at IS NULL
+            FOR UPDATE
+            "#,
+            key
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(challenge) = challenge else {
+            return Ok(OtpVerification::NotFound);
+        };
+
+        if challenge.expires_at <= Utc::now() {
+            return Ok(OtpVerification::Expired);
+        }
+        if challenge.attempts >= challenge.max_attempts {
+            return Ok(OtpVerification::TooManyAttempts);
+        }
+
+        let result = if accept(&challenge.code_hash) {
+            sqlx::query!("UPDATE otp_challenges SET used_at = NOW() WHERE id = $1", challenge.id)
+                .execute(&mut *tx)
+                .await?;
+            OtpVerification::Valid { user_id: challenge.user_id }
+        } else {
+            let attempts = sqlx::query_scalar!(
+                "UPDATE otp_challenges SET attempts = attempts + 1 WHERE id = $1 RETURNING attempts",
+                challenge.id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            OtpVerification::Invalid { attempts_remaining: challenge.max_attempts - attempts }
+        };
+
+        tx.commit().await?;
+        Ok(result)
+    }
+}
+
+/// Checks attempts, compares the hash (or takes ARGV[2] == '1' as an external
+/// accept) and consumes the challenge in one round trip.
+/// Returns {"missing"}, {"locked"}, {"ok", user_id} or {"invalid", attempts_remaining}.
+const REDIS_VERIFY_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return {'missing'}
+end
+local attempts = tonumber(redis.call('HGET', KEYS[1], 'attempts'))
+local max_attempts = tonumber(redis.call('HGET', KEYS[1], 'max_attempts'))
+if attempts >= max_attempts then
+    return {'locked'}
+end
+if ARGV[2] == '1' or redis.call('HGET', KEYS[1], 'code_hash') == ARGV[1] then
+    local user_id = redis.call('HGET', KEYS[1], 'user_id')
+    redis.call('DEL', KEYS[1])
+    return {'ok', user_id}
+end
+attempts = redis.call('HINCRBY', KEYS[1], 'attempts', 1)
+return {'invalid', tostring(max_attempts - attempts)}
+"#;
+
+/// Redis-backed store for low-latency deployments; challenges expire with the key TTL.
+pub struct RedisOtpStore {
+    redis: ConnectionManager,
+    hash_key: [u8; 32],
+    max_attempts: i32,
+}
+
+impl RedisOtpStore {
+    pub fn new(redis: ConnectionManager, hash_key: [u8; 32]) -> Self {
+        Self { redis, hash_key, max_attempts: DEFAULT_MAX_ATTEMPTS }
+    }
+
+    fn redis_key(key: &str) -> String {
+        format!("otp:{}", key)
+    }
+}
+
+#[async_trait]
+impl OtpStore for RedisOtpStore {
+    async fn issue(&self, key: &str, user_id: Option<Uuid>, ttl: Duration) -> Result<IssuedOtp> {
+        let code = generate_code();
+        let expires_at = Utc::now() + ttl;
+        let redis_key = Self::redis_key(key);
+        let user_id = user_id.map(|id| id.to_string()).unwrap_or_default();
+
+        let mut conn = self.redis.clone();
+        redis::pipe()
+            .atomic()
+            .del(&redis_key)
+            .hset_multiple(&redis_key, &[
+                ("code_hash", hash_code(&self.hash_key, key, &code)),
+                ("user_id", user_id),
+                ("attempts", "0".to_string()),
+                ("max_attempts", self.max_attempts.to_string()),
+                ("expires_at", expires_at.timestamp().to_string()),
+            ])
+            .expire(&redis_key, ttl.num_seconds().max(1))
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(IssuedOtp { code, expires_at })
+    }
+
+    async fn verify(&self, key: &str, code: &str) -> Result<OtpVerification> {
+        self.check(key, &hash_code(&self.hash_key, key, code), false).await
+    }
+
+    async fn settle(&self, key: &str, accepted: bool) -> Result<OtpVerification> {
+        self.check(key, "", accepted).await
+    }
+
+    async fn status(&self, key: &str) -> Result<Option<OtpChallengeStatus>> {
+        let mut conn = self.redis.clone();
+        let (user_id, attempts, max_attempts, expires_at): (Option<String>, Option<i32>, Option<i32>, Option<i64>) =
+            redis::cmd("HMGET")
+                .arg(Self::redis_key(key))
+                .arg("user_id")
+                .arg("attempts")
+                .arg("max_attempts")
+                .arg("expires_at")
+                .query_async(&mut conn)
+                .await?;
+
+        let (Some(attempts), Some(max_attempts), Some(expires_at)) = (attempts, max_attempts, expires_at) else {
+            return Ok(None);
+        };
+        let Some(expires_at) = DateTime::from_timestamp(expires_at, 0) else {
+            return Ok(None);
+        };
+        let user_id = user_id.and_then(|id| Uuid::parse_str(&id).ok());
+
+        Ok(Some(OtpChallengeStatus { user_id, expires_at, attempts, max_attempts }))
+    }
+}
+
+impl RedisOtpStore {
+    async fn check(&self, key: &str, code_hash: &str, accepted: bool) -> Result<OtpVerification> {
+        let mut conn = self.redis.clone();
+        let reply: Vec<String> = redis::Script::new(REDIS_VERIFY_SCRIPT)
+            .key(Self::redis_key(key))
+            .arg(code_hash)
+            .arg(if accepted { "1" } else { "0" })
+            .invoke_async(&mut conn)
+            .await?;
+
+        match reply.first().map(String::as_str) {
+            Some("missing") => Ok(OtpVerification::NotFound),
+            Some("locked") => Ok(OtpVerification::TooManyAttempts),
+            Some("ok") => {
+                let user_id = reply.get(1).and_then(|id| Uuid::parse_str(id).ok());
+                Ok(OtpVerification::Valid { user_id })
+            }
+            Some("invalid") => {
+                let attempts_remaining = reply.get(1).and_then(|n| n.parse().ok()).unwrap_or(0);
+                Ok(OtpVerification::Invalid { attempts_remaining })
+            }
+            _ => Err(anyhow!("Unexpected OTP verify reply: {:?}", reply)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+
+    #[test]
+    fn test_code_hash_is_bound_to_key() {
+        let code = generate_code();
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+
+        let secret = [7u8; 32];
+        let hash = hash_code(&secret, "challenge-a", &code);
+        assert!(hashes_match(&hash, &hash_code(&secret, "challenge-a", &code)));
+        assert!(!hashes_match(&hash, &hash_code(&secret, "challenge-b", &code)));
+        assert!(!hashes_match(&hash, &hash_code(&secret, "challenge-a", "not-it")));
+    }
+
+    #[test]
+    fn test_code_hash_needs_the_server_key() {
+        let hash = hash_code(&[7u8; 32], "challenge-a", "123456");
+        assert!(!hashes_match(&hash, &hash_code(&[8u8; 32], "challenge-a", "123456")));
+
+        // A plain digest of key and code no longer matches what is stored
+        let unkeyed = hex::encode(Sha256::digest(b"challenge-a:123456"));
+        assert!(!hashes_match(&hash, &unkeyed));
+    }
+}
@@ -1,107 +1,452 @@
-use bcrypt::{hash, verify, DEFAULT_COST, BcryptError};
-use anyhow::{Result, anyhow};
+//! Password hashing and the checks a new password must pass.
+//!
+//! New hashes are Argon2id PHC strings. bcrypt hashes from before still verify
+//! and, like Argon2 hashes made with other parameters than the configured
+//! ones, are replaced on the user's next successful login.
+//!
+//! A new password must satisfy the rules for the user's role (`PASSWORD_*`,
+//! overridden per role by `PASSWORD_ROLE_RULES`), must not be in the
+//! breached-password corpus and must not be one of the user's last
+//! `PASSWORD_HISTORY_DEPTH` passwords (kept in `password_history`).
+//!
+//! The corpus (`BREACHED_PASSWORDS_DIR`) uses the layout of the Have I Been
+//! Pwned range API: one file per 5-hex-digit SHA-1 prefix (`ABCDE.txt`) listing
+//! the `SUFFIX:COUNT` of every breached hash in that bucket. A lookup reads the
+//! one bucket of the password's hash, so the corpus never has to be in memory
+//! and no more than the prefix would ever need to leave the host.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::config::AppConfig;
+
+const MAX_PASSWORD_LENGTH: usize = 128;
+const SPECIAL_CHARACTERS: &str = "!@#$%^&*()_+-=[]{}|;:,.<>?";
+
+/// Length, strength and composition a password must have.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordRules {
+    pub min_length: usize,
+    /// Minimum of `entropy_bits`
+    pub min_entropy_bits: f64,
+    /// An uppercase and a lowercase letter, a digit and a special character
+    pub require_character_classes: bool,
+}
+
+impl Default for PasswordRules {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            min_entropy_bits: 40.0,
+            require_character_classes: true,
+        }
+    }
+}
+
+impl PasswordRules {
+    /// The reason `password` breaks these rules, if it does.
+    pub fn check(&self, password: &str) -> Result<(), String> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(format!("Password must be at least {} characters long", self.min_length));
+        }
+        if length > MAX_PASSWORD_LENGTH {
+            return Err(format!("Password must be at most {} characters long", MAX_PASSWORD_LENGTH));
+        }
+
+        if self.require_character_classes {
+            if !password.chars().any(|c| c.is_uppercase()) {
+                return Err("Password must contain at least one uppercase letter".to_string());
+            }
+            if !password.chars().any(|c| c.is_lowercase()) {
+                return Err("Password must contain at least one lowercase letter".to_string());
+            }
+            if !password.chars().any(|c| c.is_numeric()) {
+                return Err("Password must contain at least one digit".to_string());
+            }
+            if !password.chars().any(|c| SPECIAL_CHARACTERS.contains(c)) {
+                return Err("Password must contain at least one special character".to_string());
+            }
+        }
+
+        if entropy_bits(password) < self.min_entropy_bits {
+            return Err("Password is too predictable; use a longer one with fewer repeated or sequential characters".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// A conservative strength estimate: each character is worth log2 of the
+/// alphabet the password draws from (by character class), except one that
+/// repeats or continues a run from the previous character (`aaa`, `abc`,
+/// `321`), which is worth one bit.
+pub fn entropy_bits(password: &str) -> f64 {
+    let has = |class: fn(&char) -> bool| password.chars().any(|c| class(&c));
+    let alphabet: u32 = [
+        (has(char::is_ascii_lowercase), 26),
+        (has(char::is_ascii_uppercase), 26),
+        (has(char::is_ascii_digit), 10),
+        (has(|c| !c.is_ascii_alphanumeric()), 33),
+    ]
+    .iter()
+    .filter(|(present, _)| *present)
+    .map(|(_, size)| size)
+    .sum();
+    let per_character = f64::from(alphabet.max(1)).log2();
+
+    let mut previous: Option<char> = None;
+    password
+        .chars()
+        .map(|c| {
+            let predictable = previous.is_some_and(|p| (c as i64 - p as i64).abs() <= 1);
+            previous = Some(c);
+            if predictable { 1.0 } else { per_character }
+        })
+        .sum()
+}
+
+/// A directory of SHA-1 prefix buckets (see the module docs).
+pub struct BreachedCorpus {
+    dir: PathBuf,
+}
+
+impl BreachedCorpus {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// How often `password` appears in the corpus; 0 when it does not.
+    pub async fn occurrences(&self, password: &str) -> Result<u64> {
+        let digest = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = digest.split_at(5);
+
+        let bucket = match tokio::fs::read_to_string(self.dir.join(format!("{}.txt", prefix))).await {
+            Ok(bucket) => bucket,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(anyhow!("Cannot read breached-password bucket {}: {}", prefix, e)),
+        };
+
+        Ok(bucket
+            .lines()
+            .find_map(|line| {
+                let (hash, count) = line.trim().split_once(':').unwrap_or((line.trim(), "1"));
+                hash.eq_ignore_ascii_case(suffix)
+                    .then(|| count.trim().parse().unwrap_or(1))
+            })
+            .unwrap_or(0))
+    }
+}
+
+/// Whose password is being set.
+#[derive(Debug, Clone, Copy)]
+pub enum PasswordOwner<'a> {
+    /// An account being created with this role (`None` for the default rules)
+    New { role: Option<&'a str> },
+    /// An existing user; their role's rules and password history apply
+    Existing(Uuid),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Accepted,
+    /// The reason, fit to show to whoever chose the password
+    Rejected(String),
+}
 
 pub struct PasswordService {
-    cost: u32,
+    pool: PgPool,
+    argon2: Argon2<'static>,
+    rules: PasswordRules,
+    role_rules: HashMap<String, PasswordRules>,
+    breached: Option<BreachedCorpus>,
+    history_depth: i64,
 }
 
 impl PasswordService {
-    pub fn new(cost: u32) -> Self {
-        Self { cost }
+    pub fn new(config: &AppConfig, pool: PgPool) -> Self {
+        let params = Params::new(
+            config.argon2_memory_kib,
+            config.argon2_iterations,
+            config.argon2_parallelism,
+            None,
+        )
+        .expect("ARGON2_* settings are checked by AppConfig::validate");
+
+        Self {
+            pool,
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+            rules: config.password_rules.clone(),
+            role_rules: config.password_role_rules.clone(),
+            breached: (!config.breached_passwords_dir.is_empty())
+                .then(|| BreachedCorpus::new(&config.breached_passwords_dir)),
+            history_depth: config.password_history_depth as i64,
+        }
     }
-    
-    pub fn hash_password(&self, password: &str) -> Result<String, BcryptError> {
-        hash(password, self.cost)
+
+    pub fn rules_for(&self, role: Option<&str>) -> &PasswordRules {
+        role.and_then(|role| self.role_rules.get(role)).unwrap_or(&self.rules)
+    }
+
+    pub fn hash_password(&self, password: &str) -> Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = self
+            .argon2
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| anyhow!("Password hashing failed: {}", e))?;
+        Ok(hash.to_string())
+    }
+
+    /// Checks `password` against an Argon2 or a legacy bcrypt hash.
+    pub fn verify_password(&self, password: &str, hash: &str) -> Result<bool> {
+        if is_bcrypt(hash) {
+            return Ok(bcrypt::verify(password, hash)?);
+        }
+        let parsed = PasswordHash::new(hash).map_err(|e| anyhow!("Unreadable password hash: {}", e))?;
+        Ok(self.argon2.verify_password(password.as_bytes(), &parsed).is_ok())
     }
-    
-    pub fn verify_password(&self, password: &str, hash: &str) -> Result<bool, BcryptError> {
-        verify(password, hash)
+
+    /// Whether `hash` is not Argon2id with the configured parameters.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let current = self.argon2.params();
+        parsed.algorithm != Algorithm::Argon2id.ident()
+            || Params::try_from(&parsed).map_or(true, |params| {
+                (params.m_cost(), params.t_cost(), params.p_cost())
+                    != (current.m_cost(), current.t_cost(), current.p_cost())
+            })
     }
-    
-    pub fn validate_password_strength(&self, password: &str) -> Result<(), anyhow::Error> {
-        if password.len() < 8 {
-            return Err(anyhow!("Password must be at least 8 characters long"));
+
+    /// Verifies a login password and, when it matches an outdated hash, stores
+    /// a current one in its place. A failed upgrade does not fail the login.
+    pub async fn verify_and_upgrade(&self, user_id: Uuid, password: &str, hash: &str) -> Result<bool> {
+        if !self.verify_password(password, hash)? {
+            return Ok(false);
         }
-        
-        if password.len() > 128 {
-            return Err(anyhow!("Password must be less than 128 characters"));
+
+        if self.needs_rehash(hash) {
+            let upgraded = match self.hash_password(password) {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    eprintln!("Failed to upgrade password hash of user {}: {}", user_id, e);
+                    return Ok(true);
+                }
+            };
+            // Only if nobody changed the password meanwhile
+            if let Err(e) = sqlx::query!(
+                "UPDATE users SET password_hash = $2 WHERE id = $1 AND password_hash = $3",
+                user_id,
+                upgraded,
+                hash
+            )
+            .execute(&self.pool)
+            .await
+            {
+                eprintln!("Failed to upgrade password hash of user {}: {}", user_id, e);
+            }
         }
-        
-        // Check for at least one uppercase letter
-        if !password.chars().any(|c| c.is_uppercase()) {
-            return Err(anyhow!("Password must contain at least one uppercase letter"));
+
+        Ok(true)
+    }
+
+    /// The rules of `role` alone, without the corpus or history lookups.
+    pub fn validate_password_strength(&self, password: &str, role: Option<&str>) -> Result<()> {
+        self.rules_for(role).check(password).map_err(|reason| anyhow!(reason))
+    }
+
+    /// Everything a password about to be set must pass: the role's rules, the
+    /// breached corpus and, for an existing user, their recent passwords.
+    pub async fn check_new_password(&self, password: &str, owner: PasswordOwner<'_>) -> Result<PasswordCheck> {
+        let (role, user_id) = match owner {
+            PasswordOwner::New { role } => (role.map(str::to_string), None),
+            PasswordOwner::Existing(user_id) => {
+                let role = sqlx::query_scalar!("SELECT role FROM users WHERE id = $1", user_id)
+                    .fetch_optional(&self.pool)
+                    .await?;
+                (role, Some(user_id))
+            }
+        };
+
+        if let Err(reason) = self.rules_for(role.as_deref()).check(password) {
+            return Ok(PasswordCheck::Rejected(reason));
         }
-        
-        // Check for at least one lowercase letter
-        if !password.chars().any(|c| c.is_lowercase()) {
-            return Err(anyhow!("Password must contain at least one lowercase letter"));
+
+        if let Some(corpus) = &self.breached {
+            if corpus.occurrences(password).await? > 0 {
+                return Ok(PasswordCheck::Rejected(
+                    "Password appears in a known data breach; choose a different one".to_string(),
+                ));
+            }
         }
-        
-        // Check for at least one digit
-        if !password.chars().any(|c| c.is_numeric()) {
-            return Err(anyhow!("Password must contain at least one digit"));
+
+        if let Some(user_id) = user_id.filter(|_| self.history_depth > 0) {
+            let recent = sqlx::query_scalar!(
+                "SELECT password_hash FROM password_history WHERE user_id = $1 ORDER BY id DESC LIMIT $2",
+                user_id,
+                self.history_depth
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            if recent.iter().any(|hash| self.verify_password(password, hash).unwrap_or(false)) {
+                return Ok(PasswordCheck::Rejected(format!(
+                    "Password must differ from your last {} passwords",
+                    self.history_depth
+                )));
+            }
         }
-        
-        // Check for at least one special character
-        let special_chars = "!@#$%^&*()_+-=[]{}|;:,.<>?";
-        if !password.chars().any(|c| special_chars.contains(c)) {
-            return Err(anyhow!("Password must contain at least one special character"));
+
+        Ok(PasswordCheck::Accepted)
+    }
+
+    /// Records a password just set for `user_id`, keeping the last
+    /// `PASSWORD_HISTORY_DEPTH` of them.
+    pub async fn remember(&self, user_id: Uuid, hash: &str) -> Result<()> {
+        if self.history_depth == 0 {
+            return Ok(());
         }
-        
+
+        sqlx::query!(
+            "INSERT INTO password_history (user_id, password_hash) VALUES ($1, $2)",
+            user_id,
+            hash
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM password_history
+            WHERE user_id = $1
+              AND id NOT IN (SELECT id FROM password_history WHERE user_id = $1 ORDER BY id DESC LIMIT $2)
+            "#,
+            user_id,
+            self.history_depth
+        )
+        .execute(&self.pool)
+        .await?;
+
         Ok(())
     }
-    
+
     pub fn generate_secure_password() -> String {
         use rand::{thread_rng, Rng};
         use rand::distributions::Alphanumeric;
-        
+
         let mut rng = thread_rng();
         let password: String = (0..16)
             .map(|_| rng.sample(Alphanumeric) as char)
             .collect();
-        
+
         // Ensure password meets requirements
         let mut secure_password = password;
         secure_password.push_str("A1!"); // Add required characters
-        
+
         secure_password
     }
 }
 
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    
-    #[test]
-    fn test_password_hashing() {
-        let service = PasswordService::new(DEFAULT_COST);
+
+    fn service() -> PasswordService {
+        let config = AppConfig {
+            // Keeps the tests fast; the format is what is under test
+            argon2_memory_kib: 1024,
+            argon2_iterations: 1,
+            ..AppConfig::default()
+        };
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        PasswordService::new(&config, pool)
+    }
+
+    #[tokio::test]
+    async fn test_password_hashing() {
+        let service = service();
         let password = "test_password_123!";
-        
+
         let hash = service.hash_password(password).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
         assert!(service.verify_password(password, &hash).unwrap());
         assert!(!service.verify_password("wrong_password", &hash).unwrap());
+        assert!(!service.needs_rehash(&hash));
+
+        let legacy = bcrypt::hash(password, 4).unwrap();
+        assert!(service.verify_password(password, &legacy).unwrap());
+        assert!(service.needs_rehash(&legacy));
+
+        let stronger = PasswordService::new(&AppConfig { argon2_memory_kib: 2048, argon2_iterations: 1, ..AppConfig::default() }, service.pool.clone());
+        assert!(stronger.verify_password(password, &hash).unwrap());
+        assert!(stronger.needs_rehash(&hash));
     }
-    
-    #[test]
-    fn test_password_validation() {
-        let service = PasswordService::new(DEFAULT_COST);
-        
+
+    #[tokio::test]
+    async fn test_password_validation() {
+        let service = service();
+
         // Valid password
-        assert!(service.validate_password_strength("SecurePass123!").is_ok());
-        
+        assert!(service.validate_password_strength("SecurePass123!", None).is_ok());
+
         // Too short
-        assert!(service.validate_password_strength("Short1!").is_err());
-        
+        assert!(service.validate_password_strength("Short1!", None).is_err());
+
         // No uppercase
-        assert!(service.validate_password_strength("lowercase123!").is_err());
-        
+        assert!(service.validate_password_strength("lowercase123!", None).is_err());
+
         // No lowercase
-        assert!(service.validate_password_strength("UPPERCASE123!").is_err());
-        
+        assert!(service.validate_password_strength("UPPERCASE123!", None).is_err());
+
         // No digit
-        assert!(service.validate_password_strength("NoDigits!").is_err());
-        
+        assert!(service.validate_password_strength("NoDigits!", None).is_err());
+
         // No special character
-        assert!(service.validate_password_strength("NoSpecial123").is_err());
+        assert!(service.validate_password_strength("NoSpecial123", None).is_err());
+
+        // Long enough but predictable
+        assert!(service.validate_password_strength("Aaaaaaaa1234!", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_role_rules_override_the_defaults() {
+        let mut service = service();
+        service.role_rules.insert(
+            "admin".to_string(),
+            PasswordRules { min_length: 14, min_entropy_bits: 60.0, require_character_classes: false },
+        );
+
+        assert!(service.validate_password_strength("SecurePass123!", Some("user")).is_ok());
+        assert!(service.validate_password_strength("SecurePass12!", Some("admin")).is_err());
+        assert!(service.validate_password_strength("correct horse battery", Some("admin")).is_ok());
+        assert!(entropy_bits("abcdefgh") < entropy_bits("qmzwxkvj"));
+    }
+
+    #[tokio::test]
+    async fn test_breached_corpus_lookup() {
+        let dir = std::env::temp_dir().join(format!("breached-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        std::fs::write(dir.join("5BAA6.txt"), "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n").unwrap();
+
+        let corpus = BreachedCorpus::new(&dir);
+        assert_eq!(corpus.occurrences("password").await.unwrap(), 9545824);
+        assert_eq!(corpus.occurrences("SecurePass123!").await.unwrap(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,1064 @@
+//! Evaluation of the `policies` table.
+//!
+//! A subject is covered by every active policy whose `rbac_roles` lists its role
+//! (an empty list covers every role); a subject no policy covers is denied. Each
+//! covering policy's `abac_rules` document looks like:
+//!
+//! ```json
+//! {
+//!   "default_effect": "allow",
+//!   "rules": [
+//!     {
+//!       "id": "admins-from-office",
+//!       "description": "Admin API only from the office network",
+//!       "effect": "deny",
+//!       "actions": ["admin"],
+//!       "when": { "not": { "source_ip": ["10.20.0.0/16"] } }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Rules are tried in order; the first enabled rule whose `actions` include the
+//! request's action (omitted = every action) and whose `when` holds decides the
+//! policy's effect, otherwise `default_effect` (default `allow`) does. A request is
+//! allowed only if every covering policy allows it.
+//!
+//! Every key of a condition object must hold:
+//! - `roles`, `device_status`, `geo_country`: the attribute is one of the listed values
+//! - `source_ip`: the address is inside one of the listed IPs/CIDRs
+//! - `time_of_day`: `{"days": [1..=7], "start": "HH:MM", "end": "HH:MM", "utc_offset": "+03:30"}`,
+//!   Monday = 1; a window whose end is before its start wraps past midnight
+//! - `client_version`: `{"min": "1.4.0", "max": "2"}`, both optional and inclusive
+//! - `all` / `any`: lists of conditions, `not`: a condition
+//!
+//! An attribute the request does not carry (no known device, no country) satisfies
+//! no condition, so `{"not": {"geo_country": [...]}}` also matches unknown locations.
+//!
+//! A document may also limit the sessions of the users it covers:
+//!
+//! ```json
+//! "session": {
+//!   "idle_timeout_minutes": 15,
+//!   "absolute_timeout_hours": 8,
+//!   "max_concurrent": 2,
+//!   "on_limit": "deny"
+//! }
+//! ```
+//!
+//! Every key is optional and falls back to the `SESSION_*` settings. Across the
+//! covering policies the strictest value of each wins (`deny` over `evict_oldest`).
+//! The limits are fixed on the session when it starts.
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, FixedOffset, Timelike, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use sqlx::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+use crate::auth::ClientContext;
+use crate::services::audit::{AuditEvent, AuditEventType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyAction {
+    /// Starting a session (every login path)
+    Login,
+    /// Minting gateway credentials for a session
+    ClientBootstrap,
+    /// Any route under /admin
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// The attributes a decision is made on.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PolicyInput {
+    pub action: PolicyAction,
+    pub role: String,
+    #[serde(default)]
+    pub device_status: Option<String>,
+    #[serde(default)]
+    pub source_ip: Option<IpAddr>,
+    #[serde(default)]
+    pub geo_country: Option<String>,
+    #[serde(default)]
+    pub client_version: Option<String>,
+    /// Defaults to the time of the request
+    #[serde(default = "Utc::now")]
+    #[schemars(skip_serializing_if = "no_fixed_default")]
+    pub at: DateTime<Utc>,
+}
+
+/// Keeps `Utc::now` out of the generated schema, which must not change per run.
+fn no_fixed_default<T>(_: &T) -> bool {
+    true
+}
+
+/// A policy row as evaluated; `abac_rules` is parsed at evaluation time.
+#[derive(Debug, Clone)]
+pub struct StoredPolicy {
+    pub id: Uuid,
+    pub name: String,
+    pub version: i32,
+    pub rbac_roles: serde_json::Value,
+    pub abac_rules: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyDecision {
+    pub allowed: bool,
+    pub action: PolicyAction,
+    pub reason: String,
+    pub input: PolicyInput,
+    /// Every policy covering the subject, in evaluation order
+    pub policies: Vec<PolicyOutcome>,
+    /// The strictest session limits of the covering policies
+    pub session: SessionLimits,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyOutcome {
+    pub policy_id: Uuid,
+    pub name: String,
+    pub version: i32,
+    pub effect: Effect,
+    pub matched_rule: Option<String>,
+    /// Set when the stored document does not parse; such a policy denies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub rules: Vec<RuleTrace>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleTrace {
+    pub id: String,
+    pub effect: Effect,
+    pub matched: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<&'static str>,
+    pub conditions: Vec<String>,
+}
+
+impl PolicyDecision {
+    /// What is stored in `sessions.policy_snapshot`: the decision and the exact
+    /// policy versions it was made under (their rules are kept in `policy_versions`).
+    pub fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "allowed": self.allowed,
+            "action": self.action,
+            "reason": self.reason,
+            "evaluated_at": self.input.at,
+            "input": self.input,
+            "policies": self.policies.iter().map(|policy| serde_json::json!({
+                "id": policy.policy_id,
+                "name": policy.name,
+                "version": policy.version,
+                "effect": policy.effect,
+                "matched_rule": policy.matched_rule
+            })).collect::<Vec<_>>(),
+            "session": self.session
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyDocument {
+    #[serde(default)]
+    pub default_effect: Effect,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub session: SessionLimits,
+}
+
+/// Session limits a policy imposes; `None` leaves the configured default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_minutes: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub absolute_timeout_hours: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_limit: Option<SessionLimitMode>,
+}
+
+/// What starting a session beyond `max_concurrent` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLimitMode {
+    /// Terminate the user's oldest sessions to make room
+    EvictOldest,
+    /// Refuse the new session
+    Deny,
+}
+
+impl SessionLimits {
+    fn validate(&self) -> Result<(), String> {
+        let zero = [
+            ("idle_timeout_minutes", self.idle_timeout_minutes),
+            ("absolute_timeout_hours", self.absolute_timeout_hours),
+            ("max_concurrent", self.max_concurrent),
+        ]
+        .into_iter()
+        .find(|(_, value)| *value == Some(0));
+
+        match zero {
+            Some((name, _)) => Err(format!("session {} must be at least 1", name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Each limit this leaves unset taken from `defaults`.
+    pub fn or(&self, defaults: &SessionLimits) -> SessionLimits {
+        SessionLimits {
+            idle_timeout_minutes: self.idle_timeout_minutes.or(defaults.idle_timeout_minutes),
+            absolute_timeout_hours: self.absolute_timeout_hours.or(defaults.absolute_timeout_hours),
+            max_concurrent: self.max_concurrent.or(defaults.max_concurrent),
+            on_limit: self.on_limit.or(defaults.on_limit),
+        }
+    }
+
+    /// Keeps the stricter of each limit.
+    pub fn tighten(&mut self, other: &SessionLimits) {
+        fn stricter<T: Ord + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        self.idle_timeout_minutes = stricter(self.idle_timeout_minutes, other.idle_timeout_minutes);
+        self.absolute_timeout_hours = stricter(self.absolute_timeout_hours, other.absolute_timeout_hours);
+        self.max_concurrent = stricter(self.max_concurrent, other.max_concurrent);
+        self.on_limit = match (self.on_limit, other.on_limit) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub id: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    pub effect: Effect,
+    #[serde(default)]
+    pub actions: Option<Vec<PolicyAction>>,
+    #[serde(default)]
+    pub when: Condition,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    #[serde(default)]
+    pub roles: Option<Vec<String>>,
+    #[serde(default)]
+    pub device_status: Option<Vec<String>>,
+    #[serde(default)]
+    pub source_ip: Option<Vec<Cidr>>,
+    #[serde(default)]
+    pub geo_country: Option<Vec<String>>,
+    #[serde(default)]
+    pub time_of_day: Option<TimeWindow>,
+    #[serde(default)]
+    pub client_version: Option<VersionRange>,
+    #[serde(default)]
+    pub all: Option<Vec<Condition>>,
+    #[serde(default)]
+    pub any: Option<Vec<Condition>>,
+    #[serde(default)]
+    pub not: Option<Box<Condition>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeWindow {
+    #[serde(default)]
+    pub days: Option<Vec<u32>>,
+    pub start: ClockTime,
+    pub end: ClockTime,
+    #[serde(default)]
+    pub utc_offset: Option<UtcOffset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VersionRange {
+    #[serde(default)]
+    pub min: Option<Version>,
+    #[serde(default)]
+    pub max: Option<Version>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr(IpNetwork);
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value
+            .parse::<IpNetwork>()
+            .map(Cidr)
+            .map_err(|_| format!("invalid IP or CIDR '{}'", value))
+    }
+}
+
+/// Minutes since midnight, from "HH:MM"
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ClockTime(u32);
+
+impl TryFrom<String> for ClockTime {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parsed = value
+            .split_once(':')
+            .and_then(|(hours, minutes)| Some((hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?)))
+            .filter(|(hours, minutes)| *hours <= 24 && *minutes < 60 && hours * 60 + minutes <= 24 * 60);
+
+        match parsed {
+            Some((hours, minutes)) => Ok(ClockTime(hours * 60 + minutes)),
+            None => Err(format!("invalid time '{}', expected HH:MM", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct UtcOffset(FixedOffset);
+
+impl TryFrom<String> for UtcOffset {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid utc_offset '{}', expected +HH:MM or -HH:MM", value);
+        if value == "Z" {
+            return Ok(UtcOffset(FixedOffset::east_opt(0).expect("zero offset")));
+        }
+
+        let (sign, rest) = match value.split_at_checked(1) {
+            Some(("+", rest)) => (1, rest),
+            Some(("-", rest)) => (-1, rest),
+            _ => return Err(invalid()),
+        };
+        let ClockTime(minutes) = ClockTime::try_from(rest.to_string()).map_err(|_| invalid())?;
+        FixedOffset::east_opt(sign * minutes as i32 * 60)
+            .map(UtcOffset)
+            .ok_or_else(invalid)
+    }
+}
+
+/// Dotted numeric version; anything after the numeric part ("-beta") is ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Version(Vec<u64>);
+
+impl TryFrom<String> for Version {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Version::parse(&value).ok_or_else(|| format!("invalid version '{}'", value))
+    }
+}
+
+impl Version {
+    pub fn parse(value: &str) -> Option<Self> {
+        let numeric = value.trim().trim_start_matches('v');
+        let numeric = numeric
+            .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('.');
+        if numeric.is_empty() {
+            return None;
+        }
+
+        numeric
+            .split('.')
+            .map(|part| part.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()
+            .map(Version)
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts = self.0.iter().map(u64::to_string).collect::<Vec<_>>();
+        f.write_str(&parts.join("."))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let len = self.0.len().max(other.0.len());
+        let component = |version: &Version, i: usize| version.0.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| component(self, i).cmp(&component(other, i)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }
+}
+
+impl PolicyDocument {
+    /// Parses and validates an `abac_rules` document.
+    pub fn parse(value: &serde_json::Value) -> Result<Self, String> {
+        let document: PolicyDocument = serde_json::from_value(value.clone()).map_err(|e| e.to_string())?;
+
+        let mut ids = std::collections::HashSet::new();
+        for rule in &document.rules {
+            if rule.id.trim().is_empty() {
+                return Err("rule id must not be empty".to_string());
+            }
+            if !ids.insert(rule.id.as_str()) {
+                return Err(format!("duplicate rule id '{}'", rule.id));
+            }
+            rule.when.validate().map_err(|e| format!("rule '{}': {}", rule.id, e))?;
+        }
+        document.session.validate()?;
+
+        Ok(document)
+    }
+}
+
+impl Condition {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(window) = &self.time_of_day {
+            if let Some(day) = window.days.iter().flatten().find(|day| !(1..=7).contains(*day)) {
+                return Err(format!("time_of_day day {} is not 1 (Monday) to 7 (Sunday)", day));
+            }
+        }
+        if let Some(VersionRange { min: Some(min), max: Some(max) }) = &self.client_version {
+            if min > max {
+                return Err("client_version min is above max".to_string());
+            }
+        }
+
+        self.all
+            .iter()
+            .chain(self.any.iter())
+            .flatten()
+            .chain(self.not.as_deref())
+            .try_for_each(Condition::validate)
+    }
+
+    /// Evaluates the condition, appending one line per leaf check to `trace`.
+    fn check(&self, input: &PolicyInput, trace: &mut Vec<String>) -> bool {
+        let mut holds = true;
+
+        if let Some(roles) = &self.roles {
+            holds &= check_listed("roles", Some(&input.role), roles, |a, b| a == b, trace);
+        }
+        if let Some(statuses) = &self.device_status {
+            holds &= check_listed("device_status", input.device_status.as_ref(), statuses, |a, b| a == b, trace);
+        }
+        if let Some(countries) = &self.geo_country {
+            holds &= check_listed("geo_country", input.geo_country.as_ref(), countries, |a, b| a.eq_ignore_ascii_case(b), trace);
+        }
+        if let Some(networks) = &self.source_ip {
+            let matched = input
+                .source_ip
+                .map(|ip| networks.iter().any(|Cidr(network)| network.contains(ip)));
+            let listed = networks.iter().map(|Cidr(network)| network.to_string()).collect::<Vec<_>>();
+            holds &= record(trace, "source_ip", input.source_ip.map(|ip| ip.to_string()), &listed.join(", "), matched);
+        }
+        if let Some(window) = &self.time_of_day {
+            let matched = window.contains(input.at);
+            trace.push(format!(
+                "time_of_day {} in window: {}",
+                input.at.with_timezone(&window.offset()).format("%a %H:%M %:z"),
+                matched
+            ));
+            holds &= matched;
+        }
+        if let Some(range) = &self.client_version {
+            let matched = input
+                .client_version
+                .as_deref()
+                .and_then(Version::parse)
+                .map(|version| {
+                    range.min.as_ref().is_none_or(|min| &version >= min)
+                        && range.max.as_ref().is_none_or(|max| &version <= max)
+                });
+            let bound = |version: &Option<Version>| version.as_ref().map(Version::to_string).unwrap_or_default();
+            let listed = format!("{}..{}", bound(&range.min), bound(&range.max));
+            holds &= record(trace, "client_version", input.client_version.clone(), &listed, matched);
+        }
+        // Every branch is checked (no short-circuit) so the trace explains all of them
+        if let Some(all) = &self.all {
+            let results = all.iter().map(|condition| condition.check(input, trace)).collect::<Vec<_>>();
+            holds &= results.iter().all(|holds| *holds);
+        }
+        if let Some(any) = &self.any {
+            let results = any.iter().map(|condition| condition.check(input, trace)).collect::<Vec<_>>();
+            holds &= results.iter().any(|holds| *holds);
+        }
+        if let Some(not) = &self.not {
+            let inner = not.check(input, trace);
+            trace.push(format!("not: {}", !inner));
+            holds &= !inner;
+        }
+
+        holds
+    }
+}
+
+fn check_listed(
+    attribute: &str,
+    value: Option<&String>,
+    listed: &[String],
+    eq: impl Fn(&str, &str) -> bool,
+    trace: &mut Vec<String>,
+) -> bool {
+    let matched = value.map(|value| listed.iter().any(|candidate| eq(value, candidate)));
+    record(trace, attribute, value.cloned(), &listed.join(", "), matched)
+}
+
+fn record(trace: &mut Vec<String>, attribute: &str, value: Option<String>, listed: &str, matched: Option<bool>) -> bool {
+    match (value, matched) {
+        (Some(value), Some(matched)) => {
+            trace.push(format!("{} {} in [{}]: {}", attribute, value, listed, matched));
+            matched
+        }
+        (Some(value), None) => {
+            trace.push(format!("{} {} unparseable: false", attribute, value));
+            false
+        }
+        (None, _) => {
+            trace.push(format!("{} unknown: false", attribute));
+            false
+        }
+    }
+}
+
+impl TimeWindow {
+    fn offset(&self) -> FixedOffset {
+        self.utc_offset
+            .map(|UtcOffset(offset)| offset)
+            .unwrap_or_else(|| FixedOffset::east_opt(0).expect("zero offset"))
+    }
+
+    fn contains(&self, at: DateTime<Utc>) -> bool {
+        let local = at.with_timezone(&self.offset());
+        let minute = local.hour() * 60 + local.minute();
+        let (ClockTime(start), ClockTime(end)) = (self.start, self.end);
+
+        let day_ok = self
+            .days
+            .as_ref()
+            .is_none_or(|days| days.contains(&local.weekday().number_from_monday()));
+        let time_ok = if start <= end {
+            (start..end).contains(&minute)
+        } else {
+            minute >= start || minute < end
+        };
+
+        day_ok && time_ok
+    }
+}
+
+impl Rule {
+    fn applies_to(&self, action: PolicyAction) -> bool {
+        self.actions.as_ref().is_none_or(|actions| actions.contains(&action))
+    }
+}
+
+fn covers_role(rbac_roles: &serde_json::Value, role: &str) -> Result<bool, String> {
+    let roles: Vec<String> =
+        serde_json::from_value(rbac_roles.clone()).map_err(|_| "rbac_roles must be a list of role names".to_string())?;
+    Ok(roles.is_empty() || roles.iter().any(|candidate| candidate == role))
+}
+
+/// Decides `input` against `policies` (the active ones, in a stable order).
+pub fn evaluate(policies: &[StoredPolicy], input: &PolicyInput) -> PolicyDecision {
+    let mut outcomes = Vec::new();
+    let mut denial = None;
+    let mut session = SessionLimits::default();
+
+    for policy in policies {
+        let covered = covers_role(&policy.rbac_roles, &input.role);
+        if matches!(covered, Ok(false)) {
+            continue;
+        }
+
+        let mut outcome = PolicyOutcome {
+            policy_id: policy.id,
+            name: policy.name.clone(),
+            version: policy.version,
+            effect: Effect::Deny,
+            matched_rule: None,
+            error: None,
+            rules: Vec::new(),
+        };
+
+        match covered.and_then(|_| PolicyDocument::parse(&policy.abac_rules)) {
+            Err(error) => outcome.error = Some(error),
+            Ok(document) => {
+                session.tighten(&document.session);
+                outcome.effect = document.default_effect;
+                for rule in &document.rules {
+                    let mut trace = RuleTrace {
+                        id: rule.id.clone(),
+                        effect: rule.effect,
+                        matched: false,
+                        skipped: None,
+                        conditions: Vec::new(),
+                    };
+
+                    if outcome.matched_rule.is_some() {
+                        trace.skipped = Some("an earlier rule matched");
+                    } else if !rule.enabled {
+                        trace.skipped = Some("disabled");
+                    } else if !rule.applies_to(input.action) {
+                        trace.skipped = Some("action not covered");
+                    } else if rule.when.check(input, &mut trace.conditions) {
+                        trace.matched = true;
+                        outcome.effect = rule.effect;
+                        outcome.matched_rule = Some(rule.id.clone());
+                    }
+
+                    outcome.rules.push(trace);
+                }
+            }
+        }
+
+        if outcome.effect == Effect::Deny && denial.is_none() {
+            denial = Some(match (&outcome.error, &outcome.matched_rule) {
+                (Some(_), _) => format!("policy '{}' has an invalid rule document", outcome.name),
+                (None, Some(rule)) => format!("denied by rule '{}' of policy '{}'", rule, outcome.name),
+                (None, None) => format!("denied by the default effect of policy '{}'", outcome.name),
+            });
+        }
+        outcomes.push(outcome);
+    }
+
+    let allowed = denial.is_none() && !outcomes.is_empty();
+    let reason = match denial {
+        Some(reason) => reason,
+        None if outcomes.is_empty() => format!("no active policy covers role '{}'", input.role),
+        None => "allowed".to_string(),
+    };
+
+    PolicyDecision {
+        allowed,
+        action: input.action,
+        reason,
+        input: input.clone(),
+        policies: outcomes,
+        session,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyRecord {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub rbac_roles: serde_json::Value,
+    pub abac_rules: serde_json::Value,
+    pub version: i32,
+    pub is_active: bool,
+    pub created_by: Option<Uuid>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyVersion {
+    pub version: i32,
+    pub name: String,
+    pub rbac_roles: serde_json::Value,
+    pub abac_rules: serde_json::Value,
+    pub is_active: bool,
+    pub created_by: Option<Uuid>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Fields an admin may set; `None` leaves the current value on update.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct PolicyChange {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub rbac_roles: Option<Vec<String>>,
+    pub abac_rules: Option<serde_json::Value>,
+    pub is_active: Option<bool>,
+}
+
+impl PolicyChange {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = &self.name {
+            if name.trim().is_empty() || name.len() > 100 {
+                return Err("name must be 1-100 characters".to_string());
+            }
+        }
+        if let Some(abac_rules) = &self.abac_rules {
+            PolicyDocument::parse(abac_rules)?;
+        }
+        Ok(())
+    }
+}
+
+/// Loads active policies and decides requests against them. Every change to a
+/// policy bumps its version and is kept in `policy_versions` (see migration 010).
+pub struct PolicyService {
+    pool: PgPool,
+}
+
+impl PolicyService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn active_policies(&self) -> Result<Vec<StoredPolicy>> {
+        let policies = sqlx::query!(
+            r#"
+            SELECT id, name, version AS "version!", rbac_roles, abac_rules
+            FROM policies
+            WHERE is_active
+            ORDER BY created_at, id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| StoredPolicy {
+            id: row.id,
+            name: row.name,
+            version: row.version,
+            rbac_roles: row.rbac_roles,
+            abac_rules: row.abac_rules,
+        })
+        .collect();
+
+        Ok(policies)
+    }
+
+    /// Decides without recording anything.
+    pub async fn evaluate(&self, input: &PolicyInput) -> Result<PolicyDecision> {
+        Ok(evaluate(&self.active_policies().await?, input))
+    }
+
+    /// Decides `input` as if `draft` were saved (replacing the active policy with
+    /// its id, or added after the others). Nothing is recorded.
+    pub async fn dry_run(&self, input: &PolicyInput, draft: Option<StoredPolicy>) -> Result<PolicyDecision> {
+        let mut policies = self.active_policies().await?;
+        if let Some(draft) = draft {
+            match policies.iter_mut().find(|policy| policy.id == draft.id) {
+                Some(policy) => *policy = draft,
+                None => policies.push(draft),
+            }
+        }
+
+        Ok(evaluate(&policies, input))
+    }
+
+    /// Gathers the request's attributes; the device is the user's desktop device
+    /// whose fingerprint the client sent (`X-Device-Id`).
+    pub async fn input_for(
+        &self,
+        action: PolicyAction,
+        user_id: Uuid,
+        role: &str,
+        context: &ClientContext,
+    ) -> Result<PolicyInput> {
+        let device_status = match &context.device_id {
+            Some(fingerprint) => sqlx::query_scalar!(
+                "SELECT status::text FROM desktop_devices WHERE user_id = $1 AND fingerprint = $2",
+                user_id,
+                fingerprint
+            )
+            .fetch_optional(&self.pool)
+            .await?
+            .flatten(),
+            None => None,
+        };
+
+        Ok(PolicyInput {
+            action,
+            role: role.to_string(),
+            device_status,
+            source_ip: context.ip_address.map(|network| network.ip()),
+            geo_country: context.geo_country.clone(),
+            client_version: context.client_version.clone(),
+            at: Utc::now(),
+        })
+    }
+
+    /// Decides a real request and audits denials as `policy_denied`.
+    pub async fn enforce(
+        &self,
+        action: PolicyAction,
+        user_id: Uuid,
+        role: &str,
+        context: &ClientContext,
+    ) -> Result<PolicyDecision> {
+        let input = self.input_for(action, user_id, role, context).await?;
+        let decision = self.evaluate(&input).await?;
+
+        if !decision.allowed {
+            AuditEvent::new(AuditEventType::PolicyDenied)
+                .user(Some(user_id))
+                .details(decision.snapshot())
+                .context(context)
+                .write(&self.pool)
+                .await?;
+        }
+
+        Ok(decision)
+    }
+
+    pub async fn list(&self) -> Result<Vec<PolicyRecord>> {
+        let policies = sqlx::query_as!(
+            PolicyRecord,
+            r#"
+            SELECT id, name, description, rbac_roles, abac_rules, version AS "version!",
+                   is_active AS "is_active!", created_by, created_at, updated_at
+            FROM policies
+            ORDER BY created_at, id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(policies)
+    }
+
+    /// `change` must have been validated; name and abac_rules are required.
+    pub async fn create(&self, change: &PolicyChange, created_by: Option<Uuid>) -> Result<PolicyRecord> {
+        let policy = sqlx::query_as!(
+            PolicyRecord,
+            r#"
+            INSERT INTO policies (name, description, rbac_roles, abac_rules, is_active, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, name, description, rbac_roles, abac_rules, version AS "version!",
+                      is_active AS "is_active!", created_by, created_at, updated_at
+            "#,
+            change.name.as_deref().unwrap_or_default(),
+            change.description,
+            serde_json::json!(change.rbac_roles.clone().unwrap_or_default()),
+            change.abac_rules.clone().unwrap_or_else(|| serde_json::json!({})),
+            change.is_active.unwrap_or(true),
+            created_by
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(policy)
+    }
+
+    /// Returns None if the policy does not exist. `changed_by` is recorded on the new version.
+    pub async fn update(&self, id: Uuid, change: &PolicyChange, changed_by: Option<Uuid>) -> Result<Option<PolicyRecord>> {
+        let mut tx = self.pool.begin().await?;
+
+        // The version trigger attributes the new revision to this setting
+        sqlx::query_scalar!(
+            "SELECT set_config('viworks.policy_changed_by', COALESCE($1, ''), true)",
+            changed_by.map(|id| id.to_string())
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let policy = sqlx::query_as!(
+            PolicyRecord,
+            r#"
+            UPDATE policies
+            SET name = COALESCE($2, name),
+                description = COALESCE($3, description),
+                rbac_roles = COALESCE($4, rbac_roles),
+                abac_rules = COALESCE($5, abac_rules),
+                is_active = COALESCE($6, is_active)
+            WHERE id = $1
+            RETURNING id, name, description, rbac_roles, abac_rules, version AS "version!",
+                      is_active AS "is_active!", created_by, created_at, updated_at
+            "#,
+            id,
+            change.name,
+            change.description,
+            change.rbac_roles.as_ref().map(|roles| serde_json::json!(roles)),
+            change.abac_rules,
+            change.is_active
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(policy)
+    }
+
+    pub async fn versions(&self, id: Uuid) -> Result<Vec<PolicyVersion>> {
+        let versions = sqlx::query_as!(
+            PolicyVersion,
+            r#"
+            SELECT version, name, rbac_roles, abac_rules, is_active, created_by, created_at
+            FROM policy_versions
+            WHERE policy_id = $1
+            ORDER BY version DESC
+            "#,
+            id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn policy(rbac_roles: serde_json::Value, abac_rules: serde_json::Value) -> StoredPolicy {
+        StoredPolicy {
+            id: Uuid::new_v4(),
+            name: "test".to_string(),
+            version: 1,
+            rbac_roles,
+            abac_rules,
+        }
+    }
+
+    fn input(action: PolicyAction, role: &str) -> PolicyInput {
+        PolicyInput {
+            action,
+            role: role.to_string(),
+            device_status: Some("approved".to_string()),
+            source_ip: Some("10.20.1.5".parse().unwrap()),
+            geo_country: Some("de".to_string()),
+            client_version: Some("1.4.2".to_string()),
+            // Wednesday 10:00 UTC
+            at: Utc.with_ymd_and_hms(2026, 10, 14, 10, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_rbac_and_first_matching_rule_decide() {
+        let policies = [policy(
+            serde_json::json!(["admin", "user"]),
+            serde_json::json!({
+                "rules": [
+                    {"id": "admins-only", "effect": "deny", "actions": ["admin"],
+                     "when": {"not": {"roles": ["admin"]}}},
+                    {"id": "office-network", "effect": "deny",
+                     "when": {"not": {"source_ip": ["10.20.0.0/16"]}}}
+                ]
+            }),
+        )];
+
+        assert!(evaluate(&policies, &input(PolicyAction::Admin, "admin")).allowed);
+        let denied = evaluate(&policies, &input(PolicyAction::Admin, "user"));
+        assert!(!denied.allowed);
+        assert_eq!(denied.policies[0].matched_rule.as_deref(), Some("admins-only"));
+        assert!(evaluate(&policies, &input(PolicyAction::Login, "user")).allowed);
+
+        let mut remote = input(PolicyAction::Login, "user");
+        remote.source_ip = Some("203.0.113.9".parse().unwrap());
+        assert!(!evaluate(&policies, &remote).allowed);
+
+        let uncovered = evaluate(&policies, &input(PolicyAction::Login, "auditor"));
+        assert!(!uncovered.allowed);
+        assert_eq!(uncovered.reason, "no active policy covers role 'auditor'");
+    }
+
+    #[test]
+    fn test_conditions() {
+        let rules = |when: serde_json::Value| {
+            [policy(
+                serde_json::json!([]),
+                serde_json::json!({"default_effect": "deny", "rules": [{"id": "r", "effect": "allow", "when": when}]}),
+            )]
+        };
+        let allowed = |when: serde_json::Value, input: &PolicyInput| evaluate(&rules(when), input).allowed;
+        let login = input(PolicyAction::Login, "user");
+
+        // 10:00 UTC is 13:30 in Tehran
+        assert!(allowed(serde_json::json!({"time_of_day": {"days": [3], "start": "13:00", "end": "14:00", "utc_offset": "+03:30"}}), &login));
+        assert!(!allowed(serde_json::json!({"time_of_day": {"days": [1, 2], "start": "00:00", "end": "24:00"}}), &login));
+        assert!(allowed(serde_json::json!({"time_of_day": {"start": "22:00", "end": "11:00"}}), &login));
+        assert!(allowed(serde_json::json!({"client_version": {"min": "1.4", "max": "1.4.9"}}), &login));
+        assert!(!allowed(serde_json::json!({"client_version": {"min": "1.10.0"}}), &login));
+        assert!(allowed(serde_json::json!({"geo_country": ["IR", "DE"], "device_status": ["approved"]}), &login));
+        assert!(allowed(serde_json::json!({"any": [{"roles": ["admin"]}, {"source_ip": ["10.20.1.5"]}]}), &login));
+
+        let mut unknown = login.clone();
+        unknown.geo_country = None;
+        assert!(!allowed(serde_json::json!({"geo_country": ["DE"]}), &unknown));
+        assert!(allowed(serde_json::json!({"not": {"geo_country": ["DE"]}}), &unknown));
+    }
+
+    #[test]
+    fn test_session_limits_take_the_strictest() {
+        let policies = [
+            policy(
+                serde_json::json!([]),
+                serde_json::json!({"session": {"idle_timeout_minutes": 30, "max_concurrent": 3, "on_limit": "deny"}}),
+            ),
+            policy(
+                serde_json::json!(["user"]),
+                serde_json::json!({"session": {"idle_timeout_minutes": 10, "absolute_timeout_hours": 8, "on_limit": "evict_oldest"}}),
+            ),
+            policy(serde_json::json!(["admin"]), serde_json::json!({"session": {"max_concurrent": 1}})),
+        ];
+
+        let decision = evaluate(&policies, &input(PolicyAction::Login, "user"));
+        assert_eq!(
+            decision.session,
+            SessionLimits {
+                idle_timeout_minutes: Some(10),
+                absolute_timeout_hours: Some(8),
+                max_concurrent: Some(3),
+                on_limit: Some(SessionLimitMode::Deny),
+            }
+        );
+        assert_eq!(decision.snapshot()["session"]["max_concurrent"], 3);
+    }
+
+    #[test]
+    fn test_invalid_documents_are_rejected_and_deny() {
+        for document in [
+            serde_json::json!({"rules": [{"id": "r", "effect": "deny", "when": {"source_ip": ["10.0.0.0/33"]}}]}),
+            serde_json::json!({"rules": [{"id": "r", "effect": "deny", "when": {"time_of_day": {"start": "7:00", "end": "25:00"}}}]}),
+            serde_json::json!({"rules": [{"id": "r", "effect": "deny", "when": {"weekday": [1]}}]}),
+            serde_json::json!({"rules": [{"id": "r", "effect": "deny"}, {"id": "r", "effect": "allow"}]}),
+            serde_json::json!({"time_windows": []}),
+            serde_json::json!({"session": {"max_concurrent": 0}}),
+            serde_json::json!({"session": {"on_limit": "kick"}}),
+        ] {
+            assert!(PolicyDocument::parse(&document).is_err(), "{}", document);
+            let decision = evaluate(&[policy(serde_json::json!([]), document)], &input(PolicyAction::Login, "user"));
+            assert!(!decision.allowed);
+            assert!(decision.policies[0].error.is_some());
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! Push approval with number matching for the mobile second factor.
+//!
+//! A `mobile_otp` user with an approved device in `mobile_devices` that has an
+//! FCM token answers the login challenge on the phone instead of typing a
+//! code. The desktop shows a two-digit number; the push offers it among
+//! decoys, and only picking it approves the sign-in, so a prompt someone else
+//! triggered cannot be approved with a careless tap. Picking another number
+//! denies it. The push also carries the challenge's one-time code (`code` in
+//! its data) for the phone to show, so the user can type it on the desktop
+//! instead; it is the only channel the code is delivered over.
+//!
+//! Each prompt is a `verification_requests` row: `code` holds the number,
+//! `number_choices` what the phone offers, `challenge_id` the login challenge
+//! it answers and `response_token_hash` the SHA-256 of a single-use token the
+//! push carries, which the phone presents with its answer. Prompts expire after
+//! PUSH_APPROVAL_TTL_SECONDS; a background sweep marks unanswered ones and
+//! records `push_approval_timed_out`.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Serialize;
+use schemars::JsonSchema;
+use sqlx::PgPool;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::auth::ClientContext;
+use crate::config::AppConfig;
+use crate::services::account_recovery::{generate_token, hash_token};
+use crate::services::audit::{AuditEvent, AuditEventType};
+use crate::services::push::{PushDelivery, PushMessage, PushProvider};
+
+/// Numbers the phone offers, the right one included
+const NUMBER_CHOICES: usize = 3;
+
+/// What the desktop shows while the phone is asked.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PushPrompt {
+    pub request_id: Uuid,
+    /// The number to pick on the phone
+    pub number: i16,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushAnswer {
+    Approved,
+    Denied,
+    /// Approved with a number other than the one on the desktop; counts as a denial
+    NumberMismatch,
+    /// Unknown, already answered or expired prompt, or the wrong device or token
+    Invalid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushApprovalStatus {
+    Pending,
+    Approved,
+    Denied,
+    Expired,
+}
+
+impl PushApprovalStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Denied => "denied",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+/// The number to match and the choices offered for it, all distinct and two-digit.
+pub fn number_choices() -> (i16, Vec<i16>) {
+    let mut rng = rand::thread_rng();
+    let mut choices: Vec<i16> = Vec::with_capacity(NUMBER_CHOICES);
+    while choices.len() < NUMBER_CHOICES {
+        let candidate = rng.gen_range(10..=99);
+        if !choices.contains(&candidate) {
+            choices.push(candidate);
+        }
+    }
+    let number = choices[0];
+    choices.shuffle(&mut rng);
+    (number, choices)
+}
+
+struct PushDevice {
+    device_id: String,
+    fcm_token: String,
+}
+
+pub struct PushApprovalService {
+    pool: PgPool,
+    provider: Option<Arc<dyn PushProvider>>,
+    ttl: Duration,
+}
+
+impl PushApprovalService {
+    pub fn new(config: &AppConfig, pool: PgPool, provider: Option<Arc<dyn PushProvider>>) -> Self {
+        Self {
+            pool,
+            provider,
+            ttl: Duration::seconds(config.push_approval_ttl_seconds as i64),
+        }
+    }
+
+    /// False when no FCM_CREDENTIALS_FILE is configured.
+    pub fn enabled(&self) -> bool {
+        self.provider.is_some()
+    }
+
+    /// Asks the user's most recently used approved device to approve the login
+    /// challenge `challenge_id`, sending its `code` along. Returns `None` when
+    /// push is off, no device can receive it or delivery fails; the code then
+    /// reached nobody. Tokens FCM reports as unregistered are cleared and the
+    /// next device tried.
+    pub async fn send(
+        &self,
+        user_id: Uuid,
+        username: &str,
+        challenge_id: &str,
+        code: &str,
+        context: &ClientContext,
+    ) -> Result<Option<PushPrompt>> {
+        let Some(provider) = &self.provider else {
+            return Ok(None);
+        };
+
+        let devices = sqlx::query_as!(
+            PushDevice,
+            r#"
+            SELECT device_id, fcm_token AS "fcm_token!"
+            FROM mobile_devices
+            WHERE user_id = $1 AND status = 'approved' AND fcm_token IS NOT NULL AND fcm_token <> ''
+            ORDER BY last_used_at DESC NULLS LAST
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for device in devices {
+            let request_id = Uuid::new_v4();
+            let (number, choices) = number_choices();
+            let response_token = generate_token();
+            let expires_at = Utc::now() + self.ttl;
+            let from = context.ip_address.map(|ip| ip.ip().to_string());
+
+            let mut data = BTreeMap::from([
+                ("type".to_string(), "push_approval".to_string()),
+                ("request_id".to_string(), request_id.to_string()),
+                ("response_token".to_string(), response_token.clone()),
+                ("code".to_string(), code.to_string()),
+                ("username".to_string(), username.to_string()),
+                ("choices".to_string(), choices.iter().map(i16::to_string).collect::<Vec<_>>().join(",")),
+                ("expires_at".to_string(), expires_at.to_rfc3339()),
+            ]);
+            if let Some(from) = &from {
+                data.insert("ip_address".to_string(), from.clone());
+            }
+            let message = PushMessage {
+                token: device.fcm_token.clone(),
+                title: "Approve sign-in to ViWorkS".to_string(),
+                body: match &from {
+                    Some(from) => format!("{} is signing in from {}. Pick the number shown on the screen.", username, from),
+                    None => format!("{} is signing in. Pick the number shown on the screen.", username),
+                },
+                data,
+                ttl: self.ttl.to_std().unwrap_or_default(),
+            };
+
+            // Stored before sending so a fast answer always finds its row
+            sqlx::query!(
+                r#"
+                INSERT INTO verification_requests (
+                    id, user_id, device_id, code, expires_at, ip_address, network_info,
+                    push_status, challenge_id, number_choices, response_token_hash
+                ) VALUES ($1, $2, $3, $4, $5, $6, $7, 'pending', $8, $9, $10)
+                "#,
+                request_id,
+                user_id,
+                device.device_id,
+                number.to_string(),
+                expires_at,
+                context.ip_address,
+                serde_json::json!({ "user_agent": context.user_agent }),
+                challenge_id,
+                &choices,
+                hash_token(&response_token)
+            )
+            .execute(&self.pool)
+            .await?;
+
+            let delivery = provider.send(&message).await;
+            let message_id = match delivery {
+                Ok(PushDelivery::Sent(message_id)) => message_id,
+                Ok(PushDelivery::Unregistered) => {
+                    warn!("FCM token of device {} of user {} is no longer registered", device.device_id, user_id);
+                    sqlx::query!(
+                        "UPDATE mobile_devices SET fcm_token = NULL WHERE user_id = $1 AND device_id = $2 AND fcm_token = $3",
+                        user_id,
+                        device.device_id,
+                        device.fcm_token
+                    )
+                    .execute(&self.pool)
+                    .await?;
+                    sqlx::query!("DELETE FROM verification_requests WHERE id = $1", request_id)
+                        .execute(&self.pool)
+                        .await?;
+                    continue;
+                }
+                Err(e) => {
+                    error!("Push approval for user {} could not be sent: {:#}", user_id, e);
+                    sqlx::query!("DELETE FROM verification_requests WHERE id = $1", request_id)
+                        .execute(&self.pool)
+                        .await?;
+                    return Ok(None);
+                }
+            };
+
+            let mut tx = self.pool.begin().await?;
+            sqlx::query!(
+                "UPDATE verification_requests SET push_message_id = $2 WHERE id = $1",
+                request_id,
+                message_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            AuditEvent::new(AuditEventType::PushApprovalSent)
+                .user(Some(user_id))
+                .details(serde_json::json!({
+                    "request_id": request_id,
+                    "device_id": device.device_id,
+                    "challenge_id": challenge_id,
+                    "expires_at": expires_at
+                }))
+                .context(context)
+                .write(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            return Ok(Some(PushPrompt { request_id, number, expires_at }));
+        }
+
+        Ok(None)
+    }
+
+    /// Pushes the code of device verification request `request_id` to `device_id`,
+    /// one of the user's approved devices. False when push is off, the device has
+    /// no FCM token or delivery failed.
+    pub async fn send_verification_code(
+        &self,
+        user_id: Uuid,
+        device_id: &str,
+        request_id: Uuid,
+        code: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let Some(provider) = &self.provider else {
+            return Ok(false);
+        };
+
+        let fcm_token = sqlx::query_scalar!(
+            r#"
+            SELECT fcm_token AS "fcm_token!"
+            FROM mobile_devices
+            WHERE user_id = $1 AND device_id = $2 AND status = 'approved' AND fcm_token IS NOT NULL AND fcm_token <> ''
+            "#,
+            user_id,
+            device_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(fcm_token) = fcm_token else {
+            return Ok(false);
+        };
+
+        let message = PushMessage {
+            token: fcm_token.clone(),
+            title: "ViWorkS verification code".to_string(),
+            body: "Open ViWorkS to see your verification code.".to_string(),
+            data: BTreeMap::from([
+                ("type".to_string(), "device_verification".to_string()),
+                ("request_id".to_string(), request_id.to_string()),
+                ("code".to_string(), code.to_string()),
+                ("expires_at".to_string(), expires_at.to_rfc3339()),
+            ]),
+            ttl: (expires_at - Utc::now()).to_std().unwrap_or_default(),
+        };
+
+        match provider.send(&message).await {
+            Ok(PushDelivery::Sent(_)) => Ok(true),
+            Ok(PushDelivery::Unregistered) => {
+                warn!("FCM token of device {} of user {} is no longer registered", device_id, user_id);
+                sqlx::query!(
+                    "UPDATE mobile_devices SET fcm_token = NULL WHERE user_id = $1 AND device_id = $2 AND fcm_token = $3",
+                    user_id,
+                    device_id,
+                    fcm_token
+                )
+                .execute(&self.pool)
+                .await?;
+                Ok(false)
+            }
+            Err(e) => {
+                error!("Verification code for user {} could not be sent: {:#}", user_id, e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Records the phone's answer to prompt `request_id`. The device must be the
+    /// one prompted and still approved, and `response_token` the one the push
+    /// carried.
+    pub async fn answer(
+        &self,
+        request_id: Uuid,
+        response_token: &str,
+        device_id: &str,
+        approve: bool,
+        number: Option<i16>,
+        context: &ClientContext,
+    ) -> Result<PushAnswer> {
+        let mut tx = self.pool.begin().await?;
+
+        let request = sqlx::query!(
+            r#"
+            SELECT v.user_id, v.code AS "code!", v.challenge_id
+            FROM verification_requests v
+            JOIN mobile_devices d ON d.user_id = v.user_id AND d.device_id = v.device_id AND d.status = 'approved'
+            WHERE v.id = $1 AND v.response_token_hash = $2 AND v.device_id = $3
+              AND v.push_status = 'pending' AND v.expires_at > NOW()
+            FOR UPDATE OF v
+            "#,
+            request_id,
+            hash_token(response_token),
+            device_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(request) = request else {
+            return Ok(PushAnswer::Invalid);
+        };
+
+        let answer = match (approve, number) {
+            (false, _) => PushAnswer::Denied,
+            (true, Some(number)) if number.to_string() == request.code => PushAnswer::Approved,
+            (true, _) => PushAnswer::NumberMismatch,
+        };
+        let approved = answer == PushAnswer::Approved;
+
+        sqlx::query!(
+            r#"
+            UPDATE verification_requests
+            SET push_status = $2, approved = $3, completed = true, responded_at = NOW(), updated_at = NOW()
+            WHERE id = $1
+            "#,
+            request_id,
+            if approved { "approved" } else { "denied" },
+            approved
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE mobile_devices SET last_used_at = NOW() WHERE user_id = $1 AND device_id = $2",
+            request.user_id,
+            device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let event_type = if approved {
+            AuditEventType::PushApprovalApproved
+        } else {
+            AuditEventType::PushApprovalDenied
+        };
+        AuditEvent::new(event_type)
+            .user(Some(request.user_id))
+            .details(serde_json::json!({
+                "request_id": request_id,
+                "device_id": device_id,
+                "challenge_id": request.challenge_id,
+                "reason": match answer {
+                    PushAnswer::NumberMismatch => Some("number_mismatch"),
+                    PushAnswer::Denied => Some("denied_on_device"),
+                    _ => None,
+                }
+            }))
+            .context(context)
+            .write(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(answer)
+    }
+
+    /// State of the latest prompt for login challenge `challenge_id` of `user_id`.
+    pub async fn status(&self, challenge_id: &str, user_id: Uuid) -> Result<Option<PushApprovalStatus>> {
+        let request = sqlx::query!(
+            r#"
+            SELECT push_status AS "push_status!", expires_at
+            FROM verification_requests
+            WHERE challenge_id = $1 AND user_id = $2 AND push_status IS NOT NULL
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+            challenge_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(request.map(|request| match request.push_status.as_str() {
+            "approved" => PushApprovalStatus::Approved,
+            "denied" => PushApprovalStatus::Denied,
+            "pending" if request.expires_at > Utc::now() => PushApprovalStatus::Pending,
+            _ => PushApprovalStatus::Expired,
+        }))
+    }
+
+    /// Marks prompts nobody answered in time as expired, records a
+    /// `push_approval_timed_out` event for each and returns how many there were.
+    pub async fn expire_stale(&self) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        let expired = sqlx::query!(
+            r#"
+            UPDATE verification_requests
+            SET push_status = 'expired', completed = true, updated_at = NOW()
+            WHERE push_status = 'pending' AND expires_at <= NOW()
+            RETURNING id, user_id, device_id, challenge_id, host(ip_address) AS ip_address, expires_at
+            "#
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for request in &expired {
+            AuditEvent::new(AuditEventType::PushApprovalTimedOut)
+                .user(Some(request.user_id))
+                .details(serde_json::json!({
+                    "request_id": request.id,
+                    "device_id": request.device_id,
+                    "challenge_id": request.challenge_id,
+                    "ip_address": request.ip_address,
+                    "expires_at": request.expires_at
+                }))
+                .write(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_choices() {
+        for _ in 0..200 {
+            let (number, choices) = number_choices();
+            assert_eq!(choices.len(), NUMBER_CHOICES);
+            assert!(choices.contains(&number));
+            assert!(choices.iter().all(|choice| (10..=99).contains(choice)));
+            let mut distinct = choices.clone();
+            distinct.sort();
+            distinct.dedup();
+            assert_eq!(distinct.len(), NUMBER_CHOICES);
+        }
+    }
+}
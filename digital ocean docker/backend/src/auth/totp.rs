@@ -0,0 +1,300 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use totp_rs::{Algorithm, TOTP};
+use uuid::Uuid;
+use crate::config::AppConfig;
+
+const SECRET_LEN: usize = 20;
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+/// Accept codes from one step before/after the current one (clock drift)
+const TOTP_SKEW_STEPS: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// Authenticator-app (RFC 6238) second factor plus single-use recovery codes.
+pub struct TotpService {
+    pool: PgPool,
+    cipher: Aes256Gcm,
+    issuer: String,
+}
+
+impl TotpService {
+    pub fn new(config: &AppConfig, pool: PgPool) -> Result<Self> {
+        let key = config.encryption_key(&config.totp_encryption_key, "totp-secret")?;
+
+        Ok(Self {
+            pool,
+            cipher: Aes256Gcm::new(&key.into()),
+            issuer: config.totp_issuer.clone(),
+        })
+    }
+
+    /// Starts (or restarts) enrollment. Returns `None` if the user already has a
+    /// confirmed authenticator; it must be disabled first.
+    pub async fn begin_enrollment(&self, user_id: Uuid, username: &str) -> Result<Option<TotpEnrollment>> {
+        let mut secret = vec![0u8; SECRET_LEN];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let (ciphertext, nonce) = self.encrypt_secret(&secret)?;
+
+        let stored = sqlx::query_scalar!(
+            r#"
+            INSERT INTO user_totp (user_id, secret_ciphertext, secret_nonce)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE SET
+                secret_ciphertext = EXCLUDED.secret_ciphertext,
+                secret_nonce = EXCLUDED.secret_nonce,
+                last_used_step = NULL
+            WHERE user_totp.confirmed_at IS NULL
+            RETURNING user_id
+            "#,
+            user_id,
+            ciphertext,
+            nonce
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if stored.is_none() {
+            return Ok(None);
+        }
+
+        let totp = self.totp(secret, username)?;
+        Ok(Some(TotpEnrollment {
+            secret: totp.get_secret_base32(),
+            otpauth_uri: totp.get_url(),
+        }))
+    }
+
+    /// Confirms enrollment with a first code from the app, switches the user to
+    /// TOTP and returns a fresh set of recovery codes. `None` if the code is wrong.
+    pub async fn confirm_enrollment(&self, user_id: Uuid, code: &str) -> Result<Option<Vec<String>>> {
+        let Some(step) = self.check_code(user_id, code, false).await? else {
+            return Ok(None);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "UPDATE user_totp SET confirmed_at = NOW(), last_used_step = $2 WHERE user_id = $1",
+            user_id,
+            step
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("UPDATE users SET mfa_method = 'totp' WHERE id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        let codes = Self::replace_recovery_codes(&mut tx, user_id).await?;
+        tx.commit().await?;
+
+        Ok(Some(codes))
+    }
+
+    /// Verifies a code from the user's confirmed authenticator, or one of their
+    /// unused recovery codes. Each time step and each recovery code works once.
+    pub async fn verify(&self, user_id: Uuid, code: &str) -> Result<bool> {
+        let code = code.trim();
+        if code.len() != TOTP_DIGITS {
+            return self.redeem_recovery_code(user_id, code).await;
+        }
+
+        let Some(step) = self.check_code(user_id, code, true).await? else {
+            return Ok(false);
+        };
+
+        // Conditional update so two concurrent requests can't both use the same step
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_totp SET last_used_step = $2
+            WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+            "#,
+            user_id,
+            step
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn regenerate_recovery_codes(&self, user_id: Uuid) -> Result<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        let codes = Self::replace_recovery_codes(&mut tx, user_id).await?;
+        tx.commit().await?;
+        Ok(codes)
+    }
+
+    /// Removes the authenticator and recovery codes and returns the user to mobile OTP.
+    pub async fn disable(&self, user_id: Uuid) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("UPDATE users SET mfa_method = 'mobile_otp' WHERE id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns the matching time step if `code` is valid for the stored secret and
+    /// newer than the last accepted step.
+    async fn check_code(&self, user_id: Uuid, code: &str, confirmed: bool) -> Result<Option<i64>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT t.secret_ciphertext, t.secret_nonce, t.last_used_step, u.username
+            FROM user_totp t
+            JOIN users u ON t.user_id = u.id
+            WHERE t.user_id = $1 AND (t.confirmed_at IS NOT NULL) = $2
+            "#,
+            user_id,
+            confirmed
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let secret = self.decrypt_secret(&row.secret_ciphertext, &row.secret_nonce)?;
+        let totp = self.totp(secret, &row.username)?;
+        let now = Utc::now().timestamp().max(0) as u64;
+
+        Ok(matching_step(&totp, code.trim(), now)
+            .map(|step| step as i64)
+            .filter(|step| row.last_used_step.is_none_or(|last| *step > last)))
+    }
+
+    async fn redeem_recovery_code(&self, user_id: Uuid, code: &str) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE recovery_codes SET used_at = NOW()
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            "#,
+            user_id,
+            hash_recovery_code(code)
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn replace_recovery_codes(tx: &mut sqlx::PgConnection, user_id: Uuid) -> Result<Vec<String>> {
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+        let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+        sqlx::query!(
+            "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::text[])",
+            user_id,
+            &hashes
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(codes)
+    }
+
+    fn totp(&self, secret: Vec<u8>, username: &str) -> Result<TOTP> {
+        // ':' separates issuer and account in the otpauth label
+        TOTP::new(
+            Algorithm::SHA1,
+            TOTP_DIGITS,
+            TOTP_SKEW_STEPS as u8,
+            TOTP_STEP,
+            secret,
+            Some(self.issuer.replace(':', "")),
+            username.replace(':', ""),
+        )
+        .map_err(|e| anyhow!("Invalid TOTP parameters: {}", e))
+    }
+
+    fn encrypt_secret(&self, secret: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), secret)
+            .map_err(|_| anyhow!("Failed to encrypt TOTP secret"))?;
+        Ok((ciphertext, nonce.to_vec()))
+    }
+
+    fn decrypt_secret(&self, ciphertext: &[u8], nonce: &[u8]) -> Result<Vec<u8>> {
+        if nonce.len() != 12 {
+            return Err(anyhow!("Invalid TOTP secret nonce"));
+        }
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt TOTP secret (wrong TOTP_ENCRYPTION_KEY?)"))
+    }
+}
+
+/// The time step (±`TOTP_SKEW_STEPS`) whose code equals `code`, if any.
+fn matching_step(totp: &TOTP, code: &str, now: u64) -> Option<u64> {
+    let current = now / TOTP_STEP;
+    (current.saturating_sub(TOTP_SKEW_STEPS)..=current + TOTP_SKEW_STEPS)
+        .rev()
+        .find(|step| totp.generate(step * TOTP_STEP) == code)
+}
+
+fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::thread_rng();
+    let chars: String = (0..10)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+/// Recovery codes are matched case-insensitively and ignoring separators.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_window() {
+        let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, vec![7u8; SECRET_LEN], None, "alice".to_string()).unwrap();
+        let now = 1_700_000_000;
+        let current = now / TOTP_STEP;
+
+        assert_eq!(matching_step(&totp, &totp.generate(now), now), Some(current));
+        assert_eq!(matching_step(&totp, &totp.generate(now - 30), now), Some(current - 1));
+        assert_eq!(matching_step(&totp, &totp.generate(now + 30), now), Some(current + 1));
+        assert_eq!(matching_step(&totp, &totp.generate(now - 60), now), None);
+    }
+
+    #[test]
+    fn test_recovery_code_normalization() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(hash_recovery_code(&code), hash_recovery_code(&code.to_uppercase().replace('-', " ")));
+    }
+}
@@ -0,0 +1,618 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use ciborium::Value;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand::RngCore;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::auth::UserRole;
+use crate::config::AppConfig;
+
+const CHALLENGE_LEN: usize = 32;
+const CEREMONY_TIMEOUT_SECS: i64 = 300;
+/// COSE algorithm identifier for ES256 (ECDSA P-256 with SHA-256), the only one we accept
+const COSE_ALG_ES256: i64 = -7;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Browser `PublicKeyCredential` from `navigator.credentials.create()`, base64url fields.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+/// Browser `PublicKeyCredential` from `navigator.credentials.get()`, base64url fields.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AuthenticationCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+/// Options to hand to the browser as `publicKey`, plus the id to finish the ceremony with.
+#[derive(Debug, Serialize)]
+pub struct CeremonyOptions {
+    pub challenge_id: Uuid,
+    #[serde(rename = "publicKey")]
+    pub public_key: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebAuthnCredentialInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub aaguid: Uuid,
+    pub sign_count: i64,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub clone_detected_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistrationOutcome {
+    Registered { credential_id: Uuid },
+    Rejected(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthenticationOutcome {
+    Verified { credential_id: Uuid },
+    Rejected(&'static str),
+    /// The signature counter went backwards: the key has likely been cloned.
+    /// The credential is disabled.
+    CloneDetected { credential_id: Uuid },
+}
+
+/// WebAuthn relying party (ES256 security keys and platform authenticators).
+///
+/// Attestation is requested as `"none"`, so attestation statements are not
+/// verified; what we rely on is the key pair created during registration.
+pub struct WebAuthnService {
+    pool: PgPool,
+    rp_id: String,
+    rp_name: String,
+    rp_origin: String,
+    required_for_admins: bool,
+}
+
+impl WebAuthnService {
+    pub fn new(config: &AppConfig, pool: PgPool) -> Self {
+        Self {
+            pool,
+            rp_id: config.webauthn_rp_id.clone(),
+            rp_name: config.webauthn_rp_name.clone(),
+            rp_origin: config.webauthn_rp_origin.trim_end_matches('/').to_string(),
+            required_for_admins: config.webauthn_required_for_admins,
+        }
+    }
+
+    /// Whether sessions with this role must pass a WebAuthn assertion before admin routes.
+    pub fn required_for(&self, role: &str) -> bool {
+        self.required_for_admins && UserRole::from(role).is_admin()
+    }
+
+    pub async fn begin_registration(&self, user_id: Uuid, username: &str) -> Result<CeremonyOptions> {
+        let (challenge_id, challenge) = self.create_challenge(user_id, "registration").await?;
+        let exclude: Vec<serde_json::Value> = self
+            .credential_ids(user_id)
+            .await?
+            .iter()
+            .map(|id| serde_json::json!({ "type": "public-key", "id": URL_SAFE_NO_PAD.encode(id) }))
+            .collect();
+
+        Ok(CeremonyOptions {
+            challenge_id,
+            public_key: serde_json::json!({
+                "rp": { "id": self.rp_id, "name": self.rp_name },
+                "user": {
+                    "id": URL_SAFE_NO_PAD.encode(user_id.as_bytes()),
+                    "name": username,
+                    "displayName": username
+                },
+                "challenge": URL_SAFE_NO_PAD.encode(challenge),
+                "pubKeyCredParams": [{ "type": "public-key", "alg": COSE_ALG_ES256 }],
+                "timeout": CEREMONY_TIMEOUT_SECS * 1000,
+                "attestation": "none",
+                "excludeCredentials": exclude,
+                "authenticatorSelection": { "userVerification": "preferred" }
+            }),
+        })
+    }
+
+    pub async fn finish_registration(
+        &self,
+        user_id: Uuid,
+        challenge_id: Uuid,
+        name: &str,
+        credential: &RegistrationCredential,
+    ) -> Result<RegistrationOutcome> {
+        let Some(challenge) = self.consume_challenge(challenge_id, user_id, "registration").await? else {
+            return Ok(RegistrationOutcome::Rejected("Unknown or expired challenge"));
+        };
+
+        let (Some(client_data), Some(attestation_object), Some(credential_id)) = (
+            decode_b64url(&credential.response.client_data_json),
+            decode_b64url(&credential.response.attestation_object),
+            decode_b64url(&credential.id),
+        ) else {
+            return Ok(RegistrationOutcome::Rejected("Malformed credential"));
+        };
+
+        if let Err(reason) = check_client_data(&client_data, "webauthn.create", &challenge, &self.rp_origin) {
+            return Ok(RegistrationOutcome::Rejected(reason));
+        }
+
+        let Some(auth_data) = parse_attestation_object(&attestation_object)
+            .and_then(|auth_data| parse_authenticator_data(&auth_data))
+        else {
+            return Ok(RegistrationOutcome::Rejected("Malformed attestation object"));
+        };
+        if let Err(reason) = self.check_authenticator_data(&auth_data) {
+            return Ok(RegistrationOutcome::Rejected(reason));
+        }
+        let Some(attested) = auth_data.attested else {
+            return Ok(RegistrationOutcome::Rejected("No attested credential data"));
+        };
+        if attested.credential_id != credential_id {
+            return Ok(RegistrationOutcome::Rejected("Credential id mismatch"));
+        }
+
+        let stored = sqlx::query_scalar!(
+            r#"
+            INSERT INTO webauthn_credentials (user_id, credential_id, public_key, sign_count, aaguid, name)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (credential_id) DO NOTHING
+            RETURNING id
+            "#,
+            user_id,
+            attested.credential_id,
+            attested.public_key,
+            auth_data.sign_count as i64,
+            Uuid::from_bytes(attested.aaguid),
+            name
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(match stored {
+            Some(credential_id) => RegistrationOutcome::Registered { credential_id },
+            None => RegistrationOutcome::Rejected("Credential is already registered"),
+        })
+    }
+
+    /// `None` if the user has no usable credential to authenticate with.
+    pub async fn begin_authentication(&self, user_id: Uuid) -> Result<Option<CeremonyOptions>> {
+        let allow: Vec<serde_json::Value> = self
+            .credential_ids(user_id)
+            .await?
+            .iter()
+            .map(|id| serde_json::json!({ "type": "public-key", "id": URL_SAFE_NO_PAD.encode(id) }))
+            .collect();
+        if allow.is_empty() {
+            return Ok(None);
+        }
+
+        let (challenge_id, challenge) = self.create_challenge(user_id, "authentication").await?;
+        Ok(Some(CeremonyOptions {
+            challenge_id,
+            public_key: serde_json::json!({
+                "rpId": self.rp_id,
+                "challenge": URL_SAFE_NO_PAD.encode(challenge),
+                "timeout": CEREMONY_TIMEOUT_SECS * 1000,
+                "allowCredentials": allow,
+                "userVerification": "preferred"
+            }),
+        }))
+    }
+
+    /// Verifies an assertion and, on success, marks `session_id` as WebAuthn-verified.
+    pub async fn finish_authentication(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        challenge_id: Uuid,
+        credential: &AuthenticationCredential,
+    ) -> Result<AuthenticationOutcome> {
+        let Some(challenge) = self.consume_challenge(challenge_id, user_id, "authentication").await? else {
+            return Ok(AuthenticationOutcome::Rejected("Unknown or expired challenge"));
+        };
+
+        let (Some(client_data), Some(authenticator_data), Some(signature), Some(credential_id)) = (
+            decode_b64url(&credential.response.client_data_json),
+            decode_b64url(&credential.response.authenticator_data),
+            decode_b64url(&credential.response.signature),
+            decode_b64url(&credential.id),
+        ) else {
+            return Ok(AuthenticationOutcome::Rejected("Malformed credential"));
+        };
+
+        if let Err(reason) = check_client_data(&client_data, "webauthn.get", &challenge, &self.rp_origin) {
+            return Ok(AuthenticationOutcome::Rejected(reason));
+        }
+        let Some(auth_data) = parse_authenticator_data(&authenticator_data) else {
+            return Ok(AuthenticationOutcome::Rejected("Malformed authenticator data"));
+        };
+        if let Err(reason) = self.check_authenticator_data(&auth_data) {
+            return Ok(AuthenticationOutcome::Rejected(reason));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let stored = sqlx::query!(
+            r#"
+            SELECT id, public_key, sign_count, clone_detected_at
+            FROM webauthn_credentials
+            WHERE credential_id = $1 AND user_id = $2
+            FOR UPDATE
+            "#,
+            credential_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(stored) = stored else {
+            return Ok(AuthenticationOutcome::Rejected("Unknown credential"));
+        };
+        if stored.clone_detected_at.is_some() {
+            return Ok(AuthenticationOutcome::Rejected("Credential is disabled"));
+        }
+
+        let mut signed = authenticator_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+        if !verify_signature(&stored.public_key, &signed, &signature) {
+            return Ok(AuthenticationOutcome::Rejected("Invalid signature"));
+        }
+
+        if !sign_count_advanced(stored.sign_count, auth_data.sign_count) {
+            sqlx::query!(
+                "UPDATE webauthn_credentials SET clone_detected_at = NOW() WHERE id = $1",
+                stored.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Ok(AuthenticationOutcome::CloneDetected { credential_id: stored.id });
+        }
+
+        sqlx::query!(
+            "UPDATE webauthn_credentials SET sign_count = $2, last_used_at = NOW() WHERE id = $1",
+            stored.id,
+            auth_data.sign_count as i64
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE sessions SET webauthn_verified_at = NOW() WHERE id = $1 AND user_id = $2",
+            session_id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(AuthenticationOutcome::Verified { credential_id: stored.id })
+    }
+
+    /// Whether the user has at least one credential that is not disabled.
+    pub async fn has_credentials(&self, user_id: Uuid) -> Result<bool> {
+        Ok(!self.credential_ids(user_id).await?.is_empty())
+    }
+
+    pub async fn session_verified(&self, session_id: Uuid) -> Result<bool> {
+        let verified = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM sessions WHERE id = $1 AND webauthn_verified_at IS NOT NULL
+            ) AS "exists!"
+            "#,
+            session_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(verified)
+    }
+
+    pub async fn list_credentials(&self, user_id: Uuid) -> Result<Vec<WebAuthnCredentialInfo>> {
+        let credentials = sqlx::query_as!(
+            WebAuthnCredentialInfo,
+            r#"
+            SELECT id, name, aaguid, sign_count, last_used_at, clone_detected_at, created_at
+            FROM webauthn_credentials
+            WHERE user_id = $1
+            ORDER BY created_at
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(credentials)
+    }
+
+    pub async fn remove_credential(&self, user_id: Uuid, credential_id: Uuid) -> Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM webauthn_credentials WHERE id = $1 AND user_id = $2",
+            credential_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn credential_ids(&self, user_id: Uuid) -> Result<Vec<Vec<u8>>> {
+        let ids = sqlx::query_scalar!(
+            "SELECT credential_id FROM webauthn_credentials WHERE user_id = $1 AND clone_detected_at IS NULL",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids)
+    }
+
+    async fn create_challenge(&self, user_id: Uuid, ceremony: &str) -> Result<(Uuid, Vec<u8>)> {
+        let mut challenge = vec![0u8; CHALLENGE_LEN];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        let expires_at = Utc::now() + Duration::seconds(CEREMONY_TIMEOUT_SECS);
+
+        let challenge_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO webauthn_challenges (user_id, ceremony, challenge, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            user_id,
+            ceremony,
+            challenge,
+            expires_at
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((challenge_id, challenge))
+    }
+
+    /// Marks the challenge used and returns its bytes; each challenge works once.
+    async fn consume_challenge(&self, challenge_id: Uuid, user_id: Uuid, ceremony: &str) -> Result<Option<Vec<u8>>> {
+        let challenge = sqlx::query_scalar!(
+            r#"
+            UPDATE webauthn_challenges SET used_at = NOW()
+            WHERE id = $1 AND user_id = $2 AND ceremony = $3 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING challenge
+            "#,
+            challenge_id,
+            user_id,
+            ceremony
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(challenge)
+    }
+
+    fn check_authenticator_data(&self, auth_data: &AuthenticatorData) -> std::result::Result<(), &'static str> {
+        if auth_data.rp_id_hash != Sha256::digest(self.rp_id.as_bytes()).as_slice() {
+            return Err("Relying party mismatch");
+        }
+        if auth_data.flags & FLAG_USER_PRESENT == 0 {
+            return Err("User presence not asserted");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct AuthenticatorData {
+    rp_id_hash: [u8; 32],
+    flags: u8,
+    sign_count: u32,
+    attested: Option<AttestedCredential>,
+}
+
+#[derive(Debug)]
+struct AttestedCredential {
+    aaguid: [u8; 16],
+    credential_id: Vec<u8>,
+    /// SEC1 uncompressed P-256 point
+    public_key: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+fn decode_b64url(value: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).ok()
+}
+
+fn check_client_data(raw: &[u8], ceremony: &str, challenge: &[u8], origin: &str) -> std::result::Result<(), &'static str> {
+    let client_data: ClientData = serde_json::from_slice(raw).map_err(|_| "Malformed client data")?;
+    if client_data.ceremony != ceremony {
+        return Err("Wrong ceremony type");
+    }
+    if decode_b64url(&client_data.challenge).as_deref() != Some(challenge) {
+        return Err("Challenge mismatch");
+    }
+    if client_data.origin != origin {
+        return Err("Origin mismatch");
+    }
+    Ok(())
+}
+
+/// Extracts `authData` from a CBOR attestation object.
+fn parse_attestation_object(raw: &[u8]) -> Option<Vec<u8>> {
+    let Value::Map(entries) = ciborium::de::from_reader::<Value, _>(raw).ok()? else {
+        return None;
+    };
+    entries.into_iter().find_map(|(key, value)| match (key, value) {
+        (Value::Text(key), Value::Bytes(auth_data)) if key == "authData" => Some(auth_data),
+        _ => None,
+    })
+}
+
+fn parse_authenticator_data(raw: &[u8]) -> Option<AuthenticatorData> {
+    if raw.len() < 37 {
+        return None;
+    }
+    let rp_id_hash: [u8; 32] = raw[..32].try_into().ok()?;
+    let flags = raw[32];
+    let sign_count = u32::from_be_bytes(raw[33..37].try_into().ok()?);
+
+    let attested = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        let rest = raw.get(37..)?;
+        let aaguid: [u8; 16] = rest.get(..16)?.try_into().ok()?;
+        let id_len = u16::from_be_bytes(rest.get(16..18)?.try_into().ok()?) as usize;
+        let credential_id = rest.get(18..18 + id_len)?.to_vec();
+        // The COSE key is followed by extensions (if any); read exactly one CBOR item
+        let mut cose_key = rest.get(18 + id_len..)?;
+        let cose_key: Value = ciborium::de::from_reader(&mut cose_key).ok()?;
+        Some(AttestedCredential { aaguid, credential_id, public_key: parse_cose_es256_key(&cose_key)? })
+    } else {
+        None
+    };
+
+    Some(AuthenticatorData { rp_id_hash, flags, sign_count, attested })
+}
+
+/// Converts a COSE_Key (kty EC2, alg ES256, crv P-256) into a SEC1 point.
+fn parse_cose_es256_key(key: &Value) -> Option<Vec<u8>> {
+    let entries = key.as_map()?;
+    let field = |label: i64| {
+        entries
+            .iter()
+            .find(|(key, _)| key.as_integer().and_then(|k| i64::try_from(k).ok()) == Some(label))
+            .map(|(_, value)| value)
+    };
+    let int = |label: i64| field(label)?.as_integer().and_then(|v| i64::try_from(v).ok());
+
+    if int(1)? != 2 || int(3)? != COSE_ALG_ES256 || int(-1)? != 1 {
+        return None;
+    }
+    let x = field(-2)?.as_bytes()?;
+    let y = field(-3)?.as_bytes()?;
+    if x.len() != 32 || y.len() != 32 {
+        return None;
+    }
+
+    let mut point = Vec::with_capacity(65);
+    point.push(0x04);
+    point.extend_from_slice(x);
+    point.extend_from_slice(y);
+    // Rejects points that are not on the curve
+    VerifyingKey::from_sec1_bytes(&point).ok()?;
+    Some(point)
+}
+
+fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let (Ok(key), Ok(signature)) = (VerifyingKey::from_sec1_bytes(public_key), Signature::from_der(signature)) else {
+        return false;
+    };
+    key.verify(message, &signature).is_ok()
+}
+
+/// Authenticators that don't implement counters always report 0; otherwise the
+/// counter must strictly increase or the key may have been cloned.
+fn sign_count_advanced(stored: i64, received: u32) -> bool {
+    (stored == 0 && received == 0) || i64::from(received) > stored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_registration_data_round_trip() {
+        let signing_key = SigningKey::random(&mut rand::thread_rng());
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let cose_key = Value::Map(vec![
+            (Value::Integer(1.into()), Value::Integer(2.into())),
+            (Value::Integer(3.into()), Value::Integer((-7).into())),
+            (Value::Integer((-1).into()), Value::Integer(1.into())),
+            (Value::Integer((-2).into()), Value::Bytes(point.x().unwrap().to_vec())),
+            (Value::Integer((-3).into()), Value::Bytes(point.y().unwrap().to_vec())),
+        ]);
+
+        let mut auth_data = authenticator_data("localhost", FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL, 5);
+        auth_data.extend_from_slice(&[9u8; 16]);
+        auth_data.extend_from_slice(&3u16.to_be_bytes());
+        auth_data.extend_from_slice(b"key");
+        ciborium::ser::into_writer(&cose_key, &mut auth_data).unwrap();
+
+        let mut attestation_object = Vec::new();
+        let object = Value::Map(vec![
+            (Value::Text("fmt".into()), Value::Text("none".into())),
+            (Value::Text("attStmt".into()), Value::Map(vec![])),
+            (Value::Text("authData".into()), Value::Bytes(auth_data)),
+        ]);
+        ciborium::ser::into_writer(&object, &mut attestation_object).unwrap();
+
+        let parsed = parse_authenticator_data(&parse_attestation_object(&attestation_object).unwrap()).unwrap();
+        assert_eq!(parsed.sign_count, 5);
+        let attested = parsed.attested.unwrap();
+        assert_eq!(attested.credential_id, b"key");
+        assert_eq!(attested.public_key, point.as_bytes());
+
+        let message = authenticator_data("localhost", FLAG_USER_PRESENT, 6);
+        let signature: Signature = signing_key.sign(&message);
+        assert!(verify_signature(&attested.public_key, &message, signature.to_der().as_bytes()));
+        assert!(!verify_signature(&attested.public_key, b"tampered", signature.to_der().as_bytes()));
+    }
+
+    #[test]
+    fn test_client_data_and_counter_checks() {
+        let challenge = [1u8; CHALLENGE_LEN];
+        let client_data = serde_json::to_vec(&serde_json::json!({
+            "type": "webauthn.get",
+            "challenge": URL_SAFE_NO_PAD.encode(challenge),
+            "origin": "https://admin.example.com"
+        }))
+        .unwrap();
+
+        assert!(check_client_data(&client_data, "webauthn.get", &challenge, "https://admin.example.com").is_ok());
+        assert_eq!(check_client_data(&client_data, "webauthn.create", &challenge, "https://admin.example.com"), Err("Wrong ceremony type"));
+        assert_eq!(check_client_data(&client_data, "webauthn.get", &[2u8; CHALLENGE_LEN], "https://admin.example.com"), Err("Challenge mismatch"));
+        assert_eq!(check_client_data(&client_data, "webauthn.get", &challenge, "https://evil.example.com"), Err("Origin mismatch"));
+
+        assert!(sign_count_advanced(0, 0));
+        assert!(sign_count_advanced(4, 5));
+        assert!(!sign_count_advanced(5, 5));
+        assert!(!sign_count_advanced(5, 0));
+    }
+}
@@ -0,0 +1,351 @@
+//! Keyset pagination, filtering and sorting for admin list endpoints.
+//!
+//! Each list route describes its table once as a [`ListSpec`] and accepts the
+//! same query parameters ([`ListQuery`]):
+//! - `sort`: a sortable field, `-field` for descending; rows with equal values
+//!   are ordered by id so pages are stable
+//! - `limit`: page size, 1 to [`MAX_LIMIT`]
+//! - `cursor`: the `next_cursor` of the previous page; opaque to clients and
+//!   only valid with the `sort` it was issued for
+//! - `status`, `role`: exact matches, where the resource has them
+//! - `from`, `to`: the resource's date column, inclusive / exclusive
+//! - `q`: case-insensitive substring match on the resource's text columns
+//!
+//! Pages hold the total count of rows matching the filters, so clients can
+//! show it without walking every page.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder, Row};
+use uuid::Uuid;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 200;
+
+/// Query parameters shared by every list route.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct ListQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub sort: Option<String>,
+    pub q: Option<String>,
+    pub status: Option<String>,
+    pub role: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// A column clients may sort by. `expr` must not be NULL (wrap nullable
+/// columns in `COALESCE`) and must cast to and from `sql_type`. Qualify a
+/// column the select list re-aliases (`severity::text AS severity`), or
+/// `ORDER BY` sorts the alias instead.
+pub struct SortField {
+    pub name: &'static str,
+    pub expr: &'static str,
+    pub sql_type: &'static str,
+}
+
+/// An exact-match filter on an expression, optionally limited to known values
+/// (enum columns reject anything else).
+pub struct MatchFilter {
+    pub expr: &'static str,
+    pub values: &'static [&'static str],
+}
+
+/// How a list route maps onto SQL.
+pub struct ListSpec {
+    /// Columns returned for each row
+    pub select: &'static str,
+    /// `FROM` clause, with any joins
+    pub from: &'static str,
+    /// Unique, non-null tie breaker; must be a UUID
+    pub id: &'static str,
+    pub sorts: &'static [SortField],
+    /// `name` or `-name` of one of `sorts`
+    pub default_sort: &'static str,
+    /// Column `from` and `to` apply to
+    pub date: Option<&'static str>,
+    /// Columns `q` is matched against
+    pub search: &'static [&'static str],
+    pub status: Option<MatchFilter>,
+    pub role: Option<MatchFilter>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    /// The `sort` the cursor was issued for
+    sort: String,
+    /// Sort value of the last row, as text
+    key: String,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        let json = URL_SAFE_NO_PAD.decode(value).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    /// Pass as `cursor` to get the next page; absent on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// A validated [`ListQuery`] for one [`ListSpec`].
+pub struct List {
+    spec: &'static ListSpec,
+    sort: &'static SortField,
+    sort_param: String,
+    descending: bool,
+    after: Option<Cursor>,
+    limit: i64,
+    search: Option<String>,
+    status: Option<String>,
+    role: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    within: Option<(&'static str, Uuid)>,
+    matching: Vec<(&'static str, String)>,
+}
+
+impl ListQuery {
+    /// Checks the parameters against what `spec` supports. The error is meant
+    /// for a 400 response.
+    pub fn resolve(&self, spec: &'static ListSpec) -> Result<List, String> {
+        let sort_param = self.sort.clone().unwrap_or_else(|| spec.default_sort.to_string());
+        let (descending, name) = match sort_param.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, sort_param.as_str()),
+        };
+        let sort = spec.sorts.iter().find(|sort| sort.name == name).ok_or_else(|| {
+            let names: Vec<&str> = spec.sorts.iter().map(|sort| sort.name).collect();
+            format!("sort must be one of: {} (prefix with - for descending)", names.join(", "))
+        })?;
+
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+
+        let after = match self.cursor.as_deref() {
+            Some(value) => {
+                let cursor = Cursor::decode(value).ok_or("Invalid cursor")?;
+                if cursor.sort != sort_param {
+                    return Err("cursor was issued for a different sort".to_string());
+                }
+                Some(cursor)
+            }
+            None => None,
+        };
+
+        let status = match_value("status", spec.status.as_ref(), self.status.as_deref())?;
+        let role = match_value("role", spec.role.as_ref(), self.role.as_deref())?;
+        if spec.date.is_none() && (self.from.is_some() || self.to.is_some()) {
+            return Err("from/to are not supported here".to_string());
+        }
+        let search = self.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+        if search.is_some() && spec.search.is_empty() {
+            return Err("q is not supported here".to_string());
+        }
+
+        Ok(List {
+            spec,
+            sort,
+            descending,
+            sort_param,
+            after,
+            limit,
+            search: search.map(|q| format!("%{}%", escape_like(q))),
+            status,
+            role,
+            from: self.from,
+            to: self.to,
+            within: None,
+            matching: Vec::new(),
+        })
+    }
+}
+
+fn match_value(param: &str, filter: Option<&MatchFilter>, value: Option<&str>) -> Result<Option<String>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let Some(filter) = filter else {
+        return Err(format!("{} is not supported here", param));
+    };
+    if !filter.values.is_empty() && !filter.values.contains(&value) {
+        return Err(format!("{} must be one of: {}", param, filter.values.join(", ")));
+    }
+    Ok(Some(value.to_string()))
+}
+
+pub(crate) fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+impl List {
+    /// Only rows whose `column` is `id`, e.g. one user's sessions.
+    pub fn within(mut self, column: &'static str, id: Uuid) -> Self {
+        self.within = Some((column, id));
+        self
+    }
+
+    /// Only rows where `expr`, as text, is `value`; for filters specific to
+    /// one route.
+    pub fn matching(mut self, expr: &'static str, value: String) -> Self {
+        self.matching.push((expr, value));
+        self
+    }
+
+    fn push_filters(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        let spec = self.spec;
+        builder.push(" FROM ").push(spec.from).push(" WHERE TRUE");
+
+        if let Some((column, id)) = self.within {
+            builder.push(format!(" AND {} = ", column)).push_bind(id);
+        }
+        for (expr, value) in &self.matching {
+            builder.push(format!(" AND ({})::text = ", expr)).push_bind(value.clone());
+        }
+        if let (Some(filter), Some(status)) = (&spec.status, &self.status) {
+            builder.push(format!(" AND ({})::text = ", filter.expr)).push_bind(status.clone());
+        }
+        if let (Some(filter), Some(role)) = (&spec.role, &self.role) {
+            builder.push(format!(" AND ({})::text = ", filter.expr)).push_bind(role.clone());
+        }
+        if let Some(date) = spec.date {
+            if let Some(from) = self.from {
+                builder.push(format!(" AND {} >= ", date)).push_bind(from);
+            }
+            if let Some(to) = self.to {
+                builder.push(format!(" AND {} < ", date)).push_bind(to);
+            }
+        }
+        if let Some(search) = &self.search {
+            builder.push(" AND (");
+            for (i, column) in spec.search.iter().enumerate() {
+                if i > 0 {
+                    builder.push(" OR ");
+                }
+                builder.push(format!("{} ILIKE ", column)).push_bind(search.clone());
+            }
+            builder.push(")");
+        }
+    }
+
+    /// Fetches one page. `T` is built from the spec's `select` columns.
+    pub async fn fetch<T>(&self, pool: &PgPool) -> Result<Page<T>, sqlx::Error>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        let (spec, sort) = (self.spec, self.sort);
+        let (comparison, direction) = if self.descending { ("<", "DESC") } else { (">", "ASC") };
+
+        let mut builder = QueryBuilder::new("SELECT ");
+        builder.push(format!(
+            "{}, ({})::text AS list_sort_key, {} AS list_row_id",
+            spec.select, sort.expr, spec.id
+        ));
+        self.push_filters(&mut builder);
+        if let Some(after) = &self.after {
+            builder
+                .push(format!(" AND ({}, {}) {} (CAST(", sort.expr, spec.id, comparison))
+                .push_bind(after.key.clone())
+                .push(format!(" AS {}), ", sort.sql_type))
+                .push_bind(after.id)
+                .push(")");
+        }
+        builder
+            .push(format!(" ORDER BY {} {}, {} {} LIMIT ", sort.expr, direction, spec.id, direction))
+            .push_bind(self.limit + 1);
+        let mut rows = builder.build().fetch_all(pool).await?;
+
+        let mut count = QueryBuilder::new("SELECT COUNT(*)");
+        self.push_filters(&mut count);
+        let total: i64 = count.build_query_scalar().fetch_one(pool).await?;
+
+        let next_cursor = if rows.len() as i64 > self.limit {
+            rows.truncate(self.limit as usize);
+            match rows.last() {
+                Some(last) => Some(
+                    Cursor {
+                        sort: self.sort_param.clone(),
+                        key: last.try_get("list_sort_key")?,
+                        id: last.try_get("list_row_id")?,
+                    }
+                    .encode(),
+                ),
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let items = rows.iter().map(T::from_row).collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Page {
+            items,
+            total,
+            limit: self.limit,
+            next_cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SPEC: ListSpec = ListSpec {
+        select: "id",
+        from: "things",
+        id: "id",
+        sorts: &[
+            SortField { name: "created_at", expr: "created_at", sql_type: "timestamptz" },
+            SortField { name: "name", expr: "name", sql_type: "text" },
+        ],
+        default_sort: "-created_at",
+        date: Some("created_at"),
+        search: &["name"],
+        status: Some(MatchFilter { expr: "status", values: &["active", "inactive"] }),
+        role: None,
+    };
+
+    #[test]
+    fn validates_parameters() {
+        let list = ListQuery::default().resolve(&SPEC).unwrap();
+        assert!(list.descending);
+        assert_eq!(list.sort.name, "created_at");
+        assert_eq!(list.limit, DEFAULT_LIMIT);
+
+        let query = |query: ListQuery| query.resolve(&SPEC).err();
+        assert!(query(ListQuery { sort: Some("password".into()), ..Default::default() }).is_some());
+        assert!(query(ListQuery { limit: Some(0), ..Default::default() }).is_some());
+        assert!(query(ListQuery { status: Some("deleted".into()), ..Default::default() }).is_some());
+        assert!(query(ListQuery { role: Some("owner".into()), ..Default::default() }).is_some());
+        assert!(query(ListQuery { cursor: Some("garbage".into()), ..Default::default() }).is_some());
+
+        let cursor = Cursor { sort: "name".into(), key: "bob".into(), id: Uuid::new_v4() }.encode();
+        let resumed = ListQuery { sort: Some("name".into()), cursor: Some(cursor.clone()), ..Default::default() };
+        assert_eq!(resumed.resolve(&SPEC).unwrap().after.unwrap().key, "bob");
+        assert!(query(ListQuery { sort: Some("-name".into()), cursor: Some(cursor), ..Default::default() }).is_some());
+    }
+
+    #[test]
+    fn escapes_search_wildcards() {
+        let list = ListQuery { q: Some(" 50%_off\\ ".into()), ..Default::default() }.resolve(&SPEC).unwrap();
+        assert_eq!(list.search.as_deref(), Some("%50\\%\\_off\\\\%"));
+    }
+}
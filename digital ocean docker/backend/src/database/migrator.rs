@@ -0,0 +1,471 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{pool::PoolConnection, Connection, PgPool, Postgres};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::Instant;
+
+/// Key for `pg_advisory_lock`, shared by every replica so only one of them
+/// migrates at a time ("viworks" in ASCII).
+const MIGRATION_LOCK_KEY: i64 = 0x0076_6977_6f72_6b73;
+
+const CREATE_SCHEMA_MIGRATIONS: &str = r#"
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version BIGINT PRIMARY KEY,
+    name TEXT NOT NULL,
+    checksum TEXT NOT NULL,
+    applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    execution_time_ms BIGINT NOT NULL DEFAULT 0
+)
+"#;
+
+/// A numbered migration loaded from the migrations directory.
+///
+/// Files are named `NNN_description.sql` (or `NNN_description.up.sql`) with an
+/// optional `NNN_description.down.sql` holding the rollback.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub up_sql: String,
+    pub down_sql: Option<String>,
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationState {
+    Pending,
+    Applied { applied_at: DateTime<Utc> },
+    /// The file changed after it was applied.
+    ChecksumMismatch { applied_at: DateTime<Utc> },
+    /// Recorded in `schema_migrations` but no longer present on disk.
+    Missing { applied_at: DateTime<Utc> },
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub state: MigrationState,
+}
+
+enum Operation {
+    Up(Option<i64>),
+    Down(usize),
+    Baseline(i64),
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct AppliedMigration {
+    version: i64,
+    name: String,
+    checksum: String,
+    applied_at: DateTime<Utc>,
+}
+
+pub struct Migrator {
+    migrations: Vec<Migration>,
+}
+
+impl Migrator {
+    pub fn new(mut migrations: Vec<Migration>) -> Result<Self> {
+        migrations.sort_by_key(|m| m.version);
+        if let Some(pair) = migrations.windows(2).find(|w| w[0].version == w[1].version) {
+            bail!(
+                "Duplicate migration version {}: {} and {}",
+                pair[0].version,
+                pair[0].name,
+                pair[1].name
+            );
+        }
+        Ok(Self { migrations })
+    }
+
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read migrations directory {}", dir.display()))?;
+
+        let mut ups: BTreeMap<i64, (String, String)> = BTreeMap::new();
+        let mut downs: HashMap<i64, String> = HashMap::new();
+
+        for entry in entries {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some((version, name, direction)) = parse_file_name(file_name)? else {
+                continue;
+            };
+            let sql = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read migration {}", path.display()))?;
+
+            let previous = match direction {
+                Direction::Up => ups.insert(version, (name, sql)).map(|(name, _)| name),
+                Direction::Down => downs.insert(version, sql).map(|_| name),
+            };
+            if let Some(previous) = previous {
+                bail!("Duplicate migration version {} ({})", version, previous);
+            }
+        }
+
+        if let Some(version) = downs.keys().find(|v| !ups.contains_key(v)) {
+            bail!("Down migration {} has no matching up migration", version);
+        }
+
+        let migrations = ups
+            .into_iter()
+            .map(|(version, (name, up_sql))| Migration {
+                version,
+                name,
+                checksum: checksum(&up_sql),
+                down_sql: downs.remove(&version),
+                up_sql,
+            })
+            .collect();
+
+        Self::new(migrations)
+    }
+
+    pub fn migrations(&self) -> &[Migration] {
+        &self.migrations
+    }
+
+    pub async fn status(&self, pool: &PgPool) -> Result<Vec<MigrationStatus>> {
+        let mut conn = pool.acquire().await?;
+        ensure_migrations_table(&mut conn).await?;
+        let applied = fetch_applied(&mut conn).await?;
+        Ok(self.compute_status(&applied))
+    }
+
+    /// Applies pending migrations in version order, up to and including
+    /// `target` when given. Returns the versions that were applied.
+    pub async fn up(&self, pool: &PgPool, target: Option<i64>) -> Result<Vec<i64>> {
+        self.run_locked(pool, Operation::Up(target)).await
+    }
+
+    /// Rolls back the `steps` most recently applied migrations.
+    /// Returns the versions that were reverted.
+    pub async fn down(&self, pool: &PgPool, steps: usize) -> Result<Vec<i64>> {
+        self.run_locked(pool, Operation::Down(steps)).await
+    }
+
+    /// Records every migration up to `version` as applied without running it,
+    /// for databases whose schema was created before `schema_migrations` existed.
+    pub async fn baseline(&self, pool: &PgPool, version: i64) -> Result<Vec<i64>> {
+        self.run_locked(pool, Operation::Baseline(version)).await
+    }
+
+    /// Runs `operation` while holding the migration advisory lock on a single
+    /// connection, so concurrent replicas wait instead of racing.
+    async fn run_locked(&self, pool: &PgPool, operation: Operation) -> Result<Vec<i64>> {
+        let mut conn = pool.acquire().await?;
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to acquire migration lock")?;
+
+        let result = match ensure_migrations_table(&mut conn).await {
+            Ok(()) => match operation {
+                Operation::Up(target) => self.up_locked(&mut conn, target).await,
+                Operation::Down(steps) => self.down_locked(&mut conn, steps).await,
+                Operation::Baseline(version) => self.baseline_locked(&mut conn, version).await,
+            },
+            Err(e) => Err(e),
+        };
+
+        let unlock = sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *conn)
+            .await;
+
+        let versions = result?;
+        unlock.context("Failed to release migration lock")?;
+        Ok(versions)
+    }
+
+    async fn up_locked(&self, conn: &mut PoolConnection<Postgres>, target: Option<i64>) -> Result<Vec<i64>> {
+        let applied = fetch_applied(conn).await?;
+        self.verify(&applied)?;
+
+        let applied_versions: Vec<i64> = applied.iter().map(|a| a.version).collect();
+        let mut done = Vec::new();
+
+        for migration in &self.migrations {
+            if target.is_some_and(|t| migration.version > t) {
+                break;
+            }
+            if applied_versions.contains(&migration.version) {
+                continue;
+            }
+
+            println!("🔄 Applying migration {:03}_{}", migration.version, migration.name);
+            let started = Instant::now();
+            let mut tx = conn.begin().await?;
+
+            sqlx::raw_sql(&migration.up_sql)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("Migration {:03}_{} failed", migration.version, migration.name))?;
+
+            sqlx::query(
+                "INSERT INTO schema_migrations (version, name, checksum, execution_time_ms) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(migration.version)
+            .bind(&migration.name)
+            .bind(&migration.checksum)
+            .bind(started.elapsed().as_millis() as i64)
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+            println!("✅ Applied migration {:03}_{} in {:?}", migration.version, migration.name, started.elapsed());
+            done.push(migration.version);
+        }
+
+        Ok(done)
+    }
+
+    async fn down_locked(&self, conn: &mut PoolConnection<Postgres>, steps: usize) -> Result<Vec<i64>> {
+        let applied = fetch_applied(conn).await?;
+        self.verify(&applied)?;
+
+        let mut done = Vec::new();
+
+        for record in applied.iter().rev().take(steps) {
+            let migration = self
+                .migrations
+                .iter()
+                .find(|m| m.version == record.version)
+                .with_context(|| format!("Applied migration {} is missing on disk", record.version))?;
+            let down_sql = migration.down_sql.as_deref().with_context(|| {
+                format!("Migration {:03}_{} has no down migration", migration.version, migration.name)
+            })?;
+
+            println!("🔄 Reverting migration {:03}_{}", migration.version, migration.name);
+            let mut tx = conn.begin().await?;
+
+            sqlx::raw_sql(down_sql)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("Rollback of {:03}_{} failed", migration.version, migration.name))?;
+
+            sqlx::query("DELETE FROM schema_migrations WHERE version = $1")
+                .bind(migration.version)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+            println!("✅ Reverted migration {:03}_{}", migration.version, migration.name);
+            done.push(migration.version);
+        }
+
+        Ok(done)
+    }
+
+    async fn baseline_locked(&self, conn: &mut PoolConnection<Postgres>, version: i64) -> Result<Vec<i64>> {
+        let applied = fetch_applied(conn).await?;
+        self.verify(&applied)?;
+
+        let mut done = Vec::new();
+        for migration in self.migrations.iter().filter(|m| m.version <= version) {
+            if applied.iter().any(|a| a.version == migration.version) {
+                continue;
+            }
+            sqlx::query(
+                "INSERT INTO schema_migrations (version, name, checksum, execution_time_ms) VALUES ($1, $2, $3, 0)",
+            )
+            .bind(migration.version)
+            .bind(&migration.name)
+            .bind(&migration.checksum)
+            .execute(&mut **conn)
+            .await?;
+            println!("✅ Baselined migration {:03}_{}", migration.version, migration.name);
+            done.push(migration.version);
+        }
+
+        Ok(done)
+    }
+
+    /// Refuses to continue if an applied migration was edited or removed.
+    fn verify(&self, applied: &[AppliedMigration]) -> Result<()> {
+        for status in self.compute_status(applied) {
+            match status.state {
+                MigrationState::ChecksumMismatch { .. } => bail!(
+                    "Migration {:03}_{} was modified after it was applied (checksum mismatch)",
+                    status.version,
+                    status.name
+                ),
+                MigrationState::Missing { .. } => bail!(
+                    "Migration {:03}_{} is recorded as applied but missing on disk",
+                    status.version,
+                    status.name
+                ),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn compute_status(&self, applied: &[AppliedMigration]) -> Vec<MigrationStatus> {
+        let mut statuses: Vec<MigrationStatus> = self
+            .migrations
+            .iter()
+            .map(|migration| {
+                let state = match applied.iter().find(|a| a.version == migration.version) {
+                    None => MigrationState::Pending,
+                    Some(a) if a.checksum == migration.checksum => {
+                        MigrationState::Applied { applied_at: a.applied_at }
+                    }
+                    Some(a) => MigrationState::ChecksumMismatch { applied_at: a.applied_at },
+                };
+                MigrationStatus {
+                    version: migration.version,
+                    name: migration.name.clone(),
+                    state,
+                }
+            })
+            .collect();
+
+        for record in applied {
+            if !self.migrations.iter().any(|m| m.version == record.version) {
+                statuses.push(MigrationStatus {
+                    version: record.version,
+                    name: record.name.clone(),
+                    state: MigrationState::Missing { applied_at: record.applied_at },
+                });
+            }
+        }
+
+        statuses.sort_by_key(|s| s.version);
+        statuses
+    }
+}
+
+async fn ensure_migrations_table(conn: &mut PoolConnection<Postgres>) -> Result<()> {
+    sqlx::query(CREATE_SCHEMA_MIGRATIONS)
+        .execute(&mut **conn)
+        .await
+        .context("Failed to create schema_migrations table")?;
+    Ok(())
+}
+
+async fn fetch_applied(conn: &mut PoolConnection<Postgres>) -> Result<Vec<AppliedMigration>> {
+    let applied = sqlx::query_as::<_, AppliedMigration>(
+        "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version",
+    )
+    .fetch_all(&mut **conn)
+    .await?;
+    Ok(applied)
+}
+
+fn checksum(sql: &str) -> String {
+    hex::encode(Sha256::digest(sql.as_bytes()))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Direction {
+    Up,
+    Down,
+}
+
+/// Parses `NNN_name.sql`, `NNN_name.up.sql` and `NNN_name.down.sql`.
+/// Returns `None` for files that are not SQL.
+fn parse_file_name(file_name: &str) -> Result<Option<(i64, String, Direction)>> {
+    let Some(stem) = file_name.strip_suffix(".sql") else {
+        return Ok(None);
+    };
+
+    let (stem, direction) = if let Some(stem) = stem.strip_suffix(".down") {
+        (stem, Direction::Down)
+    } else {
+        (stem.strip_suffix(".up").unwrap_or(stem), Direction::Up)
+    };
+
+    let (version, name) = stem
+        .split_once('_')
+        .with_context(|| format!("Migration file {} must be named NNN_description.sql", file_name))?;
+    let version = version
+        .parse::<i64>()
+        .with_context(|| format!("Migration file {} has a non-numeric version", file_name))?;
+
+    Ok(Some((version, name.to_string(), direction)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_name() {
+        assert_eq!(
+            parse_file_name("001_initial_schema.sql").unwrap(),
+            Some((1, "initial_schema".to_string(), Direction::Up))
+        );
+        assert_eq!(
+            parse_file_name("002_add_users.up.sql").unwrap(),
+            Some((2, "add_users".to_string(), Direction::Up))
+        );
+        assert_eq!(
+            parse_file_name("002_add_users.down.sql").unwrap(),
+            Some((2, "add_users".to_string(), Direction::Down))
+        );
+        assert_eq!(parse_file_name("README.md").unwrap(), None);
+        assert!(parse_file_name("initial.sql").is_err());
+        assert!(parse_file_name("abc_initial.sql").is_err());
+    }
+
+    #[test]
+    fn test_status_detects_edited_and_missing_migrations() {
+        let migrator = Migrator::new(vec![
+            Migration {
+                version: 1,
+                name: "initial".to_string(),
+                up_sql: "SELECT 1".to_string(),
+                down_sql: None,
+                checksum: checksum("SELECT 1"),
+            },
+            Migration {
+                version: 2,
+                name: "second".to_string(),
+                up_sql: "SELECT 2".to_string(),
+                down_sql: None,
+                checksum: checksum("SELECT 2"),
+            },
+        ])
+        .unwrap();
+
+        let now = Utc::now();
+        let applied = vec![
+            AppliedMigration {
+                version: 1,
+                name: "initial".to_string(),
+                checksum: checksum("SELECT 'edited'"),
+                applied_at: now,
+            },
+            AppliedMigration {
+                version: 3,
+                name: "removed".to_string(),
+                checksum: checksum("SELECT 3"),
+                applied_at: now,
+            },
+        ];
+
+        let states: Vec<MigrationState> = migrator
+            .compute_status(&applied)
+            .into_iter()
+            .map(|s| s.state)
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                MigrationState::ChecksumMismatch { applied_at: now },
+                MigrationState::Pending,
+                MigrationState::Missing { applied_at: now },
+            ]
+        );
+        assert!(migrator.verify(&applied).is_err());
+    }
+}
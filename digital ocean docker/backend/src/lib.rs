@@ -0,0 +1,10 @@
+// ViWorkS Admin Panel Backend library
+// Shared by the production server (main.rs) and the demo server (main_demo.rs)
+
+pub mod api;
+pub mod auth;
+pub mod config;
+pub mod database;
+pub mod models;
+pub mod services;
+pub mod websocket;
@@ -0,0 +1,501 @@
+use std::sync::Arc;
+
+use actix_web::{App, HttpServer, web, HttpResponse, middleware::Logger};
+use actix_cors::Cors;
+use tracing::{info, error, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use viworks_admin_backend::{
+    api,
+    auth::{
+        attestation::AttestationService,
+        ca::CertificateAuthority,
+        credentials::CredentialService,
+        devices::DeviceBindingService,
+        login_attempts::LoginAttemptService,
+        otp::{OtpStore, PgOtpStore, RedisOtpStore},
+        password::PasswordService,
+        policy::PolicyService,
+        push_approval::PushApprovalService,
+        totp::TotpService,
+        webauthn::WebAuthnService,
+        AuthService,
+    },
+    config::AppConfig,
+    database::{
+        migrator::{MigrationState, Migrator},
+        Database,
+    },
+    services::{account_recovery::AccountRecovery, agent_bridge::AgentBridge, alerts::AlertEngine, audit::AuditLog, gateway::GatewayClient, mailer, push, realtime, scim::ScimService, session_reaper::SessionReaper, siem::SiemExporter},
+    websocket::{self, WebSocketSessionManager},
+};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new(
+            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
+        ))
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let config = match AppConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("❌ Invalid configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        if let Err(e) = run_migrate_command(&config, &args[1..]).await {
+            error!("❌ Migration command failed: {:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    info!("🚀 Starting ViWorkS Admin Panel Backend");
+
+    info!("🔗 Connecting to PostgreSQL and Redis...");
+    let database = match Database::new(&config).await {
+        Ok(database) => database,
+        Err(e) => {
+            error!("❌ Failed to connect to database: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let pool = web::Data::new(database.postgres.clone());
+    let database = web::Data::new(database);
+    let auth_service = web::Data::new(AuthService::new(&config, database.postgres.clone()));
+    let passwords = web::Data::new(PasswordService::new(&config, database.postgres.clone()));
+    if config.breached_passwords_dir.is_empty() {
+        warn!("⚠️ BREACHED_PASSWORDS_DIR is not set; new passwords are not checked against breaches");
+    } else if !std::path::Path::new(&config.breached_passwords_dir).is_dir() {
+        error!("❌ BREACHED_PASSWORDS_DIR {} is not a directory", config.breached_passwords_dir);
+        std::process::exit(1);
+    } else {
+        info!("🔐 Checking new passwords against the breached-password corpus in {}", config.breached_passwords_dir);
+    }
+    let mailer = match mailer::from_config(&config) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            error!("❌ Invalid mail configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    if mailer.is_some() {
+        info!("📧 Mailing password reset and email verification links from {}", config.mail_from);
+    } else {
+        warn!("⚠️ MAIL_TRANSPORT is not set; self-service password reset and email verification are off");
+    }
+    let account_recovery = web::Data::new(AccountRecovery::new(&config, database.postgres.clone(), mailer));
+    let push_provider = match push::from_config(&config) {
+        Ok(push_provider) => push_provider,
+        Err(e) => {
+            error!("❌ Invalid FCM configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    if push_provider.is_some() {
+        info!("📲 Asking mobile devices to approve sign-ins over FCM ({})", config.fcm_api_url);
+    } else {
+        warn!("⚠️ FCM_CREDENTIALS_FILE is not set; users without an authenticator app cannot sign in");
+    }
+    let push_approvals = web::Data::new(PushApprovalService::new(&config, database.postgres.clone(), push_provider));
+    spawn_push_approval_expiry(push_approvals.clone());
+    let login_attempts = web::Data::new(LoginAttemptService::new(&config, database.postgres.clone()));
+    let policy_service = web::Data::new(PolicyService::new(database.postgres.clone()));
+    let attestation = web::Data::new(AttestationService::new(&config, database.postgres.clone()));
+    let totp_service = match TotpService::new(&config, database.postgres.clone()) {
+        Ok(totp_service) => web::Data::new(totp_service),
+        Err(e) => {
+            error!("❌ Invalid TOTP configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let webauthn_service = web::Data::new(WebAuthnService::new(&config, database.postgres.clone()));
+    let session_manager = match realtime::start_event_relay(&config.redis_url, database.redis.clone()) {
+        Ok(session_manager) => web::Data::new(session_manager),
+        Err(e) => {
+            error!("❌ Failed to start WebSocket event relay: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let device_bindings = web::Data::new(DeviceBindingService::new(&config, database.postgres.clone()));
+    spawn_device_request_expiry(device_bindings.clone(), session_manager.clone());
+    let audit = match AuditLog::new(&config, database.postgres.clone()) {
+        Ok(audit) => web::Data::new(audit),
+        Err(e) => {
+            error!("❌ Invalid audit log configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    spawn_audit_checkpoints(audit.clone(), config.audit_checkpoint_interval_seconds);
+    match SiemExporter::from_config(&config, database.postgres.clone()) {
+        Ok(Some(exporter)) => {
+            info!("📤 Exporting audit events to {} as {}", exporter.target(), config.siem_export_format);
+            spawn_siem_export(exporter, config.siem_export_interval_seconds);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("❌ Invalid SIEM export configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+    let alerts = web::Data::new(AlertEngine::new(&config, database.postgres.clone(), (**session_manager).clone()));
+    match alerts.reload() {
+        Ok(count) => info!("🚨 Loaded {} alert rule(s) from {}", count, config.alert_rules_file),
+        Err(e) => warn!("⚠️ No alert rules loaded: {:#}", e),
+    }
+    spawn_alert_engine(alerts.clone(), config.alert_engine_interval_seconds);
+    let scim = web::Data::new(ScimService::new(&config, database.postgres.clone()));
+    if scim.enabled() {
+        info!("🪪 SCIM provisioning enabled at /scim/v2");
+    }
+
+    let certificate_authority = match CertificateAuthority::load_or_create(&config, database.postgres.clone()).await {
+        Ok(ca) => Arc::new(ca),
+        Err(e) => {
+            error!("❌ Failed to load internal CA: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let gateway = Arc::new(GatewayClient::new(&config));
+    let credential_service = web::Data::new(CredentialService::new(
+        &config,
+        database.postgres.clone(),
+        certificate_authority.clone(),
+        gateway.clone(),
+    ));
+    spawn_credential_revocation(credential_service.clone());
+    let agent_bridge = web::Data::new(AgentBridge::new(&config, database.postgres.clone()));
+    if agent_bridge.enabled() {
+        info!("🛰️ Sending gateway commands to the backend agent at {}", config.backend_agent_url);
+        spawn_agent_commands(agent_bridge.clone(), config.agent_command_interval_seconds);
+    } else {
+        warn!("⚠️ BACKEND_AGENT_JWT_SECRET is not set; gateway commands are queued but not sent");
+    }
+    let session_reaper = web::Data::new(SessionReaper::new(database.postgres.clone()));
+    spawn_session_reaper(
+        session_reaper.clone(),
+        agent_bridge.clone(),
+        session_manager.clone(),
+        config.session_reaper_interval_seconds,
+    );
+
+    let otp_hash_key = match config.encryption_key(&config.otp_hmac_key, "otp-hmac") {
+        Ok(key) => key,
+        Err(e) => {
+            error!("❌ Invalid OTP configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let otp_store: Arc<dyn OtpStore> = match config.otp_store.as_str() {
+        "redis" => Arc::new(RedisOtpStore::new(database.redis.clone(), otp_hash_key)),
+        _ => Arc::new(PgOtpStore::new(database.postgres.clone(), otp_hash_key)),
+    };
+    info!("🔑 OTP challenges stored in {}", config.otp_store);
+    let otp_store = web::Data::from(otp_store);
+
+    let host = config.host.clone();
+    let port = config.port;
+    let cors_origins = config.cors_origins.clone();
+    let config = web::Data::new(config);
+
+    info!("🌐 Starting HTTP server on {}:{}", host, port);
+
+    HttpServer::new(move || {
+        let cors = cors_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allow_any_method()
+            .allow_any_header()
+            .supports_credentials()
+            .max_age(3600);
+
+        App::new()
+            .app_data(config.clone())
+            .app_data(pool.clone())
+            .app_data(database.clone())
+            .app_data(auth_service.clone())
+            .app_data(passwords.clone())
+            .app_data(account_recovery.clone())
+            .app_data(push_approvals.clone())
+            .app_data(login_attempts.clone())
+            .app_data(policy_service.clone())
+            .app_data(attestation.clone())
+            .app_data(totp_service.clone())
+            .app_data(webauthn_service.clone())
+            .app_data(web::Data::from(certificate_authority.clone()))
+            .app_data(web::Data::from(gateway.clone()))
+            .app_data(credential_service.clone())
+            .app_data(agent_bridge.clone())
+            .app_data(session_reaper.clone())
+            .app_data(device_bindings.clone())
+            .app_data(session_manager.clone())
+            .app_data(audit.clone())
+            .app_data(alerts.clone())
+            .app_data(scim.clone())
+            .app_data(otp_store.clone())
+            .wrap(cors)
+            .wrap(Logger::default())
+            .route("/_healthz", web::get().to(|| async {
+                HttpResponse::Ok().json(serde_json::json!({
+                    "status": "ok"
+                }))
+            }))
+            .route("/health", web::get().to(health))
+            .configure(websocket::configure_routes)
+            .configure(api::scim::configure_routes)
+            .service(web::scope("/api").configure(api::configure_routes))
+    })
+    .bind((host, port))?
+    .run()
+    .await?;
+
+    info!("👋 ViWorkS Backend shutdown complete");
+    Ok(())
+}
+
+/// Revokes gateway credentials and client certificates of sessions that ended
+/// without going through logout (expiry, refresh-token reuse, deletion) and
+/// retries failed revocations.
+fn spawn_credential_revocation(credential_service: web::Data<CredentialService>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match credential_service.revoke_ended_sessions().await {
+                Ok(0) => {}
+                Ok(revoked) => info!("🔒 Revoked gateway credentials for {} ended session(s)", revoked),
+                Err(e) => error!("❌ Credential revocation sweep failed: {:#}", e),
+            }
+            match credential_service.ca().revoke_ended_session_certificates().await {
+                Ok(0) => {}
+                Ok(revoked) => info!("🔒 Revoked {} client certificate(s) of ended sessions", revoked),
+                Err(e) => error!("❌ Certificate revocation sweep failed: {:#}", e),
+            }
+        }
+    });
+}
+
+/// Submits queued gateway commands to the backend agent and polls the
+/// submitted ones, draining backlogs without pausing.
+fn spawn_agent_commands(agent_bridge: web::Data<AgentBridge>, interval_seconds: u64) {
+    tokio::spawn(async move {
+        let idle = std::time::Duration::from_secs(interval_seconds);
+        loop {
+            match agent_bridge.process().await {
+                Ok(0) => tokio::time::sleep(idle).await,
+                Ok(_) => {}
+                Err(e) => {
+                    error!("❌ Gateway command dispatch failed: {:#}", e);
+                    tokio::time::sleep(idle).await;
+                }
+            }
+        }
+    });
+}
+
+/// Expires sessions past their idle or absolute lifetime and queues gateway
+/// teardown of every ended session, telling the admin dashboard about the
+/// ones nothing else announced.
+fn spawn_session_reaper(
+    session_reaper: web::Data<SessionReaper>,
+    agent_bridge: web::Data<AgentBridge>,
+    session_manager: web::Data<WebSocketSessionManager>,
+    interval_seconds: u64,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+        loop {
+            interval.tick().await;
+            match session_reaper.sweep(&agent_bridge).await {
+                Ok(sweep) => {
+                    if sweep.expired > 0 {
+                        info!("⌛ Expired {} idle or lapsed session(s)", sweep.expired);
+                    }
+                    if !sweep.torn_down.is_empty() {
+                        info!("🧹 Queued gateway teardown of {} ended session(s)", sweep.torn_down.len());
+                    }
+                    for session in sweep.torn_down.iter().filter(|session| session.unannounced()) {
+                        api::sessions::notify_admins(
+                            &session_manager,
+                            session.id,
+                            Some(session.user_id),
+                            Some(&session.username),
+                            "ended",
+                            session.reason.as_deref(),
+                        );
+                    }
+                }
+                Err(e) => error!("❌ Session reaper sweep failed: {:#}", e),
+            }
+        }
+    });
+}
+
+/// Expires device-binding requests nobody reviewed in time and tells the
+/// admin dashboard about it.
+fn spawn_device_request_expiry(
+    device_bindings: web::Data<DeviceBindingService>,
+    session_manager: web::Data<WebSocketSessionManager>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match device_bindings.expire_stale().await {
+                Ok(expired) => {
+                    if !expired.is_empty() {
+                        info!("⌛ Expired {} device binding request(s)", expired.len());
+                    }
+                    for binding in &expired {
+                        api::devices::notify_admins(&session_manager, binding);
+                    }
+                }
+                Err(e) => error!("❌ Device request expiry sweep failed: {:#}", e),
+            }
+        }
+    });
+}
+
+/// Expires push approvals the phone did not answer in time; each one is
+/// audited as `push_approval_timed_out`.
+fn spawn_push_approval_expiry(push_approvals: web::Data<PushApprovalService>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(15));
+        loop {
+            interval.tick().await;
+            match push_approvals.expire_stale().await {
+                Ok(0) => {}
+                Ok(expired) => info!("⌛ {} push approval(s) timed out", expired),
+                Err(e) => error!("❌ Push approval expiry sweep failed: {:#}", e),
+            }
+        }
+    });
+}
+
+/// Signs the audit chain head periodically so a rewritten chain is caught by
+/// `/admin/audit/verify` even if every row was re-hashed.
+fn spawn_audit_checkpoints(audit: web::Data<AuditLog>, interval_seconds: u64) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+        loop {
+            interval.tick().await;
+            match audit.checkpoint().await {
+                Ok(Some(checkpoint)) => info!("🧾 Audit log checkpoint signed at event {}", checkpoint.seq),
+                Ok(None) => {}
+                Err(e) => error!("❌ Audit checkpoint failed: {:#}", e),
+            }
+        }
+    });
+}
+
+/// Ships audit events to the SIEM after the stored cursor, draining backlogs
+/// without pausing and retrying failed deliveries every interval.
+fn spawn_siem_export(mut exporter: SiemExporter, interval_seconds: u64) {
+    tokio::spawn(async move {
+        let idle = std::time::Duration::from_secs(interval_seconds);
+        loop {
+            match exporter.export_batch().await {
+                Ok(0) => tokio::time::sleep(idle).await,
+                Ok(exported) => info!("📤 Exported {} audit event(s) to {}", exported, exporter.target()),
+                Err(e) => {
+                    error!("❌ SIEM export to {} failed: {:#}", exporter.target(), e);
+                    if let Err(e) = exporter.record_error(&e).await {
+                        error!("❌ Failed to record SIEM export error: {:#}", e);
+                    }
+                    tokio::time::sleep(idle).await;
+                }
+            }
+        }
+    });
+}
+
+/// Evaluates the alert rules against new audit events, draining backlogs
+/// without pausing.
+fn spawn_alert_engine(alerts: web::Data<AlertEngine>, interval_seconds: u64) {
+    tokio::spawn(async move {
+        let idle = std::time::Duration::from_secs(interval_seconds);
+        loop {
+            match alerts.evaluate().await {
+                Ok(0) => tokio::time::sleep(idle).await,
+                Ok(_) => {}
+                Err(e) => {
+                    error!("❌ Alert rule evaluation failed: {:#}", e);
+                    tokio::time::sleep(idle).await;
+                }
+            }
+        }
+    });
+}
+
+async fn health(database: web::Data<Database>) -> HttpResponse {
+    match database.health_check().await {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "ok",
+            "service": "ViWorkS Admin Panel Backend",
+            "version": env!("CARGO_PKG_VERSION")
+        })),
+        _ => HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "degraded",
+            "service": "ViWorkS Admin Panel Backend",
+            "version": env!("CARGO_PKG_VERSION")
+        })),
+    }
+}
+
+const MIGRATE_USAGE: &str = "usage: viworks-backend migrate <status | up [version] | down [steps] | baseline <version>>";
+
+/// `viworks-backend migrate ...`: manages the schema without starting the server
+/// (and without requiring Redis).
+async fn run_migrate_command(config: &AppConfig, args: &[String]) -> anyhow::Result<()> {
+    let migrator = Migrator::from_dir(&config.migrations_dir)?;
+    let pool = Database::connect_postgres(config).await?;
+
+    let parse_arg = |arg: Option<&String>| -> anyhow::Result<Option<i64>> {
+        arg.map(|a| a.parse::<i64>())
+            .transpose()
+            .map_err(|_| anyhow::anyhow!(MIGRATE_USAGE))
+    };
+
+    match args.first().map(String::as_str) {
+        Some("status") => {
+            for status in migrator.status(&pool).await? {
+                let state = match status.state {
+                    MigrationState::Pending => "pending".to_string(),
+                    MigrationState::Applied { applied_at } => format!("applied {}", applied_at),
+                    MigrationState::ChecksumMismatch { applied_at } => {
+                        format!("applied {} (CHECKSUM MISMATCH)", applied_at)
+                    }
+                    MigrationState::Missing { applied_at } => {
+                        format!("applied {} (MISSING ON DISK)", applied_at)
+                    }
+                };
+                println!("{:03}  {:<40} {}", status.version, status.name, state);
+            }
+        }
+        Some("up") => {
+            let applied = migrator.up(&pool, parse_arg(args.get(1))?).await?;
+            println!("✅ {} migration(s) applied", applied.len());
+        }
+        Some("down") => {
+            let steps = parse_arg(args.get(1))?.unwrap_or(1);
+            let steps = usize::try_from(steps).map_err(|_| anyhow::anyhow!(MIGRATE_USAGE))?;
+            let reverted = migrator.down(&pool, steps).await?;
+            println!("✅ {} migration(s) reverted", reverted.len());
+        }
+        Some("baseline") => {
+            let version = parse_arg(args.get(1))?.ok_or_else(|| anyhow::anyhow!(MIGRATE_USAGE))?;
+            let baselined = migrator.baseline(&pool, version).await?;
+            println!("✅ {} migration(s) marked as applied", baselined.len());
+        }
+        _ => anyhow::bail!(MIGRATE_USAGE),
+    }
+
+    Ok(())
+}
@@ -16,7 +16,13 @@ use bcrypt::{hash, verify, DEFAULT_COST};
 use uuid::Uuid;
 
 // Import models for proper database type handling
-mod models;
+use viworks_admin_backend::models;
+use viworks_admin_backend::auth::login_attempts::{LoginAttemptService, LoginGate};
+use viworks_admin_backend::auth::devices::{BindOutcome, DeviceBindingService, DeviceType};
+use viworks_admin_backend::auth::ClientContext;
+use viworks_admin_backend::config::AppConfig;
+use viworks_admin_backend::database::listing::{ListQuery, ListSpec, MatchFilter, SortField};
+use viworks_admin_backend::services::audit::{AuditFilter, AuditLog};
 
 // Demo data structures
 #[derive(Debug, Serialize, Deserialize)]
@@ -167,7 +173,12 @@ struct BrowserConfig {
 #[derive(Debug, Serialize, Deserialize)]
 struct DeviceBindRequest {
     username: String,
+    password: String,
     fingerprint: String,
+    #[serde(default)]
+    device_type: Option<DeviceType>,
+    #[serde(default)]
+    device_info: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -189,51 +200,57 @@ struct TerminateSessionRequest {
 }
 
 // Demo data storage
-static TWO_FACTOR_CODES: Lazy<Mutex<HashMap<String, (String, chrono::DateTime<Utc>)>>> = 
-    Lazy::new(|| Mutex::new(HashMap::new()));
+type CodeStore = Lazy<Mutex<HashMap<String, (String, chrono::DateTime<Utc>)>>>;
 
-// Database connection pool
-static DB_POOL: Lazy<Option<PgPool>> = Lazy::new(|| None);
+static TWO_FACTOR_CODES: CodeStore = Lazy::new(|| Mutex::new(HashMap::new()));
 
 // Demo handlers
-async fn login(req: web::Json<LoginRequest>, pool: web::Data<Option<PgPool>>) -> HttpResponse {
-    // Try database authentication first
-    if let Some(pool) = pool.as_ref() {
-        match authenticate_user(pool, &req.username, &req.password).await {
-            Ok(Some(user_id)) => {
-                info!("✅ User authenticated: {} (ID: {})", req.username, user_id);
-                let session_id = format!("SID{}", rand::thread_rng().gen_range(100..999));
-                return HttpResponse::Ok().json(LoginResponse {
-                    success: true,
-                    data: Some(LoginData {
-                        session_id,
-                        requires_2fa: true,
-                    }),
-                });
-            }
-            Ok(None) => {
-                info!("❌ Authentication failed for user: {}", req.username);
-            }
-            Err(e) => {
-                error!("❌ Database error during authentication: {}", e);
-            }
+async fn login(
+    req: web::Json<LoginRequest>,
+    http_req: actix_web::HttpRequest,
+    pool: web::Data<Option<PgPool>>,
+    login_attempts: web::Data<Option<LoginAttemptService>>,
+) -> HttpResponse {
+    let (Some(pool), Some(login_attempts)) = (pool.as_ref(), login_attempts.as_ref()) else {
+        error!("❌ Database not available");
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "error": "Database not available"
+        }));
+    };
+
+    let context = ClientContext::from_request(&http_req);
+    match authenticate_user(pool, login_attempts, &req.username, &req.password, &context).await {
+        Ok(DemoLogin::Authenticated(user_id)) => {
+            info!("✅ User authenticated: {} (ID: {})", req.username, user_id);
+            let session_id = format!("SID{}", rand::thread_rng().gen_range(100..999));
+            HttpResponse::Ok().json(LoginResponse {
+                success: true,
+                data: Some(LoginData {
+                    session_id,
+                    requires_2fa: true,
+                }),
+            })
+        }
+        Ok(DemoLogin::Rejected) => {
+            info!("❌ Authentication failed for user: {}", req.username);
+            HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid credentials"
+            }))
+        }
+        Ok(DemoLogin::Refused(LoginGate::Throttled { retry_after })) => HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.num_seconds().to_string()))
+            .json(serde_json::json!({
+                "error": "Too many failed login attempts"
+            })),
+        Ok(DemoLogin::Refused(_)) => HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Account is locked"
+        })),
+        Err(e) => {
+            error!("❌ Database error during authentication: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Database error"
+            }))
         }
-    }
-    
-    // Fallback to demo credentials
-    if req.username == "keyvan" && req.password == "password123" {
-        let session_id = format!("SID{}", rand::thread_rng().gen_range(100..999));
-        HttpResponse::Ok().json(LoginResponse {
-            success: true,
-            data: Some(LoginData {
-                session_id,
-                requires_2fa: true,
-            }),
-        })
-    } else {
-        HttpResponse::Unauthorized().json(serde_json::json!({
-            "error": "Invalid credentials"
-        }))
     }
 }
 
@@ -308,12 +325,70 @@ async fn challenge_verify(req: web::Json<ChallengeVerifyRequest>) -> HttpRespons
     }
 }
 
-async fn device_bind_request(_req: web::Json<DeviceBindRequest>) -> HttpResponse {
-    HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "Device binding request submitted",
-        "auto_approved": true
-    }))
+async fn device_bind_request(
+    req: web::Json<DeviceBindRequest>,
+    http_req: actix_web::HttpRequest,
+    pool: web::Data<Option<PgPool>>,
+    login_attempts: web::Data<Option<LoginAttemptService>>,
+    device_bindings: web::Data<Option<DeviceBindingService>>,
+) -> HttpResponse {
+    let (Some(pool), Some(login_attempts), Some(device_bindings)) =
+        (pool.as_ref(), login_attempts.as_ref(), device_bindings.as_ref())
+    else {
+        error!("❌ Database not available");
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "error": "Database not available"
+        }));
+    };
+
+    let context = ClientContext::from_request(&http_req);
+    let user_id = match authenticate_user(pool, login_attempts, &req.username, &req.password, &context).await {
+        Ok(DemoLogin::Authenticated(user_id)) => user_id,
+        Ok(_) => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid credentials"
+            }))
+        }
+        Err(e) => {
+            error!("❌ Database error during device binding: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Database error"
+            }));
+        }
+    };
+
+    let device_info = req.device_info.clone().unwrap_or_else(|| serde_json::json!({}));
+    let device_type = req.device_type.unwrap_or(DeviceType::Desktop);
+    match device_bindings
+        .request(user_id, device_type, &req.fingerprint, device_info, &context)
+        .await
+    {
+        Ok(BindOutcome::Requested(binding)) | Ok(BindOutcome::AlreadyPending(binding)) => {
+            info!("📱 Device binding pending for {} ({})", binding.username, binding.fingerprint);
+            HttpResponse::Accepted().json(serde_json::json!({
+                "success": true,
+                "message": "Device binding request submitted for admin approval",
+                "request": binding
+            }))
+        }
+        Ok(BindOutcome::AlreadyBound) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "message": "Device is already bound",
+            "status": "approved"
+        })),
+        Ok(BindOutcome::TooManyPending) => HttpResponse::TooManyRequests().json(serde_json::json!({
+            "error": "Too many pending device binding requests"
+        })),
+        Ok(BindOutcome::Invalid(reason)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": reason
+        })),
+        Err(e) => {
+            error!("❌ Database error during device binding: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Database error"
+            }))
+        }
+    }
 }
 
 async fn client_bootstrap(_req: web::Json<ClientBootstrapRequest>, _http_req: actix_web::HttpRequest) -> HttpResponse {
@@ -416,34 +491,58 @@ fn generate_random_path() -> String {
 }
 
 // Database authentication functions
-async fn authenticate_user(pool: &PgPool, username: &str, password: &str) -> Result<Option<Uuid>, sqlx::Error> {
-    let row = sqlx::query("SELECT id, password_hash FROM users WHERE username = $1 AND status = 'active'")
+enum DemoLogin {
+    Authenticated(Uuid),
+    Rejected,
+    /// Locked account or throttled address; the password was not checked
+    Refused(LoginGate),
+}
+
+async fn authenticate_user(
+    pool: &PgPool,
+    login_attempts: &LoginAttemptService,
+    username: &str,
+    password: &str,
+    context: &ClientContext,
+) -> anyhow::Result<DemoLogin> {
+    let row = sqlx::query("SELECT id, password_hash, locked_until FROM users WHERE username = $1 AND status = 'active'")
         .bind(username)
         .fetch_optional(pool)
         .await?;
-    
-    if let Some(row) = row {
-        let user_id: Uuid = row.get("id");
-        let password_hash: String = row.get("password_hash");
-        
-        if verify(password, &password_hash).unwrap_or(false) {
-            // Update last login time
-            let _ = sqlx::query("UPDATE users SET last_login_at = NOW(), failed_login_attempts = 0 WHERE id = $1")
-                .bind(user_id)
-                .execute(pool)
-                .await;
-            
-            return Ok(Some(user_id));
-        } else {
-            // Increment failed login attempts
-            let _ = sqlx::query("UPDATE users SET failed_login_attempts = failed_login_attempts + 1 WHERE id = $1")
-                .bind(user_id)
-                .execute(pool)
-                .await;
-        }
+    let account = row.map(|row| {
+        (
+            row.get::<Uuid, _>("id"),
+            row.get::<String, _>("password_hash"),
+            row.get::<Option<chrono::DateTime<Utc>>, _>("locked_until"),
+        )
+    });
+
+    let gate = login_attempts
+        .gate(username, account.as_ref().map(|(id, _, locked_until)| (*id, *locked_until)), context)
+        .await?;
+    if gate != LoginGate::Open {
+        return Ok(DemoLogin::Refused(gate));
     }
-    
-    Ok(None)
+
+    let Some((user_id, password_hash, _)) = account else {
+        login_attempts.record_failure(None, username, context, "unknown_user").await?;
+        return Ok(DemoLogin::Rejected);
+    };
+
+    if !verify(password, &password_hash).unwrap_or(false) {
+        login_attempts
+            .record_failure(Some(user_id), username, context, "invalid_password")
+            .await?;
+        return Ok(DemoLogin::Rejected);
+    }
+
+    login_attempts.record_success(user_id).await?;
+    sqlx::query("UPDATE users SET last_login_at = NOW() WHERE id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(DemoLogin::Authenticated(user_id))
 }
 
 async fn create_user_in_db(pool: &PgPool, username: &str, email: &str, password: &str) -> Result<Uuid, sqlx::Error> {
@@ -462,15 +561,39 @@ async fn create_user_in_db(pool: &PgPool, username: &str, email: &str, password:
 }
 
 // Enhanced endpoint handlers for simplified auth flow
-async fn register_mobile_device(req: web::Json<DeviceRegistrationRequest>) -> HttpResponse {
+async fn register_mobile_device(
+    req: web::Json<DeviceRegistrationRequest>,
+    http_req: actix_web::HttpRequest,
+    pool: web::Data<Option<PgPool>>,
+    login_attempts: web::Data<Option<LoginAttemptService>>,
+) -> HttpResponse {
     info!("📱 Device registration request for user: {}", req.username);
     
-    // Validate credentials (demo: accept keyvan/password123)
-    if req.username != "keyvan" || req.password != "password123" {
-        return HttpResponse::Unauthorized().json(serde_json::json!({
+    let (Some(pool), Some(login_attempts)) = (pool.as_ref(), login_attempts.as_ref()) else {
+        error!("❌ Database not available");
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({
             "success": false,
-            "message": "Invalid credentials"
+            "message": "Database not available"
         }));
+    };
+    
+    // Validate credentials (counts towards lockout like any other login)
+    let context = ClientContext::from_request(&http_req);
+    match authenticate_user(pool, login_attempts, &req.username, &req.password, &context).await {
+        Ok(DemoLogin::Authenticated(_)) => {}
+        Ok(_) => {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "success": false,
+                "message": "Invalid credentials"
+            }));
+        }
+        Err(e) => {
+            error!("❌ Database error during authentication: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "message": "Database error"
+            }));
+        }
     }
     
     // Store device info (in demo mode, just log it)
@@ -530,25 +653,22 @@ async fn validate_2fa_code(req: web::Json<TwoFactorValidation>) -> HttpResponse
         if Utc::now() < *expires_at && stored_code == &req.code {
             // Generate connection configs
             let configs = ConnectionConfigs {
-                stunnel_config: format!(
-                    "client = yes\n\
+                stunnel_config: "client = yes\n\
                     [https]\n\
                     accept = 127.0.0.1:8443\n\
                     connect = gw.example.com:443\n\
                     cert = /etc/ssl/certs/client.crt\n\
                     key = /etc/ssl/private/client.key\n\
                     CAfile = /etc/ssl/certs/ca.crt"
-                ),
-                portknock_config: format!(
-                    "#!/bin/bash\n\
+                    .to_string(),
+                portknock_config: "#!/bin/bash\n\
                     # Port knocking sequence\n\
                     for port in 1000 2000 3000; do\n\
                         nc -z gw.example.com $port\n\
                         sleep 1\n\
                     done"
-                ),
-                openvpn_config: format!(
-                    "client\n\
+                    .to_string(),
+                openvpn_config: "client\n\
                     dev tun\n\
                     proto tcp\n\
                     remote 127.0.0.1 9443\n\
@@ -562,7 +682,7 @@ async fn validate_2fa_code(req: web::Json<TwoFactorValidation>) -> HttpResponse
                     key-direction 1\n\
                     verb 3\n\
                     auth-user-pass /etc/openvpn/auth.txt"
-                ),
+                    .to_string(),
             };
             
             info!("✅ 2FA validation successful, returning connection configs");
@@ -588,7 +708,7 @@ async fn get_users(pool: web::Data<Option<PgPool>>) -> HttpResponse {
         info!("✅ Database pool is available");
         
         // Use the proper User struct from models.rs that handles the user_status enum
-        let query_result = sqlx::query_as::<_, crate::models::User>(
+        let query_result = sqlx::query_as::<_, models::User>(
             r#"
             SELECT id, username, email, password_hash, mobile, status, roles, 
                    created_at, updated_at, last_login_at, failed_login_attempts, locked_until
@@ -642,17 +762,6 @@ async fn get_users(pool: web::Data<Option<PgPool>>) -> HttpResponse {
     }
 }
 
-// Define the UserRow struct for type-safe database queries
-#[derive(Debug, sqlx::FromRow)]
-struct UserRow {
-    id: Uuid,
-    username: String,
-    email: String,
-    status: String,
-    created_at: chrono::DateTime<chrono::Utc>,
-    last_login_at: Option<chrono::DateTime<chrono::Utc>>,
-}
-
 // Admin panel endpoint functions
 async fn get_sessions(pool: web::Data<Option<PgPool>>) -> HttpResponse {
     info!("📋 Fetching sessions from database...");
@@ -728,27 +837,47 @@ async fn get_sessions(pool: web::Data<Option<PgPool>>) -> HttpResponse {
     }
 }
 
-async fn get_mobile_devices(pool: web::Data<Option<PgPool>>) -> HttpResponse {
+/// Mobile devices matching `status`, `q` (owner's username or email, device
+/// model) and `from`/`to` (binding date), a page at a time.
+static MOBILE_DEVICE_LIST: ListSpec = ListSpec {
+    select: "md.id, md.user_id, md.device_id, md.fcm_token, md.device_model, md.device_os, \
+             md.app_version, md.bound_at, md.last_used_at, md.status",
+    from: "mobile_devices md JOIN users u ON u.id = md.user_id",
+    id: "md.id",
+    sorts: &[
+        SortField { name: "bound_at", expr: "COALESCE(md.bound_at, '-infinity')", sql_type: "timestamptz" },
+        SortField { name: "last_used_at", expr: "COALESCE(md.last_used_at, '-infinity')", sql_type: "timestamptz" },
+    ],
+    default_sort: "-bound_at",
+    date: Some("md.bound_at"),
+    search: &["u.username", "u.email", "md.device_model"],
+    status: Some(MatchFilter {
+        expr: "md.status",
+        values: &["pending", "approved", "rejected", "revoked", "expired"],
+    }),
+    role: None,
+};
+
+async fn get_mobile_devices(pool: web::Data<Option<PgPool>>, query: web::Query<ListQuery>) -> HttpResponse {
     info!("📱 Fetching mobile devices from database...");
     
+    let list = match query.resolve(&MOBILE_DEVICE_LIST) {
+        Ok(list) => list,
+        Err(message) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "success": false,
+            "error": message
+        })),
+    };
+
     match pool.as_ref() {
         Some(pool) => {
-            match sqlx::query_as::<_, crate::models::MobileDevice>(
-                r#"
-                SELECT id, user_id, device_id, fcm_token, device_model, device_os, 
-                       app_version, bound_at, last_used_at, status
-                FROM mobile_devices 
-                ORDER BY bound_at DESC
-                "#
-            )
-            .fetch_all(pool)
-            .await
-            {
-                Ok(devices) => {
-                    info!("✅ Found {} mobile devices in database", devices.len());
+            match list.fetch::<models::MobileDevice>(pool).await {
+                Ok(page) => {
+                    info!("✅ Found {} mobile devices in database", page.items.len());
                     
                     // Transform to frontend-compatible format
-                    let response_devices: Vec<serde_json::Value> = devices
+                    let response_devices: Vec<serde_json::Value> = page
+                        .items
                         .into_iter()
                         .map(|device| {
                             // Get username for the device
@@ -759,12 +888,12 @@ async fn get_mobile_devices(pool: web::Data<Option<PgPool>>) -> HttpResponse {
                                 "userName": username,
                                 "deviceName": device.device_model.as_ref().unwrap_or(&"نامشخص".to_string()).clone(),
                                 "deviceModel": device.device_model.as_ref().unwrap_or(&"نامشخص".to_string()).clone(),
-                                "platform": if device.device_os.as_ref().map_or(false, |os| os.to_lowercase().contains("ios")) { "ios" } else { "android" },
+                                "platform": if device.device_os.as_ref().is_some_and(|os| os.to_lowercase().contains("ios")) { "ios" } else { "android" },
                                 "status": match device.status {
-                                    crate::models::DeviceStatus::Approved => "active",
-                                    crate::models::DeviceStatus::Pending => "pending",
-                                    crate::models::DeviceStatus::Rejected => "inactive",
-                                    crate::models::DeviceStatus::Revoked => "inactive",
+                                    models::DeviceStatus::Approved => "active",
+                                    models::DeviceStatus::Pending => "pending",
+                                    models::DeviceStatus::Rejected => "inactive",
+                                    models::DeviceStatus::Revoked | models::DeviceStatus::Expired => "inactive",
                                 },
                                 "lastActiveCity": "تهران", // Default city
                                 "lastActivity": device.last_used_at.to_rfc3339(),
@@ -780,7 +909,10 @@ async fn get_mobile_devices(pool: web::Data<Option<PgPool>>) -> HttpResponse {
                     info!("✅ Returning {} mobile devices to frontend", response_devices.len());
                     HttpResponse::Ok().json(serde_json::json!({
                         "success": true,
-                        "devices": response_devices
+                        "devices": response_devices,
+                        "total": page.total,
+                        "limit": page.limit,
+                        "next_cursor": page.next_cursor
                     }))
                 }
                 Err(e) => {
@@ -943,48 +1075,71 @@ async fn seed_test_users(pool: web::Data<Option<PgPool>>) -> HttpResponse {
     }
 }
 
-async fn get_device_requests(_pool: web::Data<Option<PgPool>>) -> HttpResponse {
+async fn get_device_requests(device_bindings: web::Data<Option<DeviceBindingService>>) -> HttpResponse {
     info!("📋 Fetching device requests from database...");
-    
-    // For now, return mock device requests since we don't have a device_requests table yet
-    let mock_requests = vec![
-        serde_json::json!({
-            "username": "admin",
-            "fingerprint": "demo_fingerprint_123456789",
-            "status": "approved",
-            "created_at": Utc::now().to_rfc3339(),
-        })
-    ];
-    
-    HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "requests": mock_requests
-    }))
+
+    let Some(device_bindings) = device_bindings.as_ref() else {
+        error!("❌ Database pool is not available");
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "success": false,
+            "error": "Database pool not available"
+        }));
+    };
+
+    match device_bindings.list(None).await {
+        Ok(requests) => HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "requests": requests
+        })),
+        Err(e) => {
+            error!("❌ Failed to fetch device requests: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "error": "Database error"
+            }))
+        }
+    }
 }
 
-async fn get_audit_logs(_pool: web::Data<Option<PgPool>>) -> HttpResponse {
+async fn get_audit_logs(audit: web::Data<Option<AuditLog>>) -> HttpResponse {
     info!("📋 Fetching audit logs from database...");
-    
-    // For now, return mock audit logs since we don't have an audit_logs table yet
-    let mock_logs = vec![
-        serde_json::json!({
-            "timestamp": Utc::now().to_rfc3339(),
-            "event": "LOGIN_SUCCESS",
-            "user": "admin",
-            "details": "User logged in successfully",
-        }),
-        serde_json::json!({
-            "timestamp": (Utc::now() - Duration::minutes(5)).to_rfc3339(),
-            "event": "USER_CREATE",
-            "user": "admin",
-            "details": "New user created: test_user",
-        })
-    ];
-    
-    HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "logs": mock_logs
-    }))
+
+    let Some(audit) = audit.as_ref() else {
+        error!("❌ Database pool is not available");
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "success": false,
+            "error": "Database pool not available"
+        }));
+    };
+
+    match audit.query(&AuditFilter::default(), 1, 100).await {
+        Ok((events, total)) => {
+            let logs: Vec<serde_json::Value> = events
+                .into_iter()
+                .map(|event| serde_json::json!({
+                    "seq": event.seq,
+                    "timestamp": event.created_at.to_rfc3339(),
+                    "event": event.event_type.to_uppercase(),
+                    "user": event.details.get("actor").cloned()
+                        .or_else(|| event.user_id.map(|id| serde_json::Value::from(id.to_string()))),
+                    "details": event.details,
+                    "hash": event.hash,
+                }))
+                .collect();
+            HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "logs": logs,
+                "total": total
+            }))
+        }
+        Err(e) => {
+            error!("❌ Failed to fetch audit logs: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "success": false,
+                "error": "Database error"
+            }))
+        }
+    }
 }
 
 // API status handler
@@ -1077,6 +1232,24 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    // Lockout settings come from the same LOGIN_* variables as the main backend
+    let app_config = AppConfig::load().unwrap_or_default();
+    let login_attempts = web::Data::new(
+        pool.clone()
+            .map(|pool| LoginAttemptService::new(&app_config, pool)),
+    );
+    let device_bindings = web::Data::new(
+        pool.clone()
+            .map(|pool| DeviceBindingService::new(&app_config, pool)),
+    );
+    let audit = match pool.clone().map(|pool| AuditLog::new(&app_config, pool)).transpose() {
+        Ok(audit) => web::Data::new(audit),
+        Err(e) => {
+            error!("❌ Invalid audit log configuration: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
     let host = std::env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
     let port = std::env::var("PORT")
         .unwrap_or_else(|_| "8081".to_string())
@@ -1097,6 +1270,9 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(login_attempts.clone())
+            .app_data(device_bindings.clone())
+            .app_data(audit.clone())
             .wrap(cors)
             .route("/health", web::get().to(|| async { 
                 HttpResponse::Ok().json(serde_json::json!({
@@ -86,6 +86,7 @@ pub enum DeviceStatus {
     Approved,
     Rejected,
     Revoked,
+    Expired,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
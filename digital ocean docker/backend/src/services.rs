@@ -1,6 +1,19 @@
 // Service modules for business logic
 // These will be implemented in Phase 3
 
+pub mod account_recovery;
+pub mod agent_bridge;
+pub mod alerts;
+pub mod audit;
+pub mod gateway;
+pub mod mail_templates;
+pub mod mailer;
+pub mod push;
+pub mod realtime;
+pub mod scim;
+pub mod session_reaper;
+pub mod siem;
+
 // Placeholder implementations
 pub mod user_service {
     use anyhow::Result;
@@ -14,9 +27,9 @@ pub mod user_service {
 
 pub mod client_service {
     use anyhow::Result;
-    use crate::models::ViWorkSClient;
+    use crate::api::clients::ClientResponse;
     
-    pub async fn get_all_clients() -> Result<Vec<ViWorkSClient>> {
+    pub async fn get_all_clients() -> Result<Vec<ClientResponse>> {
         // Placeholder - to be implemented
         Ok(vec![])
     }
@@ -0,0 +1,498 @@
+//! Self-service password reset and email verification.
+//!
+//! Both flows mail the user a link carrying a single-use token: 32 random
+//! bytes, base64url, in the URL fragment so browsers neither send it to a
+//! server nor leak it in a Referer. `account_tokens` keeps only its SHA-256,
+//! the address it was sent to and its expiry (PASSWORD_RESET_TTL_MINUTES,
+//! EMAIL_VERIFICATION_TTL_HOURS). A new token replaces the user's earlier
+//! ones for the same purpose. A token is consumed in the transaction that
+//! applies it, so a new password that is rejected leaves it usable, and it
+//! stops working once the user's address changes or the account is not
+//! active.
+//!
+//! A reset request gets the same answer whether or not the account exists,
+//! and the mail goes out in the background so the response time does not
+//! tell either; the audit event (`password_reset_requested`) records what
+//! happened. Completing a reset ends every session of the user (the session
+//! reaper tears them down on the gateway), lifts a login lockout and counts
+//! as verifying the address the link was sent to.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::auth::password::{PasswordCheck, PasswordOwner, PasswordService};
+use crate::auth::ClientContext;
+use crate::config::AppConfig;
+use crate::services::audit::{AuditEvent, AuditEventType};
+use crate::services::mail_templates::{self, MailTemplate};
+use crate::services::mailer::{MailMessage, Mailer};
+
+/// Shortest time between two mails of the same kind to one user
+const RESEND_INTERVAL_SECONDS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+impl TokenPurpose {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PasswordReset => "password_reset",
+            Self::EmailVerification => "email_verification",
+        }
+    }
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.trim().as_bytes()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResetOutcome {
+    Completed { user_id: Uuid, ended_sessions: Vec<Uuid> },
+    /// Unknown, used, expired or superseded token
+    InvalidToken,
+    /// The new password failed a check; the token stays usable
+    Rejected(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationRequest {
+    Sent,
+    AlreadyVerified,
+    /// A verification mail went out less than a minute ago
+    TooSoon,
+}
+
+struct Account {
+    id: Uuid,
+    username: String,
+    email: String,
+}
+
+pub struct AccountRecovery {
+    pool: PgPool,
+    mailer: Option<Arc<dyn Mailer>>,
+    link_base: String,
+    default_language: String,
+    reset_ttl: Duration,
+    verification_ttl: Duration,
+}
+
+impl AccountRecovery {
+    pub fn new(config: &AppConfig, pool: PgPool, mailer: Option<Arc<dyn Mailer>>) -> Self {
+        Self {
+            pool,
+            mailer,
+            link_base: config.admin_panel_url.trim_end_matches('/').to_string(),
+            default_language: config.mail_default_language.clone(),
+            reset_ttl: Duration::minutes(config.password_reset_ttl_minutes as i64),
+            verification_ttl: Duration::hours(config.email_verification_ttl_hours as i64),
+        }
+    }
+
+    /// False when no MAIL_TRANSPORT is configured; both flows are off then.
+    pub fn enabled(&self) -> bool {
+        self.mailer.is_some()
+    }
+
+    fn language(&self, accept_language: Option<&str>) -> &'static str {
+        mail_templates::negotiate(accept_language, &self.default_language)
+    }
+
+    /// Mails a reset link to the active account whose username or email is
+    /// `login`, unless one went out within the last minute. Nothing about
+    /// the outcome is returned.
+    pub async fn request_password_reset(
+        &self,
+        login: &str,
+        accept_language: Option<&str>,
+        context: &ClientContext,
+    ) -> Result<()> {
+        let login = login.trim();
+        let audit = AuditEvent::new(AuditEventType::PasswordResetRequested).context(context);
+
+        let account = sqlx::query_as!(
+            Account,
+            r#"
+            SELECT id, username, email
+            FROM users
+            WHERE (username = $1 OR LOWER(email) = LOWER($1)) AND status = 'active'
+            ORDER BY (username = $1) DESC
+            LIMIT 1
+            "#,
+            login
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(account) = account else {
+            audit
+                .details(serde_json::json!({ "login": login, "outcome": "unknown_account" }))
+                .write(&self.pool)
+                .await?;
+            return Ok(());
+        };
+
+        let audit = audit.user(Some(account.id)).target(account.id);
+        let Some(token) = self.issue(&account, TokenPurpose::PasswordReset, self.reset_ttl, context).await? else {
+            audit
+                .details(serde_json::json!({ "outcome": "too_soon" }))
+                .write(&self.pool)
+                .await?;
+            return Ok(());
+        };
+
+        let minutes = self.reset_ttl.num_minutes().to_string();
+        let link = format!("{}/reset-password#token={}", self.link_base, token);
+        let message = mail_templates::render(
+            MailTemplate::PasswordReset,
+            self.language(accept_language),
+            &account.email,
+            &[("username", &account.username), ("link", &link), ("minutes", &minutes)],
+        );
+        self.dispatch(message, Some(audit));
+        Ok(())
+    }
+
+    /// Sets a new password with a reset token.
+    pub async fn reset_password(
+        &self,
+        passwords: &PasswordService,
+        token: &str,
+        new_password: &str,
+        accept_language: Option<&str>,
+        context: &ClientContext,
+    ) -> Result<ResetOutcome> {
+        let mut tx = self.pool.begin().await?;
+        let Some(account) = redeem(&mut tx, token, TokenPurpose::PasswordReset).await? else {
+            return Ok(ResetOutcome::InvalidToken);
+        };
+
+        if let PasswordCheck::Rejected(reason) =
+            passwords.check_new_password(new_password, PasswordOwner::Existing(account.id)).await?
+        {
+            return Ok(ResetOutcome::Rejected(reason));
+        }
+        let password_hash = passwords.hash_password(new_password)?;
+
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET password_hash = $2, failed_login_attempts = 0, locked_until = NULL,
+                email_verified_at = COALESCE(email_verified_at, NOW()), updated_at = NOW()
+            WHERE id = $1
+            "#,
+            account.id,
+            password_hash
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let ended_sessions = sqlx::query_scalar!(
+            r#"
+            UPDATE sessions
+            SET status = 'terminated', terminated_at = NOW(), terminated_reason = 'password_reset'
+            WHERE user_id = $1 AND status = 'active'
+            RETURNING id
+            "#,
+            account.id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        AuditEvent::new(AuditEventType::PasswordResetCompleted)
+            .user(Some(account.id))
+            .target(account.id)
+            .details(serde_json::json!({ "sessions_terminated": ended_sessions.len() }))
+            .context(context)
+            .write(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        if let Err(e) = passwords.remember(account.id, &password_hash).await {
+            eprintln!("Failed to record password history of user {}: {}", account.id, e);
+        }
+
+        let time = Utc::now().format("%Y-%m-%d %H:%M UTC").to_string();
+        let ip = context.ip_address.map(|ip| ip.ip().to_string()).unwrap_or_else(|| "-".to_string());
+        let notice = mail_templates::render(
+            MailTemplate::PasswordChanged,
+            self.language(accept_language),
+            &account.email,
+            &[("username", &account.username), ("time", &time), ("ip", &ip)],
+        );
+        self.dispatch(notice, None);
+
+        Ok(ResetOutcome::Completed { user_id: account.id, ended_sessions })
+    }
+
+    /// Mails a verification link to the user's current address.
+    pub async fn send_email_verification(
+        &self,
+        user_id: Uuid,
+        accept_language: Option<&str>,
+        context: &ClientContext,
+    ) -> Result<VerificationRequest> {
+        let account = sqlx::query!(
+            "SELECT id, username, email, email_verified_at FROM users WHERE id = $1 AND status = 'active'",
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("User {} is not active", user_id))?;
+        if account.email_verified_at.is_some() {
+            return Ok(VerificationRequest::AlreadyVerified);
+        }
+
+        let account = Account { id: account.id, username: account.username, email: account.email };
+        let Some(token) = self
+            .issue(&account, TokenPurpose::EmailVerification, self.verification_ttl, context)
+            .await?
+        else {
+            return Ok(VerificationRequest::TooSoon);
+        };
+
+        let hours = self.verification_ttl.num_hours().to_string();
+        let link = format!("{}/verify-email#token={}", self.link_base, token);
+        let message = mail_templates::render(
+            MailTemplate::EmailVerification,
+            self.language(accept_language),
+            &account.email,
+            &[("username", &account.username), ("email", &account.email), ("link", &link), ("hours", &hours)],
+        );
+        let audit = AuditEvent::new(AuditEventType::EmailVerificationSent)
+            .user(Some(account.id))
+            .target(account.id)
+            .details(serde_json::json!({ "email": account.email }))
+            .context(context);
+        self.dispatch(message, Some(audit));
+        Ok(VerificationRequest::Sent)
+    }
+
+    /// Marks the address the token was sent to verified; `None` for a token
+    /// that is not (or no longer) valid.
+    pub async fn verify_email(&self, token: &str, context: &ClientContext) -> Result<Option<Uuid>> {
+        let mut tx = self.pool.begin().await?;
+        let Some(account) = redeem(&mut tx, token, TokenPurpose::EmailVerification).await? else {
+            return Ok(None);
+        };
+
+        sqlx::query!(
+            "UPDATE users SET email_verified_at = NOW(), updated_at = NOW() WHERE id = $1",
+            account.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        AuditEvent::new(AuditEventType::EmailVerified)
+            .user(Some(account.id))
+            .target(account.id)
+            .details(serde_json::json!({ "email": account.email }))
+            .context(context)
+            .write(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(Some(account.id))
+    }
+
+    /// Stores a new token for `account`, dropping its earlier ones for
+    /// `purpose`; `None` when the last one is younger than the resend interval.
+    async fn issue(
+        &self,
+        account: &Account,
+        purpose: TokenPurpose,
+        ttl: Duration,
+        context: &ClientContext,
+    ) -> Result<Option<String>> {
+        let mut tx = self.pool.begin().await?;
+        // Serializes concurrent requests for the same user
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", account.id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let recent = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM account_tokens
+                WHERE user_id = $1 AND purpose = $2 AND created_at > NOW() - make_interval(secs => $3)
+            ) AS "recent!"
+            "#,
+            account.id,
+            purpose.as_str(),
+            RESEND_INTERVAL_SECONDS as f64
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if recent {
+            return Ok(None);
+        }
+
+        sqlx::query!(
+            "DELETE FROM account_tokens WHERE user_id = $1 AND purpose = $2",
+            account.id,
+            purpose.as_str()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let token = generate_token();
+        sqlx::query!(
+            r#"
+            INSERT INTO account_tokens (user_id, purpose, token_hash, email, expires_at, requested_ip)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            account.id,
+            purpose.as_str(),
+            hash_token(&token),
+            account.email,
+            Utc::now() + ttl,
+            context.ip_address
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(token))
+    }
+
+    /// Sends `message` in the background, then records `audit` with whether
+    /// it was handed over.
+    fn dispatch(&self, message: MailMessage, audit: Option<AuditEvent>) {
+        let Some(mailer) = self.mailer.clone() else {
+            return;
+        };
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let delivery = mailer.send(&message).await;
+            if let Err(e) = &delivery {
+                eprintln!("Failed to mail {}: {:#}", message.to, e);
+            }
+            if let Some(audit) = audit {
+                let audit = audit.details(serde_json::json!({
+                    "outcome": if delivery.is_ok() { "sent" } else { "delivery_failed" }
+                }));
+                if let Err(e) = audit.write(&pool).await {
+                    eprintln!("Failed to write audit event: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Consumes a live token of `purpose` whose user is active and still has the
+/// address it was sent to. The token row stays locked until `tx` ends, so two
+/// concurrent redemptions cannot both succeed.
+async fn redeem(tx: &mut PgConnection, token: &str, purpose: TokenPurpose) -> Result<Option<Account>> {
+    let found = sqlx::query!(
+        r#"
+        SELECT t.id AS token_id, t.email AS sent_to, t.expires_at, t.used_at,
+               u.id, u.username, u.email, u.status::text AS user_status
+        FROM account_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE t.token_hash = $1 AND t.purpose = $2
+        FOR UPDATE OF t
+        "#,
+        hash_token(token),
+        purpose.as_str()
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(found) = found else {
+        return Ok(None);
+    };
+    let live = redeemable(
+        found.used_at,
+        found.expires_at,
+        &found.sent_to,
+        &found.email,
+        found.user_status.as_deref(),
+        Utc::now(),
+    );
+    if !live {
+        return Ok(None);
+    }
+
+    sqlx::query!("UPDATE account_tokens SET used_at = NOW() WHERE id = $1", found.token_id)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(Some(Account { id: found.id, username: found.username, email: found.email }))
+}
+
+/// Whether a token can still be used: never used, not expired, and its user is
+/// active with the address the token was sent to.
+fn redeemable(
+    used_at: Option<DateTime<Utc>>,
+    expires_at: DateTime<Utc>,
+    sent_to: &str,
+    current_email: &str,
+    user_status: Option<&str>,
+    now: DateTime<Utc>,
+) -> bool {
+    used_at.is_none()
+        && expires_at > now
+        && user_status == Some("active")
+        && sent_to.to_lowercase() == current_email.to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(used_at: Option<DateTime<Utc>>, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        redeemable(used_at, expires_at, "alice@example.com", "alice@example.com", Some("active"), now)
+    }
+
+    #[test]
+    fn test_token_is_single_use() {
+        let now = Utc::now();
+        let expires_at = now + Duration::minutes(30);
+        assert!(check(None, expires_at, now));
+        assert!(!check(Some(now - Duration::seconds(5)), expires_at, now));
+    }
+
+    #[test]
+    fn test_token_stops_working_at_expiry() {
+        let issued = Utc::now();
+        let expires_at = issued + Duration::minutes(30);
+        assert!(check(None, expires_at, issued + Duration::minutes(29)));
+        assert!(!check(None, expires_at, expires_at));
+        assert!(!check(None, expires_at, issued + Duration::hours(1)));
+    }
+
+    #[test]
+    fn test_token_needs_an_active_user_with_the_same_address() {
+        let now = Utc::now();
+        let expires_at = now + Duration::minutes(30);
+        assert!(redeemable(None, expires_at, "Alice@Example.com", "alice@example.com", Some("active"), now));
+        assert!(!redeemable(None, expires_at, "alice@example.com", "alice@new.example", Some("active"), now));
+        for status in [Some("suspended"), Some("pending"), None] {
+            assert!(!redeemable(None, expires_at, "alice@example.com", "alice@example.com", status, now));
+        }
+    }
+
+    #[test]
+    fn test_tokens_are_stored_hashed() {
+        let token = generate_token();
+        assert_ne!(token, generate_token());
+        assert_eq!(hash_token(&token).len(), 64);
+        assert_eq!(hash_token(&token), hash_token(&format!(" {}\n", token)));
+    }
+}
@@ -0,0 +1,716 @@
+//! Gateway commands submitted through the backend agent.
+//!
+//! User lifecycle events (a user created, disabled or deleted, a session
+//! terminated) map onto gateway verbs ([`GatewayCommand`]) and are recorded in
+//! `agent_commands` before anything is sent, so a command outlives a backend
+//! agent outage or a restart of this process. Each row is posted to the
+//! backend agent's `POST /api/v1/commands` with a short-lived service JWT and
+//! the row's idempotency key, which the backend agent answers with the command
+//! it already queued when a reply was lost and the row is sent again. Events
+//! that can only happen once (a user created or deleted, a session
+//! terminated) get deterministic keys, so recording one twice yields a single
+//! command.
+//!
+//! [`AgentBridge::process`] runs in the background: it submits due pending
+//! rows, backing off exponentially after failures until
+//! `AGENT_COMMAND_MAX_ATTEMPTS`, and polls submitted rows until the backend
+//! agent reports them finished. Passwords in `args` are dropped once a command
+//! completes (failed commands keep them so they can be retried) and are never
+//! returned by the API.
+//!
+//! Per-session credentials do not go through here: a login needs the
+//! gateway's answer before it completes, so
+//! [`CredentialService`](crate::auth::credentials::CredentialService) calls
+//! the gateway directly.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::{Client, StatusCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::database::listing::{List, ListSpec, MatchFilter, Page, SortField};
+
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+/// `sub` and `role` of the service JWT; the backend agent only queues
+/// commands for operators and admins
+const SERVICE_SUBJECT: &str = "viworks-admin-backend";
+const SERVICE_ROLE: &str = "operator";
+const SERVICE_TOKEN_TTL_SECS: i64 = 300;
+/// Rows claimed per pass, and how long a claim hides them from other replicas
+const CLAIM_BATCH: i64 = 50;
+const CLAIM_LEASE_SECS: f64 = 60.0;
+const MAX_BACKOFF_SECS: f64 = 900.0;
+const PASSWORD_LEN: usize = 32;
+
+/// A gateway-side effect of a user lifecycle event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GatewayCommand {
+    /// Creates the user's OpenVPN account
+    CreateUser { user_id: Option<Uuid>, username: String, email: Option<String>, password: String },
+    /// Ends every gateway session of a user who was deactivated
+    DisableUser { user_id: Uuid, username: String },
+    /// Removes the user's accounts from the gateway
+    DeleteUser { user_id: Uuid, username: String },
+    /// Ends one session on the gateway
+    TerminateSession { user_id: Option<Uuid>, username: String, session_id: Uuid },
+    /// Starts the session's browser container
+    SpawnContainer { user_id: Option<Uuid>, username: String, session_id: Uuid },
+}
+
+impl GatewayCommand {
+    /// What happened, as recorded in `agent_commands.event`.
+    pub fn event(&self) -> &'static str {
+        match self {
+            Self::CreateUser { .. } => "user_created",
+            Self::DisableUser { .. } => "user_disabled",
+            Self::DeleteUser { .. } => "user_deleted",
+            Self::TerminateSession { .. } => "session_terminated",
+            Self::SpawnContainer { .. } => "container_spawned",
+        }
+    }
+
+    /// The gateway agent verb the backend agent relays.
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Self::CreateUser { .. } => "create_openvpn_user",
+            Self::DisableUser { .. } | Self::TerminateSession { .. } => "terminate_session",
+            Self::DeleteUser { .. } => "delete_user",
+            Self::SpawnContainer { .. } => "spawn_container",
+        }
+    }
+
+    pub fn args(&self) -> Value {
+        match self {
+            Self::CreateUser { username, email, password, .. } => json!({
+                "username": username,
+                "password": password,
+                "email": email
+            }),
+            Self::DisableUser { username, .. } | Self::DeleteUser { username, .. } => json!({
+                "username": username
+            }),
+            Self::TerminateSession { username, session_id, .. } => json!({
+                "username": username,
+                "session_id": session_id
+            }),
+            Self::SpawnContainer { username, session_id, .. } => json!({
+                "username": username,
+                "session_id": session_id,
+                "image": "viworks/chrome:latest"
+            }),
+        }
+    }
+
+    pub fn user_id(&self) -> Option<Uuid> {
+        match self {
+            Self::CreateUser { user_id, .. }
+            | Self::TerminateSession { user_id, .. }
+            | Self::SpawnContainer { user_id, .. } => *user_id,
+            Self::DisableUser { user_id, .. } | Self::DeleteUser { user_id, .. } => Some(*user_id),
+        }
+    }
+
+    pub fn session_id(&self) -> Option<Uuid> {
+        match self {
+            Self::TerminateSession { session_id, .. } | Self::SpawnContainer { session_id, .. } => Some(*session_id),
+            _ => None,
+        }
+    }
+
+    /// Deterministic for events that happen once per user or session; a user
+    /// can be disabled again after being re-enabled, so those keys are unique.
+    pub fn idempotency_key(&self) -> String {
+        match self {
+            Self::CreateUser { user_id: Some(user_id), .. } => format!("user_created:{}", user_id),
+            Self::CreateUser { user_id: None, username, .. } => {
+                format!("user_created:{}:{}", username, Uuid::new_v4())
+            }
+            Self::DisableUser { user_id, .. } => format!("user_disabled:{}:{}", user_id, Uuid::new_v4()),
+            Self::DeleteUser { user_id, .. } => format!("user_deleted:{}", user_id),
+            Self::TerminateSession { session_id, .. } => format!("session_terminated:{}", session_id),
+            Self::SpawnContainer { session_id, .. } => format!("container_spawned:{}", session_id),
+        }
+    }
+}
+
+/// A password for gateway accounts of users who never chose one (SCIM users
+/// sign in through their identity provider).
+pub fn random_password() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(PASSWORD_LEN).map(char::from).collect()
+}
+
+/// A row of `agent_commands`.
+#[derive(Debug, Clone, Serialize, JsonSchema, sqlx::FromRow)]
+pub struct AgentCommand {
+    pub id: Uuid,
+    pub idempotency_key: String,
+    pub event: String,
+    pub verb: String,
+    pub args: Value,
+    pub user_id: Option<Uuid>,
+    pub session_id: Option<Uuid>,
+    /// The backend agent's id, once it accepted the command
+    pub correlation_id: Option<String>,
+    /// pending, submitted, completed, failed or cancelled
+    pub status: String,
+    /// Status last reported by the backend agent
+    pub remote_status: Option<String>,
+    pub attempts: i32,
+    pub resubmissions: i32,
+    pub last_error: Option<String>,
+    pub result: Option<Value>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AgentCommand {
+    /// Key sent to the backend agent; each admin retry is a new submission.
+    fn remote_key(&self) -> String {
+        match self.resubmissions {
+            0 => self.idempotency_key.clone(),
+            n => format!("{}#{}", self.idempotency_key, n),
+        }
+    }
+
+    /// The row as the API shows it, without secrets in `args`.
+    fn redacted(mut self) -> Self {
+        if let Some(args) = self.args.as_object_mut() {
+            args.remove("password");
+        }
+        self
+    }
+}
+
+/// Commands matching `status`, `q` (verb, event or correlation id) and
+/// `from`/`to` (creation date), newest first.
+pub static AGENT_COMMAND_LIST: ListSpec = ListSpec {
+    select: "id, idempotency_key, event, verb, args - 'password' AS args, user_id, session_id, \
+             correlation_id, status, remote_status, attempts, resubmissions, last_error, result, \
+             next_attempt_at, created_by, created_at, updated_at",
+    from: "agent_commands",
+    id: "id",
+    sorts: &[
+        SortField { name: "created_at", expr: "created_at", sql_type: "timestamptz" },
+        SortField { name: "updated_at", expr: "updated_at", sql_type: "timestamptz" },
+        SortField { name: "attempts", expr: "attempts", sql_type: "integer" },
+    ],
+    default_sort: "-created_at",
+    date: Some("created_at"),
+    search: &["verb", "event", "correlation_id"],
+    status: Some(MatchFilter {
+        expr: "status",
+        values: &["pending", "submitted", "completed", "failed", "cancelled"],
+    }),
+    role: None,
+};
+
+/// Why a call to the backend agent did not succeed.
+#[derive(Debug)]
+pub enum AgentError {
+    /// The backend agent refused the command; sending it again will not help
+    Rejected(String),
+    /// Not delivered or no usable reply; worth another attempt
+    Unavailable(String),
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rejected(message) => write!(f, "rejected by the backend agent: {}", message),
+            Self::Unavailable(message) => write!(f, "backend agent unavailable: {}", message),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ServiceClaims<'a> {
+    sub: &'a str,
+    role: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct SubmitResponse {
+    correlation_id: String,
+}
+
+/// The part of the backend agent's `QueuedCommand` the bridge reads.
+#[derive(Deserialize)]
+struct QueuedCommand {
+    command: RemoteCommand,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoteCommand {
+    /// `CommandStatus` variant name (`Pending`, `Completed`, `Timeout`, ...)
+    pub status: String,
+    pub result: Option<Value>,
+    pub error_message: Option<String>,
+}
+
+/// Client for the backend agent's command API (`/api/v1/commands`).
+pub struct BackendAgentClient {
+    http: Client,
+    base_url: String,
+    encoding_key: Option<EncodingKey>,
+    targets: Vec<String>,
+}
+
+impl BackendAgentClient {
+    pub fn new(config: &AppConfig) -> Self {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .unwrap_or_default();
+        let encoding_key = (!config.backend_agent_jwt_secret.is_empty())
+            .then(|| EncodingKey::from_secret(config.backend_agent_jwt_secret.as_bytes()));
+
+        Self {
+            http,
+            base_url: config.backend_agent_url.trim_end_matches('/').to_string(),
+            encoding_key,
+            targets: config.backend_agent_targets.clone(),
+        }
+    }
+
+    /// False without `BACKEND_AGENT_JWT_SECRET`; commands then stay pending.
+    pub fn enabled(&self) -> bool {
+        self.encoding_key.is_some()
+    }
+
+    fn token(&self) -> Result<String, AgentError> {
+        let key = self
+            .encoding_key
+            .as_ref()
+            .ok_or_else(|| AgentError::Unavailable("BACKEND_AGENT_JWT_SECRET is not set".to_string()))?;
+        let now = Utc::now().timestamp();
+        let claims = ServiceClaims { sub: SERVICE_SUBJECT, role: SERVICE_ROLE, iat: now, exp: now + SERVICE_TOKEN_TTL_SECS };
+        encode(&Header::default(), &claims, key).map_err(|e| AgentError::Unavailable(e.to_string()))
+    }
+
+    /// Queues `verb` on the configured agents and returns its correlation id;
+    /// a repeated `idempotency_key` returns the id of the first submission.
+    pub async fn submit(&self, verb: &str, args: &Value, idempotency_key: &str) -> Result<String, AgentError> {
+        let response = self
+            .http
+            .post(format!("{}/api/v1/commands", self.base_url))
+            .bearer_auth(self.token()?)
+            .json(&json!({
+                "verb": verb,
+                "args": args,
+                "agent_targets": self.targets,
+                "idempotency_key": idempotency_key
+            }))
+            .send()
+            .await
+            .map_err(|e| AgentError::Unavailable(e.to_string()))?;
+
+        let response = check_status(response).await?;
+        let body: SubmitResponse = response.json().await.map_err(|e| AgentError::Unavailable(e.to_string()))?;
+        Ok(body.correlation_id)
+    }
+
+    /// The command's state on the backend agent; `None` if it does not know it.
+    pub async fn status(&self, correlation_id: &str) -> Result<Option<RemoteCommand>, AgentError> {
+        let response = self
+            .http
+            .get(format!("{}/api/v1/commands/{}", self.base_url, correlation_id))
+            .bearer_auth(self.token()?)
+            .send()
+            .await
+            .map_err(|e| AgentError::Unavailable(e.to_string()))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = check_status(response).await?;
+        let body: QueuedCommand = response.json().await.map_err(|e| AgentError::Unavailable(e.to_string()))?;
+        Ok(Some(body.command))
+    }
+}
+
+/// Maps non-2xx replies to errors: a 400 or 422 is the backend agent refusing
+/// the command, anything else (including a bad token) may clear up.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, AgentError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body: Value = response.json().await.unwrap_or(Value::Null);
+    let message = match body.get("error").and_then(Value::as_str) {
+        Some(error) => format!("{}: {}", status, error),
+        None => status.to_string(),
+    };
+    if status == StatusCode::BAD_REQUEST || status == StatusCode::UNPROCESSABLE_ENTITY {
+        Err(AgentError::Rejected(message))
+    } else {
+        Err(AgentError::Unavailable(message))
+    }
+}
+
+/// Maps the backend agent's `CommandStatus` onto `agent_commands.status`.
+pub fn local_status(remote_status: &str) -> &'static str {
+    match remote_status {
+        "Completed" => "completed",
+        "Failed" | "Timeout" => "failed",
+        "Cancelled" => "cancelled",
+        _ => "submitted",
+    }
+}
+
+/// Seconds to wait before attempt `attempts + 1`: the poll interval, doubled
+/// per failure, capped at 15 minutes.
+pub fn backoff_seconds(interval_seconds: u64, attempts: i32) -> f64 {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16);
+    (interval_seconds as f64 * 2f64.powi(exponent)).min(MAX_BACKOFF_SECS)
+}
+
+/// Records gateway commands and drives them through the backend agent.
+pub struct AgentBridge {
+    pool: PgPool,
+    client: BackendAgentClient,
+    max_attempts: i32,
+    interval_seconds: u64,
+}
+
+impl AgentBridge {
+    pub fn new(config: &AppConfig, pool: PgPool) -> Self {
+        Self {
+            pool,
+            client: BackendAgentClient::new(config),
+            max_attempts: config.agent_command_max_attempts,
+            interval_seconds: config.agent_command_interval_seconds,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.client.enabled()
+    }
+
+    /// Records `command` and makes a first attempt at submitting it. An event
+    /// that was already recorded returns the existing command.
+    pub async fn enqueue(&self, command: GatewayCommand, created_by: Option<Uuid>) -> Result<AgentCommand> {
+        let row = sqlx::query_as!(
+            AgentCommand,
+            r#"
+            INSERT INTO agent_commands (idempotency_key, event, verb, args, user_id, session_id, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (idempotency_key) DO UPDATE SET idempotency_key = EXCLUDED.idempotency_key
+            RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                      remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                      created_by, created_at, updated_at
+            "#,
+            command.idempotency_key(),
+            command.event(),
+            command.verb(),
+            command.args(),
+            command.user_id(),
+            command.session_id(),
+            created_by
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(self.dispatch_now(row).await?.redacted())
+    }
+
+    /// One command, as the API shows it.
+    pub async fn get(&self, id: Uuid) -> Result<Option<AgentCommand>> {
+        let row = sqlx::query_as!(
+            AgentCommand,
+            r#"
+            SELECT id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                   remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                   created_by, created_at, updated_at
+            FROM agent_commands WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(AgentCommand::redacted))
+    }
+
+    pub async fn list(&self, list: &List) -> Result<Page<AgentCommand>> {
+        Ok(list.fetch(&self.pool).await?)
+    }
+
+    /// Sends a failed or cancelled command again, as a new submission with a
+    /// fresh attempt budget. `None` if it is not in a retryable state.
+    pub async fn retry(&self, id: Uuid) -> Result<Option<AgentCommand>> {
+        let row = sqlx::query_as!(
+            AgentCommand,
+            r#"
+            UPDATE agent_commands
+            SET status = 'pending', correlation_id = NULL, remote_status = NULL, result = NULL,
+                last_error = NULL, attempts = 0, resubmissions = resubmissions + 1,
+                next_attempt_at = NOW(), updated_at = NOW()
+            WHERE id = $1 AND status IN ('failed', 'cancelled')
+            RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                      remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                      created_by, created_at, updated_at
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            Some(row) => Ok(Some(self.dispatch_now(row).await?.redacted())),
+            None => Ok(None),
+        }
+    }
+
+    /// Submits due pending commands and polls submitted ones. Returns how
+    /// many changed state.
+    pub async fn process(&self) -> Result<usize> {
+        if !self.enabled() {
+            return Ok(0);
+        }
+
+        let claimed = sqlx::query_as!(
+            AgentCommand,
+            r#"
+            UPDATE agent_commands
+            SET next_attempt_at = NOW() + make_interval(secs => $1)
+            WHERE id IN (
+                SELECT id FROM agent_commands
+                WHERE status IN ('pending', 'submitted') AND next_attempt_at <= NOW()
+                ORDER BY next_attempt_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                      remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                      created_by, created_at, updated_at
+            "#,
+            CLAIM_LEASE_SECS,
+            CLAIM_BATCH
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut changed = 0;
+        for command in claimed {
+            let before = command.status.clone();
+            let after = if command.status == "pending" {
+                self.submit(command).await?
+            } else {
+                self.poll(command).await?
+            };
+            if after.status != before {
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Submits a freshly recorded or reset row straight away, unless the
+    /// dispatcher has already claimed it.
+    async fn dispatch_now(&self, row: AgentCommand) -> Result<AgentCommand> {
+        if !self.enabled() || row.status != "pending" {
+            return Ok(row);
+        }
+        let claimed = sqlx::query_as!(
+            AgentCommand,
+            r#"
+            UPDATE agent_commands
+            SET next_attempt_at = NOW() + make_interval(secs => $2)
+            WHERE id = $1 AND status = 'pending' AND next_attempt_at <= NOW()
+            RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                      remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                      created_by, created_at, updated_at
+            "#,
+            row.id,
+            CLAIM_LEASE_SECS
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match claimed {
+            Some(command) => self.submit(command).await,
+            None => Ok(row),
+        }
+    }
+
+    async fn submit(&self, command: AgentCommand) -> Result<AgentCommand> {
+        match self.client.submit(&command.verb, &command.args, &command.remote_key()).await {
+            Ok(correlation_id) => Ok(sqlx::query_as!(
+                AgentCommand,
+                r#"
+                UPDATE agent_commands
+                SET status = 'submitted', correlation_id = $2, attempts = attempts + 1, last_error = NULL,
+                    next_attempt_at = NOW() + make_interval(secs => $3), updated_at = NOW()
+                WHERE id = $1
+                RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                          remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                          created_by, created_at, updated_at
+                "#,
+                command.id,
+                correlation_id,
+                self.interval_seconds as f64
+            )
+            .fetch_one(&self.pool)
+            .await?),
+            Err(e) => {
+                let attempts = command.attempts + 1;
+                let give_up = matches!(e, AgentError::Rejected(_)) || attempts >= self.max_attempts;
+                if give_up {
+                    error!("❌ Gateway command {} ({}) failed after {} attempt(s): {}", command.id, command.verb, attempts, e);
+                } else {
+                    warn!("⚠️ Gateway command {} ({}) not submitted, retrying: {}", command.id, command.verb, e);
+                }
+                Ok(sqlx::query_as!(
+                    AgentCommand,
+                    r#"
+                    UPDATE agent_commands
+                    SET attempts = attempts + 1, last_error = $2,
+                        status = CASE WHEN $3 THEN 'failed' ELSE status END,
+                        next_attempt_at = NOW() + make_interval(secs => $4), updated_at = NOW()
+                    WHERE id = $1
+                    RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                              remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                              created_by, created_at, updated_at
+                    "#,
+                    command.id,
+                    e.to_string(),
+                    give_up,
+                    backoff_seconds(self.interval_seconds, attempts)
+                )
+                .fetch_one(&self.pool)
+                .await?)
+            }
+        }
+    }
+
+    async fn poll(&self, command: AgentCommand) -> Result<AgentCommand> {
+        let Some(correlation_id) = command.correlation_id.as_deref() else {
+            return Ok(command);
+        };
+
+        let row = match self.client.status(correlation_id).await {
+            Ok(Some(remote)) => {
+                let status = local_status(&remote.status);
+                if status == "failed" {
+                    warn!("⚠️ Gateway command {} ({}) failed on the backend agent", command.id, command.verb);
+                }
+                sqlx::query_as!(
+                    AgentCommand,
+                    r#"
+                    UPDATE agent_commands
+                    SET status = $2::varchar, remote_status = $3, result = $4, last_error = COALESCE($5, last_error),
+                        args = CASE WHEN $2::varchar IN ('completed', 'cancelled') THEN args - 'password' ELSE args END,
+                        next_attempt_at = NOW() + make_interval(secs => $6), updated_at = NOW()
+                    WHERE id = $1
+                    RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                              remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                              created_by, created_at, updated_at
+                    "#,
+                    command.id,
+                    status,
+                    remote.status,
+                    remote.result,
+                    remote.error_message,
+                    self.interval_seconds as f64
+                )
+                .fetch_one(&self.pool)
+                .await?
+            }
+            // The backend agent lost it (e.g. its database was reset); send it again
+            Ok(None) => sqlx::query_as!(
+                AgentCommand,
+                r#"
+                UPDATE agent_commands
+                SET status = 'pending', correlation_id = NULL, remote_status = NULL,
+                    next_attempt_at = NOW(), updated_at = NOW()
+                WHERE id = $1
+                RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                          remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                          created_by, created_at, updated_at
+                "#,
+                command.id
+            )
+            .fetch_one(&self.pool)
+            .await?,
+            // Polling failures are not attempts; the next pass asks again
+            Err(e) => sqlx::query_as!(
+                AgentCommand,
+                r#"
+                UPDATE agent_commands
+                SET last_error = $2, next_attempt_at = NOW() + make_interval(secs => $3), updated_at = NOW()
+                WHERE id = $1
+                RETURNING id, idempotency_key, event, verb, args, user_id, session_id, correlation_id, status,
+                          remote_status, attempts, resubmissions, last_error, result, next_attempt_at,
+                          created_by, created_at, updated_at
+                "#,
+                command.id,
+                e.to_string(),
+                self.interval_seconds as f64
+            )
+            .fetch_one(&self.pool)
+            .await?,
+        };
+        Ok(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifecycle_events_map_onto_gateway_verbs() {
+        let user_id = Uuid::new_v4();
+        let session_id = Uuid::new_v4();
+        let username = "alice".to_string();
+
+        let delete = GatewayCommand::DeleteUser { user_id, username: username.clone() };
+        assert_eq!(delete.verb(), "delete_user");
+        assert_eq!(delete.args(), json!({ "username": "alice" }));
+
+        let disable = GatewayCommand::DisableUser { user_id, username: username.clone() };
+        assert_eq!(disable.verb(), "terminate_session");
+        assert_eq!(disable.args(), json!({ "username": "alice" }));
+
+        let terminate = GatewayCommand::TerminateSession { user_id: None, username, session_id };
+        assert_eq!(terminate.verb(), "terminate_session");
+        assert_eq!(terminate.session_id(), Some(session_id));
+        assert_eq!(terminate.args()["session_id"], json!(session_id));
+    }
+
+    #[test]
+    fn one_off_events_have_stable_keys() {
+        let user_id = Uuid::new_v4();
+        let delete = GatewayCommand::DeleteUser { user_id, username: "alice".into() };
+        assert_eq!(delete.idempotency_key(), delete.idempotency_key());
+        assert_eq!(delete.idempotency_key(), format!("user_deleted:{}", user_id));
+
+        let disable = GatewayCommand::DisableUser { user_id, username: "alice".into() };
+        assert_ne!(disable.idempotency_key(), disable.idempotency_key());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff_seconds(5, 1), 5.0);
+        assert_eq!(backoff_seconds(5, 2), 10.0);
+        assert_eq!(backoff_seconds(5, 4), 40.0);
+        assert_eq!(backoff_seconds(5, 30), MAX_BACKOFF_SECS);
+        assert_eq!(local_status("Timeout"), "failed");
+        assert_eq!(local_status("Executing"), "submitted");
+    }
+}
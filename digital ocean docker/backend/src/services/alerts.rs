@@ -0,0 +1,721 @@
+//! Security alert rules.
+//!
+//! The engine tails the audit log in `seq` order (and `verification_requests`
+//! for device locations) and evaluates the rules from `ALERT_RULES_FILE`
+//! against every new event. A rule that fires raises an alert in
+//! `security_alerts` keyed by the rule and what it grouped on (a user, an IP,
+//! an agent); while that alert is unresolved, further matches bump its
+//! `occurrences` and `last_seen_at` instead of raising another one. New alerts
+//! are pushed to the dashboard's `security_alerts` WebSocket channel.
+//!
+//! The engine's position lives in `alert_engine_state`, whose single row is
+//! locked while a replica evaluates, so each event is evaluated once no matter
+//! how many replicas run the engine.
+//!
+//! The rule file is re-read whenever its modification time changes, or on
+//! `POST /admin/alerts/rules/reload`; an invalid file keeps the previous rules.
+//! Rule kinds (`"kind"`), besides the common `id`, `title` (`{key}` is replaced
+//! with what the rule grouped on), `severity` and `enabled` fields:
+//! - `threshold`: `count` events of `event_type` for the same `group_by` key
+//!   (`user`, `ip` or `details.<field>`) within `window_seconds`, optionally
+//!   only those whose details fields have one of the listed values (`match`)
+//! - `impossible_travel`: consecutive device locations of a user further apart
+//!   than `min_distance_km` and faster than `max_speed_kmh` to travel between
+//! - `new_asn`: a session started from a network (`X-Geo-Asn`) the user has
+//!   never started one from before
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::database::listing::{List, ListSpec, MatchFilter, Page, SortField};
+use crate::services::audit::{AuditEventType, AuditFilter, AuditLog, AuditRecord};
+use crate::websocket::{channels, WebSocketEvent, WebSocketSessionManager};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+const LOCATION_BATCH: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// The `alert_severity` enum label.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "low" => Some(Self::Low),
+            "medium" => Some(Self::Medium),
+            "high" => Some(Self::High),
+            "critical" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+}
+
+/// What a threshold rule counts events per.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum GroupBy {
+    User,
+    Ip,
+    /// A top-level field of the event's details
+    Detail(String),
+}
+
+impl TryFrom<String> for GroupBy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "user" => Ok(Self::User),
+            "ip" => Ok(Self::Ip),
+            _ => match value.strip_prefix("details.") {
+                Some(field) if !field.is_empty() => Ok(Self::Detail(field.to_string())),
+                _ => Err(format!("group_by must be 'user', 'ip' or 'details.<field>', got '{}'", value)),
+            },
+        }
+    }
+}
+
+impl From<GroupBy> for String {
+    fn from(group_by: GroupBy) -> Self {
+        match group_by {
+            GroupBy::User => "user".to_string(),
+            GroupBy::Ip => "ip".to_string(),
+            GroupBy::Detail(field) => format!("details.{}", field),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RuleKind {
+    Threshold {
+        event_type: String,
+        #[serde(default, rename = "match", skip_serializing_if = "BTreeMap::is_empty")]
+        matches: BTreeMap<String, Vec<String>>,
+        group_by: GroupBy,
+        count: i64,
+        window_seconds: i64,
+    },
+    ImpossibleTravel {
+        max_speed_kmh: f64,
+        #[serde(default)]
+        min_distance_km: f64,
+    },
+    NewAsn {},
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub id: String,
+    pub title: String,
+    pub severity: Severity,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub kind: RuleKind,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleFile {
+    rules: Vec<AlertRule>,
+}
+
+/// Parses and validates a rule file.
+pub fn parse_rules(json: &str) -> Result<Vec<AlertRule>> {
+    let file: RuleFile = serde_json::from_str(json).context("Invalid alert rule file")?;
+
+    let mut ids = HashSet::new();
+    for rule in &file.rules {
+        if rule.id.is_empty() || !ids.insert(rule.id.as_str()) {
+            anyhow::bail!("Alert rule ids must be unique and non-empty ('{}')", rule.id);
+        }
+        match &rule.kind {
+            RuleKind::Threshold { event_type, count, window_seconds, .. } => {
+                if AuditEventType::parse(event_type).is_none() {
+                    anyhow::bail!("Rule '{}': unknown event_type '{}'", rule.id, event_type);
+                }
+                if *count < 1 || *window_seconds < 1 {
+                    anyhow::bail!("Rule '{}': count and window_seconds must be at least 1", rule.id);
+                }
+            }
+            RuleKind::ImpossibleTravel { max_speed_kmh, min_distance_km } => {
+                if *max_speed_kmh <= 0.0 || *min_distance_km < 0.0 {
+                    anyhow::bail!("Rule '{}': max_speed_kmh must be positive and min_distance_km not negative", rule.id);
+                }
+            }
+            RuleKind::NewAsn {} => {}
+        }
+    }
+
+    Ok(file.rules)
+}
+
+/// Great-circle distance in kilometres.
+pub fn haversine_km(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lng = (lng2 - lng1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// A detail value as text for grouping and matching (strings unquoted).
+fn detail_text(details: &serde_json::Value, field: &str) -> Option<String> {
+    match details.get(field)? {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+fn matches_details(matches: &BTreeMap<String, Vec<String>>, details: &serde_json::Value) -> bool {
+    matches.iter().all(|(field, allowed)| {
+        detail_text(details, field).is_some_and(|value| allowed.contains(&value))
+    })
+}
+
+/// The rules currently in force and where they came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadedRules {
+    pub file: String,
+    pub rules: Arc<Vec<AlertRule>>,
+    pub loaded_at: Option<DateTime<Utc>>,
+    /// Why the last reload failed, if it did; the previous rules stay in force
+    pub last_error: Option<String>,
+    #[serde(skip)]
+    modified: Option<SystemTime>,
+}
+
+/// An alert a rule wants raised.
+#[derive(Debug)]
+struct Finding<'r> {
+    rule: &'r AlertRule,
+    dedup_key: String,
+    /// Replaces `{key}` in the rule title
+    subject: String,
+    user_id: Option<Uuid>,
+    source_ip: Option<IpNetwork>,
+    description: String,
+    details: serde_json::Value,
+    seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SecurityAlertRecord {
+    pub id: Uuid,
+    pub rule_id: Option<String>,
+    pub severity: String,
+    /// `open`, `acknowledged` or `resolved`
+    pub status: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub user_id: Option<Uuid>,
+    pub source_ip: Option<String>,
+    pub occurrences: i32,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub acknowledged_by: Option<Uuid>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_by: Option<Uuid>,
+    pub resolution_note: Option<String>,
+}
+
+/// `/admin/alerts`: alerts matching `status` (`open`, `acknowledged` or
+/// `resolved`), `q` (title) and `from`/`to` (first seen), most recently seen
+/// first by default.
+pub static ALERT_LIST: ListSpec = ListSpec {
+    select: "id, rule_id, severity::text AS severity, \
+             CASE WHEN is_resolved THEN 'resolved' \
+                  WHEN acknowledged_at IS NOT NULL THEN 'acknowledged' ELSE 'open' END AS status, \
+             title, description, user_id, host(source_ip) AS source_ip, occurrences, details, \
+             created_at, last_seen_at, acknowledged_at, acknowledged_by, resolved_at, resolved_by, \
+             resolution_note",
+    from: "security_alerts",
+    id: "id",
+    sorts: &[
+        SortField { name: "last_seen_at", expr: "last_seen_at", sql_type: "timestamptz" },
+        SortField { name: "created_at", expr: "created_at", sql_type: "timestamptz" },
+        SortField { name: "severity", expr: "security_alerts.severity", sql_type: "alert_severity" },
+        SortField { name: "occurrences", expr: "occurrences", sql_type: "integer" },
+    ],
+    default_sort: "-last_seen_at",
+    date: Some("created_at"),
+    search: &["title"],
+    status: Some(MatchFilter {
+        expr: "CASE WHEN is_resolved THEN 'resolved' \
+               WHEN acknowledged_at IS NOT NULL THEN 'acknowledged' ELSE 'open' END",
+        values: &["open", "acknowledged", "resolved"],
+    }),
+    role: None,
+};
+
+pub struct AlertEngine {
+    pool: PgPool,
+    rules_file: PathBuf,
+    rules: RwLock<LoadedRules>,
+    session_manager: WebSocketSessionManager,
+}
+
+impl AlertEngine {
+    pub fn new(config: &AppConfig, pool: PgPool, session_manager: WebSocketSessionManager) -> Self {
+        Self {
+            pool,
+            rules_file: PathBuf::from(&config.alert_rules_file),
+            rules: RwLock::new(LoadedRules {
+                file: config.alert_rules_file.clone(),
+                rules: Arc::new(Vec::new()),
+                loaded_at: None,
+                last_error: None,
+                modified: None,
+            }),
+            session_manager,
+        }
+    }
+
+    pub fn rules(&self) -> LoadedRules {
+        self.rules.read().map(|rules| rules.clone()).unwrap_or_else(|e| e.into_inner().clone())
+    }
+
+    /// Re-reads the rule file. On error the current rules stay in force and
+    /// the error is kept for `/admin/alerts/rules`.
+    pub fn reload(&self) -> Result<usize> {
+        let modified = std::fs::metadata(&self.rules_file).and_then(|meta| meta.modified()).ok();
+        let loaded = std::fs::read_to_string(&self.rules_file)
+            .with_context(|| format!("Failed to read {}", self.rules_file.display()))
+            .and_then(|json| parse_rules(&json));
+
+        let mut rules = self.rules.write().unwrap_or_else(|e| e.into_inner());
+        rules.modified = modified;
+        match loaded {
+            Ok(loaded) => {
+                let count = loaded.len();
+                rules.rules = Arc::new(loaded);
+                rules.loaded_at = Some(Utc::now());
+                rules.last_error = None;
+                Ok(count)
+            }
+            Err(e) => {
+                rules.last_error = Some(format!("{:#}", e));
+                Err(e)
+            }
+        }
+    }
+
+    fn reload_if_changed(&self) {
+        let modified = std::fs::metadata(&self.rules_file).and_then(|meta| meta.modified()).ok();
+        let current = self.rules.read().map(|rules| rules.modified).unwrap_or(None);
+        if modified.is_none() || modified == current {
+            return;
+        }
+
+        match self.reload() {
+            Ok(count) => info!("🔁 Reloaded {} alert rule(s) from {}", count, self.rules_file.display()),
+            Err(e) => error!("❌ Keeping previous alert rules: {:#}", e),
+        }
+    }
+
+    /// Evaluates the rules against the events recorded since the last run.
+    /// Returns how many events were evaluated; 0 also when another replica
+    /// holds the engine state.
+    pub async fn evaluate(&self) -> Result<usize> {
+        self.reload_if_changed();
+        let rules = self.rules().rules;
+
+        let mut tx = self.pool.begin().await?;
+        let Some(state) = sqlx::query!(
+            "SELECT last_seq, last_verification_at FROM alert_engine_state FOR UPDATE SKIP LOCKED"
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(0);
+        };
+
+        let mut findings = Vec::new();
+
+        let events = AuditLog::events_after(&self.pool, &AuditFilter::default(), state.last_seq).await?;
+        for record in &events {
+            for rule in rules.iter().filter(|rule| rule.enabled) {
+                if let Some(finding) = self.evaluate_event(rule, record).await? {
+                    findings.push(finding);
+                }
+            }
+        }
+        let last_seq = events.last().map(|record| record.seq).unwrap_or(state.last_seq);
+
+        let travel_rules: Vec<&AlertRule> = rules
+            .iter()
+            .filter(|rule| rule.enabled && matches!(rule.kind, RuleKind::ImpossibleTravel { .. }))
+            .collect();
+        let (located, last_verification_at) = self.evaluate_locations(&travel_rules, state.last_verification_at).await?;
+        findings.extend(located);
+
+        let mut raised = Vec::new();
+        for finding in &findings {
+            if let Some(alert_id) = raise(&mut tx, finding).await? {
+                raised.push((alert_id, finding));
+            }
+        }
+
+        sqlx::query!(
+            "UPDATE alert_engine_state SET last_seq = $1, last_verification_at = $2, updated_at = NOW()",
+            last_seq,
+            last_verification_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        for (alert_id, finding) in raised {
+            let title = finding.rule.title.replace("{key}", &finding.subject);
+            info!("🚨 Security alert '{}' ({})", title, finding.rule.severity.as_str());
+            self.session_manager.broadcast_to_channel(
+                channels::SECURITY_ALERTS,
+                WebSocketEvent::alert_update(alert_id.to_string(), finding.rule.severity.as_str().to_string(), title),
+            );
+        }
+
+        Ok(events.len())
+    }
+
+    async fn evaluate_event<'r>(&self, rule: &'r AlertRule, record: &AuditRecord) -> Result<Option<Finding<'r>>> {
+        let source_ip = record.ip_address.as_deref().and_then(|ip| ip.parse::<IpNetwork>().ok());
+
+        match &rule.kind {
+            RuleKind::Threshold { event_type, matches, group_by, count, window_seconds } => {
+                if record.event_type != *event_type || !matches_details(matches, &record.details) {
+                    return Ok(None);
+                }
+                let key = match group_by {
+                    GroupBy::User => record.user_id.map(|id| id.to_string()),
+                    GroupBy::Ip => record.ip_address.clone(),
+                    GroupBy::Detail(field) => detail_text(&record.details, field),
+                };
+                let Some(key) = key else {
+                    return Ok(None);
+                };
+
+                let since = record.created_at - chrono::Duration::seconds(*window_seconds);
+                let (user_id, ip, detail) = match group_by {
+                    GroupBy::User => (record.user_id, None, None),
+                    GroupBy::Ip => (None, Some(key.as_str()), None),
+                    GroupBy::Detail(field) => (None, None, Some((field.as_str(), key.as_str()))),
+                };
+                let candidates = sqlx::query_scalar!(
+                    r#"
+                    SELECT details
+                    FROM audit_events
+                    WHERE event_type::text = $1 AND created_at > $2 AND seq <= $3
+                      AND ($4::uuid IS NULL OR user_id = $4)
+                      AND ($5::text IS NULL OR host(ip_address) = $5)
+                      AND ($6::text IS NULL OR details->>$6 = $7)
+                    "#,
+                    event_type,
+                    since,
+                    record.seq,
+                    user_id,
+                    ip,
+                    detail.map(|(field, _)| field),
+                    detail.map(|(_, value)| value),
+                )
+                .fetch_all(&self.pool)
+                .await?;
+                let seen = candidates.iter().filter(|details| matches_details(matches, details)).count() as i64;
+                if seen < *count {
+                    return Ok(None);
+                }
+
+                let subject = match group_by {
+                    GroupBy::User => detail_text(&record.details, "username").unwrap_or_else(|| key.clone()),
+                    _ => key.clone(),
+                };
+                Ok(Some(Finding {
+                    rule,
+                    dedup_key: key,
+                    subject,
+                    user_id: record.user_id.filter(|_| *group_by == GroupBy::User),
+                    source_ip,
+                    description: format!("{} {} event(s) within {}s", seen, event_type, window_seconds),
+                    details: serde_json::json!({
+                        "event_type": event_type,
+                        "count": seen,
+                        "window_seconds": window_seconds,
+                        "last_event_seq": record.seq
+                    }),
+                    seen_at: record.created_at,
+                }))
+            }
+            RuleKind::NewAsn {} => {
+                if record.event_type != AuditEventType::SessionStarted.as_str() {
+                    return Ok(None);
+                }
+                let (Some(user_id), Some(asn)) = (record.user_id, detail_text(&record.details, "asn")) else {
+                    return Ok(None);
+                };
+
+                let history = sqlx::query!(
+                    r#"
+                    SELECT COUNT(*) FILTER (WHERE details->>'asn' = $2) AS "same!",
+                           COUNT(*) FILTER (WHERE details->>'asn' IS NOT NULL) AS "known!"
+                    FROM audit_events
+                    WHERE event_type = 'session_started' AND user_id = $1 AND seq < $3
+                    "#,
+                    user_id,
+                    asn,
+                    record.seq
+                )
+                .fetch_one(&self.pool)
+                .await?;
+                // A user's first network is their baseline, not an anomaly
+                if history.known == 0 || history.same > 0 {
+                    return Ok(None);
+                }
+
+                Ok(Some(Finding {
+                    rule,
+                    dedup_key: format!("{}:{}", user_id, asn),
+                    subject: detail_text(&record.details, "username").unwrap_or_else(|| user_id.to_string()),
+                    user_id: Some(user_id),
+                    source_ip,
+                    description: format!("Session started from ASN {} not seen before for this user", asn),
+                    details: serde_json::json!({
+                        "asn": asn,
+                        "geo_country": record.details.get("geo_country"),
+                        "session_id": record.session_id,
+                        "event_seq": record.seq
+                    }),
+                    seen_at: record.created_at,
+                }))
+            }
+            RuleKind::ImpossibleTravel { .. } => Ok(None),
+        }
+    }
+
+    /// Compares each new device location with the user's previous one.
+    /// Returns the findings and the new location cursor.
+    async fn evaluate_locations<'r>(
+        &self,
+        rules: &[&'r AlertRule],
+        after: DateTime<Utc>,
+    ) -> Result<(Vec<Finding<'r>>, DateTime<Utc>)> {
+        let locations = sqlx::query!(
+            r#"
+            SELECT v.id, v.user_id, u.username, host(v.ip_address) AS ip_address,
+                   v.location_lat::float8 AS "lat!", v.location_lng::float8 AS "lng!",
+                   v.created_at AS "created_at!"
+            FROM verification_requests v
+            JOIN users u ON u.id = v.user_id
+            WHERE v.created_at > $1 AND v.location_lat IS NOT NULL AND v.location_lng IS NOT NULL
+            ORDER BY v.created_at
+            LIMIT $2
+            "#,
+            after,
+            LOCATION_BATCH
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let cursor = locations.last().map(|location| location.created_at).unwrap_or(after);
+
+        let mut findings = Vec::new();
+        if rules.is_empty() {
+            return Ok((findings, cursor));
+        }
+
+        for location in &locations {
+            let Some(previous) = sqlx::query!(
+                r#"
+                SELECT location_lat::float8 AS "lat!", location_lng::float8 AS "lng!", created_at AS "created_at!"
+                FROM verification_requests
+                WHERE user_id = $1 AND created_at < $2 AND id <> $3
+                  AND location_lat IS NOT NULL AND location_lng IS NOT NULL
+                ORDER BY created_at DESC
+                LIMIT 1
+                "#,
+                location.user_id,
+                location.created_at,
+                location.id
+            )
+            .fetch_optional(&self.pool)
+            .await?
+            else {
+                continue;
+            };
+
+            let distance_km = haversine_km(previous.lat, previous.lng, location.lat, location.lng);
+            let hours = (location.created_at - previous.created_at).num_seconds().max(1) as f64 / 3600.0;
+            let speed_kmh = distance_km / hours;
+
+            for rule in rules {
+                let RuleKind::ImpossibleTravel { max_speed_kmh, min_distance_km } = rule.kind else {
+                    continue;
+                };
+                if distance_km < min_distance_km || speed_kmh <= max_speed_kmh {
+                    continue;
+                }
+                findings.push(Finding {
+                    rule,
+                    dedup_key: location.user_id.to_string(),
+                    subject: location.username.clone(),
+                    user_id: Some(location.user_id),
+                    source_ip: location.ip_address.as_deref().and_then(|ip| ip.parse().ok()),
+                    description: format!(
+                        "{:.0} km between device locations in {:.1} h ({:.0} km/h)",
+                        distance_km, hours, speed_kmh
+                    ),
+                    details: serde_json::json!({
+                        "distance_km": distance_km.round(),
+                        "speed_kmh": speed_kmh.round(),
+                        "from": { "lat": previous.lat, "lng": previous.lng, "at": previous.created_at },
+                        "to": { "lat": location.lat, "lng": location.lng, "at": location.created_at },
+                        "verification_request_id": location.id
+                    }),
+                    seen_at: location.created_at,
+                });
+            }
+        }
+
+        Ok((findings, cursor))
+    }
+
+    pub async fn list(&self, list: &List) -> Result<Page<SecurityAlertRecord>> {
+        Ok(list.fetch(&self.pool).await?)
+    }
+
+    /// Marks an open alert as being looked at. Returns false if it does not
+    /// exist or is already acknowledged or resolved.
+    pub async fn acknowledge(&self, alert_id: Uuid, admin_id: Option<Uuid>) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE security_alerts
+            SET acknowledged_at = NOW(), acknowledged_by = $2
+            WHERE id = $1 AND NOT is_resolved AND acknowledged_at IS NULL
+            "#,
+            alert_id,
+            admin_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Closes an alert; the next match of its rule and key raises a new one.
+    /// Returns false if it does not exist or is already resolved.
+    pub async fn resolve(&self, alert_id: Uuid, admin_id: Option<Uuid>, note: Option<&str>) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE security_alerts
+            SET is_resolved = true, resolved_at = NOW(), resolved_by = $2, resolution_note = $3,
+                acknowledged_at = COALESCE(acknowledged_at, NOW()),
+                acknowledged_by = COALESCE(acknowledged_by, $2)
+            WHERE id = $1 AND NOT is_resolved
+            "#,
+            alert_id,
+            admin_id,
+            note
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Inserts the finding's alert, or bumps the unresolved one with the same rule
+/// and key. Returns the id if a new alert was raised.
+async fn raise(tx: &mut Transaction<'_, Postgres>, finding: &Finding<'_>) -> Result<Option<Uuid>> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO security_alerts
+            (severity, title, description, user_id, details, rule_id, dedup_key, source_ip, created_at, last_seen_at)
+        VALUES ($1::text::alert_severity, $2, $3, (SELECT id FROM users WHERE id = $4), $5, $6, $7, $8, $9, $9)
+        ON CONFLICT (rule_id, dedup_key) WHERE NOT is_resolved
+        DO UPDATE SET occurrences = security_alerts.occurrences + 1,
+                      last_seen_at = GREATEST(security_alerts.last_seen_at, EXCLUDED.last_seen_at),
+                      details = EXCLUDED.details
+        RETURNING id, (xmax = 0) AS "inserted!"
+        "#,
+        finding.rule.severity.as_str(),
+        finding.rule.title.replace("{key}", &finding.subject),
+        finding.description,
+        finding.user_id,
+        finding.details,
+        finding.rule.id,
+        finding.dedup_key,
+        finding.source_ip,
+        finding.seen_at
+    )
+    .fetch_one(&mut **tx)
+    .await
+    .map_err(|e| anyhow!("Failed to raise alert for rule '{}': {}", finding.rule.id, e))?;
+
+    Ok(row.inserted.then_some(row.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_validates_rules() {
+        let rules = parse_rules(include_str!("../../alert_rules.json")).unwrap();
+        assert!(rules.iter().any(|rule| matches!(rule.kind, RuleKind::ImpossibleTravel { .. })));
+        let otp = rules.iter().find(|rule| rule.id == "otp_brute_force").unwrap();
+        let RuleKind::Threshold { matches, group_by, .. } = &otp.kind else {
+            panic!("otp_brute_force should be a threshold rule");
+        };
+        assert_eq!(*group_by, GroupBy::User);
+        assert!(matches_details(matches, &serde_json::json!({ "reason": "invalid_otp" })));
+        assert!(!matches_details(matches, &serde_json::json!({ "reason": "invalid_password" })));
+
+        let unknown_event = r#"{"rules": [{"id": "x", "kind": "threshold", "title": "t", "severity": "low",
+            "event_type": "nope", "group_by": "ip", "count": 1, "window_seconds": 1}]}"#;
+        assert!(parse_rules(unknown_event).is_err());
+        let bad_group = r#"{"rules": [{"id": "x", "kind": "threshold", "title": "t", "severity": "low",
+            "event_type": "login_failed", "group_by": "device", "count": 1, "window_seconds": 1}]}"#;
+        assert!(parse_rules(bad_group).is_err());
+        let duplicate = r#"{"rules": [{"id": "x", "kind": "new_asn", "title": "t", "severity": "low"},
+            {"id": "x", "kind": "new_asn", "title": "t", "severity": "low"}]}"#;
+        assert!(parse_rules(duplicate).is_err());
+    }
+
+    #[test]
+    fn haversine_matches_known_distance() {
+        // Paris to New York is about 5837 km
+        let distance = haversine_km(48.8566, 2.3522, 40.7128, -74.0060);
+        assert!((distance - 5837.0).abs() < 10.0, "{}", distance);
+        assert_eq!(haversine_km(10.0, 10.0, 10.0, 10.0), 0.0);
+    }
+}
@@ -0,0 +1,697 @@
+//! Tamper-evident audit log.
+//!
+//! `audit_events` is append-only: a trigger (migration 013) numbers every
+//! inserted row and stores `hash = sha256(row || prev_hash)`, and updates,
+//! deletes and truncation are rejected. Every writer goes through
+//! [`AuditEvent::write`], directly or via [`AuditLog::record`].
+//!
+//! [`AuditLog::verify`] walks the chain and reports:
+//! - `gap`: sequence numbers are missing (rows deleted with the trigger disabled)
+//! - `hash_mismatch`: a row no longer matches its own hash (edited in place)
+//! - `broken_link`: a row's `prev_hash` is not its predecessor's hash (edited
+//!   and re-hashed, or rows inserted out of band)
+//! - `checkpoint_mismatch` / `truncated`: the chain disagrees with a signed
+//!   checkpoint, which catches a chain rewritten from scratch
+//! - `bad_signature` / `unknown_key`: a checkpoint was not signed by this
+//!   backend's key
+//!
+//! Checkpoints sign `viworks-audit-checkpoint:v1:<seq>:<hash>` with the
+//! Ed25519 key from `AUDIT_SIGNING_KEY`; keys and signatures are base64url
+//! without padding.
+
+use std::collections::BTreeMap;
+
+use actix_web::web::Bytes;
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use sqlx::types::ipnetwork::IpNetwork;
+use sqlx::{PgExecutor, PgPool};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::auth::{ClientContext, Claims};
+use crate::config::AppConfig;
+
+/// `prev_hash` of the first event in the chain.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+const VERIFY_BATCH: i64 = 1000;
+const EXPORT_BATCH: i64 = 500;
+const MAX_REPORTED_ISSUES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEventType {
+    LoginSuccess,
+    LoginFailed,
+    Logout,
+    UserCreated,
+    UserUpdated,
+    UserDeleted,
+    UserUnlocked,
+    UserPasswordReset,
+    PasswordResetRequested,
+    PasswordResetCompleted,
+    EmailVerificationSent,
+    EmailVerified,
+    PushApprovalSent,
+    PushApprovalApproved,
+    PushApprovalDenied,
+    PushApprovalTimedOut,
+    DeviceBound,
+    DeviceUnbound,
+    DeviceRejected,
+    DeviceKeyRotated,
+    DeviceKeyRevoked,
+    SessionStarted,
+    SessionTerminated,
+    PolicyUpdated,
+    PolicyDenied,
+    AdminAction,
+    ClientCreated,
+    ClientUpdated,
+    ClientDeleted,
+    ClientConnected,
+    ClientDisconnected,
+}
+
+impl AuditEventType {
+    pub const ALL: &'static [AuditEventType] = &[
+        Self::LoginSuccess,
+        Self::LoginFailed,
+        Self::Logout,
+        Self::UserCreated,
+        Self::UserUpdated,
+        Self::UserDeleted,
+        Self::UserUnlocked,
+        Self::UserPasswordReset,
+        Self::PasswordResetRequested,
+        Self::PasswordResetCompleted,
+        Self::EmailVerificationSent,
+        Self::EmailVerified,
+        Self::PushApprovalSent,
+        Self::PushApprovalApproved,
+        Self::PushApprovalDenied,
+        Self::PushApprovalTimedOut,
+        Self::DeviceBound,
+        Self::DeviceUnbound,
+        Self::DeviceRejected,
+        Self::DeviceKeyRotated,
+        Self::DeviceKeyRevoked,
+        Self::SessionStarted,
+        Self::SessionTerminated,
+        Self::PolicyUpdated,
+        Self::PolicyDenied,
+        Self::AdminAction,
+        Self::ClientCreated,
+        Self::ClientUpdated,
+        Self::ClientDeleted,
+        Self::ClientConnected,
+        Self::ClientDisconnected,
+    ];
+
+    /// The `audit_event_type` enum label.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::LoginSuccess => "login_success",
+            Self::LoginFailed => "login_failed",
+            Self::Logout => "logout",
+            Self::UserCreated => "user_created",
+            Self::UserUpdated => "user_updated",
+            Self::UserDeleted => "user_deleted",
+            Self::UserUnlocked => "user_unlocked",
+            Self::UserPasswordReset => "user_password_reset",
+            Self::PasswordResetRequested => "password_reset_requested",
+            Self::PasswordResetCompleted => "password_reset_completed",
+            Self::EmailVerificationSent => "email_verification_sent",
+            Self::EmailVerified => "email_verified",
+            Self::PushApprovalSent => "push_approval_sent",
+            Self::PushApprovalApproved => "push_approval_approved",
+            Self::PushApprovalDenied => "push_approval_denied",
+            Self::PushApprovalTimedOut => "push_approval_timed_out",
+            Self::DeviceBound => "device_bound",
+            Self::DeviceUnbound => "device_unbound",
+            Self::DeviceRejected => "device_rejected",
+            Self::DeviceKeyRotated => "device_key_rotated",
+            Self::DeviceKeyRevoked => "device_key_revoked",
+            Self::SessionStarted => "session_started",
+            Self::SessionTerminated => "session_terminated",
+            Self::PolicyUpdated => "policy_updated",
+            Self::PolicyDenied => "policy_denied",
+            Self::AdminAction => "admin_action",
+            Self::ClientCreated => "client_created",
+            Self::ClientUpdated => "client_updated",
+            Self::ClientDeleted => "client_deleted",
+            Self::ClientConnected => "client_connected",
+            Self::ClientDisconnected => "client_disconnected",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|event_type| event_type.as_str() == value)
+    }
+}
+
+/// One audit row to append. `user_id` is whoever acted, `target_user_id`
+/// whoever it was done to.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    event_type: AuditEventType,
+    user_id: Option<Uuid>,
+    session_id: Option<Uuid>,
+    target_user_id: Option<Uuid>,
+    details: serde_json::Value,
+    ip_address: Option<IpNetwork>,
+    user_agent: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(event_type: AuditEventType) -> Self {
+        Self {
+            event_type,
+            user_id: None,
+            session_id: None,
+            target_user_id: None,
+            details: serde_json::json!({}),
+            ip_address: None,
+            user_agent: None,
+        }
+    }
+
+    /// Attributes the event to an authenticated user and their session.
+    pub fn actor(mut self, claims: &Claims) -> Self {
+        self.user_id = claims.user_id();
+        self.session_id = claims.session_id();
+        self.details(serde_json::json!({ "actor": claims.username }))
+    }
+
+    pub fn user(mut self, user_id: Option<Uuid>) -> Self {
+        self.user_id = user_id;
+        self
+    }
+
+    pub fn target(mut self, user_id: Uuid) -> Self {
+        self.target_user_id = Some(user_id);
+        self
+    }
+
+    pub fn session(mut self, session_id: Uuid) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    /// Merges `details` into the event's details object.
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        match (self.details.as_object_mut(), details) {
+            (Some(current), serde_json::Value::Object(new)) => current.extend(new),
+            (_, details) => self.details = details,
+        }
+        self
+    }
+
+    pub fn context(mut self, context: &ClientContext) -> Self {
+        self.ip_address = context.ip_address;
+        self.user_agent = context.user_agent.clone();
+        self
+    }
+
+    /// Appends the event; pass a transaction to commit it with the change it records.
+    pub async fn write<'e, E: PgExecutor<'e>>(&self, executor: E) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_events (event_type, user_id, session_id, target_user_id, details, ip_address, user_agent)
+            VALUES ($1::text::audit_event_type, $2, $3, $4, $5, $6, $7)
+            "#,
+            self.event_type.as_str(),
+            self.user_id,
+            self.session_id,
+            self.target_user_id,
+            self.details,
+            self.ip_address,
+            self.user_agent
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub event_type: Option<AuditEventType>,
+    /// Matches events the user performed or was the target of
+    pub user_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub seq: i64,
+    pub id: Uuid,
+    pub event_type: String,
+    pub user_id: Option<Uuid>,
+    pub session_id: Option<Uuid>,
+    pub target_user_id: Option<Uuid>,
+    pub details: serde_json::Value,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Ndjson,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Ndjson => "application/x-ndjson",
+            Self::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Ndjson => "ndjson",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "seq,id,event_type,user_id,session_id,target_user_id,details,ip_address,user_agent,created_at,prev_hash,hash\n";
+
+impl AuditRecord {
+    fn to_csv_row(&self) -> String {
+        let fields = [
+            self.seq.to_string(),
+            self.id.to_string(),
+            self.event_type.clone(),
+            self.user_id.map(|id| id.to_string()).unwrap_or_default(),
+            self.session_id.map(|id| id.to_string()).unwrap_or_default(),
+            self.target_user_id.map(|id| id.to_string()).unwrap_or_default(),
+            self.details.to_string(),
+            self.ip_address.clone().unwrap_or_default(),
+            self.user_agent.clone().unwrap_or_default(),
+            self.created_at.to_rfc3339(),
+            self.prev_hash.clone(),
+            self.hash.clone(),
+        ];
+        let mut row = fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",");
+        row.push('\n');
+        row
+    }
+}
+
+/// Quotes a CSV field when needed and defuses values a spreadsheet would run as a formula.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditCheckpoint {
+    pub id: Uuid,
+    pub seq: i64,
+    pub hash: String,
+    pub signature: String,
+    pub public_key: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditIssue {
+    pub seq: i64,
+    pub kind: &'static str,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub valid: bool,
+    pub events_checked: i64,
+    pub checkpoints_checked: usize,
+    pub head_seq: Option<i64>,
+    pub head_hash: Option<String>,
+    /// Total issues found; at most `MAX_REPORTED_ISSUES` are listed
+    pub issue_count: usize,
+    pub issues: Vec<AuditIssue>,
+}
+
+impl VerifyReport {
+    fn push(&mut self, seq: i64, kind: &'static str, detail: String) {
+        self.issue_count += 1;
+        if self.issues.len() < MAX_REPORTED_ISSUES {
+            self.issues.push(AuditIssue { seq, kind, detail });
+        }
+    }
+}
+
+fn checkpoint_message(seq: i64, hash: &str) -> String {
+    format!("viworks-audit-checkpoint:v1:{}:{}", seq, hash)
+}
+
+pub struct AuditLog {
+    pool: PgPool,
+    signing_key: SigningKey,
+}
+
+impl AuditLog {
+    pub fn new(config: &AppConfig, pool: PgPool) -> Result<Self> {
+        let seed = config.encryption_key(&config.audit_signing_key, "audit-signing")?;
+
+        Ok(Self {
+            pool,
+            signing_key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    /// Public half of the checkpoint signing key.
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.signing_key.verifying_key().as_bytes())
+    }
+
+    /// Appends `event`, logging instead of failing: by the time a handler
+    /// records what it did, the change has already been made.
+    pub async fn record(&self, event: AuditEvent) {
+        if let Err(e) = event.write(&self.pool).await {
+            error!("❌ Failed to write {} audit event: {:#}", event.event_type.as_str(), e);
+        }
+    }
+
+    /// Newest events first, with the total number matching `filter`.
+    pub async fn query(&self, filter: &AuditFilter, page: i64, per_page: i64) -> Result<(Vec<AuditRecord>, i64)> {
+        let event_type = filter.event_type.map(AuditEventType::as_str);
+
+        let records = sqlx::query_as!(
+            AuditRecord,
+            r#"
+            SELECT seq, id, event_type::text AS "event_type!", user_id, session_id, target_user_id, details,
+                   host(ip_address) AS ip_address, user_agent, created_at AS "created_at!",
+                   prev_hash, hash
+            FROM audit_events
+            WHERE ($1::text IS NULL OR event_type::text = $1)
+              AND ($2::uuid IS NULL OR user_id = $2 OR target_user_id = $2)
+              AND ($3::timestamptz IS NULL OR created_at >= $3)
+              AND ($4::timestamptz IS NULL OR created_at < $4)
+            ORDER BY seq DESC
+            LIMIT $5 OFFSET $6
+            "#,
+            event_type,
+            filter.user_id,
+            filter.from,
+            filter.to,
+            per_page,
+            (page - 1) * per_page
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let total = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM audit_events
+            WHERE ($1::text IS NULL OR event_type::text = $1)
+              AND ($2::uuid IS NULL OR user_id = $2 OR target_user_id = $2)
+              AND ($3::timestamptz IS NULL OR created_at >= $3)
+              AND ($4::timestamptz IS NULL OR created_at < $4)
+            "#,
+            event_type,
+            filter.user_id,
+            filter.from,
+            filter.to
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((records, total))
+    }
+
+    /// Events matching `filter` oldest first, fetched in batches as the
+    /// response is written so large exports do not sit in memory.
+    pub fn export(
+        &self,
+        filter: AuditFilter,
+        format: ExportFormat,
+    ) -> impl Stream<Item = Result<Bytes, anyhow::Error>> + 'static {
+        let pool = self.pool.clone();
+        let header = match format {
+            ExportFormat::Csv => Some(Bytes::from_static(CSV_HEADER.as_bytes())),
+            ExportFormat::Ndjson => None,
+        };
+
+        let batches = stream::try_unfold(Some(0i64), move |after| {
+            let pool = pool.clone();
+            let filter = filter.clone();
+            async move {
+                let Some(after) = after else {
+                    return Ok(None);
+                };
+                let records = Self::events_after(&pool, &filter, after).await?;
+                let Some(last) = records.last().map(|record| record.seq) else {
+                    return Ok(None);
+                };
+                let next = (records.len() as i64 == EXPORT_BATCH).then_some(last);
+
+                let mut chunk = String::new();
+                for record in &records {
+                    match format {
+                        ExportFormat::Ndjson => {
+                            chunk.push_str(&serde_json::to_string(record)?);
+                            chunk.push('\n');
+                        }
+                        ExportFormat::Csv => chunk.push_str(&record.to_csv_row()),
+                    }
+                }
+                Ok(Some((Bytes::from(chunk), next)))
+            }
+        });
+
+        stream::iter(header.map(Ok::<_, anyhow::Error>)).chain(batches)
+    }
+
+    /// The next batch of events matching `filter` with `seq` above `after`, oldest first.
+    pub async fn events_after(pool: &PgPool, filter: &AuditFilter, after: i64) -> Result<Vec<AuditRecord>> {
+        let records = sqlx::query_as!(
+            AuditRecord,
+            r#"
+            SELECT seq, id, event_type::text AS "event_type!", user_id, session_id, target_user_id, details,
+                   host(ip_address) AS ip_address, user_agent, created_at AS "created_at!",
+                   prev_hash, hash
+            FROM audit_events
+            WHERE seq > $1
+              AND ($2::text IS NULL OR event_type::text = $2)
+              AND ($3::uuid IS NULL OR user_id = $3 OR target_user_id = $3)
+              AND ($4::timestamptz IS NULL OR created_at >= $4)
+              AND ($5::timestamptz IS NULL OR created_at < $5)
+            ORDER BY seq
+            LIMIT $6
+            "#,
+            after,
+            filter.event_type.map(AuditEventType::as_str),
+            filter.user_id,
+            filter.from,
+            filter.to,
+            EXPORT_BATCH
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(records)
+    }
+
+    /// Signs the current head of the chain. Returns `None` if the chain is
+    /// empty or its head already has a checkpoint.
+    pub async fn checkpoint(&self) -> Result<Option<AuditCheckpoint>> {
+        let Some(head) = sqlx::query!("SELECT seq, hash FROM audit_events ORDER BY seq DESC LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let signature = self.signing_key.sign(checkpoint_message(head.seq, &head.hash).as_bytes());
+
+        let checkpoint = sqlx::query_as!(
+            AuditCheckpoint,
+            r#"
+            INSERT INTO audit_checkpoints (seq, hash, signature, public_key)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (seq) DO NOTHING
+            RETURNING id, seq, hash, signature, public_key, created_at
+            "#,
+            head.seq,
+            head.hash,
+            URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+            self.public_key()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(checkpoint)
+    }
+
+    pub async fn list_checkpoints(&self) -> Result<Vec<AuditCheckpoint>> {
+        let checkpoints = sqlx::query_as!(
+            AuditCheckpoint,
+            "SELECT id, seq, hash, signature, public_key, created_at FROM audit_checkpoints ORDER BY seq"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(checkpoints)
+    }
+
+    /// Recomputes every hash in the chain and checks it against the signed checkpoints.
+    pub async fn verify(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport {
+            valid: true,
+            events_checked: 0,
+            checkpoints_checked: 0,
+            head_seq: None,
+            head_hash: None,
+            issue_count: 0,
+            issues: Vec::new(),
+        };
+
+        let public_key = self.public_key();
+        let mut checkpoints = BTreeMap::new();
+        for checkpoint in self.list_checkpoints().await? {
+            report.checkpoints_checked += 1;
+            if checkpoint.public_key != public_key {
+                report.push(checkpoint.seq, "unknown_key", "checkpoint was signed by a different key".to_string());
+            } else if let Err(reason) = verify_checkpoint_signature(&checkpoint) {
+                report.push(checkpoint.seq, "bad_signature", reason.to_string());
+            }
+            checkpoints.insert(checkpoint.seq, checkpoint.hash);
+        }
+
+        let mut expected_seq = 1;
+        let mut previous_hash = GENESIS_HASH.to_string();
+        loop {
+            let rows = sqlx::query!(
+                r#"
+                SELECT seq, prev_hash, hash,
+                       audit_event_hash(seq, prev_hash, id, event_type::text, user_id, session_id, target_user_id,
+                                        details, ip_address, user_agent, created_at) AS "computed!"
+                FROM audit_events
+                WHERE seq >= $1
+                ORDER BY seq
+                LIMIT $2
+                "#,
+                expected_seq,
+                VERIFY_BATCH
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            let Some(last) = rows.last().map(|row| row.seq) else {
+                break;
+            };
+
+            for row in &rows {
+                if row.seq != expected_seq {
+                    report.push(
+                        expected_seq,
+                        "gap",
+                        format!("events {} to {} are missing", expected_seq, row.seq - 1),
+                    );
+                } else if row.prev_hash != previous_hash {
+                    report.push(row.seq, "broken_link", "prev_hash does not match the previous event".to_string());
+                }
+                if row.computed != row.hash {
+                    report.push(row.seq, "hash_mismatch", "event contents do not match its hash".to_string());
+                }
+                if let Some(signed_hash) = checkpoints.get(&row.seq) {
+                    if *signed_hash != row.hash {
+                        report.push(row.seq, "checkpoint_mismatch", "event hash differs from the signed checkpoint".to_string());
+                    }
+                }
+                report.events_checked += 1;
+                expected_seq = row.seq + 1;
+                previous_hash = row.hash.clone();
+            }
+
+            report.head_seq = Some(last);
+            report.head_hash = Some(previous_hash.clone());
+            if (rows.len() as i64) < VERIFY_BATCH {
+                break;
+            }
+        }
+
+        for (&seq, _) in checkpoints.range(expected_seq..) {
+            report.push(
+                seq,
+                "truncated",
+                format!("checkpoint covers event {} but the chain ends at {}", seq, expected_seq - 1),
+            );
+        }
+
+        report.valid = report.issue_count == 0;
+        Ok(report)
+    }
+}
+
+fn verify_checkpoint_signature(checkpoint: &AuditCheckpoint) -> Result<()> {
+    let key_bytes: [u8; 32] = URL_SAFE_NO_PAD
+        .decode(&checkpoint.public_key)?
+        .try_into()
+        .map_err(|_| anyhow!("public key is not 32 bytes"))?;
+    let signature_bytes: [u8; 64] = URL_SAFE_NO_PAD
+        .decode(&checkpoint.signature)?
+        .try_into()
+        .map_err(|_| anyhow!("signature is not 64 bytes"))?;
+
+    VerifyingKey::from_bytes(&key_bytes)?
+        .verify(
+            checkpoint_message(checkpoint.seq, &checkpoint.hash).as_bytes(),
+            &Signature::from_bytes(&signature_bytes),
+        )
+        .map_err(|_| anyhow!("checkpoint signature does not verify"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field_quotes_and_defuses_formulas() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("=cmd()"), "'=cmd()");
+    }
+
+    #[test]
+    fn test_event_type_labels_round_trip() {
+        for event_type in AuditEventType::ALL {
+            assert_eq!(AuditEventType::parse(event_type.as_str()), Some(*event_type));
+        }
+        assert_eq!(AuditEventType::parse("nope"), None);
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::{Result, anyhow};
+use reqwest::Client;
+use std::time::Duration;
+use crate::config::AppConfig;
+
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// Client for the gateway agent's command API (`POST /api/v1/command`).
+/// Only for calls whose answer is needed straight away (per-session
+/// credentials); user lifecycle commands go through
+/// [`AgentBridge`](crate::services::agent_bridge::AgentBridge).
+pub struct GatewayClient {
+    http: Client,
+    base_url: String,
+}
+
+impl GatewayClient {
+    pub fn new(config: &AppConfig) -> Self {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            http,
+            base_url: config.gateway_agent_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Runs `command` on the gateway and returns its JSON reply; non-2xx replies are errors.
+    pub async fn command(&self, command: &str, params: serde_json::Value) -> Result<serde_json::Value> {
+        let response = self
+            .http
+            .post(format!("{}/api/v1/command", self.base_url))
+            .json(&serde_json::json!({
+                "command": command,
+                "params": params
+            }))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("Gateway agent returned {} for {}", status, command));
+        }
+
+        Ok(response.json().await.unwrap_or(serde_json::Value::Null))
+    }
+}
@@ -0,0 +1,203 @@
+//! Messages mailed to users, in every language we ship.
+//!
+//! A template is a subject and a plain-text body with `{name}` placeholders,
+//! filled in one pass so a value can never expand another placeholder. The
+//! language is the best match for the request's Accept-Language (`de-AT`
+//! matches `de`), or MAIL_DEFAULT_LANGUAGE when nothing matches.
+
+use crate::services::mailer::MailMessage;
+
+pub const LANGUAGES: &[&str] = &["en", "de", "fr"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailTemplate {
+    /// `{username}`, `{link}`, `{minutes}`
+    PasswordReset,
+    /// `{username}`, `{time}`, `{ip}`
+    PasswordChanged,
+    /// `{username}`, `{email}`, `{link}`, `{hours}`
+    EmailVerification,
+}
+
+fn text(template: MailTemplate, language: &str) -> (&'static str, &'static str) {
+    match (template, language) {
+        (MailTemplate::PasswordReset, "de") => (
+            "Ihr ViWorkS-Passwort zurücksetzen",
+            "Hallo {username},\n\n\
+             jemand (hoffentlich Sie) hat angefordert, das Passwort Ihres ViWorkS-Kontos zurückzusetzen. \
+             Öffnen Sie innerhalb von {minutes} Minuten diesen Link, um ein neues Passwort zu wählen:\n\n\
+             {link}\n\n\
+             Der Link funktioniert nur einmal. Falls Sie das nicht angefordert haben, ignorieren Sie diese \
+             Nachricht; Ihr Passwort bleibt unverändert.\n\n\
+             ViWorkS\n",
+        ),
+        (MailTemplate::PasswordReset, "fr") => (
+            "Réinitialisez votre mot de passe ViWorkS",
+            "Bonjour {username},\n\n\
+             quelqu'un (vous, nous l'espérons) a demandé à réinitialiser le mot de passe de votre compte \
+             ViWorkS. Pour choisir un nouveau mot de passe, ouvrez ce lien dans les {minutes} minutes :\n\n\
+             {link}\n\n\
+             Le lien n'est valable qu'une fois. Si vous n'êtes pas à l'origine de cette demande, ignorez \
+             ce message ; votre mot de passe reste inchangé.\n\n\
+             ViWorkS\n",
+        ),
+        (MailTemplate::PasswordReset, _) => (
+            "Reset your ViWorkS password",
+            "Hello {username},\n\n\
+             someone (hopefully you) asked to reset the password of your ViWorkS account. To choose a \
+             new password, open this link within {minutes} minutes:\n\n\
+             {link}\n\n\
+             The link works once. If you did not ask for this, ignore this message; your password stays \
+             as it is.\n\n\
+             ViWorkS\n",
+        ),
+        (MailTemplate::PasswordChanged, "de") => (
+            "Ihr ViWorkS-Passwort wurde geändert",
+            "Hallo {username},\n\n\
+             das Passwort Ihres ViWorkS-Kontos wurde am {time} von {ip} aus zurückgesetzt, und alle Ihre \
+             Sitzungen wurden abgemeldet.\n\n\
+             Falls Sie das nicht waren, wenden Sie sich umgehend an Ihren Administrator.\n\n\
+             ViWorkS\n",
+        ),
+        (MailTemplate::PasswordChanged, "fr") => (
+            "Votre mot de passe ViWorkS a été modifié",
+            "Bonjour {username},\n\n\
+             le mot de passe de votre compte ViWorkS a été réinitialisé le {time} depuis {ip}, et toutes \
+             vos sessions ont été fermées.\n\n\
+             Si ce n'était pas vous, contactez immédiatement votre administrateur.\n\n\
+             ViWorkS\n",
+        ),
+        (MailTemplate::PasswordChanged, _) => (
+            "Your ViWorkS password was changed",
+            "Hello {username},\n\n\
+             the password of your ViWorkS account was reset at {time} from {ip}, and all your sessions \
+             were signed out.\n\n\
+             If this was not you, contact your administrator right away.\n\n\
+             ViWorkS\n",
+        ),
+        (MailTemplate::EmailVerification, "de") => (
+            "Bestätigen Sie Ihre E-Mail-Adresse für ViWorkS",
+            "Hallo {username},\n\n\
+             bitte bestätigen Sie, dass {email} Ihre Adresse ist, indem Sie innerhalb von {hours} Stunden \
+             diesen Link öffnen:\n\n\
+             {link}\n\n\
+             Falls Sie diese Nachricht nicht erwartet haben, können Sie sie ignorieren.\n\n\
+             ViWorkS\n",
+        ),
+        (MailTemplate::EmailVerification, "fr") => (
+            "Confirmez votre adresse e-mail pour ViWorkS",
+            "Bonjour {username},\n\n\
+             veuillez confirmer que {email} est bien votre adresse en ouvrant ce lien dans les {hours} \
+             heures :\n\n\
+             {link}\n\n\
+             Si vous n'attendiez pas ce message, vous pouvez l'ignorer.\n\n\
+             ViWorkS\n",
+        ),
+        (MailTemplate::EmailVerification, _) => (
+            "Confirm your email address for ViWorkS",
+            "Hello {username},\n\n\
+             please confirm that {email} is your address by opening this link within {hours} hours:\n\n\
+             {link}\n\n\
+             If you did not expect this message, you can ignore it.\n\n\
+             ViWorkS\n",
+        ),
+    }
+}
+
+/// The shipped language that best matches an Accept-Language header.
+pub fn negotiate(accept_language: Option<&str>, default: &str) -> &'static str {
+    let mut ranges: Vec<(&str, f32)> = accept_language
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    ranges
+        .iter()
+        .filter_map(|(tag, _)| {
+            let primary = tag.split('-').next().unwrap_or_default();
+            LANGUAGES.iter().find(|language| language.eq_ignore_ascii_case(primary))
+        })
+        .chain(LANGUAGES.iter().filter(|language| **language == default))
+        .next()
+        .copied()
+        .unwrap_or(LANGUAGES[0])
+}
+
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder.find('}').and_then(|end| {
+            let name = &placeholder[1..end];
+            values.iter().find(|(key, _)| *key == name).map(|(_, value)| (*value, end + 1))
+        });
+        match value {
+            Some((value, consumed)) => {
+                filled.push_str(value);
+                rest = &placeholder[consumed..];
+            }
+            None => {
+                filled.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// `template` in `language` for `to`, with its placeholders filled in.
+pub fn render(template: MailTemplate, language: &str, to: &str, values: &[(&str, &str)]) -> MailMessage {
+    let (subject, body) = text(template, language);
+    MailMessage {
+        to: to.to_string(),
+        subject: fill(subject, values),
+        body: fill(body, values),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_negotiation() {
+        assert_eq!(negotiate(Some("de-AT,de;q=0.9,en;q=0.8"), "en"), "de");
+        assert_eq!(negotiate(Some("es, fr;q=0.5, en;q=0.7"), "en"), "en");
+        assert_eq!(negotiate(Some("en;q=0, FR-ca"), "de"), "fr");
+        assert_eq!(negotiate(Some("es"), "de"), "de");
+        assert_eq!(negotiate(None, "fr"), "fr");
+    }
+
+    #[test]
+    fn test_render_fills_placeholders_once() {
+        let message = render(
+            MailTemplate::PasswordReset,
+            "en",
+            "user@example.com",
+            &[("username", "{link}"), ("link", "https://panel/reset#token=abc"), ("minutes", "30")],
+        );
+        assert_eq!(message.subject, "Reset your ViWorkS password");
+        assert!(message.body.starts_with("Hello {link},\n"));
+        assert!(message.body.contains("\nhttps://panel/reset#token=abc\n"));
+        assert!(message.body.contains("within 30 minutes"));
+
+        for language in LANGUAGES {
+            for template in [MailTemplate::PasswordReset, MailTemplate::PasswordChanged, MailTemplate::EmailVerification] {
+                let message = render(template, language, "user@example.com", &[]);
+                assert!(message.body.contains("{username}"), "{:?} {}", template, language);
+            }
+        }
+    }
+}
@@ -1,106 +1,96 @@
-use std::sync::Arc;
-use tokio::time::{Duration, interval};
-use crate::websocket::{WebSocketEvent, WebSocketSessionManager};
+//! Shares admin dashboard events between backend replicas.
+//!
+//! Handlers publish with `WebSocketSessionManager::broadcast_to_channel`. The
+//! relay PUBLISHes each event on one Redis channel, and every replica
+//! (including the publishing one) SUBSCRIBEs to it and hands what it receives
+//! to its own WebSocket connections, so a dashboard sees every replica's events
+//! no matter which replica it is connected to.
+//!
+//! Delivery is best effort: events published while a replica's subscription is
+//! reconnecting are not replayed to it. If publishing fails the event is still
+//! delivered to the local connections.
 
-pub struct RealtimeService {
-    session_manager: Arc<WebSocketSessionManager>,
+use std::time::Duration;
+
+use anyhow::Result;
+use futures_util::StreamExt;
+use redis::aio::ConnectionManager;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tracing::{error, info, warn};
+
+use crate::websocket::{ChannelEvent, WebSocketSessionManager};
+
+/// Redis pub/sub channel the replicas share.
+pub const REDIS_CHANNEL: &str = "viworks:ws-events";
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(2);
+
+/// Creates the session manager handlers publish to and starts relaying its
+/// events through Redis. Must be called from within the Tokio runtime.
+pub fn start_event_relay(redis_url: &str, publisher: ConnectionManager) -> Result<WebSocketSessionManager> {
+    let client = redis::Client::open(redis_url)?;
+    let (sender, outbound) = mpsc::unbounded_channel();
+    let session_manager = WebSocketSessionManager::with_relay(sender);
+
+    tokio::spawn(publish(outbound, publisher, session_manager.clone()));
+    tokio::spawn(subscribe(client, session_manager.clone()));
+
+    Ok(session_manager)
 }
 
-impl RealtimeService {
-    pub fn new(session_manager: Arc<WebSocketSessionManager>) -> Self {
-        Self {
-            session_manager,
-        }
-    }
-    
-    pub async fn start_monitoring(&self) {
-        let session_manager = Arc::clone(&self.session_manager);
-        
-        // Start system metrics monitoring
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(30));
-            
-            loop {
-                interval.tick().await;
-                
-                // Simulate system metrics
-                let cpu_usage = rand::random::<f64>() * 100.0;
-                let memory_usage = 60.0 + (rand::random::<f64>() * 20.0);
-                let disk_usage = 45.0 + (rand::random::<f64>() * 15.0);
-                
-                let event = WebSocketEvent::system_metrics(cpu_usage, memory_usage, disk_usage);
-                session_manager.broadcast_to_channel("system_metrics", event);
-                
-                log::info!("Broadcasted system metrics: CPU={:.1}%, Memory={:.1}%, Disk={:.1}%", 
-                    cpu_usage, memory_usage, disk_usage);
-            }
-        });
-        
-        // Start security monitoring
-        let session_manager = Arc::clone(&self.session_manager);
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(60));
-            
-            loop {
-                interval.tick().await;
-                
-                // Simulate security events
-                let events = vec![
-                    ("failed_login", "Multiple failed login attempts detected"),
-                    ("suspicious_activity", "Unusual network activity detected"),
-                    ("system_alert", "High CPU usage detected"),
-                    ("connection_drop", "Client connection lost"),
-                ];
-                
-                let random_event = events[rand::random::<usize>() % events.len()];
-                let event = WebSocketEvent::security_event(
-                    random_event.0.to_string(),
-                    random_event.1.to_string(),
-                );
-                
-                session_manager.broadcast_to_channel("security_alerts", event);
-                log::info!("Broadcasted security event: {} - {}", random_event.0, random_event.1);
-            }
-        });
-        
-        // Start client status monitoring
-        let session_manager = Arc::clone(&self.session_manager);
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(45));
-            
-            loop {
-                interval.tick().await;
-                
-                // Simulate client status updates
-                let client_ids = vec!["client-001", "client-002", "client-003", "client-004"];
-                let statuses = vec!["connected", "disconnected", "connecting", "error"];
-                
-                let random_client = client_ids[rand::random::<usize>() % client_ids.len()];
-                let random_status = statuses[rand::random::<usize>() % statuses.len()];
-                
-                let event = WebSocketEvent::client_update(
-                    random_client.to_string(),
-                    random_status.to_string(),
-                );
-                
-                session_manager.broadcast_to_channel("client_updates", event);
-                log::info!("Broadcasted client update: {} - {}", random_client, random_status);
+async fn publish(
+    mut outbound: UnboundedReceiver<ChannelEvent>,
+    mut publisher: ConnectionManager,
+    session_manager: WebSocketSessionManager,
+) {
+    while let Some(event) = outbound.recv().await {
+        let payload = match serde_json::to_string(&event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("❌ Failed to serialize {} event: {}", event.channel, e);
+                continue;
             }
-        });
-    }
-    
-    pub fn broadcast_alert(&self, alert_id: String, severity: String, title: String) {
-        let event = WebSocketEvent::alert_update(alert_id, severity, title);
-        self.session_manager.broadcast_to_channel("security_alerts", event);
+        };
+
+        let published: redis::RedisResult<i64> = redis::cmd("PUBLISH")
+            .arg(REDIS_CHANNEL)
+            .arg(payload)
+            .query_async(&mut publisher)
+            .await;
+        if let Err(e) = published {
+            error!("❌ Failed to publish {} event to Redis, delivering locally: {}", event.channel, e);
+            session_manager.deliver(&event);
+        }
     }
-    
-    pub fn broadcast_client_update(&self, client_id: String, status: String) {
-        let event = WebSocketEvent::client_update(client_id, status);
-        self.session_manager.broadcast_to_channel("client_updates", event);
+}
+
+async fn subscribe(client: redis::Client, session_manager: WebSocketSessionManager) {
+    loop {
+        match relay_messages(&client, &session_manager).await {
+            Ok(()) => warn!("⚠️ Redis event subscription closed, resubscribing"),
+            Err(e) => error!("❌ Redis event subscription failed, resubscribing: {}", e),
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
     }
-    
-    pub fn broadcast_system_metrics(&self, cpu: f64, memory: f64, disk: f64) {
-        let event = WebSocketEvent::system_metrics(cpu, memory, disk);
-        self.session_manager.broadcast_to_channel("system_metrics", event);
+}
+
+/// Delivers events from the shared channel until the connection drops.
+async fn relay_messages(client: &redis::Client, session_manager: &WebSocketSessionManager) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(REDIS_CHANNEL).await?;
+    info!("📡 Relaying WebSocket events through Redis channel {}", REDIS_CHANNEL);
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let event = message
+            .get_payload::<String>()
+            .ok()
+            .and_then(|payload| serde_json::from_str::<ChannelEvent>(&payload).ok());
+        match event {
+            Some(event) => session_manager.deliver(&event),
+            None => warn!("⚠️ Ignoring malformed event on {}", REDIS_CHANNEL),
+        }
     }
+
+    Ok(())
 }
@@ -0,0 +1,1385 @@
+//! SCIM 2.0 provisioning (RFC 7643/7644) for the identity provider.
+//!
+//! SCIM Users are rows of `users`: `userName` is `username`, the primary
+//! email is `email`, `externalId` is `external_id` and `active` is
+//! `status = 'active'` (inactive users are `suspended`). SCIM Groups are the
+//! role names in the users' `roles` JSON; a group's id and displayName are the
+//! role, and its members are the users holding it. Groups cannot be created
+//! empty or deleted, since a role exists only while someone holds it or a
+//! policy grants access to it. `role` follows the first entry of `roles`.
+//!
+//! Filters are parsed into a [`Filter`] and translated to SQL for Users and
+//! evaluated in memory for Groups. Sorting, bulk operations and ETags are not
+//! supported, which `/ServiceProviderConfig` advertises.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::auth::password::{PasswordCheck, PasswordOwner, PasswordService};
+use crate::config::AppConfig;
+use crate::database::listing::escape_like;
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_RESPONSE_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const PATCH_OP_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+pub const SERVICE_PROVIDER_CONFIG_SCHEMA: &str =
+    "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+pub const RESOURCE_TYPE_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:ResourceType";
+
+/// Page size when the request gives no `count`.
+pub const DEFAULT_COUNT: i64 = 100;
+/// Largest page returned, whatever `count` asks for.
+pub const MAX_COUNT: i64 = 200;
+
+/// A failed SCIM request, rendered as a SCIM error response.
+#[derive(Debug)]
+pub enum ScimError {
+    /// 400 with the RFC 7644 `scimType` (`invalidFilter`, `invalidValue`, ...)
+    BadRequest(&'static str, String),
+    NotFound(String),
+    /// 409 `uniqueness`
+    Conflict(String),
+    Database(sqlx::Error),
+    /// 500 for anything else that failed underneath (hashing, the breached-password corpus)
+    Internal(anyhow::Error),
+}
+
+impl ScimError {
+    fn invalid_filter(detail: impl Into<String>) -> Self {
+        ScimError::BadRequest("invalidFilter", detail.into())
+    }
+
+    fn invalid_value(detail: impl Into<String>) -> Self {
+        ScimError::BadRequest("invalidValue", detail.into())
+    }
+
+    fn mutability(attribute: &str) -> Self {
+        ScimError::BadRequest("mutability", format!("{} cannot be modified", attribute))
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            ScimError::BadRequest(..) => 400,
+            ScimError::NotFound(_) => 404,
+            ScimError::Conflict(_) => 409,
+            ScimError::Database(_) | ScimError::Internal(_) => 500,
+        }
+    }
+
+    pub fn body(&self) -> Value {
+        match self {
+            ScimError::BadRequest(scim_type, detail) => error_body(400, Some(scim_type), detail),
+            ScimError::NotFound(detail) => error_body(404, None, detail),
+            ScimError::Conflict(detail) => error_body(409, Some("uniqueness"), detail),
+            ScimError::Database(_) => error_body(500, None, "Database error"),
+            ScimError::Internal(_) => error_body(500, None, "Internal error"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ScimError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+                ScimError::Conflict("userName, email or externalId is already in use".to_string())
+            }
+            _ => ScimError::Database(e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ScimError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<sqlx::Error>() {
+            Ok(e) => e.into(),
+            Err(e) => ScimError::Internal(e),
+        }
+    }
+}
+
+/// Body of a SCIM error response; `status` is a string, as RFC 7644 §3.12 has it.
+pub fn error_body(status: u16, scim_type: Option<&str>, detail: &str) -> Value {
+    let mut body = json!({
+        "schemas": [ERROR_SCHEMA],
+        "status": status.to_string(),
+        "detail": detail
+    });
+    if let Some(scim_type) = scim_type {
+        body["scimType"] = json!(scim_type);
+    }
+    body
+}
+
+// ---------------------------------------------------------------------------
+// Filters
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl CompareOp {
+    fn parse(word: &str) -> Option<Self> {
+        Some(match word.to_ascii_lowercase().as_str() {
+            "eq" => CompareOp::Eq,
+            "ne" => CompareOp::Ne,
+            "co" => CompareOp::Co,
+            "sw" => CompareOp::Sw,
+            "ew" => CompareOp::Ew,
+            "gt" => CompareOp::Gt,
+            "ge" => CompareOp::Ge,
+            "lt" => CompareOp::Lt,
+            "le" => CompareOp::Le,
+            _ => return None,
+        })
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            CompareOp::Eq => " = ",
+            CompareOp::Ne => " IS DISTINCT FROM ",
+            CompareOp::Co | CompareOp::Sw | CompareOp::Ew => " LIKE ",
+            CompareOp::Gt => " > ",
+            CompareOp::Ge => " >= ",
+            CompareOp::Lt => " < ",
+            CompareOp::Le => " <= ",
+        }
+    }
+}
+
+/// A parsed filter. Attribute paths are lowercased with any schema URN
+/// prefix removed (`urn:...:User:userName` is `username`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Compare(String, CompareOp, Value),
+    Present(String),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+    Literal(Value),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ScimError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut escaped = false;
+                let mut end = None;
+                for (i, c) in chars.by_ref() {
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        end = Some(i);
+                        break;
+                    }
+                }
+                let end = end.ok_or_else(|| ScimError::invalid_filter("Unterminated string in filter"))?;
+                let literal = serde_json::from_str(&input[start..=end])
+                    .map_err(|_| ScimError::invalid_filter("Invalid string in filter"))?;
+                tokens.push(Token::Literal(literal));
+            }
+            _ => {
+                let mut end = input.len();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        end = i;
+                        break;
+                    }
+                    chars.next();
+                }
+                tokens.push(Token::Word(input[start..end].to_string()));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Lowercases an attribute path and strips its schema URN, if any.
+pub fn normalize_path(path: &str) -> String {
+    let path = if path.len() > 4 && path[..4].eq_ignore_ascii_case("urn:") {
+        // The attribute follows the last ':' of the schema URN; sub-attributes
+        // are '.'-separated, so they stay attached
+        path.rsplit(':').next().unwrap_or(path)
+    } else {
+        path
+    };
+    path.to_ascii_lowercase()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), ScimError> {
+        if self.next() == Some(token) {
+            Ok(())
+        } else {
+            Err(ScimError::invalid_filter(format!("Expected {} in filter", what)))
+        }
+    }
+
+    fn or(&mut self) -> Result<Filter, ScimError> {
+        let mut left = self.and()?;
+        while self.at_keyword("or") {
+            self.pos += 1;
+            left = Filter::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Filter, ScimError> {
+        let mut left = self.factor()?;
+        while self.at_keyword("and") {
+            self.pos += 1;
+            left = Filter::And(Box::new(left), Box::new(self.factor()?));
+        }
+        Ok(left)
+    }
+
+    fn factor(&mut self) -> Result<Filter, ScimError> {
+        match self.next() {
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                self.expect(Token::Close, "')'")?;
+                Ok(filter)
+            }
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("not") => {
+                self.expect(Token::Open, "'(' after not")?;
+                let filter = self.or()?;
+                self.expect(Token::Close, "')'")?;
+                Ok(Filter::Not(Box::new(filter)))
+            }
+            Some(Token::Word(path)) => {
+                if path.contains('[') {
+                    return Err(ScimError::invalid_filter("Value filters are not supported in filter expressions"));
+                }
+                let operator = match self.next() {
+                    Some(Token::Word(operator)) => operator,
+                    _ => return Err(ScimError::invalid_filter(format!("Expected an operator after {}", path))),
+                };
+                if operator.eq_ignore_ascii_case("pr") {
+                    return Ok(Filter::Present(normalize_path(&path)));
+                }
+                let op = CompareOp::parse(&operator)
+                    .ok_or_else(|| ScimError::invalid_filter(format!("Unknown operator {}", operator)))?;
+                let value = match self.next() {
+                    Some(Token::Literal(value)) => value,
+                    Some(Token::Word(word)) => match serde_json::from_str::<Value>(&word) {
+                        Ok(value) if !value.is_string() && !value.is_object() && !value.is_array() => value,
+                        _ => return Err(ScimError::invalid_filter(format!("Invalid value {}", word))),
+                    },
+                    _ => return Err(ScimError::invalid_filter(format!("Expected a value after {}", operator))),
+                };
+                Ok(Filter::Compare(normalize_path(&path), op, value))
+            }
+            _ => Err(ScimError::invalid_filter("Expected a filter expression")),
+        }
+    }
+}
+
+/// Parses a `filter` query parameter.
+pub fn parse_filter(input: &str) -> Result<Filter, ScimError> {
+    let mut parser = Parser { tokens: tokenize(input)?, pos: 0 };
+    let filter = parser.or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(ScimError::invalid_filter("Unexpected trailing input in filter"));
+    }
+    Ok(filter)
+}
+
+impl Filter {
+    /// Evaluates the filter against a resource whose attributes `lookup`
+    /// returns (every value of a multi-valued attribute); `None` means the
+    /// attribute is not filterable. String comparisons ignore case.
+    pub fn matches(&self, lookup: &dyn Fn(&str) -> Option<Vec<String>>) -> Result<bool, ScimError> {
+        let values = |path: &str| {
+            lookup(path).ok_or_else(|| ScimError::invalid_filter(format!("Cannot filter on {}", path)))
+        };
+        Ok(match self {
+            Filter::And(a, b) => a.matches(lookup)? && b.matches(lookup)?,
+            Filter::Or(a, b) => a.matches(lookup)? || b.matches(lookup)?,
+            Filter::Not(filter) => !filter.matches(lookup)?,
+            Filter::Present(path) => values(path)?.iter().any(|value| !value.is_empty()),
+            Filter::Compare(path, op, value) => {
+                let expected = match value {
+                    Value::String(s) => s.to_lowercase(),
+                    other => other.to_string(),
+                };
+                let values = values(path)?;
+                let mut values = values.iter().map(|value| value.to_lowercase());
+                match op {
+                    CompareOp::Ne => values.all(|value| value != expected),
+                    _ => values.any(|value| match op {
+                        CompareOp::Eq => value == expected,
+                        CompareOp::Co => value.contains(&expected),
+                        CompareOp::Sw => value.starts_with(&expected),
+                        CompareOp::Ew => value.ends_with(&expected),
+                        CompareOp::Gt => value > expected,
+                        CompareOp::Ge => value >= expected,
+                        CompareOp::Lt => value < expected,
+                        CompareOp::Le => value <= expected,
+                        CompareOp::Ne => unreachable!(),
+                    }),
+                }
+            }
+        })
+    }
+}
+
+/// What a filterable User attribute is stored as.
+enum UserColumn {
+    Text { expr: &'static str, case_exact: bool },
+    Bool(&'static str),
+    Time(&'static str),
+    Roles,
+}
+
+fn user_column(path: &str) -> Option<UserColumn> {
+    Some(match path {
+        "id" => UserColumn::Text { expr: "id::text", case_exact: true },
+        "username" | "displayname" => UserColumn::Text { expr: "username", case_exact: false },
+        "externalid" => UserColumn::Text { expr: "external_id", case_exact: true },
+        "emails" | "emails.value" => UserColumn::Text { expr: "email", case_exact: false },
+        "active" => UserColumn::Bool("(status = 'active')"),
+        "meta.created" => UserColumn::Time("created_at"),
+        "meta.lastmodified" => UserColumn::Time("updated_at"),
+        "groups" | "groups.value" | "groups.display" => UserColumn::Roles,
+        _ => return None,
+    })
+}
+
+/// Appends the SQL condition for a Users filter.
+pub fn push_user_filter(filter: &Filter, query: &mut QueryBuilder<'_, Postgres>) -> Result<(), ScimError> {
+    match filter {
+        Filter::And(a, b) | Filter::Or(a, b) => {
+            query.push("(");
+            push_user_filter(a, query)?;
+            query.push(if matches!(filter, Filter::And(..)) { " AND " } else { " OR " });
+            push_user_filter(b, query)?;
+            query.push(")");
+        }
+        Filter::Not(filter) => {
+            query.push("NOT (");
+            push_user_filter(filter, query)?;
+            query.push(")");
+        }
+        Filter::Present(path) => {
+            let column = user_column(path)
+                .ok_or_else(|| ScimError::invalid_filter(format!("Cannot filter on {}", path)))?;
+            query.push(match column {
+                UserColumn::Text { expr, .. } => format!("({} IS NOT NULL AND {} <> '')", expr, expr),
+                UserColumn::Bool(_) => "TRUE".to_string(),
+                UserColumn::Time(expr) => format!("{} IS NOT NULL", expr),
+                UserColumn::Roles => "jsonb_array_length(roles) > 0".to_string(),
+            });
+        }
+        Filter::Compare(path, op, value) => {
+            let column = user_column(path)
+                .ok_or_else(|| ScimError::invalid_filter(format!("Cannot filter on {}", path)))?;
+            push_user_comparison(path, column, *op, value, query)?;
+        }
+    }
+    Ok(())
+}
+
+fn push_user_comparison(
+    path: &str,
+    column: UserColumn,
+    op: CompareOp,
+    value: &Value,
+    query: &mut QueryBuilder<'_, Postgres>,
+) -> Result<(), ScimError> {
+    let unsupported = || ScimError::invalid_filter(format!("Operator {:?} is not supported for {}", op, path));
+    match column {
+        UserColumn::Text { expr, case_exact } => match value {
+            Value::Null => match op {
+                CompareOp::Eq => {
+                    query.push(format!("{} IS NULL", expr));
+                }
+                CompareOp::Ne => {
+                    query.push(format!("{} IS NOT NULL", expr));
+                }
+                _ => return Err(unsupported()),
+            },
+            Value::String(s) => {
+                let (lhs, s) = if case_exact {
+                    (expr.to_string(), s.clone())
+                } else {
+                    (format!("lower({})", expr), s.to_lowercase())
+                };
+                let operand = match op {
+                    CompareOp::Co => format!("%{}%", escape_like(&s)),
+                    CompareOp::Sw => format!("{}%", escape_like(&s)),
+                    CompareOp::Ew => format!("%{}", escape_like(&s)),
+                    _ => s,
+                };
+                query.push(lhs).push(op.sql()).push_bind(operand);
+            }
+            _ => return Err(ScimError::invalid_filter(format!("{} is compared with a string", path))),
+        },
+        UserColumn::Bool(expr) => {
+            let value = value
+                .as_bool()
+                .ok_or_else(|| ScimError::invalid_filter(format!("{} is compared with true or false", path)))?;
+            match op {
+                CompareOp::Eq | CompareOp::Ne => {
+                    query.push(expr).push(op.sql()).push_bind(value);
+                }
+                _ => return Err(unsupported()),
+            }
+        }
+        UserColumn::Time(expr) => {
+            let value = value
+                .as_str()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .ok_or_else(|| ScimError::invalid_filter(format!("{} is compared with an RFC 3339 timestamp", path)))?;
+            if matches!(op, CompareOp::Co | CompareOp::Sw | CompareOp::Ew) {
+                return Err(unsupported());
+            }
+            query.push(expr).push(op.sql()).push_bind(value.with_timezone(&Utc));
+        }
+        UserColumn::Roles => {
+            let role = value
+                .as_str()
+                .ok_or_else(|| ScimError::invalid_filter(format!("{} is compared with a string", path)))?;
+            match op {
+                CompareOp::Eq => query.push("roles @> jsonb_build_array("),
+                CompareOp::Ne => query.push("NOT roles @> jsonb_build_array("),
+                _ => return Err(unsupported()),
+            };
+            query.push_bind(role.to_string()).push("::text)");
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Resources
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ScimUserRow {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub status: String,
+    pub roles: Value,
+    pub external_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+const USER_COLUMNS: &str =
+    "id, username, email, status::text AS status, roles, external_id, created_at, updated_at";
+
+impl ScimUserRow {
+    pub fn is_active(&self) -> bool {
+        self.status == "active"
+    }
+
+    pub fn roles(&self) -> Vec<String> {
+        role_names(&self.roles)
+    }
+
+    /// The SCIM User resource; `base_url` is the `/scim/v2` URL.
+    pub fn to_resource(&self, base_url: &str) -> Value {
+        let groups: Vec<Value> = self
+            .roles()
+            .into_iter()
+            .map(|role| {
+                json!({
+                    "value": role,
+                    "display": role,
+                    "$ref": format!("{}/Groups/{}", base_url, role),
+                    "type": "direct"
+                })
+            })
+            .collect();
+        json!({
+            "schemas": [USER_SCHEMA],
+            "id": self.id,
+            "externalId": self.external_id,
+            "userName": self.username,
+            "displayName": self.username,
+            "active": self.is_active(),
+            "emails": [{ "value": self.email, "type": "work", "primary": true }],
+            "groups": groups,
+            "meta": {
+                "resourceType": "User",
+                "created": self.created_at,
+                "lastModified": self.updated_at,
+                "location": format!("{}/Users/{}", base_url, self.id)
+            }
+        })
+    }
+}
+
+fn role_names(roles: &Value) -> Vec<String> {
+    roles
+        .as_array()
+        .map(|roles| roles.iter().filter_map(|role| role.as_str().map(str::to_string)).collect())
+        .unwrap_or_default()
+}
+
+/// A role and the users holding it.
+#[derive(Debug, Clone)]
+pub struct ScimGroup {
+    pub name: String,
+    pub members: Vec<(Uuid, String)>,
+}
+
+impl ScimGroup {
+    pub fn to_resource(&self, base_url: &str, include_members: bool) -> Value {
+        let mut resource = json!({
+            "schemas": [GROUP_SCHEMA],
+            "id": self.name,
+            "displayName": self.name,
+            "meta": {
+                "resourceType": "Group",
+                "location": format!("{}/Groups/{}", base_url, self.name)
+            }
+        });
+        if include_members {
+            resource["members"] = self
+                .members
+                .iter()
+                .map(|(id, username)| {
+                    json!({
+                        "value": id,
+                        "display": username,
+                        "$ref": format!("{}/Users/{}", base_url, id),
+                        "type": "User"
+                    })
+                })
+                .collect();
+        }
+        resource
+    }
+
+    fn attribute(&self, path: &str) -> Option<Vec<String>> {
+        Some(match path {
+            "id" | "displayname" => vec![self.name.clone()],
+            "members" | "members.value" => self.members.iter().map(|(id, _)| id.to_string()).collect(),
+            "members.display" => self.members.iter().map(|(_, username)| username.clone()).collect(),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+/// The attributes of a POSTed or PUT User that ViWorkS stores.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUserInput {
+    pub user_name: String,
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub emails: Vec<ScimEmail>,
+    pub active: Option<bool>,
+    pub password: Option<String>,
+}
+
+fn primary_email(emails: &[ScimEmail]) -> Option<&str> {
+    emails
+        .iter()
+        .find(|email| email.primary)
+        .or_else(|| emails.first())
+        .map(|email| email.value.as_str())
+}
+
+/// Attributes of a User to change; `None` keeps the current value.
+#[derive(Debug, Default, PartialEq)]
+pub struct UserChanges {
+    pub user_name: Option<String>,
+    pub email: Option<String>,
+    pub active: Option<bool>,
+    pub external_id: Option<Option<String>>,
+    pub password: Option<String>,
+}
+
+impl UserChanges {
+    /// A PUT replaces every stored attribute.
+    pub fn replace(input: ScimUserInput) -> Result<Self, ScimError> {
+        let email = primary_email(&input.emails)
+            .ok_or_else(|| ScimError::invalid_value("An email address is required"))?
+            .to_string();
+        Ok(Self {
+            user_name: Some(input.user_name),
+            email: Some(email),
+            active: Some(input.active.unwrap_or(true)),
+            external_id: Some(input.external_id),
+            password: input.password,
+        })
+    }
+
+    /// Names of the changed attributes, for the audit log.
+    pub fn changed(&self) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.user_name.is_some() {
+            changed.push("userName");
+        }
+        if self.email.is_some() {
+            changed.push("emails");
+        }
+        if self.active.is_some() {
+            changed.push("active");
+        }
+        if self.external_id.is_some() {
+            changed.push("externalId");
+        }
+        if self.password.is_some() {
+            changed.push("password");
+        }
+        changed
+    }
+}
+
+// ---------------------------------------------------------------------------
+// PATCH
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatchKind {
+    Add,
+    Replace,
+    Remove,
+}
+
+impl PatchOperation {
+    fn kind(&self) -> Result<PatchKind, ScimError> {
+        match self.op.to_ascii_lowercase().as_str() {
+            "add" => Ok(PatchKind::Add),
+            "replace" => Ok(PatchKind::Replace),
+            "remove" => Ok(PatchKind::Remove),
+            _ => Err(ScimError::invalid_value(format!("Unknown PATCH op {}", self.op))),
+        }
+    }
+}
+
+/// A PATCH path: `attribute[filter].sub`, with the filter and sub-attribute optional.
+#[derive(Debug, PartialEq)]
+pub struct PatchPath {
+    pub attribute: String,
+    pub filter: Option<Filter>,
+    pub sub_attribute: Option<String>,
+}
+
+pub fn parse_patch_path(path: &str) -> Result<PatchPath, ScimError> {
+    let invalid = || ScimError::BadRequest("invalidPath", format!("Invalid path {}", path));
+    let Some(open) = path.find('[') else {
+        return Ok(PatchPath { attribute: normalize_path(path), filter: None, sub_attribute: None });
+    };
+    let close = path.rfind(']').filter(|&close| close > open).ok_or_else(invalid)?;
+    let sub_attribute = match &path[close + 1..] {
+        "" => None,
+        rest => Some(rest.strip_prefix('.').ok_or_else(invalid)?.to_ascii_lowercase()),
+    };
+    Ok(PatchPath {
+        attribute: normalize_path(&path[..open]),
+        filter: Some(parse_filter(&path[open + 1..close])?),
+        sub_attribute,
+    })
+}
+
+/// Booleans as some identity providers send them (`"False"`).
+fn patch_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(b) => Some(*b),
+        Value::String(s) if s.eq_ignore_ascii_case("true") => Some(true),
+        Value::String(s) if s.eq_ignore_ascii_case("false") => Some(false),
+        _ => None,
+    }
+}
+
+fn patch_string(attribute: &str, value: &Value) -> Result<String, ScimError> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| ScimError::invalid_value(format!("{} must be a string", attribute)))
+}
+
+fn set_user_attribute(changes: &mut UserChanges, attribute: &str, value: &Value) -> Result<(), ScimError> {
+    match attribute {
+        "username" => changes.user_name = Some(patch_string("userName", value)?),
+        "externalid" => {
+            changes.external_id = Some(match value {
+                Value::Null => None,
+                value => Some(patch_string("externalId", value)?),
+            })
+        }
+        "active" => {
+            changes.active =
+                Some(patch_bool(value).ok_or_else(|| ScimError::invalid_value("active must be a boolean"))?)
+        }
+        "password" => changes.password = Some(patch_string("password", value)?),
+        "emails" => {
+            let emails: Vec<ScimEmail> = serde_json::from_value(value.clone())
+                .map_err(|_| ScimError::invalid_value("emails must be a list of email objects"))?;
+            if let Some(email) = primary_email(&emails) {
+                changes.email = Some(email.to_string());
+            }
+        }
+        "emails.value" => changes.email = Some(patch_string("emails.value", value)?),
+        "id" | "meta" | "groups" => return Err(ScimError::mutability(attribute)),
+        attribute if attribute.starts_with("meta.") || attribute.starts_with("groups.") => {
+            return Err(ScimError::mutability(attribute))
+        }
+        // Attributes ViWorkS does not store (name, title, addresses, ...)
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The changes a User PATCH makes.
+pub fn user_patch(operations: &[PatchOperation]) -> Result<UserChanges, ScimError> {
+    let mut changes = UserChanges::default();
+    for operation in operations {
+        let kind = operation.kind()?;
+        let path = operation.path.as_deref().map(parse_patch_path).transpose()?;
+        let attribute = path.as_ref().map(|path| match &path.sub_attribute {
+            Some(sub) => format!("{}.{}", path.attribute, sub),
+            None => path.attribute.clone(),
+        });
+        match (kind, attribute) {
+            (PatchKind::Remove, None) => {
+                return Err(ScimError::BadRequest("noTarget", "remove requires a path".to_string()))
+            }
+            (PatchKind::Remove, Some(attribute)) => match attribute.as_str() {
+                "externalid" => changes.external_id = Some(None),
+                "username" | "emails" | "emails.value" | "active" | "id" | "meta" | "groups" => {
+                    return Err(ScimError::mutability(&attribute))
+                }
+                _ => {}
+            },
+            (_, Some(attribute)) => {
+                let value = operation.value.as_ref().unwrap_or(&Value::Null);
+                set_user_attribute(&mut changes, &attribute, value)?;
+            }
+            (_, None) => {
+                let Some(Value::Object(attributes)) = &operation.value else {
+                    return Err(ScimError::invalid_value("An operation without a path takes an object value"));
+                };
+                for (name, value) in attributes {
+                    set_user_attribute(&mut changes, &normalize_path(name), value)?;
+                }
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// A change to a Group's members.
+#[derive(Debug, PartialEq)]
+pub enum MemberChange {
+    Add(Vec<Uuid>),
+    Remove(Vec<Uuid>),
+    RemoveAll,
+    Replace(Vec<Uuid>),
+}
+
+fn member_ids(value: Option<&Value>) -> Result<Vec<Uuid>, ScimError> {
+    let invalid = || ScimError::invalid_value("members must be a list of {\"value\": \"<user id>\"}");
+    let members = match value {
+        Some(Value::Array(members)) => members.clone(),
+        Some(member @ Value::Object(_)) => vec![member.clone()],
+        _ => return Err(invalid()),
+    };
+    members
+        .iter()
+        .map(|member| {
+            member
+                .get("value")
+                .and_then(Value::as_str)
+                .and_then(|id| Uuid::parse_str(id).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+/// The user ids a `members[value eq "..." or ...]` filter selects.
+fn filtered_member_ids(filter: &Filter) -> Result<Vec<Uuid>, ScimError> {
+    match filter {
+        Filter::Or(a, b) => Ok([filtered_member_ids(a)?, filtered_member_ids(b)?].concat()),
+        Filter::Compare(path, CompareOp::Eq, Value::String(id)) if path == "value" => Uuid::parse_str(id)
+            .map(|id| vec![id])
+            .map_err(|_| ScimError::invalid_value(format!("{} is not a user id", id))),
+        _ => Err(ScimError::invalid_filter("Members are selected with value eq \"<user id>\"")),
+    }
+}
+
+/// The member changes a Group PATCH makes.
+pub fn group_patch(operations: &[PatchOperation]) -> Result<Vec<MemberChange>, ScimError> {
+    let mut changes = Vec::new();
+    for operation in operations {
+        let kind = operation.kind()?;
+        let members_change = |value: Option<&Value>| -> Result<MemberChange, ScimError> {
+            let ids = member_ids(value)?;
+            Ok(if kind == PatchKind::Replace { MemberChange::Replace(ids) } else { MemberChange::Add(ids) })
+        };
+        match operation.path.as_deref().map(parse_patch_path).transpose()? {
+            None if kind == PatchKind::Remove => {
+                return Err(ScimError::BadRequest("noTarget", "remove requires a path".to_string()))
+            }
+            None => {
+                let Some(Value::Object(attributes)) = &operation.value else {
+                    return Err(ScimError::invalid_value("An operation without a path takes an object value"));
+                };
+                for (name, value) in attributes {
+                    match normalize_path(name).as_str() {
+                        "members" => changes.push(members_change(Some(value))?),
+                        attribute => return Err(ScimError::mutability(attribute)),
+                    }
+                }
+            }
+            Some(path) if path.attribute == "members" => match (kind, path.filter) {
+                (PatchKind::Remove, Some(filter)) => changes.push(MemberChange::Remove(filtered_member_ids(&filter)?)),
+                (PatchKind::Remove, None) => changes.push(match &operation.value {
+                    Some(value) => MemberChange::Remove(member_ids(Some(value))?),
+                    None => MemberChange::RemoveAll,
+                }),
+                (_, None) => changes.push(members_change(operation.value.as_ref())?),
+                (_, Some(_)) => {
+                    return Err(ScimError::BadRequest("invalidPath", "members are added without a filter".to_string()))
+                }
+            },
+            Some(path) => return Err(ScimError::mutability(&path.attribute)),
+        }
+    }
+    Ok(changes)
+}
+
+// ---------------------------------------------------------------------------
+// Service
+// ---------------------------------------------------------------------------
+
+pub struct ScimService {
+    pool: PgPool,
+    passwords: PasswordService,
+    /// SHA-256 of `SCIM_BEARER_TOKEN`; `None` disables SCIM
+    token_digest: Option<[u8; 32]>,
+}
+
+impl ScimService {
+    pub fn new(config: &AppConfig, pool: PgPool) -> Self {
+        let token_digest = (!config.scim_bearer_token.is_empty())
+            .then(|| Sha256::digest(config.scim_bearer_token.as_bytes()).into());
+        Self {
+            passwords: PasswordService::new(config, pool.clone()),
+            pool,
+            token_digest,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.token_digest.is_some()
+    }
+
+    /// Whether `token` is the configured bearer token (compared in constant time).
+    pub fn authorize(&self, token: &str) -> bool {
+        let Some(expected) = &self.token_digest else {
+            return false;
+        };
+        let actual: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        expected.iter().zip(actual.iter()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+
+    pub async fn list_users(
+        &self,
+        filter: Option<&Filter>,
+        start_index: i64,
+        count: i64,
+    ) -> Result<(i64, Vec<ScimUserRow>), ScimError> {
+        let mut total = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM users WHERE ");
+        let mut page = QueryBuilder::<Postgres>::new(format!("SELECT {} FROM users WHERE ", USER_COLUMNS));
+        match filter {
+            Some(filter) => {
+                push_user_filter(filter, &mut total)?;
+                push_user_filter(filter, &mut page)?;
+            }
+            None => {
+                total.push("TRUE");
+                page.push("TRUE");
+            }
+        }
+        page.push(" ORDER BY created_at, id LIMIT ")
+            .push_bind(count)
+            .push(" OFFSET ")
+            .push_bind(start_index - 1);
+
+        let total: i64 = total.build_query_scalar().fetch_one(&self.pool).await?;
+        let users = page.build_query_as::<ScimUserRow>().fetch_all(&self.pool).await?;
+        Ok((total, users))
+    }
+
+    pub async fn get_user(&self, id: Uuid) -> Result<ScimUserRow, ScimError> {
+        sqlx::query_as!(
+            ScimUserRow,
+            r#"
+            SELECT id, username, email, status::text AS "status!", roles, external_id, created_at, updated_at
+            FROM users WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| ScimError::NotFound(format!("User {} not found", id)))
+    }
+
+    pub async fn create_user(&self, input: ScimUserInput) -> Result<ScimUserRow, ScimError> {
+        let email = primary_email(&input.emails)
+            .ok_or_else(|| ScimError::invalid_value("An email address is required"))?
+            .to_string();
+        if let Some(password) = &input.password {
+            self.check_password(password, PasswordOwner::New { role: None }).await?;
+        }
+        // Users provisioned without a password sign in through the IdP only
+        let password = input.password.unwrap_or_else(|| {
+            rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect()
+        });
+        let password_hash = self.passwords.hash_password(&password)?;
+        let active = input.active.unwrap_or(true);
+
+        let user = sqlx::query_as!(
+            ScimUserRow,
+            r#"
+            INSERT INTO users (username, email, password_hash, status, roles, external_id)
+            VALUES ($1, $2, $3, CASE WHEN $4 THEN 'active' ELSE 'suspended' END::user_status, '[]'::jsonb, $5)
+            RETURNING id, username, email, status::text AS "status!", roles, external_id, created_at, updated_at
+            "#,
+            input.user_name,
+            email,
+            password_hash,
+            active,
+            input.external_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        self.passwords.remember(user.id, &password_hash).await?;
+        Ok(user)
+    }
+
+    /// Rejects a password the IdP sent that breaks the password rules.
+    async fn check_password(&self, password: &str, owner: PasswordOwner<'_>) -> Result<(), ScimError> {
+        match self.passwords.check_new_password(password, owner).await? {
+            PasswordCheck::Accepted => Ok(()),
+            PasswordCheck::Rejected(reason) => Err(ScimError::invalid_value(reason)),
+        }
+    }
+
+    /// Applies `changes`; also returns whether the user was active before.
+    pub async fn update_user(&self, id: Uuid, changes: &UserChanges) -> Result<(ScimUserRow, bool), ScimError> {
+        let password_hash = match &changes.password {
+            Some(password) if !self.is_current_password(id, password).await? => {
+                self.check_password(password, PasswordOwner::Existing(id)).await?;
+                Some(self.passwords.hash_password(password)?)
+            }
+            // A PUT that repeats the current password leaves it (and the history) alone
+            _ => None,
+        };
+
+        let row = sqlx::query!(
+            r#"
+            WITH previous AS (SELECT id, status FROM users WHERE id = $1 FOR UPDATE)
+            UPDATE users u SET
+                username = COALESCE($2, u.username),
+                email = COALESCE($3, u.email),
+                status = CASE
+                    WHEN $4::boolean IS NULL THEN u.status
+                    WHEN $4 THEN 'active'::user_status
+                    ELSE 'suspended'::user_status
+                END,
+                external_id = CASE WHEN $5 THEN $6 ELSE u.external_id END,
+                password_hash = COALESCE($7, u.password_hash),
+                updated_at = NOW()
+            FROM previous
+            WHERE u.id = previous.id
+            RETURNING u.id, u.username, u.email, u.status::text AS "status!", u.roles, u.external_id,
+                      u.created_at, u.updated_at, previous.status = 'active' AS "was_active!"
+            "#,
+            id,
+            changes.user_name,
+            changes.email,
+            changes.active,
+            changes.external_id.is_some(),
+            changes.external_id.clone().flatten(),
+            password_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| ScimError::NotFound(format!("User {} not found", id)))?;
+
+        let user = ScimUserRow {
+            id: row.id,
+            username: row.username,
+            email: row.email,
+            status: row.status,
+            roles: row.roles,
+            external_id: row.external_id,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        };
+        if let Some(password_hash) = &password_hash {
+            self.passwords.remember(user.id, password_hash).await?;
+        }
+        Ok((user, row.was_active))
+    }
+
+    async fn is_current_password(&self, id: Uuid, password: &str) -> Result<bool, ScimError> {
+        let current = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = $1", id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(current.is_some_and(|hash| self.passwords.verify_password(password, &hash).unwrap_or(false)))
+    }
+
+    /// Ends the user's active sessions, returning their ids.
+    pub async fn terminate_sessions(&self, user_id: Uuid) -> Result<Vec<Uuid>, ScimError> {
+        let session_ids = sqlx::query_scalar!(
+            r#"
+            UPDATE sessions
+            SET status = 'terminated', terminated_at = NOW(), terminated_reason = 'deprovisioned'
+            WHERE user_id = $1 AND status = 'active'
+            RETURNING id
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(session_ids)
+    }
+
+    pub async fn delete_user(&self, id: Uuid) -> Result<(), ScimError> {
+        let result = sqlx::query!("DELETE FROM users WHERE id = $1", id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(ScimError::NotFound(format!("User {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Every role held by a user or granted access by an active policy, with its members.
+    async fn groups(&self) -> Result<Vec<ScimGroup>, ScimError> {
+        let mut groups: BTreeMap<String, Vec<(Uuid, String)>> = BTreeMap::new();
+        let policy_roles = sqlx::query_scalar!(
+            r#"
+            SELECT DISTINCT jsonb_array_elements_text(rbac_roles) AS "role!"
+            FROM policies
+            WHERE is_active AND jsonb_typeof(rbac_roles) = 'array'
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for role in policy_roles {
+            groups.entry(role).or_default();
+        }
+
+        let members = sqlx::query!(
+            r#"
+            SELECT id, username, roles FROM users
+            WHERE jsonb_typeof(roles) = 'array' AND jsonb_array_length(roles) > 0
+            ORDER BY username
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        for member in members {
+            for role in role_names(&member.roles) {
+                groups.entry(role).or_default().push((member.id, member.username.clone()));
+            }
+        }
+
+        Ok(groups.into_iter().map(|(name, members)| ScimGroup { name, members }).collect())
+    }
+
+    pub async fn list_groups(
+        &self,
+        filter: Option<&Filter>,
+        start_index: i64,
+        count: i64,
+    ) -> Result<(i64, Vec<ScimGroup>), ScimError> {
+        let mut matching = Vec::new();
+        for group in self.groups().await? {
+            let keep = match filter {
+                Some(filter) => filter.matches(&|path| group.attribute(path))?,
+                None => true,
+            };
+            if keep {
+                matching.push(group);
+            }
+        }
+        let total = matching.len() as i64;
+        let page = matching
+            .into_iter()
+            .skip((start_index - 1) as usize)
+            .take(count as usize)
+            .collect();
+        Ok((total, page))
+    }
+
+    pub async fn get_group(&self, name: &str) -> Result<ScimGroup, ScimError> {
+        self.groups()
+            .await?
+            .into_iter()
+            .find(|group| group.name == name)
+            .ok_or_else(|| ScimError::NotFound(format!("Group {} not found", name)))
+    }
+
+    /// Applies member changes to the role in one transaction.
+    pub async fn update_members(&self, role: &str, changes: &[MemberChange]) -> Result<(), ScimError> {
+        let mut tx = self.pool.begin().await?;
+        for change in changes {
+            let ids = match change {
+                MemberChange::Add(ids) | MemberChange::Remove(ids) | MemberChange::Replace(ids) => ids.as_slice(),
+                MemberChange::RemoveAll => &[],
+            };
+            let mut unique = ids.to_vec();
+            unique.sort();
+            unique.dedup();
+            let existing = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM users WHERE id = ANY($1)"#,
+                &unique
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if existing != unique.len() as i64 {
+                return Err(ScimError::invalid_value("members refers to a user that does not exist"));
+            }
+
+            let (add, remove_all_but): (&[Uuid], Option<&[Uuid]>) = match change {
+                MemberChange::Add(ids) => (ids, None),
+                MemberChange::Remove(ids) => {
+                    sqlx::query!(
+                        r#"
+                        UPDATE users
+                        SET roles = roles - $1::text,
+                            role = COALESCE((roles - $1::text)->>0, 'user'),
+                            updated_at = NOW()
+                        WHERE id = ANY($2) AND roles @> jsonb_build_array($1::text)
+                        "#,
+                        role,
+                        ids
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                    (&[], None)
+                }
+                MemberChange::RemoveAll => (&[], Some(&[])),
+                MemberChange::Replace(ids) => (ids, Some(ids)),
+            };
+            if let Some(keep) = remove_all_but {
+                sqlx::query!(
+                    r#"
+                    UPDATE users
+                    SET roles = roles - $1::text,
+                        role = COALESCE((roles - $1::text)->>0, 'user'),
+                        updated_at = NOW()
+                    WHERE roles @> jsonb_build_array($1::text) AND NOT id = ANY($2)
+                    "#,
+                    role,
+                    keep
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            if !add.is_empty() {
+                sqlx::query!(
+                    r#"
+                    UPDATE users
+                    SET roles = roles || jsonb_build_array($1::text),
+                        role = COALESCE((roles || jsonb_build_array($1::text))->>0, 'user'),
+                        updated_at = NOW()
+                    WHERE id = ANY($2) AND NOT roles @> jsonb_build_array($1::text)
+                    "#,
+                    role,
+                    add
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(path: &str, op: CompareOp, value: Value) -> Filter {
+        Filter::Compare(path.to_string(), op, value)
+    }
+
+    #[test]
+    fn parses_filters_with_precedence_and_urn_paths() {
+        let filter = parse_filter(
+            r#"urn:ietf:params:scim:schemas:core:2.0:User:userName Eq "a\"b" or active eq true and not (emails pr)"#,
+        )
+        .unwrap();
+        assert_eq!(
+            filter,
+            Filter::Or(
+                Box::new(compare("username", CompareOp::Eq, json!("a\"b"))),
+                Box::new(Filter::And(
+                    Box::new(compare("active", CompareOp::Eq, json!(true))),
+                    Box::new(Filter::Not(Box::new(Filter::Present("emails".to_string())))),
+                )),
+            )
+        );
+
+        for invalid in ["userName eq", "userName xx \"a\"", "(userName pr", "userName eq \"a", "emails[type eq \"work\"] pr"] {
+            assert!(matches!(parse_filter(invalid), Err(ScimError::BadRequest("invalidFilter", _))), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn translates_user_filters_to_sql() {
+        let filter = parse_filter(r#"userName sw "J_" and (groups eq "admin" or meta.created gt "2024-01-01T00:00:00Z")"#)
+            .unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("SELECT id FROM users WHERE ");
+        push_user_filter(&filter, &mut query).unwrap();
+        assert_eq!(
+            query.sql(),
+            "SELECT id FROM users WHERE (lower(username) LIKE $1 AND (roles @> jsonb_build_array($2::text) OR created_at > $3))"
+        );
+
+        let filter = parse_filter(r#"title eq "x""#).unwrap();
+        assert!(push_user_filter(&filter, &mut QueryBuilder::<Postgres>::new("")).is_err());
+    }
+
+    #[test]
+    fn user_patch_accepts_identity_provider_variants() {
+        let request: PatchRequest = serde_json::from_value(json!({
+            "schemas": [PATCH_OP_SCHEMA],
+            "Operations": [
+                { "op": "Replace", "path": "active", "value": "False" },
+                { "op": "replace", "path": "emails[type eq \"work\"].value", "value": "new@example.com" },
+                { "op": "add", "value": { "externalId": "ext-1", "name.givenName": "Ann" } },
+                { "op": "remove", "path": "title" }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            user_patch(&request.operations).unwrap(),
+            UserChanges {
+                email: Some("new@example.com".to_string()),
+                active: Some(false),
+                external_id: Some(Some("ext-1".to_string())),
+                ..Default::default()
+            }
+        );
+
+        let request: PatchRequest = serde_json::from_value(json!({
+            "Operations": [{ "op": "replace", "path": "id", "value": "x" }]
+        }))
+        .unwrap();
+        assert!(matches!(user_patch(&request.operations), Err(ScimError::BadRequest("mutability", _))));
+    }
+
+    #[test]
+    fn group_patch_reads_member_changes() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let request: PatchRequest = serde_json::from_value(json!({
+            "Operations": [
+                { "op": "add", "path": "members", "value": [{ "value": a.to_string() }] },
+                { "op": "remove", "path": format!("members[value eq \"{}\" or value eq \"{}\"]", a, b) },
+                { "op": "replace", "value": { "members": [{ "value": b.to_string() }] } },
+                { "op": "remove", "path": "members" }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            group_patch(&request.operations).unwrap(),
+            vec![
+                MemberChange::Add(vec![a]),
+                MemberChange::Remove(vec![a, b]),
+                MemberChange::Replace(vec![b]),
+                MemberChange::RemoveAll,
+            ]
+        );
+    }
+
+    #[test]
+    fn group_filters_evaluate_in_memory() {
+        let id = Uuid::new_v4();
+        let group = ScimGroup { name: "Admin".to_string(), members: vec![(id, "ann".to_string())] };
+        let lookup = |path: &str| group.attribute(path);
+        assert!(parse_filter(r#"displayName eq "admin""#).unwrap().matches(&lookup).unwrap());
+        assert!(parse_filter(&format!(r#"members.value eq "{}""#, id)).unwrap().matches(&lookup).unwrap());
+        assert!(!parse_filter(r#"displayName ne "admin""#).unwrap().matches(&lookup).unwrap());
+        assert!(parse_filter(r#"externalId eq "x""#).unwrap().matches(&lookup).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Ends sessions that outlived their limits and tears ended sessions down on
+//! the gateway.
+//!
+//! Each sweep first marks `expired` every active session past `expires_at`
+//! (which never moves beyond `absolute_expires_at`) or idle for longer than
+//! its `idle_timeout_seconds`; rows are kept, with the cause in
+//! `terminated_reason` (`absolute_timeout`, `idle_timeout` or `expired`).
+//! It then queues a `terminate_session` gateway command for every ended
+//! session, whatever ended it (logout, revocation, eviction, expiry), that has
+//! no `gateway_teardown_at` yet. The command's idempotency key is per session,
+//! so sessions already torn down by the handler that ended them are not sent
+//! twice. Gateway credentials of ended sessions are revoked by the credential
+//! sweep.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::services::agent_bridge::{AgentBridge, GatewayCommand};
+use crate::services::audit::{AuditEvent, AuditEventType};
+
+/// Sessions handed to the gateway per sweep
+const TEARDOWN_BATCH: i64 = 100;
+
+/// Reasons for which nothing announced the end of the session when it happened.
+const UNANNOUNCED_REASONS: &[&str] = &["absolute_timeout", "idle_timeout", "expired", "concurrent_limit"];
+
+/// A session the sweep handed to the gateway.
+#[derive(Debug, Clone)]
+pub struct EndedSession {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub reason: Option<String>,
+}
+
+impl EndedSession {
+    /// True when the sweep is the first to report the session as ended
+    /// (it expired, or a newer login evicted it).
+    pub fn unannounced(&self) -> bool {
+        self.reason
+            .as_deref()
+            .is_some_and(|reason| UNANNOUNCED_REASONS.contains(&reason))
+    }
+}
+
+/// The `terminated_reason` for an active session that has lapsed at `now`, or
+/// `None` while it is still live. Running out of `expires_at` once it reached
+/// `absolute_expires_at` is `absolute_timeout`; any other lapse of
+/// `expires_at` is `expired`; otherwise the idle timeout decides.
+fn expiry_reason(
+    expires_at: DateTime<Utc>,
+    absolute_expires_at: DateTime<Utc>,
+    idle_timeout_seconds: Option<i32>,
+    last_activity_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Option<&'static str> {
+    if expires_at <= now {
+        return Some(if expires_at >= absolute_expires_at { "absolute_timeout" } else { "expired" });
+    }
+    let idle_timeout = Duration::seconds(i64::from(idle_timeout_seconds?));
+    (last_activity_at <= now - idle_timeout).then_some("idle_timeout")
+}
+
+#[derive(Debug, Default)]
+pub struct ReaperSweep {
+    pub expired: usize,
+    pub torn_down: Vec<EndedSession>,
+}
+
+pub struct SessionReaper {
+    pool: PgPool,
+}
+
+impl SessionReaper {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Expires lapsed sessions, then queues gateway teardown of ended ones.
+    pub async fn sweep(&self, bridge: &AgentBridge) -> Result<ReaperSweep> {
+        let expired = self.expire().await?;
+        let torn_down = self.tear_down(bridge).await?;
+        Ok(ReaperSweep { expired, torn_down })
+    }
+
+    /// Marks active sessions past their absolute lifetime or idle timeout `expired`.
+    pub async fn expire(&self) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+        let lapsed = sqlx::query!(
+            r#"
+            SELECT id, user_id, expires_at, absolute_expires_at, idle_timeout_seconds, last_activity_at
+            FROM sessions
+            WHERE status = 'active'
+              AND (expires_at <= NOW()
+                   OR (idle_timeout_seconds IS NOT NULL
+                       AND last_activity_at <= NOW() - make_interval(secs => idle_timeout_seconds)))
+            FOR UPDATE SKIP LOCKED
+            "#
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let now = Utc::now();
+        let mut expired = 0;
+        for session in lapsed {
+            let Some(reason) = expiry_reason(
+                session.expires_at,
+                session.absolute_expires_at,
+                session.idle_timeout_seconds,
+                session.last_activity_at,
+                now,
+            ) else {
+                continue;
+            };
+
+            sqlx::query!(
+                r#"
+                UPDATE sessions
+                SET status = 'expired', terminated_at = NOW(), terminated_reason = $2
+                WHERE id = $1
+                "#,
+                session.id,
+                reason
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            AuditEvent::new(AuditEventType::SessionTerminated)
+                .target(session.user_id)
+                .session(session.id)
+                .details(serde_json::json!({
+                    "reason": reason,
+                    "status": "expired",
+                    "last_activity_at": session.last_activity_at
+                }))
+                .write(&mut *tx)
+                .await?;
+            expired += 1;
+        }
+        tx.commit().await?;
+
+        Ok(expired)
+    }
+
+    /// Queues `terminate_session` for ended sessions not yet handed to the
+    /// gateway. A session whose command could not be queued stays pending.
+    pub async fn tear_down(&self, bridge: &AgentBridge) -> Result<Vec<EndedSession>> {
+        let pending = sqlx::query_as!(
+            EndedSession,
+            r#"
+            SELECT s.id, s.user_id, u.username, s.terminated_reason AS reason
+            FROM sessions s
+            JOIN users u ON u.id = s.user_id
+            WHERE s.status <> 'active' AND s.gateway_teardown_at IS NULL
+            ORDER BY s.terminated_at NULLS FIRST, s.id
+            LIMIT $1
+            "#,
+            TEARDOWN_BATCH
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut torn_down = Vec::with_capacity(pending.len());
+        for session in pending {
+            let command = GatewayCommand::TerminateSession {
+                user_id: Some(session.user_id),
+                username: session.username.clone(),
+                session_id: session.id,
+            };
+            bridge.enqueue(command, None).await?;
+
+            sqlx::query!("UPDATE sessions SET gateway_teardown_at = NOW() WHERE id = $1", session.id)
+                .execute(&self.pool)
+                .await?;
+            torn_down.push(session);
+        }
+
+        Ok(torn_down)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ended(reason: Option<&str>) -> EndedSession {
+        EndedSession {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            username: "alice".to_string(),
+            reason: reason.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_live_sessions_do_not_expire() {
+        let now = Utc::now();
+        let expires_at = now + Duration::minutes(10);
+        let absolute = now + Duration::hours(8);
+        assert_eq!(expiry_reason(expires_at, absolute, None, now - Duration::hours(2), now), None);
+        assert_eq!(expiry_reason(expires_at, absolute, Some(900), now - Duration::minutes(5), now), None);
+    }
+
+    #[test]
+    fn test_expiry_reason_classification() {
+        let now = Utc::now();
+        let absolute = now - Duration::seconds(1);
+        // expires_at capped at the absolute lifetime
+        assert_eq!(
+            expiry_reason(absolute, absolute, Some(900), now, now),
+            Some("absolute_timeout")
+        );
+        // lapsed before the absolute lifetime, e.g. not refreshed
+        assert_eq!(
+            expiry_reason(now - Duration::minutes(1), now + Duration::hours(1), None, now, now),
+            Some("expired")
+        );
+        // lifetime left, but no activity for longer than the idle timeout
+        assert_eq!(
+            expiry_reason(now + Duration::minutes(10), now + Duration::hours(1), Some(900), now - Duration::minutes(15), now),
+            Some("idle_timeout")
+        );
+    }
+
+    #[test]
+    fn test_lapsed_expiry_wins_over_idle_timeout() {
+        let now = Utc::now();
+        let reason = expiry_reason(now, now + Duration::hours(1), Some(60), now - Duration::hours(1), now);
+        assert_eq!(reason, Some("expired"));
+    }
+
+    #[test]
+    fn test_only_silent_endings_are_unannounced() {
+        for reason in ["absolute_timeout", "idle_timeout", "expired", "concurrent_limit"] {
+            assert!(ended(Some(reason)).unannounced(), "{}", reason);
+        }
+        for reason in ["terminated", "admin_revoked", "password_reset", "device_key_revoked"] {
+            assert!(!ended(Some(reason)).unannounced(), "{}", reason);
+        }
+        assert!(!ended(None).unannounced());
+    }
+}
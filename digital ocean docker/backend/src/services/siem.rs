@@ -0,0 +1,638 @@
+//! Streams the audit log to a SIEM.
+//!
+//! The exporter tails `audit_events` in `seq` order and remembers the last
+//! event each target accepted in `siem_export_cursors`, so a restart resumes
+//! where it stopped and delivery is at least once. Inserts into the chain are
+//! serialized until commit (migration 013), so a visible `seq` never has an
+//! uncommitted predecessor that the cursor could skip past. A target's cursor
+//! row is locked while a replica exports a batch, so replicas sharing the
+//! target take turns instead of each sending every event.
+//!
+//! Targets (`SIEM_EXPORT_TARGET`):
+//! - `udp://host:port`: one RFC 5424 message per datagram (RFC 5426)
+//! - `tcp://host:port`, `tls://host:port`: RFC 5424 messages with octet-counting
+//!   framing (RFC 6587 / RFC 5425)
+//! - `file:///path`: one event per line, appended and synced per batch
+//!
+//! `SIEM_EXPORT_FORMAT` picks the payload: `rfc5424` (event fields as
+//! structured data, details as the message), `cef` or `json`. On network
+//! targets CEF and JSON payloads travel as the MSG of an RFC 5424 message.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::SecondsFormat;
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, ServerName};
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use crate::config::AppConfig;
+use crate::services::audit::{AuditFilter, AuditLog, AuditRecord};
+
+/// RFC 5424 facility 13, "log audit".
+const FACILITY_LOG_AUDIT: u8 = 13;
+const APP_NAME: &str = "viworks-backend";
+/// Structured-data ID; 32473 is the private enterprise number reserved for examples (RFC 5612).
+const SD_ID: &str = "viworks@32473";
+/// Largest payload sent in one UDP datagram; longer messages are truncated
+/// rather than blocking the cursor forever.
+const MAX_DATAGRAM: usize = 65_000;
+const UTF8_BOM: &str = "\u{feff}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiemFormat {
+    Rfc5424,
+    Cef,
+    Json,
+}
+
+impl SiemFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "rfc5424" => Some(Self::Rfc5424),
+            "cef" => Some(Self::Cef),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SiemTarget {
+    Udp(String),
+    Tcp(String),
+    Tls { address: String, host: String },
+    File(PathBuf),
+}
+
+impl SiemTarget {
+    pub fn parse(value: &str) -> Result<Self> {
+        let (scheme, rest) = value
+            .split_once("://")
+            .ok_or_else(|| anyhow!("SIEM target must look like scheme://address"))?;
+
+        let address = || -> Result<String> {
+            let (host, port) = rest
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("SIEM target {} needs a port", value))?;
+            if host.is_empty() || port.parse::<u16>().is_err() {
+                anyhow::bail!("SIEM target {} needs host:port", value);
+            }
+            Ok(rest.to_string())
+        };
+
+        match scheme {
+            "udp" => Ok(Self::Udp(address()?)),
+            "tcp" => Ok(Self::Tcp(address()?)),
+            "tls" => {
+                let address = address()?;
+                let host = address
+                    .rsplit_once(':')
+                    .map(|(host, _)| host.trim_start_matches('[').trim_end_matches(']').to_string())
+                    .unwrap_or_default();
+                Ok(Self::Tls { address, host })
+            }
+            "file" if !rest.is_empty() => Ok(Self::File(PathBuf::from(rest))),
+            _ => Err(anyhow!("Unsupported SIEM target: {}", value)),
+        }
+    }
+
+    fn is_network(&self) -> bool {
+        !matches!(self, Self::File(_))
+    }
+}
+
+/// RFC 5424 severity of an event type.
+fn severity(event_type: &str) -> u8 {
+    match event_type {
+        "login_failed" | "policy_denied" | "device_rejected" | "device_key_revoked" | "push_approval_denied" => 4,
+        "user_deleted" | "user_password_reset" | "password_reset_completed" | "session_terminated" | "device_unbound"
+        | "policy_updated" => 5,
+        _ => 6,
+    }
+}
+
+/// CEF severity (0-10) for the same buckets.
+fn cef_severity(event_type: &str) -> u8 {
+    match severity(event_type) {
+        4 => 7,
+        5 => 5,
+        _ => 3,
+    }
+}
+
+/// Header field: printable ASCII only, `-` when empty (RFC 5424 section 6).
+fn header_field(value: &str, max_len: usize) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max_len)
+        .collect();
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value
+    }
+}
+
+fn sd_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+fn cef_header_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn cef_value_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn truncate_utf8(value: &str, max_len: usize) -> &str {
+    if value.len() <= max_len {
+        return value;
+    }
+    let mut end = max_len;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Renders audit records in the configured format.
+pub struct SiemFormatter {
+    format: SiemFormat,
+    hostname: String,
+    procid: String,
+}
+
+impl SiemFormatter {
+    pub fn new(format: SiemFormat, hostname: &str) -> Self {
+        Self {
+            format,
+            hostname: header_field(hostname, 255),
+            procid: std::process::id().to_string(),
+        }
+    }
+
+    /// A complete RFC 5424 message carrying the record.
+    pub fn syslog(&self, record: &AuditRecord) -> String {
+        let pri = FACILITY_LOG_AUDIT * 8 + severity(&record.event_type);
+        let msg = match self.format {
+            SiemFormat::Rfc5424 => record.details.to_string(),
+            SiemFormat::Cef => self.cef(record),
+            SiemFormat::Json => self.json(record),
+        };
+
+        format!(
+            "<{}>1 {} {} {} {} {} {} {}{}",
+            pri,
+            record.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.hostname,
+            APP_NAME,
+            self.procid,
+            header_field(&record.event_type, 32),
+            self.structured_data(record),
+            UTF8_BOM,
+            msg
+        )
+    }
+
+    fn structured_data(&self, record: &AuditRecord) -> String {
+        let mut params = vec![("seq", record.seq.to_string()), ("id", record.id.to_string())];
+        if let Some(user_id) = record.user_id {
+            params.push(("user", user_id.to_string()));
+        }
+        if let Some(target_user_id) = record.target_user_id {
+            params.push(("target", target_user_id.to_string()));
+        }
+        if let Some(session_id) = record.session_id {
+            params.push(("session", session_id.to_string()));
+        }
+        if let Some(ip_address) = &record.ip_address {
+            params.push(("src", ip_address.clone()));
+        }
+        params.push(("hash", record.hash.clone()));
+
+        let params: Vec<String> = params
+            .into_iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, sd_escape(&value)))
+            .collect();
+        format!("[{} {}]", SD_ID, params.join(" "))
+    }
+
+    pub fn cef(&self, record: &AuditRecord) -> String {
+        let mut extension = vec![
+            ("rt", record.created_at.timestamp_millis().to_string()),
+            ("externalId", record.seq.to_string()),
+        ];
+        if let Some(actor) = record.details.get("actor").and_then(|actor| actor.as_str()) {
+            extension.push(("suser", actor.to_string()));
+        }
+        if let Some(user_id) = record.user_id {
+            extension.push(("suid", user_id.to_string()));
+        }
+        if let Some(target_user_id) = record.target_user_id {
+            extension.push(("duid", target_user_id.to_string()));
+        }
+        if let Some(ip_address) = &record.ip_address {
+            extension.push(("src", ip_address.clone()));
+        }
+        if let Some(user_agent) = &record.user_agent {
+            extension.push(("requestClientApplication", user_agent.clone()));
+        }
+        if let Some(session_id) = record.session_id {
+            extension.push(("cs1Label", "sessionId".to_string()));
+            extension.push(("cs1", session_id.to_string()));
+        }
+        extension.push(("cs2Label", "details".to_string()));
+        extension.push(("cs2", record.details.to_string()));
+        extension.push(("cs3Label", "hash".to_string()));
+        extension.push(("cs3", record.hash.clone()));
+
+        let extension: Vec<String> = extension
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, cef_value_escape(&value)))
+            .collect();
+
+        format!(
+            "CEF:0|ViWorkS|Admin Backend|{}|{}|{}|{}|{}",
+            cef_header_escape(env!("CARGO_PKG_VERSION")),
+            cef_header_escape(&record.event_type),
+            cef_header_escape(&record.event_type.replace('_', " ")),
+            cef_severity(&record.event_type),
+            extension.join(" ")
+        )
+    }
+
+    pub fn json(&self, record: &AuditRecord) -> String {
+        serde_json::to_string(record).unwrap_or_default()
+    }
+
+    /// What goes to `target` for one record: a syslog message on the wire, a bare line in files.
+    pub fn render(&self, record: &AuditRecord, target: &SiemTarget) -> String {
+        if target.is_network() {
+            return self.syslog(record);
+        }
+        match self.format {
+            SiemFormat::Rfc5424 => self.syslog(record),
+            SiemFormat::Cef => self.cef(record),
+            SiemFormat::Json => self.json(record),
+        }
+    }
+}
+
+/// Where rendered events go. A failed `send` is retried with the same
+/// messages on a fresh connection, so sinks must not skip anything.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    async fn send(&mut self, messages: &[String]) -> Result<()>;
+}
+
+pub struct UdpSink {
+    socket: UdpSocket,
+}
+
+impl UdpSink {
+    pub async fn connect(address: &str) -> Result<Self> {
+        let socket = UdpSocket::bind(if address.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" }).await?;
+        socket.connect(address).await?;
+        Ok(Self { socket })
+    }
+}
+
+#[async_trait]
+impl EventSink for UdpSink {
+    async fn send(&mut self, messages: &[String]) -> Result<()> {
+        for message in messages {
+            self.socket.send(truncate_utf8(message, MAX_DATAGRAM).as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// TCP or TLS stream with RFC 6587 octet-counting framing: `<len> <message>`.
+pub struct StreamSink<S> {
+    stream: S,
+}
+
+impl<S> StreamSink<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+}
+
+#[async_trait]
+impl<S: AsyncWrite + Unpin + Send + Sync> EventSink for StreamSink<S> {
+    async fn send(&mut self, messages: &[String]) -> Result<()> {
+        let mut buffer = Vec::new();
+        for message in messages {
+            buffer.extend_from_slice(format!("{} ", message.len()).as_bytes());
+            buffer.extend_from_slice(message.as_bytes());
+        }
+        self.stream.write_all(&buffer).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct FileSink {
+    file: tokio::fs::File,
+}
+
+impl FileSink {
+    pub async fn open(path: &PathBuf) -> Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self { file })
+    }
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    async fn send(&mut self, messages: &[String]) -> Result<()> {
+        let mut buffer = String::new();
+        for message in messages {
+            buffer.push_str(message);
+            buffer.push('\n');
+        }
+        self.file.write_all(buffer.as_bytes()).await?;
+        self.file.sync_data().await?;
+        Ok(())
+    }
+}
+
+fn tls_connector(ca_file: &str) -> Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    if ca_file.is_empty() {
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    } else {
+        for certificate in CertificateDer::pem_file_iter(ca_file)
+            .with_context(|| format!("Failed to read SIEM_TLS_CA_FILE {}", ca_file))?
+        {
+            roots.add(certificate?)?;
+        }
+    }
+
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportCursor {
+    pub target: String,
+    pub last_seq: i64,
+    pub exported_count: i64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub struct SiemExporter {
+    pool: PgPool,
+    name: String,
+    target: SiemTarget,
+    formatter: SiemFormatter,
+    tls_ca_file: String,
+    sink: Option<Box<dyn EventSink>>,
+}
+
+impl SiemExporter {
+    /// `None` when `SIEM_EXPORT_TARGET` is empty.
+    pub fn from_config(config: &AppConfig, pool: PgPool) -> Result<Option<Self>> {
+        if config.siem_export_target.is_empty() {
+            return Ok(None);
+        }
+        let format = SiemFormat::parse(&config.siem_export_format)
+            .ok_or_else(|| anyhow!("Unsupported SIEM_EXPORT_FORMAT {}", config.siem_export_format))?;
+        let hostname = std::env::var("HOSTNAME").unwrap_or_default();
+
+        Ok(Some(Self {
+            pool,
+            name: config.siem_export_target.clone(),
+            target: SiemTarget::parse(&config.siem_export_target)?,
+            formatter: SiemFormatter::new(format, &hostname),
+            tls_ca_file: config.siem_tls_ca_file.clone(),
+            sink: None,
+        }))
+    }
+
+    pub fn target(&self) -> &str {
+        &self.name
+    }
+
+    async fn connect(&self) -> Result<Box<dyn EventSink>> {
+        let sink: Box<dyn EventSink> = match &self.target {
+            SiemTarget::Udp(address) => Box::new(UdpSink::connect(address).await?),
+            SiemTarget::Tcp(address) => Box::new(StreamSink::new(TcpStream::connect(address).await?)),
+            SiemTarget::Tls { address, host } => {
+                let connector = tls_connector(&self.tls_ca_file)?;
+                let server_name = ServerName::try_from(host.clone())?;
+                let stream = TcpStream::connect(address).await?;
+                Box::new(StreamSink::new(connector.connect(server_name, stream).await?))
+            }
+            SiemTarget::File(path) => Box::new(FileSink::open(path).await?),
+        };
+        Ok(sink)
+    }
+
+    /// Sends the next batch after the cursor and advances it. Returns the
+    /// number of events exported; a full batch means more may be waiting.
+    /// Returns 0 while another replica holds the cursor.
+    pub async fn export_batch(&mut self) -> Result<usize> {
+        sqlx::query!(
+            "INSERT INTO siem_export_cursors (target) VALUES ($1) ON CONFLICT (target) DO NOTHING",
+            self.name
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let mut tx = self.pool.begin().await?;
+        let Some(last_seq) = sqlx::query_scalar!(
+            "SELECT last_seq FROM siem_export_cursors WHERE target = $1 FOR UPDATE SKIP LOCKED",
+            self.name
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(0);
+        };
+
+        let records = AuditLog::events_after(&self.pool, &AuditFilter::default(), last_seq).await?;
+        let Some(next_seq) = records.last().map(|record| record.seq) else {
+            return Ok(0);
+        };
+        let messages: Vec<String> = records
+            .iter()
+            .map(|record| self.formatter.render(record, &self.target))
+            .collect();
+
+        if self.sink.is_none() {
+            self.sink = Some(self.connect().await?);
+        }
+        if let Some(sink) = self.sink.as_mut() {
+            if let Err(e) = sink.send(&messages).await {
+                // Reconnect on the next attempt
+                self.sink = None;
+                return Err(e);
+            }
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE siem_export_cursors
+            SET last_seq = GREATEST(last_seq, $2), exported_count = exported_count + $3,
+                last_error = NULL, updated_at = NOW()
+            WHERE target = $1
+            "#,
+            self.name,
+            next_seq,
+            records.len() as i64
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(records.len())
+    }
+
+    /// Keeps the failure on the cursor row so admins can see why export is stuck.
+    pub async fn record_error(&self, error: &anyhow::Error) -> Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE siem_export_cursors
+            SET last_error = $2, last_error_at = NOW()
+            WHERE target = $1
+            "#,
+            self.name,
+            format!("{:#}", error)
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn cursors(pool: &PgPool) -> Result<Vec<ExportCursor>> {
+        let cursors = sqlx::query_as!(
+            ExportCursor,
+            r#"
+            SELECT target, last_seq, exported_count, last_error, last_error_at, updated_at
+            FROM siem_export_cursors
+            ORDER BY target
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(cursors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    fn record(event_type: &str) -> AuditRecord {
+        AuditRecord {
+            seq: 42,
+            id: Uuid::nil(),
+            event_type: event_type.to_string(),
+            user_id: Some(Uuid::nil()),
+            session_id: None,
+            target_user_id: None,
+            details: serde_json::json!({ "actor": "ad|min", "note": "a=b]" }),
+            ip_address: Some("10.0.0.7".to_string()),
+            user_agent: None,
+            created_at: Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap(),
+            prev_hash: "0".repeat(64),
+            hash: "ab".repeat(32),
+        }
+    }
+
+    #[test]
+    fn test_rfc5424_message_layout() {
+        let formatter = SiemFormatter::new(SiemFormat::Rfc5424, "gw 1");
+        let message = formatter.syslog(&record("login_failed"));
+        let expected_prefix = format!(
+            "<108>1 2026-01-02T03:04:05.000000Z gw_1 viworks-backend {} login_failed [viworks@32473 seq=\"42\" id=\"{}\" user=\"{}\" src=\"10.0.0.7\" hash=\"{}\"] \u{feff}",
+            std::process::id(),
+            Uuid::nil(),
+            Uuid::nil(),
+            "ab".repeat(32)
+        );
+        assert!(message.starts_with(&expected_prefix), "{}", message);
+        assert!(message.ends_with("{\"actor\":\"ad|min\",\"note\":\"a=b]\"}"));
+    }
+
+    #[test]
+    fn test_cef_escapes_extension_values() {
+        let formatter = SiemFormatter::new(SiemFormat::Cef, "");
+        let line = formatter.cef(&record("user_created"));
+        assert!(line.starts_with(&format!("CEF:0|ViWorkS|Admin Backend|{}|user_created|user created|3|rt=", env!("CARGO_PKG_VERSION"))));
+        assert!(line.contains("suser=ad|min "));
+        assert!(line.contains("cs2={\"actor\":\"ad|min\",\"note\":\"a\\=b]\"}"));
+    }
+
+    #[test]
+    fn test_target_parsing() {
+        assert_eq!(SiemTarget::parse("udp://siem:514").unwrap(), SiemTarget::Udp("siem:514".into()));
+        assert_eq!(
+            SiemTarget::parse("tls://siem.example.com:6514").unwrap(),
+            SiemTarget::Tls { address: "siem.example.com:6514".into(), host: "siem.example.com".into() }
+        );
+        assert_eq!(SiemTarget::parse("file:///var/log/a.cef").unwrap(), SiemTarget::File("/var/log/a.cef".into()));
+        assert!(SiemTarget::parse("tcp://siem").is_err());
+        assert!(SiemTarget::parse("http://siem:80").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_sink_frames_messages_for_a_syslog_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = String::new();
+            socket.read_to_string(&mut received).await.unwrap();
+            received
+        });
+
+        let mut sink = StreamSink::new(TcpStream::connect(&address).await.unwrap());
+        sink.send(&["<110>1 first".to_string(), "<110>1 sécond".to_string()]).await.unwrap();
+        drop(sink);
+
+        assert_eq!(server.await.unwrap(), "12 <110>1 first14 <110>1 sécond");
+    }
+
+    #[tokio::test]
+    async fn test_udp_sink_sends_one_datagram_per_message() {
+        let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sink = UdpSink::connect(&listener.local_addr().unwrap().to_string()).await.unwrap();
+        sink.send(&["<110>1 a".to_string(), "<110>1 b".to_string()]).await.unwrap();
+
+        let mut buffer = [0u8; 64];
+        for expected in ["<110>1 a", "<110>1 b"] {
+            let length = listener.recv(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..length], expected.as_bytes());
+        }
+    }
+}
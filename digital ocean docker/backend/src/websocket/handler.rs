@@ -1,19 +1,119 @@
-use actix::{Actor, StreamHandler, AsyncContext, ActorContext};
+use actix::{Actor, ActorContext, ActorFutureExt, AsyncContext, Handler, StreamHandler, WrapFuture};
+use actix_web::web;
 use actix_web_actors::ws;
 use std::time::{Duration, Instant};
 
+use crate::auth::{
+    bearer_token, policy::PolicyAction, AdminWebAuthnMiddleware, AuthMiddleware, AuthService, Claims,
+    PolicyMiddleware,
+};
+use crate::websocket::messages::{channels, ChannelEvent, WebSocketMessage};
+use crate::websocket::session::{WebSocketSession, WebSocketSessionManager};
+
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
 
-#[derive(Debug)]
+/// Subprotocol the server selects; browsers offer it next to `bearer.<jwt>`.
+pub const EVENTS_PROTOCOL: &str = "viworks.events";
+
+/// The admin dashboard's live event stream. Requires the same access as the
+/// `/admin` routes.
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/ws")
+            .wrap(AdminWebAuthnMiddleware::new())
+            .wrap(PolicyMiddleware::new(PolicyAction::Admin))
+            .wrap(AuthMiddleware::new())
+            .route("", web::get().to(websocket_route)),
+    );
+}
+
 pub struct WebSocketActor {
     pub hb: Instant,
+    pub session_id: String,
+    pub session_manager: web::Data<WebSocketSessionManager>,
+    pub auth_service: web::Data<AuthService>,
+    /// The access token the connection was opened with, re-checked every
+    /// heartbeat so logout, revocation and expiry close the socket
+    pub token: String,
+    pub claims: Claims,
+}
+
+impl WebSocketActor {
+    fn send(&self, ctx: &mut ws::WebsocketContext<Self>, message: &WebSocketMessage) {
+        match serde_json::to_string(message) {
+            Ok(text) => ctx.text(text),
+            Err(e) => log::error!("Failed to serialize WebSocket message: {}", e),
+        }
+    }
+
+    fn handle_message(&mut self, message: WebSocketMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match message {
+            WebSocketMessage::Subscribe { channel } => {
+                if !channels::is_known(&channel) {
+                    self.send(ctx, &WebSocketMessage::Error {
+                        message: format!("Unknown channel: {}", channel),
+                    });
+                    return;
+                }
+                self.session_manager.subscribe(&self.session_id, &channel);
+                self.send(ctx, &WebSocketMessage::Subscribed { channel });
+            }
+            WebSocketMessage::Unsubscribe { channel } => {
+                self.session_manager.unsubscribe(&self.session_id, &channel);
+                self.send(ctx, &WebSocketMessage::Unsubscribed { channel });
+            }
+            WebSocketMessage::Ping => self.send(ctx, &WebSocketMessage::Pong),
+            WebSocketMessage::Disconnect => {
+                self.send(ctx, &WebSocketMessage::Disconnected);
+                ctx.close(Some(ws::CloseCode::Normal.into()));
+                ctx.stop();
+            }
+            _ => self.send(ctx, &WebSocketMessage::Error {
+                message: "Unsupported message".to_string(),
+            }),
+        }
+    }
+
+    /// Closes the connection once its access token stops being valid.
+    fn check_token(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        let auth_service = self.auth_service.clone();
+        let token = self.token.clone();
+
+        ctx.spawn(
+            async move { auth_service.validate_access_token(&token).await }
+                .into_actor(self)
+                .map(|result, act, ctx| match result {
+                    Ok(Some(_)) => {}
+                    Ok(None) => {
+                        log::info!("Closing WebSocket of {}: session ended or token expired", act.claims.username);
+                        ctx.close(Some(ws::CloseReason {
+                            code: ws::CloseCode::Policy,
+                            description: Some("Session ended or token expired".to_string()),
+                        }));
+                        ctx.stop();
+                    }
+                    // Keep the connection on transient database errors
+                    Err(e) => log::error!("WebSocket token check failed: {}", e),
+                }),
+        );
+    }
 }
 
 impl Actor for WebSocketActor {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        let mut session = WebSocketSession::new();
+        session.id = self.session_id.clone();
+        session.user_id = Some(self.claims.sub.clone());
+        session.recipient = Some(ctx.address().recipient());
+        self.session_manager.add_session(session);
+
+        self.send(ctx, &WebSocketMessage::Connected {
+            session_id: self.session_id.clone(),
+        });
+
         // Start heartbeat
         ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
             if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
@@ -21,11 +121,28 @@ impl Actor for WebSocketActor {
                 ctx.stop();
                 return;
             }
-            
+
+            act.check_token(ctx);
+
             // Send ping
             ctx.ping(b"");
         });
     }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.session_manager.remove_session(&self.session_id);
+    }
+}
+
+impl Handler<ChannelEvent> for WebSocketActor {
+    type Result = ();
+
+    fn handle(&mut self, event: ChannelEvent, ctx: &mut Self::Context) {
+        match serde_json::to_string(&event) {
+            Ok(text) => ctx.text(text),
+            Err(e) => log::error!("Failed to serialize WebSocket event: {}", e),
+        }
+    }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketActor {
@@ -43,16 +160,22 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketActor {
                 self.hb = Instant::now();
             }
             Ok(ws::Message::Text(text)) => {
-                log::info!("Received WebSocket message: {}", text);
-                // Echo the message back
-                ctx.text(text);
+                self.hb = Instant::now();
+                match serde_json::from_str::<WebSocketMessage>(&text) {
+                    Ok(message) => self.handle_message(message, ctx),
+                    Err(_) => self.send(ctx, &WebSocketMessage::Error {
+                        message: "Invalid message".to_string(),
+                    }),
+                }
             }
-            Ok(ws::Message::Binary(bin)) => {
-                log::info!("Received binary message of {} bytes", bin.len());
-                ctx.binary(bin);
+            Ok(ws::Message::Binary(_)) => {
+                self.send(ctx, &WebSocketMessage::Error {
+                    message: "Binary messages are not supported".to_string(),
+                });
             }
             Ok(ws::Message::Close(reason)) => {
                 log::info!("WebSocket closing: {:?}", reason);
+                ctx.close(reason);
                 ctx.stop();
             }
             _ => {}
@@ -64,12 +187,28 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketActor {
 pub async fn websocket_route(
     req: actix_web::HttpRequest,
     stream: actix_web::web::Payload,
+    session_manager: web::Data<WebSocketSessionManager>,
+    auth_service: web::Data<AuthService>,
+    claims: web::ReqData<Claims>,
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-    ws::start(
+    // AuthMiddleware has already validated it
+    let token = bearer_token(req.headers())
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing authorization token"))?;
+    let claims = claims.into_inner();
+    log::info!("WebSocket connected for {}", claims.username);
+
+    ws::WsResponseBuilder::new(
         WebSocketActor {
             hb: Instant::now(),
+            session_id: uuid::Uuid::new_v4().to_string(),
+            session_manager,
+            auth_service,
+            token,
+            claims,
         },
         &req,
         stream,
     )
+    .protocols(&[EVENTS_PROTOCOL])
+    .start()
 }
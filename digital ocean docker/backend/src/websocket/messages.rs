@@ -1,5 +1,23 @@
+use actix::Message;
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+
+/// Channels a dashboard connection can subscribe to.
+pub mod channels {
+    /// Sessions started and ended (login, logout, revocation)
+    pub const SESSIONS: &str = "sessions";
+    /// Device-binding requests opened, reviewed or expired
+    pub const DEVICE_REQUESTS: &str = "device_requests";
+    /// Desktop agents connecting and disconnecting
+    pub const CLIENT_UPDATES: &str = "client_updates";
+    /// Security alerts such as account lockouts
+    pub const SECURITY_ALERTS: &str = "security_alerts";
+
+    pub const ALL: &[&str] = &[SESSIONS, DEVICE_REQUESTS, CLIENT_UPDATES, SECURITY_ALERTS];
+
+    pub fn is_known(channel: &str) -> bool {
+        ALL.contains(&channel)
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum WebSocketMessage {
@@ -12,6 +30,8 @@ pub enum WebSocketMessage {
     // Server messages
     Connected { session_id: String },
     Disconnected,
+    Subscribed { channel: String },
+    Unsubscribed { channel: String },
     Error { message: String },
     
     // Real-time updates
@@ -26,6 +46,16 @@ pub enum WebSocketMessage {
     Pong,
 }
 
+/// An event published on a channel; this is what subscribers receive
+/// (`{"channel": ..., "event_type": ..., "data": ..., "timestamp": ...}`).
+#[derive(Debug, Clone, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+pub struct ChannelEvent {
+    pub channel: String,
+    #[serde(flatten)]
+    pub event: WebSocketEvent,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebSocketEvent {
     pub event_type: String,
@@ -87,4 +117,37 @@ impl WebSocketEvent {
             })
         )
     }
+
+    pub fn device_binding(request_id: String, username: String, device_type: String, status: String) -> Self {
+        Self::new(
+            "device_binding".to_string(),
+            serde_json::json!({
+                "request_id": request_id,
+                "username": username,
+                "device_type": device_type,
+                "status": status,
+                "timestamp": chrono::Utc::now()
+            })
+        )
+    }
+
+    pub fn session_update(
+        session_id: String,
+        user_id: Option<String>,
+        username: Option<String>,
+        status: String,
+        reason: Option<String>,
+    ) -> Self {
+        Self::new(
+            "session_update".to_string(),
+            serde_json::json!({
+                "session_id": session_id,
+                "user_id": user_id,
+                "username": username,
+                "status": status,
+                "reason": reason,
+                "timestamp": chrono::Utc::now()
+            })
+        )
+    }
 }
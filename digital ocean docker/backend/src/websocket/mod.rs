@@ -2,4 +2,6 @@ pub mod handler;
 pub mod messages;
 pub mod session;
 
-pub use handler::websocket_route;
+pub use handler::{configure_routes, websocket_route};
+pub use messages::{channels, ChannelEvent, WebSocketEvent, WebSocketMessage};
+pub use session::{WebSocketSession, WebSocketSessionManager};
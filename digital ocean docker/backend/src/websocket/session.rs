@@ -1,14 +1,18 @@
+use actix::Recipient;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 
-use crate::websocket::messages::WebSocketEvent;
+use crate::websocket::messages::{ChannelEvent, WebSocketEvent};
 
 #[derive(Clone)]
 pub struct WebSocketSession {
     pub id: String,
     pub user_id: Option<String>,
     pub subscribed_channels: Vec<String>,
+    /// The connection's actor; `None` for sessions that only track state
+    pub recipient: Option<Recipient<ChannelEvent>>,
 }
 
 impl WebSocketSession {
@@ -17,47 +21,70 @@ impl WebSocketSession {
             id: Uuid::new_v4().to_string(),
             user_id: None,
             subscribed_channels: Vec::new(),
+            recipient: None,
         }
     }
-    
+
     pub fn subscribe(&mut self, channel: String) {
         if !self.subscribed_channels.contains(&channel) {
             self.subscribed_channels.push(channel);
         }
     }
-    
+
     pub fn unsubscribe(&mut self, channel: &str) {
         self.subscribed_channels.retain(|c| c != channel);
     }
-    
+
     pub fn is_subscribed(&self, channel: &str) -> bool {
         self.subscribed_channels.contains(&channel.to_string())
     }
 }
 
+/// Connected dashboard sessions and their subscriptions.
+///
+/// Events are published with [`broadcast_to_channel`](Self::broadcast_to_channel).
+/// With a relay attached (see `services::realtime`) they go through Redis so
+/// every backend replica delivers them to its own connections; without one
+/// they are delivered locally.
 pub struct WebSocketSessionManager {
     sessions: Arc<Mutex<HashMap<String, WebSocketSession>>>,
+    relay: Option<UnboundedSender<ChannelEvent>>,
+}
+
+impl Default for WebSocketSession {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WebSocketSessionManager {
     pub fn new() -> Self {
         Self {
             sessions: Arc::new(Mutex::new(HashMap::new())),
+            relay: None,
+        }
+    }
+
+    /// Publishes through `relay` instead of delivering locally.
+    pub fn with_relay(relay: UnboundedSender<ChannelEvent>) -> Self {
+        Self {
+            relay: Some(relay),
+            ..Self::new()
         }
     }
-    
+
     pub fn add_session(&self, session: WebSocketSession) {
         if let Ok(mut sessions) = self.sessions.lock() {
             sessions.insert(session.id.clone(), session);
         }
     }
-    
+
     pub fn remove_session(&self, session_id: &str) {
         if let Ok(mut sessions) = self.sessions.lock() {
             sessions.remove(session_id);
         }
     }
-    
+
     pub fn get_session(&self, session_id: &str) -> Option<WebSocketSession> {
         if let Ok(sessions) = self.sessions.lock() {
             sessions.get(session_id).cloned()
@@ -65,33 +92,65 @@ impl WebSocketSessionManager {
             None
         }
     }
-    
+
+    pub fn subscribe(&self, session_id: &str, channel: &str) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            if let Some(session) = sessions.get_mut(session_id) {
+                session.subscribe(channel.to_string());
+            }
+        }
+    }
+
+    pub fn unsubscribe(&self, session_id: &str, channel: &str) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            if let Some(session) = sessions.get_mut(session_id) {
+                session.unsubscribe(channel);
+            }
+        }
+    }
+
     pub fn broadcast_to_channel(&self, channel: &str, event: WebSocketEvent) {
-        if let Ok(sessions) = self.sessions.lock() {
-            for session in sessions.values() {
-                if session.is_subscribed(channel) {
-                    // In a real implementation, you would send the message to the session
-                    // For now, we'll just log it
-                    log::info!("Broadcasting to session {}: {:?}", session.id, event);
+        let event = ChannelEvent {
+            channel: channel.to_string(),
+            event,
+        };
+
+        match &self.relay {
+            Some(relay) => {
+                if let Err(unsent) = relay.send(event) {
+                    log::warn!("WebSocket event relay is gone, delivering locally");
+                    self.deliver(&unsent.0);
                 }
             }
+            None => self.deliver(&event),
         }
     }
-    
-    pub fn broadcast_to_all(&self, event: WebSocketEvent) {
+
+    /// Hands `event` to every local connection subscribed to its channel.
+    pub fn deliver(&self, event: &ChannelEvent) {
         if let Ok(sessions) = self.sessions.lock() {
             for session in sessions.values() {
-                // In a real implementation, you would send the message to the session
-                log::info!("Broadcasting to all sessions: {:?}", event);
+                if let Some(recipient) = &session.recipient {
+                    if session.is_subscribed(&event.channel) {
+                        recipient.do_send(event.clone());
+                    }
+                }
             }
         }
     }
 }
 
+impl Default for WebSocketSessionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Clone for WebSocketSessionManager {
     fn clone(&self) -> Self {
         Self {
             sessions: Arc::clone(&self.sessions),
+            relay: self.relay.clone(),
         }
     }
 }
@@ -410,7 +410,7 @@ export function DemoDashboardSection() {
                 });
                 const data = await response.json();
                 // eslint-disable-next-line no-console
-                console.log(`Container spawn test: ${data.container_id ? 'SUCCESS' : 'FAILED'}\nContainer ID: ${data.container_id}`);
+                console.log(`Container spawn test: ${data.command ? 'QUEUED' : 'FAILED'}\nCommand: ${data.command?.id} (${data.command?.status})`);
               } catch (error) {
                 // eslint-disable-next-line no-console
                 console.log(`Container spawn test failed: ${error}`);